@@ -0,0 +1,33 @@
+-- Slot length used when splitting a doctor's working hours into bookable slots
+ALTER TABLE tn_specialities ADD COLUMN slot_duration int DEFAULT 30;
+
+-- Weekly working calendar, one row per working interval (weekday: 1 = Monday ... 7 = Sunday).
+-- Lunch breaks are expressed as a gap between two intervals of the same weekday.
+create table tn_doctor_working_hours
+(
+	id serial primary key,
+	doctor_id int not null,
+	weekday int not null check (weekday between 1 and 7),
+	start_time time not null,
+	end_time time not null,
+	check (start_time < end_time),
+	FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE CASCADE
+);
+
+-- Holidays, leave and other one-off unavailability.
+-- A row without start_time/end_time blocks the whole day.
+create table tn_doctor_schedule_exceptions
+(
+	id serial primary key,
+	doctor_id int not null,
+	date date not null,
+	start_time time,
+	end_time time,
+	reason varchar(255),
+	create_at timestamp,
+	check (start_time is null or end_time is null or start_time < end_time),
+	FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_doctor_working_hours_doctor ON tn_doctor_working_hours (doctor_id, weekday);
+CREATE INDEX idx_doctor_schedule_exceptions_doctor ON tn_doctor_schedule_exceptions (doctor_id, date);
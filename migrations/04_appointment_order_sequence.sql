@@ -0,0 +1,29 @@
+-- Per-day, per-specialty queue counter. The row lock taken by the upsert that
+-- increments last_order serializes concurrent bookings for the same day and specialty.
+create table tn_appointment_sequences
+(
+	date date not null,
+	speciality_id int not null,
+	last_order int not null default 0,
+	primary key (date, speciality_id)
+);
+
+-- Renumber duplicated orders left by the old count-based allocation, keeping the existing order
+WITH ranked AS (
+	SELECT id, ROW_NUMBER() OVER (PARTITION BY date, speciality_id ORDER BY numerical_order, create_at, id) AS rn
+	FROM tn_appointments
+	WHERE date IS NOT NULL AND speciality_id IS NOT NULL
+)
+UPDATE tn_appointments a
+SET numerical_order = ranked.rn
+FROM ranked
+WHERE a.id = ranked.id AND a.numerical_order IS DISTINCT FROM ranked.rn;
+
+INSERT INTO tn_appointment_sequences (date, speciality_id, last_order)
+SELECT date, speciality_id, MAX(numerical_order)
+FROM tn_appointments
+WHERE date IS NOT NULL AND speciality_id IS NOT NULL
+GROUP BY date, speciality_id;
+
+ALTER TABLE tn_appointments
+	ADD CONSTRAINT uq_appointments_date_speciality_order UNIQUE (date, speciality_id, numerical_order);
@@ -0,0 +1,30 @@
+-- Normalize free-form values written before statuses were typed
+UPDATE tn_appointments SET status = 'Unpaid'
+WHERE status IS NULL OR status NOT IN ('Unpaid', 'Paid', 'Refunded');
+
+UPDATE tn_appointments SET treatment_status = lower(replace(replace(treatment_status, '_', '-'), ' ', '-'))
+WHERE treatment_status IS NOT NULL;
+
+UPDATE tn_appointments SET treatment_status = 'scheduled'
+WHERE treatment_status IS NULL
+   OR treatment_status NOT IN ('scheduled', 'checked-in', 'in-progress', 'completed', 'cancelled', 'no-show');
+
+ALTER TABLE tn_appointments
+	ALTER COLUMN status SET DEFAULT 'Unpaid',
+	ALTER COLUMN status SET NOT NULL,
+	ALTER COLUMN treatment_status SET DEFAULT 'scheduled',
+	ALTER COLUMN treatment_status SET NOT NULL,
+	ADD CONSTRAINT chk_appointments_status
+		CHECK (status IN ('Unpaid', 'Paid', 'Refunded')),
+	ADD CONSTRAINT chk_appointments_treatment_status
+		CHECK (treatment_status IN ('scheduled', 'checked-in', 'in-progress', 'completed', 'cancelled', 'no-show'));
+
+-- Which status column a record describes and who made the change
+ALTER TABLE tn_appointment_records
+	ADD COLUMN status_type varchar(20),
+	ADD COLUMN actor_id int,
+	ADD COLUMN actor_role varchar(15);
+
+UPDATE tn_appointment_records SET status_type = 'treatment_status' WHERE status_type IS NULL;
+
+CREATE INDEX idx_appointment_records_appointment ON tn_appointment_records (appointment_id, create_at);
@@ -0,0 +1,25 @@
+-- Receptionist accounts, already expected by the login query
+create table if not exists tn_receptionist
+(
+	id serial primary key,
+	email varchar(255) unique,
+	phone varchar(15),
+	password varchar(255),
+	name varchar(50),
+	gender int,
+	birthday varchar(10),
+	address varchar(255),
+	avatar varchar(255),
+	create_at timestamp,
+	update_at timestamp
+);
+
+-- Arrival stamp, walk-in flag and queue priority for reception check-in
+ALTER TABLE tn_appointments
+	ADD COLUMN arrived_at timestamp,
+	ADD COLUMN is_walk_in int NOT NULL DEFAULT 0,
+	ADD COLUMN priority varchar(15) NOT NULL DEFAULT 'normal',
+	ADD CONSTRAINT chk_appointments_priority
+		CHECK (priority IN ('normal', 'elderly', 'pregnant', 'emergency'));
+
+CREATE INDEX idx_appointments_queue ON tn_appointments (date, speciality_id, treatment_status);
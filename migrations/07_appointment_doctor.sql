@@ -0,0 +1,7 @@
+-- The doctor who sees the patient; NULL until one is assigned
+ALTER TABLE tn_appointments
+	ADD COLUMN doctor_id int,
+	ADD CONSTRAINT fk_appointments_doctor
+		FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_appointments_doctor_date ON tn_appointments (doctor_id, date);
@@ -0,0 +1,24 @@
+-- Specialty that performs a service, used when a booking becomes an appointment
+ALTER TABLE tn_services
+	ADD COLUMN speciality_id int,
+	ADD CONSTRAINT fk_services_speciality
+		FOREIGN KEY (speciality_id) REFERENCES tn_specialities(id) ON DELETE SET NULL;
+
+-- Booking workflow: pending -> confirmed (with its appointment) | rejected | cancelled
+UPDATE tn_booking SET status = LOWER(status);
+UPDATE tn_booking SET status = 'pending'
+	WHERE status IS NULL OR status NOT IN ('pending', 'confirmed', 'rejected', 'cancelled');
+
+ALTER TABLE tn_booking
+	ALTER COLUMN status SET DEFAULT 'pending',
+	ALTER COLUMN status SET NOT NULL,
+	ADD CONSTRAINT chk_booking_status
+		CHECK (status IN ('pending', 'confirmed', 'rejected', 'cancelled')),
+	ADD COLUMN appointment_id int,
+	ADD COLUMN reject_reason varchar(255),
+	ADD CONSTRAINT fk_booking_appointment
+		FOREIGN KEY (appointment_id) REFERENCES tn_appointments(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_booking_patient ON tn_booking (patient_id);
+CREATE INDEX idx_booking_status ON tn_booking (status);
+CREATE INDEX idx_booking_photo_booking ON tn_booking_photo (booking_id);
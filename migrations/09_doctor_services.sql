@@ -0,0 +1,24 @@
+-- One row per doctor and service; mappings go away with the doctor or service
+DELETE FROM tn_doctor_and_service WHERE doctor_id IS NULL OR service_id IS NULL;
+DELETE FROM tn_doctor_and_service a
+	USING tn_doctor_and_service b
+	WHERE a.doctor_id = b.doctor_id AND a.service_id = b.service_id AND a.id > b.id;
+
+ALTER TABLE tn_doctor_and_service
+	ALTER COLUMN doctor_id SET NOT NULL,
+	ALTER COLUMN service_id SET NOT NULL,
+	DROP CONSTRAINT tn_doctor_and_service_doctor_id_fkey,
+	DROP CONSTRAINT tn_doctor_and_service_service_id_fkey,
+	ADD CONSTRAINT tn_doctor_and_service_doctor_id_fkey
+		FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE CASCADE,
+	ADD CONSTRAINT tn_doctor_and_service_service_id_fkey
+		FOREIGN KEY (service_id) REFERENCES tn_services(id) ON DELETE CASCADE,
+	ADD CONSTRAINT uq_doctor_and_service UNIQUE (doctor_id, service_id);
+
+CREATE INDEX idx_doctor_and_service_service ON tn_doctor_and_service (service_id);
+
+-- Doctor the patient asked for when booking a service
+ALTER TABLE tn_booking
+	ADD COLUMN doctor_id int,
+	ADD CONSTRAINT fk_booking_doctor
+		FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE SET NULL;
@@ -0,0 +1,19 @@
+ALTER TABLE tn_rooms ADD CONSTRAINT uq_rooms_name UNIQUE (name);
+
+-- A doctor sitting in a room for part of a day; overrides tn_doctors.room_id for that time
+create table tn_doctor_room_shifts
+(
+	id serial primary key,
+	doctor_id int NOT NULL,
+	room_id int NOT NULL,
+	date date NOT NULL,
+	start_time time NOT NULL,
+	end_time time NOT NULL,
+	create_at timestamp,
+	FOREIGN KEY (doctor_id) REFERENCES tn_doctors(id) ON DELETE CASCADE,
+	FOREIGN KEY (room_id) REFERENCES tn_rooms(id) ON DELETE CASCADE,
+	CHECK (start_time < end_time)
+);
+
+CREATE INDEX idx_room_shifts_room_date ON tn_doctor_room_shifts (room_id, date);
+CREATE INDEX idx_room_shifts_doctor_date ON tn_doctor_room_shifts (doctor_id, date);
@@ -0,0 +1,66 @@
+-- Staff accounts, already expected by the login query
+create table if not exists tn_staffs
+(
+	id serial primary key,
+	email varchar(255) unique,
+	phone varchar(15),
+	password varchar(255),
+	name varchar(50),
+	gender int,
+	birthday varchar(10),
+	address varchar(255),
+	avatar varchar(255),
+	create_at timestamp,
+	update_at timestamp
+);
+
+-- One account per login, with ids unique across roles. Profile data stays in the
+-- role tables, which point back at their account.
+create table tn_users
+(
+	id serial primary key,
+	email varchar(255) NOT NULL,
+	password varchar(255) NOT NULL,
+	role varchar(15) NOT NULL,
+	active int NOT NULL DEFAULT 1,
+	create_at timestamp,
+	update_at timestamp,
+	CONSTRAINT chk_users_role CHECK (role IN ('patient', 'doctor', 'receptionist', 'staff', 'admin')),
+	CONSTRAINT uq_users_email_role UNIQUE (email, role)
+);
+
+ALTER TABLE tn_patients ADD COLUMN user_id int UNIQUE REFERENCES tn_users(id) ON DELETE SET NULL;
+ALTER TABLE tn_doctors ADD COLUMN user_id int UNIQUE REFERENCES tn_users(id) ON DELETE SET NULL;
+ALTER TABLE tn_receptionist ADD COLUMN user_id int UNIQUE REFERENCES tn_users(id) ON DELETE SET NULL;
+ALTER TABLE tn_staffs ADD COLUMN user_id int UNIQUE REFERENCES tn_users(id) ON DELETE SET NULL;
+ALTER TABLE tn_admins ADD COLUMN user_id int UNIQUE REFERENCES tn_users(id) ON DELETE SET NULL;
+
+-- Rows that could log in (email and password set) become accounts
+INSERT INTO tn_users (email, password, role, active, create_at, update_at)
+SELECT email, password, 'patient', 1, create_at, update_at FROM tn_patients
+WHERE email IS NOT NULL AND password IS NOT NULL;
+INSERT INTO tn_users (email, password, role, active, create_at, update_at)
+SELECT email, password, 'doctor', COALESCE(active, 1), create_at, update_at FROM tn_doctors
+WHERE email IS NOT NULL AND password IS NOT NULL;
+INSERT INTO tn_users (email, password, role, active, create_at, update_at)
+SELECT email, password, 'receptionist', 1, create_at, update_at FROM tn_receptionist
+WHERE email IS NOT NULL AND password IS NOT NULL;
+INSERT INTO tn_users (email, password, role, active, create_at, update_at)
+SELECT email, password, 'staff', 1, create_at, update_at FROM tn_staffs
+WHERE email IS NOT NULL AND password IS NOT NULL;
+INSERT INTO tn_users (email, password, role, active, create_at, update_at)
+SELECT email, password, 'admin', 1, create_at, update_at FROM tn_admins
+WHERE email IS NOT NULL AND password IS NOT NULL;
+
+UPDATE tn_patients p SET user_id = u.id FROM tn_users u WHERE u.role = 'patient' AND u.email = p.email;
+UPDATE tn_doctors d SET user_id = u.id FROM tn_users u WHERE u.role = 'doctor' AND u.email = d.email;
+UPDATE tn_receptionist r SET user_id = u.id FROM tn_users u WHERE u.role = 'receptionist' AND u.email = r.email;
+UPDATE tn_staffs s SET user_id = u.id FROM tn_users u WHERE u.role = 'staff' AND u.email = s.email;
+UPDATE tn_admins a SET user_id = u.id FROM tn_users u WHERE u.role = 'admin' AND u.email = a.email;
+
+-- Credentials now live only on the account
+ALTER TABLE tn_patients DROP COLUMN password;
+ALTER TABLE tn_doctors DROP COLUMN password;
+ALTER TABLE tn_receptionist DROP COLUMN password;
+ALTER TABLE tn_staffs DROP COLUMN password;
+ALTER TABLE tn_admins DROP COLUMN password;
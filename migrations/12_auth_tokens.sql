@@ -0,0 +1,27 @@
+-- Rotating refresh tokens; only a SHA-256 hash of each token is stored
+create table tn_refresh_tokens
+(
+	id serial primary key,
+	user_id int NOT NULL,
+	token_hash varchar(64) NOT NULL UNIQUE,
+	expires_at timestamp NOT NULL,
+	revoked_at timestamp,
+	replaced_by int,
+	create_at timestamp,
+	FOREIGN KEY (user_id) REFERENCES tn_users(id) ON DELETE CASCADE,
+	FOREIGN KEY (replaced_by) REFERENCES tn_refresh_tokens(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_refresh_tokens_user ON tn_refresh_tokens (user_id);
+
+-- Access tokens revoked before they expire, e.g. on logout. Rows can go once expired.
+create table tn_revoked_tokens
+(
+	jti varchar(64) primary key,
+	user_id int,
+	expires_at timestamp NOT NULL,
+	create_at timestamp
+);
+
+-- Access tokens issued before this moment are rejected, e.g. after deactivation
+ALTER TABLE tn_users ADD COLUMN tokens_revoked_at timestamp;
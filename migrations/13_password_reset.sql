@@ -0,0 +1,7 @@
+-- Password reset tokens belong to the account; only a SHA-256 hash of the token is stored
+ALTER TABLE tn_users
+	ADD COLUMN recovery_token varchar(64) UNIQUE,
+	ADD COLUMN recovery_token_expires_at timestamp;
+
+-- Never used, and a doctor profile is no place for credentials
+ALTER TABLE tn_doctors DROP COLUMN recovery_token;
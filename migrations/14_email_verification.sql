@@ -0,0 +1,3 @@
+-- Self-registered accounts stay unverified until the emailed link is opened. Accounts
+-- that already exist were created by staff or predate verification, so they count as verified.
+ALTER TABLE tn_users ADD COLUMN verified int NOT NULL DEFAULT 1;
@@ -0,0 +1,31 @@
+-- Failed logins per account. After too many in a row the account is locked for a while,
+-- longer with every lockout in a row.
+ALTER TABLE tn_users
+	ADD COLUMN failed_login_count int NOT NULL DEFAULT 0,
+	ADD COLUMN lockout_count int NOT NULL DEFAULT 0,
+	ADD COLUMN locked_until timestamp;
+
+-- The same per client address, which also catches guessing across many accounts
+create table tn_login_ip_attempts
+(
+	ip varchar(45) primary key,
+	failed_count int NOT NULL DEFAULT 0,
+	lockout_count int NOT NULL DEFAULT 0,
+	last_failed_at timestamp,
+	locked_until timestamp
+);
+
+-- Audit trail of failed logins, lockouts and unlocks
+create table tn_security_events
+(
+	id serial primary key,
+	event_type varchar(30) NOT NULL,
+	user_id int,
+	email varchar(255),
+	ip varchar(45),
+	detail text,
+	create_at timestamp NOT NULL,
+	FOREIGN KEY (user_id) REFERENCES tn_users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_security_events_user ON tn_security_events (user_id, create_at);
@@ -0,0 +1,31 @@
+-- TOTP second factor. The secret is stored once enrollment starts and only counts once a
+-- code confirmed it; the last accepted time step keeps a code from being used twice.
+ALTER TABLE tn_users
+	ADD COLUMN totp_secret varchar(64),
+	ADD COLUMN totp_enabled int NOT NULL DEFAULT 0,
+	ADD COLUMN totp_last_step bigint;
+
+-- Single-use codes for when the authenticator is lost; only SHA-256 hashes are stored
+create table tn_recovery_codes
+(
+	id serial primary key,
+	user_id int NOT NULL,
+	code_hash varchar(64) NOT NULL,
+	used_at timestamp,
+	create_at timestamp,
+	FOREIGN KEY (user_id) REFERENCES tn_users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_recovery_codes_user ON tn_recovery_codes (user_id);
+
+-- Roles whose accounts must sign in with a second factor
+create table tn_two_factor_policy
+(
+	role varchar(15) primary key,
+	required int NOT NULL DEFAULT 0,
+	update_at timestamp,
+	CONSTRAINT chk_two_factor_policy_role CHECK (role IN ('patient', 'doctor', 'receptionist', 'staff', 'admin'))
+);
+
+INSERT INTO tn_two_factor_policy (role, required) VALUES
+	('patient', 0), ('doctor', 0), ('receptionist', 0), ('staff', 0), ('admin', 0);
@@ -0,0 +1,19 @@
+-- One row per login: the device it came from and when it was last used. Refresh tokens
+-- and access tokens belong to a session and stop working once it is revoked.
+create table tn_sessions
+(
+	id serial primary key,
+	user_id int NOT NULL,
+	user_agent varchar(512),
+	ip varchar(45),
+	create_at timestamp NOT NULL,
+	last_seen_at timestamp NOT NULL,
+	expires_at timestamp NOT NULL,
+	revoked_at timestamp,
+	FOREIGN KEY (user_id) REFERENCES tn_users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_sessions_user ON tn_sessions (user_id);
+
+-- Tokens issued before sessions existed have none
+ALTER TABLE tn_refresh_tokens ADD COLUMN session_id int REFERENCES tn_sessions(id) ON DELETE CASCADE;
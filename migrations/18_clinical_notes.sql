@@ -0,0 +1,73 @@
+-- SOAP sections of a visit's note. The old free-text diagnosis stays as a summary and is
+-- no longer limited to 255 characters.
+ALTER TABLE tn_medical_records
+	ADD COLUMN chief_complaint text,
+	ADD COLUMN history text,
+	ADD COLUMN examination text,
+	ADD COLUMN assessment text,
+	ADD COLUMN plan text,
+	ADD COLUMN create_at timestamp,
+	ADD COLUMN update_at timestamp,
+	ALTER COLUMN diagnosis TYPE text;
+
+-- ICD-10 codes, filled by importing a code list; codes are stored with the dot, e.g. J45.909
+create table tn_icd10_codes
+(
+	code varchar(8) primary key,
+	description text NOT NULL,
+	update_at timestamp
+);
+
+CREATE INDEX idx_icd10_codes_description ON tn_icd10_codes (lower(description));
+
+-- Coded diagnoses of a record, at most one of them primary. Removing one keeps the row.
+create table tn_medical_record_diagnoses
+(
+	id serial primary key,
+	medical_record_id int NOT NULL,
+	icd10_code varchar(8) NOT NULL,
+	kind varchar(10) NOT NULL,
+	note text,
+	create_at timestamp NOT NULL,
+	created_by int,
+	removed_at timestamp,
+	removed_by int,
+	FOREIGN KEY (medical_record_id) REFERENCES tn_medical_records(id),
+	FOREIGN KEY (icd10_code) REFERENCES tn_icd10_codes(code),
+	FOREIGN KEY (created_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	FOREIGN KEY (removed_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	CONSTRAINT chk_medical_record_diagnoses_kind CHECK (kind IN ('primary', 'secondary'))
+);
+
+CREATE INDEX idx_medical_record_diagnoses_record ON tn_medical_record_diagnoses (medical_record_id);
+CREATE UNIQUE INDEX idx_medical_record_diagnoses_primary
+	ON tn_medical_record_diagnoses (medical_record_id) WHERE kind = 'primary' AND removed_at IS NULL;
+
+-- Every change to a record after it was written: the text before and after, who made the
+-- change and why. Rows are never changed or deleted, so the author is a snapshot rather
+-- than a foreign key that deleting the account would have to clear.
+create table tn_medical_record_amendments
+(
+	id serial primary key,
+	medical_record_id int NOT NULL,
+	section varchar(20) NOT NULL,
+	old_value text,
+	new_value text,
+	reason text,
+	amended_by int,
+	amended_by_name varchar(255),
+	create_at timestamp NOT NULL,
+	FOREIGN KEY (medical_record_id) REFERENCES tn_medical_records(id)
+);
+
+CREATE INDEX idx_medical_record_amendments_record ON tn_medical_record_amendments (medical_record_id);
+
+CREATE OR REPLACE FUNCTION tn_reject_amendment_change() RETURNS trigger AS $$
+BEGIN
+	RAISE EXCEPTION 'medical record amendments are append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_medical_record_amendments_append_only
+	BEFORE UPDATE OR DELETE ON tn_medical_record_amendments
+	FOR EACH ROW EXECUTE FUNCTION tn_reject_amendment_change();
@@ -0,0 +1,52 @@
+-- Measurements keep their decimals and each is stored in one unit: temperature in degrees
+-- Celsius, weight in kilograms, height in centimetres, blood pressure in mmHg, heart rate
+-- in beats per minute and SpO2 in percent. Old whole-number values too large for the new
+-- columns cannot be real readings and are dropped rather than failing the migration.
+UPDATE tn_vital_signs
+SET temperature = CASE WHEN abs(temperature) >= 1000 THEN NULL ELSE temperature END,
+	weight = CASE WHEN abs(weight) >= 1000 THEN NULL ELSE weight END,
+	height = CASE WHEN abs(height) >= 1000 THEN NULL ELSE height END
+WHERE abs(temperature) >= 1000 OR abs(weight) >= 1000 OR abs(height) >= 1000;
+
+ALTER TABLE tn_vital_signs
+	ALTER COLUMN temperature TYPE numeric(4, 1),
+	ALTER COLUMN weight TYPE numeric(5, 2),
+	ALTER COLUMN height TYPE numeric(4, 1),
+	ADD COLUMN recorded_at timestamp,
+	ADD COLUMN recorded_by int REFERENCES tn_users(id) ON DELETE SET NULL;
+
+-- Readings taken before had no time of their own; the record's is the closest there is
+UPDATE tn_vital_signs v
+SET recorded_at = COALESCE((SELECT mr.create_at FROM tn_medical_records mr WHERE mr.id = v.medical_record_id), NOW());
+
+ALTER TABLE tn_vital_signs ALTER COLUMN recorded_at SET NOT NULL;
+
+CREATE INDEX idx_vital_signs_record ON tn_vital_signs (medical_record_id, recorded_at);
+
+-- Normal ranges readings are flagged against, per age group. BMI has no pediatric range
+-- since children are judged by percentile for their age.
+create table tn_vital_reference_ranges
+(
+	age_group varchar(10) NOT NULL,
+	vital varchar(30) NOT NULL,
+	low numeric(5, 1) NOT NULL,
+	high numeric(5, 1) NOT NULL,
+	update_at timestamp,
+	PRIMARY KEY (age_group, vital),
+	CONSTRAINT chk_vital_reference_ranges_age_group CHECK (age_group IN ('adult', 'pediatric')),
+	CONSTRAINT chk_vital_reference_ranges_vital CHECK (vital IN ('temperature', 'blood_pressure_systolic', 'blood_pressure_diastolic', 'heart_rate', 'spo2', 'bmi')),
+	CONSTRAINT chk_vital_reference_ranges_bounds CHECK (low < high)
+);
+
+INSERT INTO tn_vital_reference_ranges (age_group, vital, low, high) VALUES
+	('adult', 'temperature', 36.1, 37.8),
+	('adult', 'blood_pressure_systolic', 90, 139),
+	('adult', 'blood_pressure_diastolic', 60, 89),
+	('adult', 'heart_rate', 60, 100),
+	('adult', 'spo2', 95, 100),
+	('adult', 'bmi', 18.5, 24.9),
+	('pediatric', 'temperature', 36.1, 37.8),
+	('pediatric', 'blood_pressure_systolic', 85, 120),
+	('pediatric', 'blood_pressure_diastolic', 50, 80),
+	('pediatric', 'heart_rate', 70, 120),
+	('pediatric', 'spo2', 95, 100);
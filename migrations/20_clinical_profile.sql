@@ -0,0 +1,77 @@
+-- What a doctor should know about a patient before treating them, kept across visits.
+-- Entries are never deleted, only marked removed, so the profile's history stays readable.
+create table tn_patient_clinical_profiles
+(
+	patient_id int primary key,
+	blood_type varchar(3),
+	family_history text,
+	update_at timestamp,
+	updated_by int,
+	FOREIGN KEY (patient_id) REFERENCES tn_patients(id) ON DELETE CASCADE,
+	FOREIGN KEY (updated_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	CONSTRAINT chk_clinical_profiles_blood_type CHECK (blood_type IN ('A+', 'A-', 'B+', 'B-', 'AB+', 'AB-', 'O+', 'O-'))
+);
+
+create table tn_patient_allergies
+(
+	id serial primary key,
+	patient_id int NOT NULL,
+	substance varchar(255) NOT NULL,
+	reaction varchar(255),
+	severity varchar(20) NOT NULL,
+	create_at timestamp NOT NULL,
+	created_by int,
+	removed_at timestamp,
+	removed_by int,
+	FOREIGN KEY (patient_id) REFERENCES tn_patients(id) ON DELETE CASCADE,
+	FOREIGN KEY (created_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	FOREIGN KEY (removed_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	CONSTRAINT chk_patient_allergies_severity CHECK (severity IN ('mild', 'moderate', 'severe', 'life_threatening'))
+);
+
+-- The same substance is listed once while current
+CREATE UNIQUE INDEX uq_patient_allergies_substance ON tn_patient_allergies (patient_id, lower(substance)) WHERE removed_at IS NULL;
+
+create table tn_patient_conditions
+(
+	id serial primary key,
+	patient_id int NOT NULL,
+	name varchar(255) NOT NULL,
+	icd10_code varchar(8),
+	diagnosed_on date,
+	note text,
+	create_at timestamp NOT NULL,
+	created_by int,
+	removed_at timestamp,
+	removed_by int,
+	FOREIGN KEY (patient_id) REFERENCES tn_patients(id) ON DELETE CASCADE,
+	FOREIGN KEY (icd10_code) REFERENCES tn_icd10_codes(code),
+	FOREIGN KEY (created_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	FOREIGN KEY (removed_by) REFERENCES tn_users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_patient_conditions_patient ON tn_patient_conditions (patient_id);
+
+-- Long-term medications, whether prescribed here or elsewhere; medicine_id links the ones
+-- in the hospital's formulary
+create table tn_patient_medications
+(
+	id serial primary key,
+	patient_id int NOT NULL,
+	medicine_id int,
+	name varchar(255) NOT NULL,
+	dose varchar(100),
+	frequency varchar(100),
+	started_on date,
+	note text,
+	create_at timestamp NOT NULL,
+	created_by int,
+	removed_at timestamp,
+	removed_by int,
+	FOREIGN KEY (patient_id) REFERENCES tn_patients(id) ON DELETE CASCADE,
+	FOREIGN KEY (medicine_id) REFERENCES tn_medicine(id) ON DELETE SET NULL,
+	FOREIGN KEY (created_by) REFERENCES tn_users(id) ON DELETE SET NULL,
+	FOREIGN KEY (removed_by) REFERENCES tn_users(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_patient_medications_patient ON tn_patient_medications (patient_id);
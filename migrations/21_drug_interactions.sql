@@ -0,0 +1,40 @@
+-- Interaction knowledge base, filled by importing CSV files. A pair is stored once, with
+-- the lower medicine id first.
+create table tn_drug_interactions
+(
+	medicine_a int NOT NULL,
+	medicine_b int NOT NULL,
+	severity varchar(20) NOT NULL,
+	note text,
+	update_at timestamp,
+	primary key (medicine_a, medicine_b),
+	FOREIGN KEY (medicine_a) REFERENCES tn_medicine(id) ON DELETE CASCADE,
+	FOREIGN KEY (medicine_b) REFERENCES tn_medicine(id) ON DELETE CASCADE,
+	CONSTRAINT chk_drug_interactions_order CHECK (medicine_a < medicine_b),
+	CONSTRAINT chk_drug_interactions_severity CHECK (severity IN ('minor', 'moderate', 'major', 'contraindicated'))
+);
+
+CREATE INDEX idx_drug_interactions_medicine_b ON tn_drug_interactions (medicine_b);
+
+-- Active ingredients of a medicine and the allergen class each belongs to, e.g. amoxicillin
+-- is a penicillin; matched against the substances of patients' allergies
+create table tn_medicine_ingredients
+(
+	id serial primary key,
+	medicine_id int NOT NULL,
+	ingredient varchar(255) NOT NULL,
+	allergen varchar(255),
+	update_at timestamp,
+	FOREIGN KEY (medicine_id) REFERENCES tn_medicine(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX uq_medicine_ingredients ON tn_medicine_ingredients (medicine_id, lower(ingredient));
+
+-- A prescription with blocking safety issues is only stored with the prescriber's reason
+-- for going ahead, next to the issues as they were shown
+ALTER TABLE medicine_of_prescription
+	ADD COLUMN override_reason text,
+	ADD COLUMN overridden_issues text[],
+	ADD COLUMN overridden_by int,
+	ADD COLUMN create_at timestamp,
+	ADD CONSTRAINT medicine_of_prescription_overridden_by_fkey FOREIGN KEY (overridden_by) REFERENCES tn_users(id) ON DELETE SET NULL;
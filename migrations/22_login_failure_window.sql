@@ -0,0 +1,3 @@
+-- When the account last failed a login. Failures older than one lockout period no longer
+-- count towards a lockout, like those of an address.
+ALTER TABLE tn_users ADD COLUMN last_failed_login_at timestamp;
@@ -1,7 +1,10 @@
-use crate::models::Appointment;
+use crate::db::queue::QUEUE_ORDER_BY;
+use crate::models::{
+    Appointment, AppointmentRecord, AppointmentStatus, AvailableSlot, TreatmentStatus,
+};
 use crate::{error::Error, models::AppointmentHistoryResponse};
-use chrono::{Duration, NaiveDate, NaiveTime};
-use sqlx::PgPool;
+use chrono::{NaiveDate, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 
 #[allow(unused_variables)]
 pub async fn get_appointments_of_patient(
@@ -17,20 +20,94 @@ pub async fn get_appointments_of_patient(
         .map_err(Error::Database)
 }
 
+// A doctor's worklist for one day, in call order
 pub async fn get_appointments_of_doctor(
     pool: &PgPool,
     doctor_id: i32,
+    date: NaiveDate,
 ) -> Result<Vec<Appointment>, Error> {
-    let query = "SELECT * FROM tn_appointments WHERE doctor_id = $1";
+    let query = format!(
+        "SELECT * FROM tn_appointments WHERE doctor_id = $1 AND date = $2 ORDER BY {}",
+        QUEUE_ORDER_BY
+    );
     sqlx::query_as::<_, Appointment>(&query)
+        .bind(doctor_id)
+        .bind(date)
         .fetch_all(pool)
         .await
         .map_err(Error::Database)
 }
 
-pub async fn create_appointment(pool: &PgPool, appointment: Appointment) -> Result<(), Error> {
-    let query = "INSERT INTO tn_appointments (patient_id, patient_name, patient_birthday, patient_phone, patient_reason, speciality_id, date, numerical_order, appointment_time, status, create_at, update_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)";
-    sqlx::query(query)
+// Allocates the next numerical_order and inserts the appointment in one transaction.
+// The upsert on tn_appointment_sequences locks the (date, speciality_id) counter row until
+// commit, so concurrent bookings for the same day queue up behind each other and the slot
+// capacity check below always sees the rows committed before it.
+//
+// `doctors` are the doctors working the slot. A doctor_id already set on the appointment
+// must be one of them and still free; otherwise the least-loaded free one is assigned.
+pub async fn create_appointment(
+    pool: &PgPool,
+    appointment: Appointment,
+    slot_capacity: i32,
+    doctors: &[i32],
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let created = insert_appointment(&mut tx, appointment, slot_capacity, doctors).await?;
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(created)
+}
+
+// create_appointment inside the caller's transaction, returning the stored row
+pub async fn insert_appointment(
+    tx: &mut Transaction<'_, Postgres>,
+    mut appointment: Appointment,
+    slot_capacity: i32,
+    doctors: &[i32],
+) -> Result<Appointment, Error> {
+    let numerical_order = allocate_numerical_order(
+        tx,
+        appointment.date,
+        appointment.speciality_id,
+    )
+    .await?;
+    check_slot_capacity(
+        tx,
+        appointment.date,
+        appointment.speciality_id,
+        &appointment.appointment_time,
+        slot_capacity,
+        None,
+    )
+    .await?;
+
+    appointment.doctor_id = match appointment.doctor_id {
+        Some(doctor_id) => Some(
+            require_free_doctor(
+                tx,
+                doctor_id,
+                doctors,
+                appointment.date,
+                Some(&appointment.appointment_time),
+                None,
+            )
+            .await?,
+        ),
+        None => {
+            pick_doctor(
+                tx,
+                doctors,
+                appointment.date,
+                Some(&appointment.appointment_time),
+                None,
+                None,
+            )
+            .await?
+        }
+    };
+
+    appointment.numerical_order = Some(numerical_order);
+    let query = "INSERT INTO tn_appointments (patient_id, patient_name, patient_birthday, patient_phone, patient_reason, speciality_id, date, numerical_order, appointment_time, status, treatment_status, doctor_id, create_at, update_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING *";
+    sqlx::query_as::<_, Appointment>(query)
         .bind(appointment.patient_id)
         .bind(appointment.patient_name)
         .bind(appointment.patient_birthday)
@@ -41,14 +118,330 @@ pub async fn create_appointment(pool: &PgPool, appointment: Appointment) -> Resu
         .bind(appointment.numerical_order)
         .bind(appointment.appointment_time)
         .bind(appointment.status)
+        .bind(appointment.treatment_status)
+        .bind(appointment.doctor_id)
         .bind(appointment.create_at)
         .bind(appointment.update_at)
-        .execute(pool)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(Error::Database)?;
+        .map_err(Error::Database)
+}
+
+// Takes the next number from the per-day counter. The upsert keeps the counter row locked
+// until the transaction ends; numbers are never reused, so cancelled or moved appointments
+// leave gaps instead of forcing the rest of the queue to be renumbered.
+pub async fn allocate_numerical_order(
+    tx: &mut Transaction<'_, Postgres>,
+    date: Option<NaiveDate>,
+    speciality_id: Option<i32>,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_appointment_sequences (date, speciality_id, last_order) VALUES ($1, $2, 1)
+         ON CONFLICT (date, speciality_id)
+         DO UPDATE SET last_order = tn_appointment_sequences.last_order + 1
+         RETURNING last_order",
+        date,
+        speciality_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(Error::Database)
+}
+
+// Cancelled appointments no longer hold their slot, and walk-ins never did
+async fn check_slot_capacity(
+    tx: &mut Transaction<'_, Postgres>,
+    date: Option<NaiveDate>,
+    speciality_id: Option<i32>,
+    appointment_time: &str,
+    slot_capacity: i32,
+    exclude_id: Option<i32>,
+) -> Result<(), Error> {
+    let booked = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM tn_appointments
+         WHERE date = $1 AND speciality_id = $2 AND appointment_time = $3
+         AND treatment_status <> 'cancelled' AND COALESCE(is_walk_in, 0) = 0
+         AND id IS DISTINCT FROM $4",
+        date,
+        speciality_id,
+        appointment_time,
+        exclude_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(Error::Database)?
+    .unwrap_or(0);
+
+    if booked >= slot_capacity as i64 {
+        // Dropping the transaction rolls the counter back as well
+        return Err(Error::Conflict(format!(
+            "slot {} is already fully booked",
+            appointment_time
+        )));
+    }
     Ok(())
 }
 
+// Least-loaded of the candidate doctors that day, skipping anyone who already has an
+// appointment in the slot; walk-ins count towards the load but hold no slot. `prefer` wins whenever it is eligible, so rescheduling keeps
+// the same doctor where possible. Callers hold the day's counter lock.
+pub async fn pick_doctor(
+    tx: &mut Transaction<'_, Postgres>,
+    candidates: &[i32],
+    date: Option<NaiveDate>,
+    appointment_time: Option<&str>,
+    prefer: Option<i32>,
+    exclude_id: Option<i32>,
+) -> Result<Option<i32>, Error> {
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    sqlx::query_scalar::<_, i32>(
+        "SELECT d.id FROM unnest($1::int4[]) AS d(id)
+         WHERE $3::varchar IS NULL OR NOT EXISTS (
+             SELECT 1 FROM tn_appointments a
+             WHERE a.doctor_id = d.id AND a.date = $2 AND a.appointment_time = $3
+             AND a.treatment_status NOT IN ('cancelled', 'no-show') AND COALESCE(a.is_walk_in, 0) = 0
+             AND a.id IS DISTINCT FROM $5)
+         ORDER BY d.id = $4 DESC NULLS LAST,
+             (SELECT COUNT(*) FROM tn_appointments a
+              WHERE a.doctor_id = d.id AND a.date = $2
+              AND a.treatment_status NOT IN ('cancelled', 'no-show') AND a.id IS DISTINCT FROM $5),
+             d.id
+         LIMIT 1",
+    )
+    .bind(candidates)
+    .bind(date)
+    .bind(appointment_time)
+    .bind(prefer)
+    .bind(exclude_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(Error::Database)
+}
+
+async fn require_free_doctor(
+    tx: &mut Transaction<'_, Postgres>,
+    doctor_id: i32,
+    doctors: &[i32],
+    date: Option<NaiveDate>,
+    appointment_time: Option<&str>,
+    exclude_id: Option<i32>,
+) -> Result<i32, Error> {
+    let candidates: Vec<i32> = doctors.iter().copied().filter(|id| *id == doctor_id).collect();
+    pick_doctor(tx, &candidates, date, appointment_time, None, exclude_id)
+        .await?
+        .ok_or_else(|| {
+            Error::Conflict(match appointment_time {
+                Some(time) => format!("doctor {} is not available at {}", doctor_id, time),
+                None => format!("doctor {} is not working that day", doctor_id),
+            })
+        })
+}
+
+// Moves a scheduled appointment to another date/slot with a freshly allocated number,
+// keeping its doctor if they are free at the new slot
+pub async fn reschedule_appointment(
+    pool: &PgPool,
+    id: i32,
+    date: NaiveDate,
+    slot: &AvailableSlot,
+    doctors: &[i32],
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<Appointment, Error> {
+    let appointment_time = slot.time.as_str();
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let current = sqlx::query_as::<_, Appointment>(
+        "SELECT * FROM tn_appointments WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if current.treatment_status != Some(TreatmentStatus::Scheduled) {
+        return Err(Error::Conflict(
+            "only scheduled appointments can be rescheduled".to_string(),
+        ));
+    }
+
+    let numerical_order =
+        allocate_numerical_order(&mut tx, Some(date), current.speciality_id).await?;
+    check_slot_capacity(
+        &mut tx,
+        Some(date),
+        current.speciality_id,
+        appointment_time,
+        slot.capacity,
+        Some(id),
+    )
+    .await?;
+    let doctor_id = pick_doctor(
+        &mut tx,
+        doctors,
+        Some(date),
+        Some(appointment_time),
+        current.doctor_id,
+        Some(id),
+    )
+    .await?;
+
+    let updated = sqlx::query_as::<_, Appointment>(
+        "UPDATE tn_appointments SET date = $1, appointment_time = $2, numerical_order = $3, doctor_id = $4, update_at = $5 WHERE id = $6 RETURNING *",
+    )
+    .bind(date)
+    .bind(appointment_time)
+    .bind(numerical_order)
+    .bind(doctor_id)
+    .bind(Utc::now().naive_utc())
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    let before = format!(
+        "{} {} #{}",
+        current.date.map(|d| d.to_string()).unwrap_or_default(),
+        current.appointment_time,
+        current.numerical_order.unwrap_or_default()
+    );
+    let after = format!("{} {} #{}", date, appointment_time, numerical_order);
+    insert_record(&mut tx, id, "schedule", Some(&before), &after, actor, reason).await?;
+    if doctor_id != current.doctor_id {
+        insert_record(
+            &mut tx,
+            id,
+            "doctor",
+            Some(&doctor_label(current.doctor_id)),
+            &doctor_label(doctor_id),
+            actor,
+            reason,
+        )
+        .await?;
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(updated)
+}
+
+// How assign_doctor picks among the candidate doctors
+pub enum Assignment {
+    // This doctor, who must be a candidate and free at the slot
+    To(i32),
+    // The least-loaded free candidate; a conflict if there is none
+    LeastLoaded,
+    // The least-loaded free candidate, or nobody so the front desk can deal with it
+    LeastLoadedOrNone,
+}
+
+// (Re)assigns an open appointment to one of `doctors`. Walk-ins have no slot of their
+// own, so only the doctors' daily load is considered for them.
+pub async fn assign_doctor(
+    pool: &PgPool,
+    id: i32,
+    assignment: Assignment,
+    doctors: &[i32],
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let current = sqlx::query_as::<_, Appointment>(
+        "SELECT * FROM tn_appointments WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if !matches!(
+        current.treatment_status,
+        Some(TreatmentStatus::Scheduled) | Some(TreatmentStatus::CheckedIn)
+    ) {
+        return Err(Error::Conflict(
+            "only scheduled or checked-in appointments can be reassigned".to_string(),
+        ));
+    }
+
+    // Same lock as booking, so two assignments cannot pick the same free doctor
+    sqlx::query(
+        "SELECT last_order FROM tn_appointment_sequences WHERE date = $1 AND speciality_id = $2 FOR UPDATE",
+    )
+    .bind(current.date)
+    .bind(current.speciality_id)
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    let slot = Some(current.appointment_time.as_str()).filter(|_| current.is_walk_in != Some(1));
+    let doctor_id = match assignment {
+        Assignment::To(doctor_id) => Some(
+            require_free_doctor(&mut tx, doctor_id, doctors, current.date, slot, Some(id))
+                .await?,
+        ),
+        Assignment::LeastLoaded => Some(
+            pick_doctor(&mut tx, doctors, current.date, slot, None, Some(id))
+                .await?
+                .ok_or_else(|| Error::Conflict("no other doctor is available".to_string()))?,
+        ),
+        Assignment::LeastLoadedOrNone => {
+            pick_doctor(&mut tx, doctors, current.date, slot, None, Some(id)).await?
+        }
+    };
+
+    let appointment = sqlx::query_as::<_, Appointment>(
+        "UPDATE tn_appointments SET doctor_id = $1, update_at = $2 WHERE id = $3 RETURNING *",
+    )
+    .bind(doctor_id)
+    .bind(Utc::now().naive_utc())
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    insert_record(
+        &mut tx,
+        id,
+        "doctor",
+        Some(&doctor_label(current.doctor_id)),
+        &doctor_label(doctor_id),
+        actor,
+        reason,
+    )
+    .await?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(appointment)
+}
+
+// Open appointments of a doctor on a day, for moving them elsewhere
+pub async fn get_open_appointments_of_doctor(
+    pool: &PgPool,
+    doctor_id: i32,
+    date: NaiveDate,
+) -> Result<Vec<Appointment>, Error> {
+    sqlx::query_as::<_, Appointment>(
+        "SELECT * FROM tn_appointments
+         WHERE doctor_id = $1 AND date = $2 AND treatment_status IN ('scheduled', 'checked-in')
+         ORDER BY numerical_order",
+    )
+    .bind(doctor_id)
+    .bind(date)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+fn doctor_label(doctor_id: Option<i32>) -> String {
+    doctor_id
+        .map(|id| format!("doctor #{}", id))
+        .unwrap_or_else(|| "unassigned".to_string())
+}
+
 pub async fn get_appointments_by_speciality(
     pool: &PgPool,
     speciality_id: i32,
@@ -61,75 +454,179 @@ pub async fn get_appointments_by_speciality(
         .map_err(Error::Database)
 }
 
+pub async fn get_appointment_by_id(pool: &PgPool, id: i32) -> Result<Appointment, Error> {
+    sqlx::query_as::<_, Appointment>("SELECT * FROM tn_appointments WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)
+}
+
+// The user performing a status change, recorded in tn_appointment_records
+pub struct Actor<'a> {
+    pub id: Option<i32>,
+    pub role: &'a str,
+}
+
 pub async fn update_appointment_status(
     pool: &PgPool,
     id: i32,
-    status: String,
-) -> Result<(), Error> {
-    let query: &str = "UPDATE tn_appointments SET status = $1 WHERE id = $2";
-    sqlx::query(query)
+    status: AppointmentStatus,
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<AppointmentStatus, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let before = sqlx::query_scalar::<_, AppointmentStatus>(
+        "SELECT status FROM tn_appointments WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if !before.can_transition_to(status) {
+        return Err(Error::Conflict(format!(
+            "cannot change status from {} to {}",
+            before.as_str(),
+            status.as_str()
+        )));
+    }
+
+    sqlx::query("UPDATE tn_appointments SET status = $1, update_at = $2 WHERE id = $3")
         .bind(status)
+        .bind(Utc::now().naive_utc())
         .bind(id)
-        .execute(pool)
+        .execute(&mut tx)
         .await
         .map_err(Error::Database)?;
-    Ok(())
+
+    insert_record(
+        &mut tx,
+        id,
+        "status",
+        Some(before.as_str()),
+        status.as_str(),
+        actor,
+        reason,
+    )
+    .await?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(before)
 }
 
 pub async fn update_appointment_treatment_status(
     pool: &PgPool,
     id: i32,
-    treatment_status: String,
-) -> Result<(), Error> {
-    let query = "UPDATE tn_appointments SET treatment_status = $1 WHERE id = $2";
-    sqlx::query(query)
-        .bind(treatment_status)
-        .bind(id)
-        .execute(pool)
-        .await
-        .map_err(Error::Database)?;
-    Ok(())
+    treatment_status: TreatmentStatus,
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let appointment =
+        transition_treatment_status(&mut tx, id, treatment_status, actor, reason).await?;
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(appointment)
 }
 
-pub async fn update_appointment_time(
-    pool: &PgPool,
+// Validates and applies a treatment status change inside the caller's transaction
+pub async fn transition_treatment_status(
+    tx: &mut Transaction<'_, Postgres>,
     id: i32,
-    time: String,
-    order: i32,
-) -> Result<(), Error> {
-    let query: &str =
-        "UPDATE tn_appointments SET appointment_time = $1, numerical_order = $2 WHERE id = $3";
-    sqlx::query(query)
-        .bind(time)
-        .bind(order)
-        .bind(id)
-        .execute(pool)
-        .await
-        .map_err(Error::Database)?;
+    treatment_status: TreatmentStatus,
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<Appointment, Error> {
+    let before = sqlx::query_scalar::<_, TreatmentStatus>(
+        "SELECT treatment_status FROM tn_appointments WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if !before.can_transition_to(treatment_status) {
+        return Err(Error::Conflict(format!(
+            "cannot change treatment status from {} to {}",
+            before.as_str(),
+            treatment_status.as_str()
+        )));
+    }
+
+    let appointment = sqlx::query_as::<_, Appointment>(
+        "UPDATE tn_appointments SET treatment_status = $1, update_at = $2 WHERE id = $3 RETURNING *",
+    )
+    .bind(treatment_status)
+    .bind(Utc::now().naive_utc())
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(Error::Database)?;
+
+    insert_record(
+        tx,
+        id,
+        "treatment_status",
+        Some(before.as_str()),
+        treatment_status.as_str(),
+        actor,
+        reason,
+    )
+    .await?;
 
+    Ok(appointment)
+}
+
+// status_before is None for records that start a history, such as walk-ins
+pub async fn insert_record(
+    tx: &mut Transaction<'_, Postgres>,
+    appointment_id: i32,
+    status_type: &str,
+    status_before: Option<&str>,
+    status_after: &str,
+    actor: &Actor<'_>,
+    reason: Option<&str>,
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "INSERT INTO tn_appointment_records (appointment_id, doctor_id, reason, status_type, status_before, status_after, actor_id, actor_role, create_at, update_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $9)",
+        appointment_id,
+        actor.id.filter(|_| actor.role == "doctor"),
+        reason,
+        status_type,
+        status_before,
+        status_after,
+        actor.id,
+        actor.role,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(Error::Database)?;
     Ok(())
 }
 
-pub async fn calculate_appointment_time(
+pub async fn get_appointment_records(
     pool: &PgPool,
-    date: Option<NaiveDate>,
-    speciality_id: Option<i32>,
-) -> Result<(i64, NaiveTime), Error> {
-    let numerical_order = sqlx::query_scalar!(
-        "SELECT COUNT(*) + 1 FROM tn_appointments WHERE date = $1 AND speciality_id = $2",
-        date,
-        speciality_id
+    appointment_id: i32,
+) -> Result<Vec<AppointmentRecord>, Error> {
+    sqlx::query_as!(
+        AppointmentRecord,
+        "SELECT id, appointment_id, doctor_id, room_id, reason, description, status_type,
+         status_before, status_after, actor_id, actor_role, create_at, update_at
+         FROM tn_appointment_records
+         WHERE appointment_id = $1
+         ORDER BY create_at, id",
+        appointment_id
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await
-    .map_err(Error::Database)?
-    .unwrap_or(1);
-
-    let base_time = NaiveTime::parse_from_str("07:00", "%H:%M").unwrap();
-    let minutes_to_add = (numerical_order - 1) * 30;
-    let appointment_time = base_time + Duration::minutes(minutes_to_add);
-
-    Ok((numerical_order, appointment_time))
+    .map_err(Error::Database)
 }
 
 pub async fn get_appointment_history(
@@ -1,155 +1,245 @@
-use crate::models::LoginRequest;
-use sqlx::{PgPool, Row}; // Make sure to import necessary models
+use crate::db::token;
+use crate::models::{Account, User, UserRole};
+use chrono::{NaiveDateTime, Utc};
+use sqlx::PgPool;
 
+// Looks up the account and its profile for a login. Only the profile table of `role` is
+// consulted, so equal emails under different roles never get mixed up.
 pub async fn get_user_credentials(
     pool: &PgPool,
-    login_req: &LoginRequest,
-) -> Result<Option<(i32, String, String, Option<i32>)>, sqlx::Error> {
-    let is_patient = login_req.login_type == "patient";
-    let is_doctor = login_req.login_type == "doctor";
-    let is_receptionist = login_req.login_type == "receptionist";
-    let is_staff = login_req.login_type == "staff";
-    let is_admin = login_req.login_type == "admin";
-
-    let query = if is_patient {
-        "SELECT id, password, name, NULL as speciality_id FROM tn_patients WHERE email = $1"
-    } else if is_doctor {
-        "SELECT id, password, name, speciality_id FROM tn_doctors WHERE email = $1"
-    } else if is_receptionist {
-        "SELECT id, password, name, NULL as speciality_id FROM tn_receptionist WHERE email = $1"
-    } else if is_staff {
-        "SELECT id, password, name, NULL as speciality_id FROM tn_staffs WHERE email = $1"
-    } else if is_admin {
-        "SELECT id, password, name, NULL as speciality_id FROM tn_admins WHERE email = $1"
-    } else {
-        return Err(sqlx::Error::RowNotFound);
-    };
-
-    let row = sqlx::query(query)
-        .bind(&login_req.email)
+    email: &str,
+    role: UserRole,
+) -> Result<Option<Account>, sqlx::Error> {
+    sqlx::query_as::<_, Account>(&account_query(role, "u.email = $1 AND u.role = $2"))
+        .bind(email)
+        .bind(role)
         .fetch_optional(pool)
-        .await?;
+        .await
+}
 
-    if let Some(r) = &row {
-        println!(
-            "Found user: id={}, name={}",
-            r.get::<i32, _>("id"),
-            r.get::<String, _>("name")
-        );
-    }
+// The account and profile behind a user id, e.g. to issue a token on refresh
+pub async fn get_account_by_user_id(
+    pool: &PgPool,
+    user_id: i32,
+) -> Result<Option<Account>, sqlx::Error> {
+    let Some(user) = get_user_by_id(pool, user_id).await? else {
+        return Ok(None);
+    };
+    sqlx::query_as::<_, Account>(&account_query(user.role, "u.id = $1"))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
 
-    Ok(row.map(|row| {
+// A doctor also counts as inactive while the doctor profile is switched off
+fn account_query(role: UserRole, condition: &str) -> String {
+    let (active, speciality) = if role == UserRole::Doctor {
         (
-            row.get("id"),
-            row.get("password"),
-            row.get("name"),
-            row.get("speciality_id"),
+            "CASE WHEN COALESCE(p.active, 1) = 1 THEN u.active ELSE 0 END",
+            "p.speciality_id",
         )
-    }))
+    } else {
+        ("u.active", "NULL::int")
+    };
+    format!(
+        "SELECT u.id as user_id, u.password, u.role, {} as active, u.verified, u.locked_until, u.totp_enabled, p.id as profile_id, p.name, {} as speciality_id
+         FROM tn_users u
+         JOIN {} p ON p.user_id = u.id
+         WHERE {}",
+        active,
+        speciality,
+        role.profile_table(),
+        condition
+    )
+}
+
+pub async fn get_user_by_id(pool: &PgPool, id: i32) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>("SELECT * FROM tn_users WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
 }
 
+// Creates the account and its profile row together; returns (user id, profile id).
+// Unverified accounts cannot log in until `mark_verified`.
 pub async fn create_user(
     pool: &PgPool,
     email: &str,
     hashed_password: &str,
     name: &str,
-    role: &str,
-) -> Result<(), sqlx::Error> {
-    let query = if role == "doctor" {
-        "INSERT INTO tn_doctors (email, password, name) VALUES ($1, $2, $3)"
-    } else if role == "admin" {
-        "INSERT INTO tn_admins (email, password, name) VALUES ($1, $2, $3)"
-    } else if role == "patient" {
-        "INSERT INTO tn_patients (email, password, name) VALUES ($1, $2, $3)"
-    } else if role == "staff" {
-        "INSERT INTO tn_staffs (email, password, name) VALUES ($1, $2, $3)"
-    } else if role == "admin" {
-        "INSERT INTO tn_admins (email, password, name) VALUES ($1, $2, $3)"
-    } else {
-        return Err(sqlx::Error::RowNotFound);
-    };
+    role: UserRole,
+    verified: bool,
+) -> Result<(i32, i32), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now().naive_utc();
+
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role, verified, create_at, update_at) VALUES ($1, $2, $3, $4, $5, $5) RETURNING id",
+    )
+    .bind(email)
+    .bind(hashed_password)
+    .bind(role)
+    .bind(verified as i32)
+    .bind(now)
+    .fetch_one(&mut tx)
+    .await?;
 
-    sqlx::query(query)
+    let query = format!(
+        "INSERT INTO {} (email, name, user_id, create_at, update_at) VALUES ($1, $2, $3, $4, $4) RETURNING id",
+        role.profile_table()
+    );
+    let profile_id: i32 = sqlx::query_scalar(&query)
         .bind(email)
-        .bind(hashed_password)
         .bind(name)
+        .bind(user_id)
+        .bind(now)
+        .fetch_one(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((user_id, profile_id))
+}
+
+// False when the account does not exist
+pub async fn mark_verified(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE tn_users SET verified = 1, update_at = $1 WHERE id = $2")
+        .bind(Utc::now().naive_utc())
+        .bind(user_id)
         .execute(pool)
         .await?;
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn update_password(
-    pool: &PgPool,
-    role: &str,
-    email: &str,
-    new_password: &str,
-) -> Result<bool, sqlx::Error> {
-    let is_patient = role == "patient";
-    let query = if is_patient {
-        "UPDATE tn_patients SET password = $1 WHERE email = $2 RETURNING id"
-    } else {
-        "UPDATE tn_doctors SET password = $1 WHERE email = $2 RETURNING id"
-    };
+// The patient account behind an email that still waits for verification
+pub async fn get_unverified_patient(pool: &PgPool, email: &str) -> Result<Option<i32>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT id FROM tn_users WHERE email = $1 AND role = 'patient' AND verified = 0",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await
+}
 
-    let result = sqlx::query(query)
-        .bind(new_password)
-        .bind(email)
-        .fetch_optional(pool)
+// Enables or disables an account; disabling also revokes every token it holds
+pub async fn set_user_active(pool: &PgPool, user_id: i32, active: bool) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query("UPDATE tn_users SET active = $1, update_at = $2 WHERE id = $3")
+        .bind(active as i32)
+        .bind(Utc::now().naive_utc())
+        .bind(user_id)
+        .execute(&mut tx)
         .await?;
+    if result.rows_affected() == 0 {
+        return Ok(false);
+    }
+    if !active {
+        token::revoke_all(&mut tx, user_id).await?;
+    }
 
-    Ok(result.is_some())
+    tx.commit().await?;
+    Ok(true)
 }
 
-pub async fn user_exists(pool: &PgPool, email: &str, role: &str) -> Result<bool, sqlx::Error> {
-    let is_patient = role == "patient";
-    let query = if is_patient {
-        "SELECT COUNT(*) FROM tn_patients WHERE email = $1"
-    } else {
-        "SELECT COUNT(*) FROM tn_doctors WHERE email = $1"
-    };
+// Deleting the account cascades to its refresh tokens; the profile row stays, unlinked
+pub async fn delete_user(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM tn_users WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
 
-    let count: i64 = sqlx::query_scalar(query)
+// Ids of the accounts using this email, only those of `role` when given
+pub async fn get_user_ids_by_email(
+    pool: &PgPool,
+    email: &str,
+    role: Option<UserRole>,
+) -> Result<Vec<i32>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM tn_users WHERE email = $1 AND ($2::varchar IS NULL OR role = $2) ORDER BY id")
         .bind(email)
-        .fetch_one(pool)
+        .bind(role)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn update_password(
+    pool: &PgPool,
+    user_id: i32,
+    new_password: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE tn_users SET password = $1, update_at = $2 WHERE id = $3")
+        .bind(new_password)
+        .bind(Utc::now().naive_utc())
+        .bind(user_id)
+        .execute(pool)
         .await?;
 
-    Ok(count > 0)
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_role(pool: &PgPool, email: &str) -> Result<String, sqlx::Error> {
-    // Check in patients table
-    let patient_count: i64 =
-        sqlx::query_scalar("SELECT COUNT(*) FROM tn_patients WHERE email = $1")
-            .bind(email)
-            .fetch_one(pool)
-            .await?;
-
-    if patient_count > 0 {
-        return Ok("patient".to_string());
-    }
+// Stores the hash of a fresh reset token for the account, replacing any earlier one.
+// False when no account has this email and role.
+pub async fn set_recovery_token(
+    pool: &PgPool,
+    email: &str,
+    role: UserRole,
+    token_hash: &str,
+    expires_at: NaiveDateTime,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE tn_users SET recovery_token = $1, recovery_token_expires_at = $2 WHERE email = $3 AND role = $4",
+    )
+    .bind(token_hash)
+    .bind(expires_at)
+    .bind(email)
+    .bind(role)
+    .execute(pool)
+    .await?;
 
-    // Check in doctors table
-    let doctor_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tn_doctors WHERE email = $1")
-        .bind(email)
-        .fetch_one(pool)
-        .await?;
+    Ok(result.rows_affected() > 0)
+}
 
-    if doctor_count > 0 {
-        return Ok("doctor".to_string());
-    }
+// Sets a new password through a reset token and returns the account, None when the token
+// is unknown, used or expired. The reset also ends every session of the account.
+pub async fn reset_password_with_token(
+    pool: &PgPool,
+    token_hash: &str,
+    new_password: &str,
+) -> Result<Option<i32>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let now = Utc::now().naive_utc();
 
-    // Check in admins table
-    let admin_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tn_admins WHERE email = $1")
-        .bind(email)
-        .fetch_one(pool)
-        .await?;
+    let user_id: Option<i32> = sqlx::query_scalar(
+        "UPDATE tn_users SET password = $1, recovery_token = NULL, recovery_token_expires_at = NULL, update_at = $2
+         WHERE recovery_token = $3 AND recovery_token_expires_at > $2
+         RETURNING id",
+    )
+    .bind(new_password)
+    .bind(now)
+    .bind(token_hash)
+    .fetch_optional(&mut tx)
+    .await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+    token::revoke_all(&mut tx, user_id).await?;
 
-    if admin_count > 0 {
-        return Ok("admin".to_string());
-    }
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
 
-    // If email not found in any table
-    Err(sqlx::Error::RowNotFound)
+// An email may hold accounts under several roles; patient wins, then doctor, then staff roles
+pub async fn get_role(pool: &PgPool, email: &str) -> Result<UserRole, sqlx::Error> {
+    sqlx::query_scalar::<_, UserRole>(
+        "SELECT role FROM tn_users WHERE email = $1
+         ORDER BY CASE role WHEN 'patient' THEN 0 WHEN 'doctor' THEN 1 WHEN 'receptionist' THEN 2 WHEN 'staff' THEN 3 ELSE 4 END
+         LIMIT 1",
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(sqlx::Error::RowNotFound)
 }
 
 pub async fn get_user_email(pool: &PgPool, user_id: &str) -> Result<Option<String>, sqlx::Error> {
@@ -158,52 +248,21 @@ pub async fn get_user_email(pool: &PgPool, user_id: &str) -> Result<Option<Strin
         sqlx::Error::Protocol("Invalid user ID format".into())
     })?;
 
-    // Query to get user email from all possible user tables
-    let query = r#"
-        SELECT email FROM (
-            SELECT email FROM tn_patients WHERE id = $1
-            UNION ALL
-            SELECT email FROM tn_doctors WHERE id = $1
-            UNION ALL
-            SELECT email FROM tn_admins WHERE id = $1
-        ) AS users
-        LIMIT 1
-    "#;
-
-    let result = sqlx::query_scalar::<_, String>(query)
-        .bind(user_id)
-        .fetch_optional(pool)
-        .await?;
-
-    Ok(result)
+    Ok(get_user_by_id(pool, user_id).await?.map(|user| user.email))
 }
 
 // Optional: Add a function to verify current password before allowing changes
 pub async fn verify_current_password(
-    pool: &PgPool, 
-    email: &str, 
-    role: &str,
+    pool: &PgPool,
+    user_id: &str,
     current_password: &str
 ) -> Result<bool, sqlx::Error> {
-    let table_name = match role {
-        "patient" => "tn_patients",
-        "doctor" => "tn_doctors", 
-        "admin" => "tn_admins",
-        _ => return Ok(false),
-    };
-
-    let query = format!(
-        "SELECT password FROM {} WHERE email = $1",
-        table_name
-    );
-
-    let stored_hash: Option<String> = sqlx::query_scalar(&query)
-        .bind(email)
-        .fetch_optional(pool)
-        .await?;
+    let user_id = user_id.parse::<i32>().map_err(|_| {
+        sqlx::Error::Protocol("Invalid user ID format".into())
+    })?;
 
-    match stored_hash {
-        Some(hash) => Ok(bcrypt::verify(current_password, &hash).unwrap_or(false)),
+    match get_user_by_id(pool, user_id).await? {
+        Some(user) => Ok(bcrypt::verify(current_password, &user.password).unwrap_or(false)),
         None => Ok(false),
     }
-}
\ No newline at end of file
+}
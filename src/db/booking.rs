@@ -0,0 +1,184 @@
+use crate::db::appointment;
+use crate::error::Error;
+use crate::models::{Appointment, Booking, BookingForm, BookingPhoto, BookingStatus};
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+
+// Creates a pending booking together with its photos
+pub async fn create_booking(
+    pool: &PgPool,
+    patient_id: i32,
+    booking: &BookingForm,
+) -> Result<i32, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let now = Utc::now().naive_utc();
+    let id = sqlx::query_scalar!(
+        "INSERT INTO tn_booking (service_id, patient_id, booking_name, booking_phone, name, gender, birthday, address, reason, appointment_date, appointment_time, doctor_id, status, create_at, update_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, 'pending', $13, $13) RETURNING id",
+        booking.service_id,
+        patient_id,
+        booking.booking_name,
+        booking.booking_phone,
+        booking.name,
+        booking.gender,
+        booking.birthday,
+        booking.address,
+        booking.reason,
+        booking.appointment_date.to_string(),
+        booking.appointment_time,
+        booking.doctor_id,
+        now
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    for url in booking.photos.iter().flatten() {
+        insert_photo(&mut tx, id, url).await?;
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(id)
+}
+
+pub async fn get_booking_by_id(pool: &PgPool, id: i32) -> Result<Booking, Error> {
+    sqlx::query_as::<_, Booking>("SELECT * FROM tn_booking WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)
+}
+
+pub async fn get_bookings_of_patient(
+    pool: &PgPool,
+    patient_id: i32,
+) -> Result<Vec<Booking>, Error> {
+    sqlx::query_as::<_, Booking>(
+        "SELECT * FROM tn_booking WHERE patient_id = $1 ORDER BY create_at DESC",
+    )
+    .bind(patient_id)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get_bookings(
+    pool: &PgPool,
+    status: Option<BookingStatus>,
+) -> Result<Vec<Booking>, Error> {
+    sqlx::query_as::<_, Booking>(
+        "SELECT * FROM tn_booking WHERE $1::varchar IS NULL OR status = $1
+         ORDER BY appointment_date, appointment_time, id",
+    )
+    .bind(status)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get_booking_photos(pool: &PgPool, booking_id: i32) -> Result<Vec<BookingPhoto>, Error> {
+    sqlx::query_as!(
+        BookingPhoto,
+        r#"SELECT id, url as "url!", booking_id as "booking_id!" FROM tn_booking_photo WHERE booking_id = $1 ORDER BY id"#,
+        booking_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn add_booking_photo(pool: &PgPool, booking_id: i32, url: &str) -> Result<i32, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    lock_pending(&mut tx, booking_id).await?;
+    let id = insert_photo(&mut tx, booking_id, url).await?;
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(id)
+}
+
+// Converts a pending booking into an appointment and marks it confirmed, atomically,
+// so a booking can never produce two appointments
+pub async fn confirm_booking(
+    pool: &PgPool,
+    id: i32,
+    appointment: Appointment,
+    slot_capacity: i32,
+    doctors: &[i32],
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    lock_pending(&mut tx, id).await?;
+
+    let appointment =
+        appointment::insert_appointment(&mut tx, appointment, slot_capacity, doctors).await?;
+
+    sqlx::query(
+        "UPDATE tn_booking SET status = $1, appointment_id = $2, update_at = $3 WHERE id = $4",
+    )
+    .bind(BookingStatus::Confirmed)
+    .bind(appointment.id)
+    .bind(Utc::now().naive_utc())
+    .bind(id)
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(appointment)
+}
+
+// Moves a pending booking to rejected or cancelled
+pub async fn close_booking(
+    pool: &PgPool,
+    id: i32,
+    status: BookingStatus,
+    reason: Option<&str>,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    lock_pending(&mut tx, id).await?;
+
+    sqlx::query(
+        "UPDATE tn_booking SET status = $1, reject_reason = $2, update_at = $3 WHERE id = $4",
+    )
+    .bind(status)
+    .bind(reason)
+    .bind(Utc::now().naive_utc())
+    .bind(id)
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(())
+}
+
+async fn lock_pending(tx: &mut Transaction<'_, Postgres>, id: i32) -> Result<(), Error> {
+    let status = sqlx::query_scalar::<_, BookingStatus>(
+        "SELECT status FROM tn_booking WHERE id = $1 FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if status != BookingStatus::Pending {
+        return Err(Error::Conflict("booking is no longer pending".to_string()));
+    }
+    Ok(())
+}
+
+async fn insert_photo(
+    tx: &mut Transaction<'_, Postgres>,
+    booking_id: i32,
+    url: &str,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_booking_photo (url, booking_id) VALUES ($1, $2) RETURNING id",
+        url,
+        booking_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(Error::Database)
+}
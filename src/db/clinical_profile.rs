@@ -0,0 +1,231 @@
+use crate::error::Error;
+use crate::models::{
+    Allergy, AllergyForm, AllergySeverity, BloodType, ChronicCondition, ChronicConditionForm,
+    ClinicalProfile, ClinicalProfileForm, LongTermMedication, LongTermMedicationForm,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+
+pub async fn get(pool: &PgPool, patient_id: i32) -> Result<ClinicalProfile, Error> {
+    let basics = sqlx::query!(
+        r#"SELECT blood_type as "blood_type: BloodType", family_history, update_at
+           FROM tn_patient_clinical_profiles WHERE patient_id = $1"#,
+        patient_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?;
+    let allergies = sqlx::query_as!(
+        Allergy,
+        r#"SELECT id, substance, reaction, severity as "severity: AllergySeverity", create_at
+           FROM tn_patient_allergies WHERE patient_id = $1 AND removed_at IS NULL
+           ORDER BY create_at, id"#,
+        patient_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+    let conditions = sqlx::query_as!(
+        ChronicCondition,
+        "SELECT id, name, icd10_code, diagnosed_on, note, create_at
+         FROM tn_patient_conditions WHERE patient_id = $1 AND removed_at IS NULL
+         ORDER BY create_at, id",
+        patient_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+    let medications = sqlx::query_as!(
+        LongTermMedication,
+        "SELECT id, medicine_id, name, dose, frequency, started_on, note, create_at
+         FROM tn_patient_medications WHERE patient_id = $1 AND removed_at IS NULL
+         ORDER BY create_at, id",
+        patient_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    let (blood_type, family_history, update_at) = match basics {
+        Some(basics) => (basics.blood_type, basics.family_history, basics.update_at),
+        None => (None, None, None),
+    };
+    Ok(ClinicalProfile {
+        blood_type,
+        family_history,
+        allergies,
+        conditions,
+        medications,
+        update_at,
+    })
+}
+
+// Blood type and family history; both are replaced, so a missing one is cleared
+pub async fn set_basics(
+    pool: &PgPool,
+    patient_id: i32,
+    form: &ClinicalProfileForm,
+    user_id: i32,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO tn_patient_clinical_profiles (patient_id, blood_type, family_history, update_at, updated_by)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (patient_id) DO UPDATE SET blood_type = $2, family_history = $3, update_at = $4, updated_by = $5",
+        patient_id,
+        form.blood_type as Option<BloodType>,
+        form.family_history,
+        Utc::now().naive_utc(),
+        user_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+pub async fn add_allergy(
+    pool: &PgPool,
+    patient_id: i32,
+    allergy: &AllergyForm,
+    user_id: i32,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_patient_allergies (patient_id, substance, reaction, severity, create_at, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        patient_id,
+        allergy.substance.trim(),
+        allergy.reaction,
+        allergy.severity as AllergySeverity,
+        Utc::now().naive_utc(),
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            Error::Conflict("the patient already has an allergy to this substance".to_string())
+        }
+        e => Error::Database(e),
+    })
+}
+
+pub async fn remove_allergy(
+    pool: &PgPool,
+    patient_id: i32,
+    id: i32,
+    user_id: i32,
+) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "UPDATE tn_patient_allergies SET removed_at = $1, removed_by = $2
+         WHERE id = $3 AND patient_id = $4 AND removed_at IS NULL",
+        Utc::now().naive_utc(),
+        user_id,
+        id,
+        patient_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+// `icd10_code` is the form's code in canonical form, known to exist
+pub async fn add_condition(
+    pool: &PgPool,
+    patient_id: i32,
+    condition: &ChronicConditionForm,
+    icd10_code: Option<&str>,
+    user_id: i32,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_patient_conditions (patient_id, name, icd10_code, diagnosed_on, note, create_at, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        patient_id,
+        condition.name.trim(),
+        icd10_code,
+        condition.diagnosed_on,
+        condition.note,
+        Utc::now().naive_utc(),
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn remove_condition(
+    pool: &PgPool,
+    patient_id: i32,
+    id: i32,
+    user_id: i32,
+) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "UPDATE tn_patient_conditions SET removed_at = $1, removed_by = $2
+         WHERE id = $3 AND patient_id = $4 AND removed_at IS NULL",
+        Utc::now().naive_utc(),
+        user_id,
+        id,
+        patient_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+// NotFound if `medicine_id` is not in the formulary
+pub async fn add_medication(
+    pool: &PgPool,
+    patient_id: i32,
+    medication: &LongTermMedicationForm,
+    user_id: i32,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_patient_medications (patient_id, medicine_id, name, dose, frequency, started_on, note, create_at, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) RETURNING id",
+        patient_id,
+        medication.medicine_id,
+        medication.name.trim(),
+        medication.dose,
+        medication.frequency,
+        medication.started_on,
+        medication.note,
+        Utc::now().naive_utc(),
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23503") => Error::NotFound,
+        e => Error::Database(e),
+    })
+}
+
+pub async fn remove_medication(
+    pool: &PgPool,
+    patient_id: i32,
+    id: i32,
+    user_id: i32,
+) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "UPDATE tn_patient_medications SET removed_at = $1, removed_by = $2
+         WHERE id = $3 AND patient_id = $4 AND removed_at IS NULL",
+        Utc::now().naive_utc(),
+        user_id,
+        id,
+        patient_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
@@ -89,11 +89,21 @@ pub async fn update_doctor(pool: &PgPool, email: String, doctor: &Doctor) -> Res
     Ok(())
 }
 
+// Removes the doctor together with their login account, which ends their sessions
 pub async fn delete_doctor(pool: &PgPool, id: &i32) -> Result<(), Error> {
-    sqlx::query!("DELETE FROM tn_doctors WHERE id = $1", id)
-        .execute(pool)
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let user_id = sqlx::query_scalar!("DELETE FROM tn_doctors WHERE id = $1 RETURNING user_id", id)
+        .fetch_optional(&mut tx)
         .await
-        .map_err(Error::Database)?;
+        .map_err(Error::Database)?
+        .flatten();
+    if let Some(user_id) = user_id {
+        sqlx::query!("DELETE FROM tn_users WHERE id = $1", user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(Error::Database)?;
+    }
+    tx.commit().await.map_err(Error::Database)?;
     Ok(())
 }
 
@@ -0,0 +1,89 @@
+use crate::error::Error;
+use crate::models::{DrugInteraction, InteractionSeverity, MedicineIngredient};
+use chrono::Utc;
+use sqlx::PgPool;
+
+// Adds the pairs, replacing the severity and note of pairs already known, and returns how
+// many were written. Each pair must have its lower id first and be listed once.
+pub async fn import_interactions(
+    pool: &PgPool,
+    interactions: &[DrugInteraction],
+) -> Result<u64, Error> {
+    let medicine_a: Vec<i32> = interactions.iter().map(|i| i.medicine_a).collect();
+    let medicine_b: Vec<i32> = interactions.iter().map(|i| i.medicine_b).collect();
+    let severity: Vec<&str> = interactions.iter().map(|i| i.severity.as_str()).collect();
+    let note: Vec<Option<String>> = interactions.iter().map(|i| i.note.clone()).collect();
+    let result = sqlx::query!(
+        "INSERT INTO tn_drug_interactions (medicine_a, medicine_b, severity, note, update_at)
+         SELECT medicine_a, medicine_b, severity, note, $5
+         FROM UNNEST($1::int[], $2::int[], $3::varchar[], $4::text[]) AS t(medicine_a, medicine_b, severity, note)
+         ON CONFLICT (medicine_a, medicine_b) DO UPDATE SET severity = EXCLUDED.severity, note = EXCLUDED.note, update_at = EXCLUDED.update_at",
+        &medicine_a,
+        &medicine_b,
+        &severity as &[&str],
+        &note as &[Option<String>],
+        Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(result.rows_affected())
+}
+
+// Adds the ingredients, replacing the allergen of ingredients already listed for the
+// medicine. Each ingredient must be listed once per medicine.
+pub async fn import_ingredients(
+    pool: &PgPool,
+    ingredients: &[MedicineIngredient],
+) -> Result<u64, Error> {
+    let medicine_id: Vec<i32> = ingredients.iter().map(|i| i.medicine_id).collect();
+    let ingredient: Vec<String> = ingredients.iter().map(|i| i.ingredient.clone()).collect();
+    let allergen: Vec<Option<String>> = ingredients.iter().map(|i| i.allergen.clone()).collect();
+    let result = sqlx::query!(
+        "INSERT INTO tn_medicine_ingredients (medicine_id, ingredient, allergen, update_at)
+         SELECT medicine_id, ingredient, allergen, $4
+         FROM UNNEST($1::int[], $2::varchar[], $3::varchar[]) AS t(medicine_id, ingredient, allergen)
+         ON CONFLICT (medicine_id, lower(ingredient)) DO UPDATE SET allergen = EXCLUDED.allergen, update_at = EXCLUDED.update_at",
+        &medicine_id,
+        &ingredient,
+        &allergen as &[Option<String>],
+        Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(result.rows_affected())
+}
+
+// Known interactions between any two of the medicines
+pub async fn get_interactions(
+    pool: &PgPool,
+    medicine_ids: &[i32],
+) -> Result<Vec<DrugInteraction>, Error> {
+    sqlx::query_as!(
+        DrugInteraction,
+        r#"SELECT medicine_a, medicine_b, severity as "severity: InteractionSeverity", note
+           FROM tn_drug_interactions
+           WHERE medicine_a = ANY($1) AND medicine_b = ANY($1)
+           ORDER BY medicine_a, medicine_b"#,
+        medicine_ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get_ingredients(
+    pool: &PgPool,
+    medicine_ids: &[i32],
+) -> Result<Vec<MedicineIngredient>, Error> {
+    sqlx::query_as!(
+        MedicineIngredient,
+        "SELECT medicine_id, ingredient, allergen FROM tn_medicine_ingredients
+         WHERE medicine_id = ANY($1) ORDER BY medicine_id, id",
+        medicine_ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
@@ -0,0 +1,70 @@
+use crate::error::Error;
+use crate::models::Icd10Code;
+use chrono::Utc;
+use sqlx::PgPool;
+
+// Rows per INSERT when importing; a full ICD-10-CM list has over 70,000 codes
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+// Adds the codes, replacing the description of codes already known, and returns how many
+// were written. Codes are never deleted since diagnoses refer to them.
+pub async fn import(pool: &PgPool, codes: &[Icd10Code]) -> Result<u64, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let now = Utc::now().naive_utc();
+
+    let mut imported = 0;
+    for batch in codes.chunks(IMPORT_BATCH_SIZE) {
+        let code: Vec<String> = batch.iter().map(|c| c.code.clone()).collect();
+        let description: Vec<String> = batch.iter().map(|c| c.description.clone()).collect();
+        imported += sqlx::query!(
+            "INSERT INTO tn_icd10_codes (code, description, update_at)
+             SELECT code, description, $3 FROM UNNEST($1::varchar[], $2::text[]) AS t(code, description)
+             ON CONFLICT (code) DO UPDATE SET description = EXCLUDED.description, update_at = EXCLUDED.update_at",
+            &code,
+            &description,
+            now
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?
+        .rows_affected();
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(imported)
+}
+
+// Codes starting with `code_prefix` (dots ignored) or whose description contains `text`,
+// code matches first
+pub async fn search(
+    pool: &PgPool,
+    code_prefix: &str,
+    text: &str,
+    limit: i64,
+) -> Result<Vec<Icd10Code>, Error> {
+    sqlx::query_as!(
+        Icd10Code,
+        r#"SELECT code, description FROM tn_icd10_codes
+           WHERE replace(code, '.', '') LIKE $1 || '%' ESCAPE '\'
+              OR lower(description) LIKE '%' || lower($2) || '%' ESCAPE '\'
+           ORDER BY replace(code, '.', '') LIKE $1 || '%' ESCAPE '\' DESC, code
+           LIMIT $3"#,
+        code_prefix,
+        text,
+        limit
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get(pool: &PgPool, code: &str) -> Result<Option<Icd10Code>, Error> {
+    sqlx::query_as!(
+        Icd10Code,
+        "SELECT code, description FROM tn_icd10_codes WHERE code = $1",
+        code
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+}
@@ -0,0 +1,145 @@
+use chrono::{NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+// How failed logins are throttled. Every `max_failures` failures in a row lock the account,
+// or the client address, for `lockout_minutes`, doubled for each lockout in a row. Failures
+// more than `lockout_minutes` apart are not in a row, and neither are lockouts followed by
+// `lockout_minutes` without failures once they ended, so a busy shared address is never
+// stuck at the longest lockout.
+#[derive(Debug, Clone, Copy)]
+pub struct LockoutPolicy {
+    pub max_failures: i32,
+    pub max_ip_failures: i32,
+    pub lockout_minutes: i64,
+}
+
+// Longest lockout is 2^6 times the base
+const MAX_BACKOFF_EXPONENT: i32 = 6;
+
+// When the address is locked, the moment it opens again
+pub async fn ip_locked_until(pool: &PgPool, ip: &str) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT locked_until FROM tn_login_ip_attempts WHERE ip = $1 AND locked_until > $2",
+    )
+    .bind(ip)
+    .bind(Utc::now().naive_utc())
+    .fetch_optional(pool)
+    .await
+    .map(Option::flatten)
+}
+
+// When the account is locked, the moment it opens again
+pub async fn account_locked_until(pool: &PgPool, user_id: i32) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    sqlx::query_scalar("SELECT locked_until FROM tn_users WHERE id = $1 AND locked_until > $2")
+        .bind(user_id)
+        .bind(Utc::now().naive_utc())
+        .fetch_optional(pool)
+        .await
+        .map(Option::flatten)
+}
+
+// Counts a failed password for the account; returns the end of the lockout if this
+// failure started one. Failures older than one base lockout period are forgotten, as for
+// addresses, so a mistyped password now and then never adds up to a lockout. Earlier
+// lockouts are forgotten the same way.
+pub async fn record_account_failure(
+    pool: &PgPool,
+    user_id: i32,
+    policy: &LockoutPolicy,
+) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+    let result: Option<(bool, Option<NaiveDateTime>)> = sqlx::query_as(
+        "UPDATE tn_users SET
+            failed_login_count = CASE WHEN recent.failures >= $1 THEN 0 ELSE recent.failures END,
+            lockout_count = CASE WHEN recent.failures >= $1 THEN recent.lockouts + 1 ELSE recent.lockouts END,
+            locked_until = CASE WHEN recent.failures >= $1
+                THEN $2 + make_interval(mins => ($3 * power(2, LEAST(recent.lockouts, $4)))::int)
+                ELSE locked_until END,
+            last_failed_login_at = $2
+         FROM (SELECT CASE WHEN last_failed_login_at >= $2 - make_interval(mins => $3)
+                    THEN failed_login_count ELSE 0 END + 1 AS failures,
+                      CASE WHEN GREATEST(last_failed_login_at, locked_until) < $2 - make_interval(mins => $3)
+                    THEN 0 ELSE lockout_count END AS lockouts
+               FROM tn_users WHERE id = $5 FOR UPDATE) recent
+         WHERE id = $5
+         RETURNING failed_login_count = 0, locked_until",
+    )
+    .bind(policy.max_failures)
+    .bind(now)
+    .bind(policy.lockout_minutes as i32)
+    .bind(MAX_BACKOFF_EXPONENT)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result.and_then(|(locked, locked_until)| if locked { locked_until } else { None }))
+}
+
+// Counts a failed login from the address. Failures older than one base lockout period
+// are forgotten, so the count only tracks recent guessing, and so are lockouts that ended
+// that long ago.
+pub async fn record_ip_failure(
+    pool: &PgPool,
+    ip: &str,
+    policy: &LockoutPolicy,
+) -> Result<Option<NaiveDateTime>, sqlx::Error> {
+    let now = Utc::now().naive_utc();
+    let (failed_count, locked_until): (i32, Option<NaiveDateTime>) = sqlx::query_as(
+        "INSERT INTO tn_login_ip_attempts AS a (ip, failed_count, lockout_count, last_failed_at)
+         VALUES ($1, 1, 0, $2)
+         ON CONFLICT (ip) DO UPDATE SET
+            failed_count = CASE WHEN a.last_failed_at < $2 - make_interval(mins => $3) THEN 1 ELSE a.failed_count + 1 END,
+            lockout_count = CASE WHEN GREATEST(a.last_failed_at, a.locked_until) < $2 - make_interval(mins => $3)
+                THEN 0 ELSE a.lockout_count END,
+            last_failed_at = $2
+         RETURNING failed_count, locked_until",
+    )
+    .bind(ip)
+    .bind(now)
+    .bind(policy.lockout_minutes as i32)
+    .fetch_one(pool)
+    .await?;
+
+    if failed_count < policy.max_ip_failures || locked_until.is_some_and(|until| until > now) {
+        return Ok(None);
+    }
+    sqlx::query_scalar(
+        "UPDATE tn_login_ip_attempts SET
+            failed_count = 0,
+            lockout_count = lockout_count + 1,
+            locked_until = $2 + make_interval(mins => ($3 * power(2, LEAST(lockout_count, $4)))::int)
+         WHERE ip = $1
+         RETURNING locked_until",
+    )
+    .bind(ip)
+    .bind(now)
+    .bind(policy.lockout_minutes as i32)
+    .bind(MAX_BACKOFF_EXPONENT)
+    .fetch_one(pool)
+    .await
+}
+
+// A successful login starts the account's count over
+pub async fn clear_account_failures(pool: &PgPool, user_id: i32) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE tn_users SET failed_login_count = 0, lockout_count = 0, locked_until = NULL
+         WHERE id = $1 AND (failed_login_count > 0 OR lockout_count > 0 OR locked_until IS NOT NULL)",
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Lifts a lockout by hand; false when the account does not exist
+pub async fn unlock_user(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE tn_users SET failed_login_count = 0, lockout_count = 0, locked_until = NULL, update_at = $1
+         WHERE id = $2",
+    )
+    .bind(Utc::now().naive_utc())
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -1,6 +1,16 @@
 use crate::error::Error;
-use crate::models::{MedicalRecord, MedicalRecordResponse, VitalSign};
-use sqlx::PgPool;
+use crate::models::{
+    Amendment, DiagnosisKind, Icd10Code, MedicalRecord, MedicalRecordResponse, RecordDiagnosis,
+    RecordSection, VitalSign,
+};
+use chrono::{NaiveDateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+// Who is changing a record, as kept in the amendment trail
+pub struct Author<'a> {
+    pub user_id: i32,
+    pub name: &'a str,
+}
 
 enum PaymentStatus {
     Paid = 1,
@@ -9,13 +19,20 @@ enum PaymentStatus {
 
 pub async fn create(pool: &PgPool, record: &MedicalRecord) -> Result<i32, Error> {
     let result = sqlx::query!(
-        "INSERT INTO tn_medical_records (appointment_id, payment_status, patient_id, doctor_id, diagnosis) 
-         VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        "INSERT INTO tn_medical_records (appointment_id, payment_status, patient_id, doctor_id, diagnosis,
+            chief_complaint, history, examination, assessment, plan, create_at, update_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $11) RETURNING id",
         record.appointment_id,
         record.payment_status,
         record.patient_id,
         record.doctor_id,
-        record.diagnosis
+        record.diagnosis,
+        record.chief_complaint,
+        record.history,
+        record.examination,
+        record.assessment,
+        record.plan,
+        Utc::now().naive_utc()
     )
     .fetch_one(pool)
     .await
@@ -31,7 +48,8 @@ pub async fn get_by_patient_id(
     sqlx::query_as!(
         MedicalRecordResponse,
         "SELECT mr.id, mr.appointment_id, mr.payment_status, mr.patient_id, 
-        mr.diagnosis, d.name as doctor_name, a.date
+        mr.diagnosis, d.name as doctor_name, a.date, mr.chief_complaint, mr.history,
+        mr.examination, mr.assessment, mr.plan, mr.update_at
         FROM tn_medical_records mr
         JOIN tn_doctors d ON mr.doctor_id = d.id
         JOIN tn_appointments a ON mr.appointment_id = a.id
@@ -64,7 +82,7 @@ pub async fn get_vital_signs(
 ) -> Result<Vec<VitalSign>, Error> {
     sqlx::query_as!(
         VitalSign,
-        "SELECT * FROM tn_vital_signs WHERE medical_record_id = $1",
+        "SELECT * FROM tn_vital_signs WHERE medical_record_id = $1 ORDER BY recorded_at, id",
         medical_record_id
     )
     .fetch_all(pool)
@@ -72,9 +90,11 @@ pub async fn get_vital_signs(
     .map_err(Error::Database)
 }
 
-pub async fn create_vital_sign(pool: &PgPool, vital_sign: &VitalSign) -> Result<(), Error> {
-    sqlx::query!(
-        "INSERT INTO tn_vital_signs (medical_record_id, temperature, blood_pressure_systolic, blood_pressure_diastolic, heart_rate, spo2, weight, height) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+pub async fn create_vital_sign(pool: &PgPool, vital_sign: &VitalSign) -> Result<VitalSign, Error> {
+    sqlx::query_as!(
+        VitalSign,
+        "INSERT INTO tn_vital_signs (medical_record_id, temperature, blood_pressure_systolic, blood_pressure_diastolic, heart_rate, spo2, weight, height, recorded_at, recorded_by)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING *",
         vital_sign.medical_record_id,
         vital_sign.temperature,
         vital_sign.blood_pressure_systolic,
@@ -82,30 +102,328 @@ pub async fn create_vital_sign(pool: &PgPool, vital_sign: &VitalSign) -> Result<
         vital_sign.heart_rate,
         vital_sign.spo2,
         vital_sign.weight,
-        vital_sign.height
+        vital_sign.height,
+        vital_sign.recorded_at,
+        vital_sign.recorded_by
     )
-    .execute(pool)
+    .fetch_one(pool)
     .await
-    .map_err(Error::Database)?;
+    .map_err(Error::Database)
+}
 
-    Ok(())
+// Readings across all of a patient's medical records, oldest first, optionally only
+// those taken in [from, to)
+pub async fn get_patient_vital_signs(
+    pool: &PgPool,
+    patient_id: i32,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+) -> Result<Vec<VitalSign>, Error> {
+    sqlx::query_as!(
+        VitalSign,
+        r#"SELECT v.* FROM tn_vital_signs v
+           JOIN tn_medical_records mr ON mr.id = v.medical_record_id
+           WHERE mr.patient_id = $1
+             AND ($2::timestamp IS NULL OR v.recorded_at >= $2)
+             AND ($3::timestamp IS NULL OR v.recorded_at < $3)
+           ORDER BY v.recorded_at, v.id"#,
+        patient_id,
+        from,
+        to
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
 }
 
-pub async fn update_diagnosis(pool: &PgPool, id: i32, diagnosis: &str) -> Result<(), Error> {
-    sqlx::query!(
-        "UPDATE tn_medical_records 
-         SET diagnosis = $1
-         WHERE id = $2",
-        diagnosis,
+// Birthday of the patient a medical record belongs to, as entered (YYYY-MM-DD)
+pub async fn get_patient_birthday(pool: &PgPool, id: i32) -> Result<Option<String>, Error> {
+    sqlx::query_scalar!(
+        "SELECT p.birthday FROM tn_medical_records mr
+         JOIN tn_patients p ON p.id = mr.patient_id
+         WHERE mr.id = $1",
         id
     )
-    .execute(pool)
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+    .map(Option::flatten)
+}
+
+// Doctor who owns a medical record, None if the record has none
+pub async fn get_doctor_id(pool: &PgPool, id: i32) -> Result<Option<i32>, Error> {
+    sqlx::query_scalar!("SELECT doctor_id FROM tn_medical_records WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)
+}
+
+// Patient a medical record belongs to, None if the record has none
+pub async fn get_patient_id(pool: &PgPool, id: i32) -> Result<Option<i32>, Error> {
+    sqlx::query_scalar!("SELECT patient_id FROM tn_medical_records WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)
+}
+
+pub async fn get_by_id(pool: &PgPool, id: i32) -> Result<MedicalRecordResponse, Error> {
+    sqlx::query_as!(
+        MedicalRecordResponse,
+        r#"SELECT mr.id, mr.appointment_id, mr.payment_status, mr.patient_id,
+        mr.diagnosis, d.name as "doctor_name?", a.date as "date?", mr.chief_complaint, mr.history,
+        mr.examination, mr.assessment, mr.plan, mr.update_at
+        FROM tn_medical_records mr
+        LEFT JOIN tn_doctors d ON mr.doctor_id = d.id
+        LEFT JOIN tn_appointments a ON mr.appointment_id = a.id
+        WHERE mr.id = $1"#,
+        id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)
+}
+
+// Writes new text into sections of a record and appends one amendment per section that
+// actually changed, all or nothing. Blank text clears a section. Replacing text that was
+// already there needs a reason. Returns the sections that changed.
+pub async fn amend(
+    pool: &PgPool,
+    id: i32,
+    changes: &[(RecordSection, &str)],
+    reason: Option<&str>,
+    author: &Author<'_>,
+) -> Result<Vec<RecordSection>, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let current = sqlx::query!(
+        "SELECT chief_complaint, history, examination, assessment, plan, diagnosis
+         FROM tn_medical_records WHERE id = $1 FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    let mut changed = Vec::new();
+    for &(section, new_value) in changes {
+        let (column, old_value) = match section {
+            RecordSection::ChiefComplaint => ("chief_complaint", &current.chief_complaint),
+            RecordSection::History => ("history", &current.history),
+            RecordSection::Examination => ("examination", &current.examination),
+            RecordSection::Assessment => ("assessment", &current.assessment),
+            RecordSection::Plan => ("plan", &current.plan),
+            RecordSection::Diagnosis => ("diagnosis", &current.diagnosis),
+            RecordSection::CodedDiagnosis => continue,
+        };
+        let old_value = old_value
+            .as_deref()
+            .filter(|value| !value.trim().is_empty());
+        let new_value = Some(new_value).filter(|value| !value.trim().is_empty());
+        if old_value == new_value {
+            continue;
+        }
+        if old_value.is_some() && reason.is_none_or(|reason| reason.trim().is_empty()) {
+            return Err(Error::Conflict(
+                "a reason is required to change text that was already written".to_string(),
+            ));
+        }
+
+        sqlx::query(&format!(
+            "UPDATE tn_medical_records SET {} = $1 WHERE id = $2",
+            column
+        ))
+        .bind(new_value)
+        .bind(id)
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+        record_amendment(&mut tx, id, section, old_value, new_value, reason, author).await?;
+        changed.push(section);
+    }
+
+    if !changed.is_empty() {
+        sqlx::query!(
+            "UPDATE tn_medical_records SET update_at = $1 WHERE id = $2",
+            Utc::now().naive_utc(),
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+    }
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(changed)
+}
+
+// Appended in the caller's transaction, so the change and its trail land together
+async fn record_amendment(
+    tx: &mut Transaction<'_, Postgres>,
+    medical_record_id: i32,
+    section: RecordSection,
+    old_value: Option<&str>,
+    new_value: Option<&str>,
+    reason: Option<&str>,
+    author: &Author<'_>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO tn_medical_record_amendments
+            (medical_record_id, section, old_value, new_value, reason, amended_by, amended_by_name, create_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        medical_record_id,
+        section as RecordSection,
+        old_value,
+        new_value,
+        reason,
+        author.user_id,
+        author.name,
+        Utc::now().naive_utc()
+    )
+    .execute(&mut **tx)
     .await
     .map_err(Error::Database)?;
+    Ok(())
+}
 
+// The amendment trail of a record, oldest first
+pub async fn get_amendments(
+    pool: &PgPool,
+    medical_record_id: i32,
+) -> Result<Vec<Amendment>, Error> {
+    sqlx::query_as!(
+        Amendment,
+        r#"SELECT id, section as "section: RecordSection", old_value, new_value, reason,
+                  amended_by, amended_by_name, create_at
+           FROM tn_medical_record_amendments
+           WHERE medical_record_id = $1
+           ORDER BY create_at, id"#,
+        medical_record_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Coded diagnoses of a record that were not removed, the primary one first
+pub async fn get_diagnoses(
+    pool: &PgPool,
+    medical_record_id: i32,
+) -> Result<Vec<RecordDiagnosis>, Error> {
+    sqlx::query_as!(
+        RecordDiagnosis,
+        r#"SELECT rd.id, rd.icd10_code, c.description, rd.kind as "kind: DiagnosisKind",
+                  rd.note, rd.create_at
+           FROM tn_medical_record_diagnoses rd
+           JOIN tn_icd10_codes c ON c.code = rd.icd10_code
+           WHERE rd.medical_record_id = $1 AND rd.removed_at IS NULL
+           ORDER BY rd.kind = 'primary' DESC, rd.id"#,
+        medical_record_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Adds a coded diagnosis and notes it in the amendment trail. A second primary diagnosis
+// is a conflict; the first has to be removed.
+pub async fn add_diagnosis(
+    pool: &PgPool,
+    medical_record_id: i32,
+    code: &Icd10Code,
+    kind: DiagnosisKind,
+    note: Option<&str>,
+    author: &Author<'_>,
+) -> Result<i32, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO tn_medical_record_diagnoses (medical_record_id, icd10_code, kind, note, create_at, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        medical_record_id,
+        code.code,
+        kind as DiagnosisKind,
+        note,
+        Utc::now().naive_utc(),
+        author.user_id
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            Error::Conflict("the record already has a primary diagnosis".to_string())
+        }
+        _ => Error::Database(e),
+    })?;
+    let entry = diagnosis_entry(&code.code, &code.description, kind);
+    record_amendment(
+        &mut tx,
+        medical_record_id,
+        RecordSection::CodedDiagnosis,
+        None,
+        Some(&entry),
+        None,
+        author,
+    )
+    .await?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(id)
+}
+
+// Takes a coded diagnosis off the record. The row stays, marked removed, and the trail
+// keeps what it said.
+pub async fn remove_diagnosis(
+    pool: &PgPool,
+    medical_record_id: i32,
+    diagnosis_id: i32,
+    reason: Option<&str>,
+    author: &Author<'_>,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let removed = sqlx::query!(
+        r#"UPDATE tn_medical_record_diagnoses rd SET removed_at = $1, removed_by = $2
+           FROM tn_icd10_codes c
+           WHERE rd.id = $3 AND rd.medical_record_id = $4 AND rd.removed_at IS NULL
+             AND c.code = rd.icd10_code
+           RETURNING rd.icd10_code, c.description, rd.kind as "kind: DiagnosisKind""#,
+        Utc::now().naive_utc(),
+        author.user_id,
+        diagnosis_id,
+        medical_record_id
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+    let entry = diagnosis_entry(&removed.icd10_code, &removed.description, removed.kind);
+    record_amendment(
+        &mut tx,
+        medical_record_id,
+        RecordSection::CodedDiagnosis,
+        Some(&entry),
+        None,
+        reason,
+        author,
+    )
+    .await?;
+
+    tx.commit().await.map_err(Error::Database)?;
     Ok(())
 }
 
+// How a coded diagnosis reads in the amendment trail, e.g. "J45.909 Unspecified asthma (primary)"
+fn diagnosis_entry(code: &str, description: &str, kind: DiagnosisKind) -> String {
+    let kind = match kind {
+        DiagnosisKind::Primary => "primary",
+        DiagnosisKind::Secondary => "secondary",
+    };
+    format!("{} {} ({})", code, description, kind)
+}
+
 pub async fn get_by_appointment_id(
     pool: &PgPool,
     appointment_id: i32,
@@ -113,7 +431,8 @@ pub async fn get_by_appointment_id(
     let record = sqlx::query_as!(
         MedicalRecordResponse,
         "SELECT mr.id, mr.appointment_id, mr.payment_status, mr.patient_id, 
-        mr.diagnosis, d.name as doctor_name, a.date
+        mr.diagnosis, d.name as doctor_name, a.date, mr.chief_complaint, mr.history,
+        mr.examination, mr.assessment, mr.plan, mr.update_at
         FROM tn_medical_records mr
         JOIN tn_doctors d ON mr.doctor_id = d.id
         JOIN tn_appointments a ON mr.appointment_id = a.id
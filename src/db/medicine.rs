@@ -1,5 +1,6 @@
 use crate::error::Error;
-use crate::models::{Medicine, MedicineCreateForm, MedicineOfPrescription};
+use crate::models::{Medicine, MedicineCreateForm, MedicineOfPrescription, PrescriptionForm};
+use chrono::Utc;
 use sqlx::PgPool;
 
 pub async fn get_medicines(pool: &PgPool) -> Result<Vec<Medicine>, Error> {
@@ -12,6 +13,18 @@ pub async fn get_medicines(pool: &PgPool) -> Result<Vec<Medicine>, Error> {
     .map_err(Error::Database)
 }
 
+pub async fn get_medicines_by_ids(pool: &PgPool, ids: &[i32]) -> Result<Vec<Medicine>, Error> {
+    sqlx::query_as!(
+        Medicine,
+        "SELECT id, name, price, unit, description, manufacture_date, expiry_date, side_effects, dosage
+         FROM tn_medicine WHERE id = ANY($1)",
+        ids
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
 pub async fn get_medicine_by_id(pool: &PgPool, id: i32) -> Result<Medicine, sqlx::Error> {
     sqlx::query_as!(Medicine, "SELECT * FROM tn_medicine WHERE id = $1", id)
         .fetch_one(pool)
@@ -85,18 +98,31 @@ pub async fn get_medicine_of_prescription(
     .map_err(Error::Database)
 }
 
+// `overridden_issues` are the blocking issues the prescriber went ahead despite, for
+// which `override_reason` is their reason
 pub async fn create_medicine_of_prescription(
     pool: &PgPool,
-    medicine_of_prescription: &MedicineOfPrescription,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "INSERT INTO medicine_of_prescription (medical_record_id, medicine_ids, quantity) 
-         VALUES ($1, $2, $3)",
-        medicine_of_prescription.medical_record_id,
-        medicine_of_prescription.medicine_ids.as_deref(),
-        medicine_of_prescription.quantity
+    prescription: &PrescriptionForm,
+    overridden_issues: &[String],
+    user_id: i32,
+) -> Result<i32, Error> {
+    let (override_reason, overridden_by) = if overridden_issues.is_empty() {
+        (None, None)
+    } else {
+        (prescription.override_reason.as_deref().map(str::trim), Some(user_id))
+    };
+    sqlx::query_scalar!(
+        "INSERT INTO medicine_of_prescription (medical_record_id, medicine_ids, quantity, override_reason, overridden_issues, overridden_by, create_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+        prescription.medical_record_id,
+        &prescription.medicine_ids,
+        prescription.quantity,
+        override_reason,
+        (!overridden_issues.is_empty()).then_some(overridden_issues),
+        overridden_by,
+        Utc::now().naive_utc()
     )
-    .execute(pool)
-        .await?;
-    Ok(())
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
 }
@@ -2,6 +2,7 @@ pub mod appointment;
 pub mod doctor;
 pub mod nurse;
 pub mod patient;
+pub mod icd10;
 pub mod payment;
 pub mod staff;
 pub mod authentication;
@@ -9,3 +10,16 @@ pub mod specialty;
 pub mod medicine;
 pub mod service;
 pub mod medical_record;
+pub mod queue;
+pub mod schedule;
+pub mod receptionest;
+pub mod booking;
+pub mod room;
+pub mod token;
+pub mod login_attempt;
+pub mod security_event;
+pub mod two_factor;
+pub mod session;
+pub mod vital_range;
+pub mod clinical_profile;
+pub mod drug_interaction;
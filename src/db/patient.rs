@@ -33,26 +33,18 @@ pub async fn get_patients(
         query.push_str(&format!(" OFFSET {}", offset_val));
     }
 
-    let mut patients = sqlx::query_as::<_, Patient>(&query).fetch_all(pool).await?;
-
-    // Set password to null for all patients
-    for patient in &mut patients {
-        patient.password = None;
-    }
-
+    let patients = sqlx::query_as::<_, Patient>(&query).fetch_all(pool).await?;
     Ok(patients)
 }
 
 pub async fn get_patient_by_id(pool: &PgPool, patient_id: &i32) -> Result<Patient, sqlx::Error> {
-    let mut patient = sqlx::query_as!(
+    let patient = sqlx::query_as!(
         Patient,
         "SELECT * FROM tn_patients WHERE id = $1",
         patient_id
     )
     .fetch_one(pool)
     .await?;
-
-    patient.password = None;
     Ok(patient)
 }
 
@@ -83,15 +75,15 @@ pub async fn create_patient(
     patient: PatientForm,
     create_at: NaiveDateTime,
     update_at: NaiveDateTime,
-) -> Result<(), sqlx::Error> {
-    sqlx::query_as!(
+) -> Result<Patient, sqlx::Error> {
+    let patient = sqlx::query_as!(
         Patient,
-        "INSERT INTO tn_patients (phone, name, gender, birthday, address, create_at, update_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        "INSERT INTO tn_patients (phone, name, gender, birthday, address, create_at, update_at) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
         patient.phone, patient.name, patient.gender, patient.birthday, patient.address, create_at, update_at
     )
     .fetch_one(pool)
-    .await;
-    Ok(())
+    .await?;
+    Ok(patient)
 }
 
 pub async fn get_patient_by_phone(pool: &PgPool, phone: String) -> Result<Patient, sqlx::Error> {
@@ -100,3 +92,19 @@ pub async fn get_patient_by_phone(pool: &PgPool, phone: String) -> Result<Patien
         .await?;
     Ok(patient)
 }
+
+// Whether the doctor has an appointment or a medical record with the patient
+pub async fn is_patient_of_doctor(
+    pool: &PgPool,
+    patient_id: i32,
+    doctor_id: i32,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM tn_appointments WHERE patient_id = $1 AND doctor_id = $2)
+               OR EXISTS (SELECT 1 FROM tn_medical_records WHERE patient_id = $1 AND doctor_id = $2) as "treats!""#,
+        patient_id,
+        doctor_id
+    )
+    .fetch_one(pool)
+    .await
+}
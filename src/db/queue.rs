@@ -0,0 +1,151 @@
+use crate::error::Error;
+use crate::models::{QueueEntry, QueueSnapshot, RoomOccupants, TreatmentStatus};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use sqlx::PgPool;
+
+// How many checked-in patients are announced as "next up"
+const NEXT_UP_COUNT: usize = 3;
+
+// Call order of a queue: emergencies first, then elderly and pregnant patients, then
+// everyone else by numerical_order
+pub const QUEUE_ORDER_BY: &str = "CASE priority WHEN 'emergency' THEN 0 WHEN 'pregnant' THEN 1 WHEN 'elderly' THEN 1 ELSE 2 END, numerical_order";
+
+pub async fn get_specialty_queue(
+    pool: &PgPool,
+    speciality_id: i32,
+    date: NaiveDate,
+) -> Result<QueueSnapshot, Error> {
+    let entries = get_entries(pool, "speciality_id", &[speciality_id], date).await?;
+
+    // Average slot length spread over the doctors working that day
+    let capacity = sqlx::query!(
+        "SELECT COALESCE(s.slot_duration, 30)::int8 as slot_minutes,
+                (SELECT COUNT(DISTINCT wh.doctor_id)
+                 FROM tn_doctor_working_hours wh
+                 JOIN tn_doctors d ON d.id = wh.doctor_id
+                 WHERE d.speciality_id = $1 AND COALESCE(d.active, 1) = 1 AND wh.weekday = $2) as doctors
+         FROM tn_specialities s WHERE s.id = $1",
+        speciality_id,
+        date.weekday().number_from_monday() as i32
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?;
+    let (slot_minutes, doctors) = match capacity {
+        Some(capacity) => (
+            capacity.slot_minutes.unwrap_or(30),
+            capacity.doctors.unwrap_or(0),
+        ),
+        None => (30, 0),
+    };
+
+    Ok(snapshot(date, &entries, slot_minutes, doctors))
+}
+
+// The doctors sitting in a room at a moment, as `room::get_doctor_room_at` places them: a
+// shift in the room, or the room as their default and no shift anywhere else then
+pub async fn get_room_occupants(
+    pool: &PgPool,
+    room_id: i32,
+    at: NaiveDateTime,
+) -> Result<RoomOccupants, Error> {
+    let exists = sqlx::query_scalar!("SELECT id FROM tn_rooms WHERE id = $1", room_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?;
+    if exists.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    let doctors = sqlx::query!(
+        "SELECT d.id, d.speciality_id, COALESCE(s.slot_duration, 30) as slot_minutes
+         FROM tn_doctors d
+         LEFT JOIN tn_specialities s ON s.id = d.speciality_id
+         WHERE COALESCE(d.active, 1) = 1
+         AND COALESCE(
+             (SELECT sh.room_id FROM tn_doctor_room_shifts sh
+              WHERE sh.doctor_id = d.id AND sh.date = $2 AND sh.start_time <= $3 AND $3 < sh.end_time
+              LIMIT 1),
+             d.room_id) = $1
+         ORDER BY d.id",
+        room_id,
+        at.date(),
+        at.time()
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    let slot_minutes = doctors.iter().filter_map(|d| d.slot_minutes).sum::<i32>();
+    Ok(RoomOccupants {
+        slot_minutes: match doctors.len() {
+            0 => 30,
+            count => (slot_minutes / count as i32) as i64,
+        },
+        speciality_ids: doctors.iter().filter_map(|d| d.speciality_id).collect(),
+        doctor_ids: doctors.into_iter().map(|d| d.id).collect(),
+    })
+}
+
+// The queue of the doctors in a room; another room of the same specialty has its own
+pub async fn get_room_queue(
+    pool: &PgPool,
+    occupants: &RoomOccupants,
+    date: NaiveDate,
+) -> Result<QueueSnapshot, Error> {
+    let entries = get_entries(pool, "doctor_id", &occupants.doctor_ids, date).await?;
+    Ok(snapshot(
+        date,
+        &entries,
+        occupants.slot_minutes,
+        occupants.doctor_ids.len() as i64,
+    ))
+}
+
+// Patients in the queue whose `column` is one of `ids`, in call order
+async fn get_entries(
+    pool: &PgPool,
+    column: &str,
+    ids: &[i32],
+    date: NaiveDate,
+) -> Result<Vec<QueueEntry>, Error> {
+    sqlx::query_as::<_, QueueEntry>(&format!(
+        "SELECT numerical_order, treatment_status FROM tn_appointments
+         WHERE date = $1 AND {} = ANY($2)
+         AND treatment_status IN ('checked-in', 'in-progress')
+         ORDER BY {}",
+        column, QUEUE_ORDER_BY
+    ))
+    .bind(date)
+    .bind(ids)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// The waiting patients are spread over `doctors` seeing one every `slot_minutes`
+fn snapshot(
+    date: NaiveDate,
+    entries: &[QueueEntry],
+    slot_minutes: i64,
+    doctors: i64,
+) -> QueueSnapshot {
+    let now_serving: Vec<i32> = entries
+        .iter()
+        .filter(|e| e.treatment_status == TreatmentStatus::InProgress)
+        .filter_map(|e| e.numerical_order)
+        .collect();
+    let waiting: Vec<i32> = entries
+        .iter()
+        .filter(|e| e.treatment_status == TreatmentStatus::CheckedIn)
+        .filter_map(|e| e.numerical_order)
+        .collect();
+
+    QueueSnapshot {
+        date,
+        now_serving,
+        next_up: waiting.iter().take(NEXT_UP_COUNT).copied().collect(),
+        waiting: waiting.len() as i64,
+        estimated_wait_minutes: waiting.len() as i64 * slot_minutes / doctors.max(1),
+    }
+}
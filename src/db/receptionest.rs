@@ -0,0 +1,149 @@
+use crate::db::appointment::{self, Actor};
+use crate::db::queue::QUEUE_ORDER_BY;
+use crate::error::Error;
+use crate::models::{Appointment, Patient, QueuePriority, TreatmentStatus};
+use chrono::{Local, NaiveDate, Utc};
+use sqlx::PgPool;
+
+// Marks a scheduled appointment as arrived: stamps arrived_at, optionally raises its
+// priority and moves it to checked-in, all in one transaction. Only appointments of today
+// can be checked in; anything else would put the patient in a queue nobody is serving.
+pub async fn check_in_appointment(
+    pool: &PgPool,
+    id: i32,
+    priority: Option<QueuePriority>,
+    actor: &Actor<'_>,
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let scheduled = appointment::transition_treatment_status(
+        &mut tx,
+        id,
+        TreatmentStatus::CheckedIn,
+        actor,
+        Some("Checked in at reception"),
+    )
+    .await?;
+    check_in_day(scheduled.date, Local::now().date_naive())?;
+
+    let now = Utc::now().naive_utc();
+    let appointment = sqlx::query_as::<_, Appointment>(
+        "UPDATE tn_appointments SET arrived_at = $1, priority = COALESCE($2, priority), update_at = $1
+         WHERE id = $3 RETURNING *",
+    )
+    .bind(now)
+    .bind(priority)
+    .bind(id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(appointment)
+}
+
+fn check_in_day(date: Option<NaiveDate>, today: NaiveDate) -> Result<(), Error> {
+    match date {
+        Some(date) if date == today => Ok(()),
+        Some(date) => Err(Error::Conflict(format!(
+            "the appointment is on {}, not today",
+            date
+        ))),
+        None => Err(Error::Conflict("the appointment has no date".to_string())),
+    }
+}
+
+// Puts a patient without an appointment straight into today's queue of a specialty.
+// Walk-ins take the next number like any booking but do not hold a slot, so they are
+// stamped with their arrival time and start out checked in. The least-loaded of
+// `doctors` (those working today) sees them.
+pub async fn create_walk_in(
+    pool: &PgPool,
+    patient: &Patient,
+    speciality_id: i32,
+    reason: Option<String>,
+    priority: QueuePriority,
+    doctors: &[i32],
+    actor: &Actor<'_>,
+) -> Result<Appointment, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    let today = Local::now().date_naive();
+    let numerical_order =
+        appointment::allocate_numerical_order(&mut tx, Some(today), Some(speciality_id)).await?;
+    let doctor_id =
+        appointment::pick_doctor(&mut tx, doctors, Some(today), None, None, None).await?;
+
+    let now = Utc::now().naive_utc();
+    let appointment = sqlx::query_as::<_, Appointment>(
+        "INSERT INTO tn_appointments (patient_id, patient_name, patient_birthday, patient_phone, patient_reason, speciality_id, date, numerical_order, appointment_time, status, treatment_status, arrived_at, is_walk_in, priority, doctor_id, create_at, update_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'Unpaid', $10, $11, 1, $12, $13, $11, $11)
+         RETURNING *",
+    )
+    .bind(patient.id)
+    .bind(&patient.name)
+    .bind(&patient.birthday)
+    .bind(&patient.phone)
+    .bind(reason)
+    .bind(speciality_id)
+    .bind(today)
+    .bind(numerical_order)
+    .bind(Local::now().format("%H:%M").to_string())
+    .bind(TreatmentStatus::CheckedIn)
+    .bind(now)
+    .bind(priority)
+    .bind(doctor_id)
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    if let Some(id) = appointment.id {
+        appointment::insert_record(
+            &mut tx,
+            id,
+            "treatment_status",
+            None,
+            TreatmentStatus::CheckedIn.as_str(),
+            actor,
+            Some("Walk-in registered at reception"),
+        )
+        .await?;
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(appointment)
+}
+
+// Everyone still expected or waiting on a day, in the order they will be called
+pub async fn get_reception_queue(
+    pool: &PgPool,
+    speciality_id: i32,
+    date: NaiveDate,
+) -> Result<Vec<Appointment>, Error> {
+    sqlx::query_as::<_, Appointment>(&format!(
+        "SELECT * FROM tn_appointments
+         WHERE date = $1 AND speciality_id = $2
+         AND treatment_status IN ('scheduled', 'checked-in', 'in-progress')
+         ORDER BY {}",
+        QUEUE_ORDER_BY
+    ))
+    .bind(date)
+    .bind(speciality_id)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_appointments_of_today_are_checked_in() {
+        let today = NaiveDate::from_ymd_opt(2026, 10, 18).unwrap();
+        assert!(check_in_day(Some(today), today).is_ok());
+        for date in [today.pred_opt(), today.succ_opt(), None] {
+            assert!(matches!(check_in_day(date, today), Err(Error::Conflict(_))));
+        }
+    }
+}
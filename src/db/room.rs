@@ -0,0 +1,336 @@
+use crate::error::Error;
+use crate::models::{Room, RoomForm, RoomShift, RoomShiftForm};
+use chrono::{Datelike, Local, NaiveDate, NaiveTime, Utc};
+use sqlx::PgPool;
+
+pub async fn get_rooms(pool: &PgPool) -> Result<Vec<Room>, Error> {
+    sqlx::query_as!(Room, "SELECT id, name, location FROM tn_rooms ORDER BY name, id")
+        .fetch_all(pool)
+        .await
+        .map_err(Error::Database)
+}
+
+pub async fn get_room_by_id(pool: &PgPool, id: i32) -> Result<Room, Error> {
+    sqlx::query_as!(Room, "SELECT id, name, location FROM tn_rooms WHERE id = $1", id)
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)
+}
+
+pub async fn create_room(pool: &PgPool, room: &RoomForm) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_rooms (name, location) VALUES ($1, $2) RETURNING id",
+        room.name,
+        room.location
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(duplicate_name)
+}
+
+pub async fn update_room(pool: &PgPool, id: i32, room: &RoomForm) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "UPDATE tn_rooms SET name = $1, location = $2 WHERE id = $3",
+        room.name,
+        room.location,
+        id
+    )
+    .execute(pool)
+    .await
+    .map_err(duplicate_name)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+// Shifts go with the room; doctors who sat there by default lose their default room
+pub async fn delete_room(pool: &PgPool, id: i32) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    sqlx::query!("UPDATE tn_doctors SET room_id = NULL WHERE room_id = $1", id)
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+    let result = sqlx::query!("DELETE FROM tn_rooms WHERE id = $1", id)
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(())
+}
+
+pub async fn get_room_shifts(
+    pool: &PgPool,
+    room_id: i32,
+    date: NaiveDate,
+) -> Result<Vec<RoomShift>, Error> {
+    sqlx::query_as!(
+        RoomShift,
+        "SELECT s.id, s.doctor_id, d.name as doctor_name, s.room_id, s.date, s.start_time, s.end_time
+         FROM tn_doctor_room_shifts s
+         JOIN tn_doctors d ON d.id = s.doctor_id
+         WHERE s.room_id = $1 AND s.date = $2
+         ORDER BY s.start_time",
+        room_id,
+        date
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Puts a doctor in a room for part of a day, if neither another shift nor a doctor whose
+// default room it is holds the room then. The room row is locked while checking, so two
+// concurrent assignments cannot both see the room as free.
+pub async fn create_room_shift(
+    pool: &PgPool,
+    room_id: i32,
+    shift: &RoomShiftForm,
+) -> Result<i32, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    sqlx::query_scalar!("SELECT id FROM tn_rooms WHERE id = $1 FOR UPDATE", room_id)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(Error::Database)?
+        .ok_or(Error::NotFound)?;
+
+    let clash = sqlx::query!(
+        r#"SELECT s.room_id, s.doctor_id, r.name as room_name, d.name as doctor_name,
+                  s.start_time, s.end_time
+           FROM tn_doctor_room_shifts s
+           JOIN tn_rooms r ON r.id = s.room_id
+           JOIN tn_doctors d ON d.id = s.doctor_id
+           WHERE s.date = $1 AND (s.room_id = $2 OR s.doctor_id = $3)
+           AND s.start_time < $5 AND $4 < s.end_time
+           LIMIT 1"#,
+        shift.date,
+        room_id,
+        shift.doctor_id,
+        shift.start_time,
+        shift.end_time
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    if let Some(clash) = clash {
+        let message = if clash.room_id == room_id {
+            format!(
+                "room {} is already taken by {} from {} to {}",
+                clash.room_name.unwrap_or_default(),
+                clash.doctor_name.unwrap_or_default(),
+                clash.start_time.format("%H:%M"),
+                clash.end_time.format("%H:%M")
+            )
+        } else {
+            format!(
+                "doctor is already in room {} from {} to {}",
+                clash.room_name.unwrap_or_default(),
+                clash.start_time.format("%H:%M"),
+                clash.end_time.format("%H:%M")
+            )
+        };
+        return Err(Error::Conflict(message));
+    }
+
+    // Doctors whose default room it is sit in it whenever they work, unless a shift of
+    // their own puts them elsewhere for the whole overlap
+    let occupant = sqlx::query!(
+        r#"SELECT d.name, wh.start_time, wh.end_time
+           FROM tn_doctors d
+           JOIN tn_doctor_working_hours wh ON wh.doctor_id = d.id
+           WHERE d.room_id = $1 AND d.id <> $2 AND COALESCE(d.active, 1) = 1
+           AND wh.weekday = $3 AND wh.start_time < $5 AND $4 < wh.end_time
+           AND NOT EXISTS (SELECT 1 FROM tn_doctor_room_shifts s
+                           WHERE s.doctor_id = d.id AND s.date = $6 AND s.room_id <> $1
+                           AND s.start_time <= GREATEST(wh.start_time, $4)
+                           AND LEAST(wh.end_time, $5) <= s.end_time)
+           LIMIT 1"#,
+        room_id,
+        shift.doctor_id,
+        shift.date.weekday().number_from_monday() as i32,
+        shift.start_time,
+        shift.end_time,
+        shift.date
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    if let Some(occupant) = occupant {
+        return Err(Error::Conflict(format!(
+            "room is the default room of {}, who works from {} to {} that day",
+            occupant.name.unwrap_or_default(),
+            occupant.start_time.format("%H:%M"),
+            occupant.end_time.format("%H:%M")
+        )));
+    }
+
+    let id = sqlx::query_scalar!(
+        "INSERT INTO tn_doctor_room_shifts (doctor_id, room_id, date, start_time, end_time, create_at)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        shift.doctor_id,
+        room_id,
+        shift.date,
+        shift.start_time,
+        shift.end_time,
+        Utc::now().naive_utc()
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23503") => Error::NotFound,
+        _ => Error::Database(e),
+    })?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(id)
+}
+
+// Returns the day the shift was on
+pub async fn delete_room_shift(pool: &PgPool, room_id: i32, id: i32) -> Result<NaiveDate, Error> {
+    sqlx::query_scalar!(
+        "DELETE FROM tn_doctor_room_shifts WHERE id = $1 AND room_id = $2 RETURNING date",
+        id,
+        room_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)
+}
+
+// Sets a doctor's default room. Two doctors may share a default room only if their
+// weekly working hours never overlap, and nobody may have a shift in it while the doctor
+// works.
+pub async fn set_doctor_room(
+    pool: &PgPool,
+    doctor_id: i32,
+    room_id: Option<i32>,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    if let Some(room_id) = room_id {
+        sqlx::query_scalar!("SELECT id FROM tn_rooms WHERE id = $1 FOR UPDATE", room_id)
+            .fetch_optional(&mut tx)
+            .await
+            .map_err(Error::Database)?
+            .ok_or(Error::NotFound)?;
+
+        let clash = sqlx::query!(
+            "SELECT d.name, mine.weekday
+             FROM tn_doctors d
+             JOIN tn_doctor_working_hours theirs ON theirs.doctor_id = d.id
+             JOIN tn_doctor_working_hours mine ON mine.doctor_id = $1
+                AND mine.weekday = theirs.weekday
+                AND mine.start_time < theirs.end_time AND theirs.start_time < mine.end_time
+             WHERE d.room_id = $2 AND d.id <> $1 AND COALESCE(d.active, 1) = 1
+             LIMIT 1",
+            doctor_id,
+            room_id
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+
+        if let Some(clash) = clash {
+            return Err(Error::Conflict(format!(
+                "{} already uses this room during overlapping hours on weekday {}",
+                clash.name.unwrap_or_default(),
+                clash.weekday
+            )));
+        }
+
+        // Shifts other doctors have in the room from today on, unless the doctor has a
+        // shift elsewhere covering it
+        let shift = sqlx::query!(
+            "SELECT d.name, s.date, s.start_time, s.end_time
+             FROM tn_doctor_room_shifts s
+             JOIN tn_doctors d ON d.id = s.doctor_id
+             JOIN tn_doctor_working_hours mine ON mine.doctor_id = $1
+                AND mine.weekday = EXTRACT(ISODOW FROM s.date)::int
+                AND mine.start_time < s.end_time AND s.start_time < mine.end_time
+             WHERE s.room_id = $2 AND s.doctor_id <> $1 AND s.date >= $3
+             AND NOT EXISTS (SELECT 1 FROM tn_doctor_room_shifts away
+                             WHERE away.doctor_id = $1 AND away.date = s.date AND away.room_id <> $2
+                             AND away.start_time <= GREATEST(mine.start_time, s.start_time)
+                             AND LEAST(mine.end_time, s.end_time) <= away.end_time)
+             ORDER BY s.date, s.start_time
+             LIMIT 1",
+            doctor_id,
+            room_id,
+            Local::now().date_naive()
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+
+        if let Some(shift) = shift {
+            return Err(Error::Conflict(format!(
+                "{} has a shift in this room on {} from {} to {}",
+                shift.name.unwrap_or_default(),
+                shift.date,
+                shift.start_time.format("%H:%M"),
+                shift.end_time.format("%H:%M")
+            )));
+        }
+    }
+
+    let result = sqlx::query!(
+        "UPDATE tn_doctors SET room_id = $1 WHERE id = $2",
+        room_id,
+        doctor_id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(())
+}
+
+// Where a doctor sits at a given time: the room of a covering shift, otherwise their
+// default room
+pub async fn get_doctor_room_at(
+    pool: &PgPool,
+    doctor_id: i32,
+    date: NaiveDate,
+    time: NaiveTime,
+) -> Result<Option<Room>, Error> {
+    sqlx::query_as!(
+        Room,
+        r#"SELECT r.id as "id!", r.name, r.location
+           FROM tn_rooms r
+           WHERE r.id = COALESCE(
+               (SELECT s.room_id FROM tn_doctor_room_shifts s
+                WHERE s.doctor_id = $1 AND s.date = $2 AND s.start_time <= $3 AND $3 < s.end_time
+                LIMIT 1),
+               (SELECT d.room_id FROM tn_doctors d WHERE d.id = $1))"#,
+        doctor_id,
+        date,
+        time
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+fn duplicate_name(e: sqlx::Error) -> Error {
+    match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+            Error::Conflict("a room with this name already exists".to_string())
+        }
+        _ => Error::Database(e),
+    }
+}
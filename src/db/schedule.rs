@@ -0,0 +1,420 @@
+use crate::error::Error;
+use crate::models::{
+    AvailableSlot, DoctorWorkingHour, ScheduleException, ScheduleExceptionForm, WorkingHourForm,
+};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap};
+
+const DEFAULT_SLOT_MINUTES: i32 = 30;
+
+pub async fn get_working_hours(
+    pool: &PgPool,
+    doctor_id: i32,
+) -> Result<Vec<DoctorWorkingHour>, Error> {
+    sqlx::query_as!(
+        DoctorWorkingHour,
+        "SELECT id, doctor_id, weekday, start_time, end_time FROM tn_doctor_working_hours
+         WHERE doctor_id = $1 ORDER BY weekday, start_time",
+        doctor_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Replaces the whole weekly calendar of a doctor in one transaction
+pub async fn replace_working_hours(
+    pool: &PgPool,
+    doctor_id: i32,
+    hours: &[WorkingHourForm],
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+
+    sqlx::query!(
+        "DELETE FROM tn_doctor_working_hours WHERE doctor_id = $1",
+        doctor_id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    for hour in hours {
+        sqlx::query!(
+            "INSERT INTO tn_doctor_working_hours (doctor_id, weekday, start_time, end_time) VALUES ($1, $2, $3, $4)",
+            doctor_id,
+            hour.weekday,
+            hour.start_time,
+            hour.end_time
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(Error::Database)?;
+    }
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(())
+}
+
+pub async fn get_exceptions(
+    pool: &PgPool,
+    doctor_id: i32,
+) -> Result<Vec<ScheduleException>, Error> {
+    sqlx::query_as!(
+        ScheduleException,
+        "SELECT id, doctor_id, date, start_time, end_time, reason, create_at
+         FROM tn_doctor_schedule_exceptions
+         WHERE doctor_id = $1 ORDER BY date, start_time",
+        doctor_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn create_exception(
+    pool: &PgPool,
+    doctor_id: i32,
+    exception: &ScheduleExceptionForm,
+) -> Result<i32, Error> {
+    let result = sqlx::query!(
+        "INSERT INTO tn_doctor_schedule_exceptions (doctor_id, date, start_time, end_time, reason, create_at)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+        doctor_id,
+        exception.date,
+        exception.start_time,
+        exception.end_time,
+        exception.reason,
+        Utc::now().naive_utc()
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(result.id)
+}
+
+pub async fn delete_exception(pool: &PgPool, doctor_id: i32, id: i32) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "DELETE FROM tn_doctor_schedule_exceptions WHERE id = $1 AND doctor_id = $2",
+        id,
+        doctor_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+pub async fn get_slot_duration(pool: &PgPool, speciality_id: i32) -> Result<i32, Error> {
+    let slot_duration = sqlx::query_scalar!(
+        "SELECT slot_duration FROM tn_specialities WHERE id = $1",
+        speciality_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    Ok(slot_duration
+        .filter(|minutes| *minutes > 0)
+        .unwrap_or(DEFAULT_SLOT_MINUTES))
+}
+
+// Free slots of a specialty on a date. Every active doctor of the specialty adds one
+// unit of capacity to each slot inside their working hours that is not blocked by an
+// exception; slots already filled by appointments, and those that have started, are left
+// out. Walk-ins fill no slot.
+pub async fn get_available_slots(
+    pool: &PgPool,
+    speciality_id: i32,
+    date: NaiveDate,
+) -> Result<Vec<AvailableSlot>, Error> {
+    let slot_minutes = get_slot_duration(pool, speciality_id).await?;
+    let intervals_by_doctor = get_doctor_intervals(pool, speciality_id, date).await?;
+
+    let booked = sqlx::query!(
+        "SELECT appointment_time, COUNT(*) as count FROM tn_appointments
+         WHERE date = $1 AND speciality_id = $2 AND treatment_status <> 'cancelled'
+         AND COALESCE(is_walk_in, 0) = 0
+         GROUP BY appointment_time",
+        date,
+        speciality_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    let now = Local::now().naive_local();
+    let mut capacity: BTreeMap<NaiveTime, i32> = BTreeMap::new();
+    for intervals in intervals_by_doctor.values() {
+        for start in slot_starts(intervals, slot_minutes) {
+            if has_started(date, start, now) {
+                continue;
+            }
+            *capacity.entry(start).or_insert(0) += 1;
+        }
+    }
+
+    let booked: HashMap<String, i32> = booked
+        .into_iter()
+        .filter_map(|row| Some((row.appointment_time?, row.count.unwrap_or(0) as i32)))
+        .collect();
+
+    Ok(capacity
+        .into_iter()
+        .map(|(start, capacity)| {
+            let time = start.format("%H:%M").to_string();
+            let booked = booked.get(&time).copied().unwrap_or(0);
+            AvailableSlot {
+                time,
+                capacity,
+                booked,
+            }
+        })
+        .filter(|slot| slot.booked < slot.capacity)
+        .collect())
+}
+
+fn has_started(date: NaiveDate, start: NaiveTime, now: NaiveDateTime) -> bool {
+    date.and_time(start) <= now
+}
+
+// Active doctors of a specialty who work the given slot on a date, or who work at any
+// time that day when no slot is given. Whether they are already booked is not checked.
+pub async fn get_working_doctors(
+    pool: &PgPool,
+    speciality_id: i32,
+    date: NaiveDate,
+    slot_start: Option<NaiveTime>,
+) -> Result<Vec<i32>, Error> {
+    let slot_minutes = get_slot_duration(pool, speciality_id).await?;
+    let intervals_by_doctor = get_doctor_intervals(pool, speciality_id, date).await?;
+
+    let mut doctor_ids: Vec<i32> = intervals_by_doctor
+        .into_iter()
+        .filter(|(_, intervals)| match slot_start {
+            Some(start) => slot_starts(intervals, slot_minutes).contains(&start),
+            None => !intervals.is_empty(),
+        })
+        .map(|(doctor_id, _)| doctor_id)
+        .collect();
+    doctor_ids.sort_unstable();
+    Ok(doctor_ids)
+}
+
+// Working intervals of each active doctor of a specialty on a date, with that day's
+// exceptions already cut out
+async fn get_doctor_intervals(
+    pool: &PgPool,
+    speciality_id: i32,
+    date: NaiveDate,
+) -> Result<HashMap<i32, Vec<(NaiveTime, NaiveTime)>>, Error> {
+    let weekday = date.weekday().number_from_monday() as i32;
+
+    let hours = sqlx::query_as!(
+        DoctorWorkingHour,
+        "SELECT wh.id, wh.doctor_id, wh.weekday, wh.start_time, wh.end_time
+         FROM tn_doctor_working_hours wh
+         JOIN tn_doctors d ON d.id = wh.doctor_id
+         WHERE d.speciality_id = $1 AND COALESCE(d.active, 1) = 1 AND wh.weekday = $2",
+        speciality_id,
+        weekday
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    let exceptions = sqlx::query_as!(
+        ScheduleException,
+        "SELECT e.id, e.doctor_id, e.date, e.start_time, e.end_time, e.reason, e.create_at
+         FROM tn_doctor_schedule_exceptions e
+         JOIN tn_doctors d ON d.id = e.doctor_id
+         WHERE d.speciality_id = $1 AND e.date = $2",
+        speciality_id,
+        date
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    let mut intervals_by_doctor: HashMap<i32, Vec<(NaiveTime, NaiveTime)>> = HashMap::new();
+    for hour in &hours {
+        intervals_by_doctor
+            .entry(hour.doctor_id)
+            .or_default()
+            .push((hour.start_time, hour.end_time));
+    }
+    for (doctor_id, intervals) in intervals_by_doctor.iter_mut() {
+        for exception in exceptions.iter().filter(|e| e.doctor_id == *doctor_id) {
+            *intervals = subtract_exception(intervals, exception);
+        }
+    }
+    Ok(intervals_by_doctor)
+}
+
+// Checks a weekly calendar for invalid weekdays, empty intervals and overlaps within a day
+pub fn validate_working_hours(hours: &[WorkingHourForm]) -> Result<(), String> {
+    for hour in hours {
+        if !(1..=7).contains(&hour.weekday) {
+            return Err(format!("Invalid weekday {}, expected 1-7", hour.weekday));
+        }
+        if hour.start_time >= hour.end_time {
+            return Err(format!(
+                "Working hours on weekday {} must start before they end",
+                hour.weekday
+            ));
+        }
+    }
+
+    for (i, a) in hours.iter().enumerate() {
+        for b in &hours[i + 1..] {
+            if a.weekday == b.weekday && a.start_time < b.end_time && b.start_time < a.end_time {
+                return Err(format!(
+                    "Overlapping working hours on weekday {}",
+                    a.weekday
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Removes the part of each interval covered by the exception
+fn subtract_exception(
+    intervals: &[(NaiveTime, NaiveTime)],
+    exception: &ScheduleException,
+) -> Vec<(NaiveTime, NaiveTime)> {
+    let (block_start, block_end) = match (exception.start_time, exception.end_time) {
+        (None, None) => return Vec::new(),
+        (start, end) => (
+            start.unwrap_or(NaiveTime::MIN),
+            end.unwrap_or(NaiveTime::from_hms_opt(23, 59, 59).unwrap()),
+        ),
+    };
+
+    let mut result = Vec::new();
+    for &(start, end) in intervals {
+        if block_end <= start || end <= block_start {
+            result.push((start, end));
+            continue;
+        }
+        if start < block_start {
+            result.push((start, block_start));
+        }
+        if block_end < end {
+            result.push((block_end, end));
+        }
+    }
+    result
+}
+
+// Start times of every whole slot that fits inside the intervals
+fn slot_starts(intervals: &[(NaiveTime, NaiveTime)], slot_minutes: i32) -> Vec<NaiveTime> {
+    let length = Duration::minutes(slot_minutes as i64);
+    let mut starts = Vec::new();
+    for &(start, end) in intervals {
+        let mut current = start;
+        // overflowing_add_signed guards against wrapping past midnight
+        loop {
+            let (slot_end, wrapped) = current.overflowing_add_signed(length);
+            if wrapped != 0 || slot_end > end {
+                break;
+            }
+            starts.push(current);
+            current = slot_end;
+        }
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(text, "%H:%M").unwrap()
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn hour(weekday: i32, start: &str, end: &str) -> WorkingHourForm {
+        WorkingHourForm {
+            weekday,
+            start_time: time(start),
+            end_time: time(end),
+        }
+    }
+
+    #[test]
+    fn slots_of_past_days_and_earlier_today_have_started() {
+        let now = date("2026-10-18").and_time(time("10:15"));
+        assert!(has_started(date("2026-10-17"), time("16:00"), now));
+        assert!(has_started(date("2026-10-18"), time("10:00"), now));
+        assert!(has_started(date("2026-10-18"), time("10:15"), now));
+        assert!(!has_started(date("2026-10-18"), time("10:30"), now));
+        assert!(!has_started(date("2026-10-19"), time("08:00"), now));
+    }
+
+    #[test]
+    fn overlapping_working_hours_are_refused() {
+        let overlapping = [hour(1, "08:00", "12:00"), hour(1, "11:30", "16:00")];
+        assert_eq!(
+            validate_working_hours(&overlapping),
+            Err("Overlapping working hours on weekday 1".to_string())
+        );
+        // Back to back, or the same hours on other days, is fine
+        let adjacent = [
+            hour(1, "08:00", "12:00"),
+            hour(1, "12:00", "16:00"),
+            hour(2, "08:00", "12:00"),
+        ];
+        assert_eq!(validate_working_hours(&adjacent), Ok(()));
+        assert!(validate_working_hours(&[hour(8, "08:00", "12:00")]).is_err());
+        assert!(validate_working_hours(&[hour(1, "12:00", "12:00")]).is_err());
+    }
+
+    #[test]
+    fn exceptions_cut_working_hours() {
+        let exception = |start: Option<&str>, end: Option<&str>| ScheduleException {
+            id: 1,
+            doctor_id: 1,
+            date: date("2026-10-19"),
+            start_time: start.map(time),
+            end_time: end.map(time),
+            reason: None,
+            create_at: None,
+        };
+        let day = [(time("08:00"), time("16:00"))];
+        assert_eq!(
+            subtract_exception(&day, &exception(Some("11:00"), Some("13:00"))),
+            vec![
+                (time("08:00"), time("11:00")),
+                (time("13:00"), time("16:00"))
+            ]
+        );
+        assert_eq!(
+            subtract_exception(&day, &exception(None, Some("10:00"))),
+            vec![(time("10:00"), time("16:00"))]
+        );
+        assert!(subtract_exception(&day, &exception(None, None)).is_empty());
+    }
+
+    #[test]
+    fn only_whole_slots_fit() {
+        let intervals = [
+            (time("08:00"), time("09:40")),
+            (time("23:00"), time("23:59")),
+        ];
+        assert_eq!(
+            slot_starts(&intervals, 30),
+            vec![time("08:00"), time("08:30"), time("09:00"), time("23:00")]
+        );
+    }
+}
@@ -0,0 +1,44 @@
+use crate::models::{SecurityEvent, SecurityEventType};
+use chrono::Utc;
+use sqlx::PgPool;
+
+pub async fn record(
+    pool: &PgPool,
+    event_type: SecurityEventType,
+    user_id: Option<i32>,
+    email: Option<&str>,
+    ip: Option<&str>,
+    detail: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO tn_security_events (event_type, user_id, email, ip, detail, create_at)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(event_type)
+    .bind(user_id)
+    .bind(email)
+    .bind(ip)
+    .bind(detail)
+    .bind(Utc::now().naive_utc())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Newest first, optionally for one account
+pub async fn list(
+    pool: &PgPool,
+    user_id: Option<i32>,
+    limit: i64,
+) -> Result<Vec<SecurityEvent>, sqlx::Error> {
+    sqlx::query_as::<_, SecurityEvent>(
+        "SELECT * FROM tn_security_events
+         WHERE ($1::int IS NULL OR user_id = $1)
+         ORDER BY create_at DESC, id DESC
+         LIMIT $2",
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
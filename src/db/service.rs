@@ -1,11 +1,11 @@
 use crate::error::Error;
-use crate::models::{Service, ServiceCreateForm};
+use crate::models::{Service, ServiceCreateForm, ServiceDoctor};
 use sqlx::PgPool;
 
 pub async fn get_services(pool: &PgPool) -> Result<Vec<Service>, Error> {
     sqlx::query_as!(
         Service,
-        "SELECT id, name, description,image, price FROM tn_services ORDER BY id"
+        "SELECT id, name, description, image, price, speciality_id FROM tn_services ORDER BY id"
     )
     .fetch_all(pool)
     .await
@@ -15,21 +15,25 @@ pub async fn get_services(pool: &PgPool) -> Result<Vec<Service>, Error> {
 pub async fn get_service_by_id(pool: &PgPool, id: i32) -> Result<Service, Error> {
     sqlx::query_as!(
         Service,
-        "SELECT id, name, description,image, price FROM tn_services WHERE id = $1",
+        "SELECT id, name, description, image, price, speciality_id FROM tn_services WHERE id = $1",
         id
     )
     .fetch_one(pool)
     .await
-    .map_err(Error::Database)
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => Error::NotFound,
+        _ => Error::Database(e),
+    })
 }
 
 pub async fn create_service(pool: &PgPool, service: &ServiceCreateForm) -> Result<i32, Error> {
     let result = sqlx::query!(
-        "INSERT INTO tn_services (name, price, description, image) VALUES ($1, $2, $3, $4) RETURNING id",
+        "INSERT INTO tn_services (name, price, description, image, speciality_id) VALUES ($1, $2, $3, $4, $5) RETURNING id",
         service.name,
         service.price,
         service.description,
         service.image,
+        service.speciality_id,
     )
     .fetch_one(pool)
     .await
@@ -44,11 +48,12 @@ pub async fn update_service(
     service: &ServiceCreateForm,
 ) -> Result<(), Error> {
     sqlx::query!(
-        "UPDATE tn_services SET name = $1, price = $2, description = $3, image = $4 WHERE id = $5",
+        "UPDATE tn_services SET name = $1, price = $2, description = $3, image = $4, speciality_id = $5 WHERE id = $6",
         service.name,
         service.price,
         service.description,
         service.image,
+        service.speciality_id,
         id
     )
     .execute(pool)
@@ -66,3 +71,99 @@ pub async fn delete_service(pool: &PgPool, id: i32) -> Result<(), Error> {
 
     Ok(())
 }
+
+pub async fn get_doctors_of_service(
+    pool: &PgPool,
+    service_id: i32,
+) -> Result<Vec<ServiceDoctor>, Error> {
+    sqlx::query_as::<_, ServiceDoctor>(
+        "SELECT d.id, d.name, d.avatar, d.speciality_id, sp.name as speciality_name,
+                d.room_id, r.name as room_name, r.location as room_location
+         FROM tn_doctor_and_service ds
+         JOIN tn_doctors d ON d.id = ds.doctor_id
+         LEFT JOIN tn_specialities sp ON sp.id = d.speciality_id
+         LEFT JOIN tn_rooms r ON r.id = d.room_id
+         WHERE ds.service_id = $1 AND COALESCE(d.active, 1) = 1
+         ORDER BY d.name, d.id",
+    )
+    .bind(service_id)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn get_services_of_doctor(pool: &PgPool, doctor_id: i32) -> Result<Vec<Service>, Error> {
+    sqlx::query_as!(
+        Service,
+        "SELECT s.id, s.name, s.description, s.image, s.price, s.speciality_id
+         FROM tn_doctor_and_service ds
+         JOIN tn_services s ON s.id = ds.service_id
+         WHERE ds.doctor_id = $1
+         ORDER BY s.name, s.id",
+        doctor_id
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Attaching a service the doctor already provides is a no-op
+pub async fn attach_service_to_doctor(
+    pool: &PgPool,
+    doctor_id: i32,
+    service_id: i32,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO tn_doctor_and_service (doctor_id, service_id) VALUES ($1, $2)
+         ON CONFLICT (doctor_id, service_id) DO NOTHING",
+        doctor_id,
+        service_id
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some("23503") => Error::NotFound,
+        _ => Error::Database(e),
+    })?;
+
+    Ok(())
+}
+
+pub async fn detach_service_from_doctor(
+    pool: &PgPool,
+    doctor_id: i32,
+    service_id: i32,
+) -> Result<(), Error> {
+    let result = sqlx::query!(
+        "DELETE FROM tn_doctor_and_service WHERE doctor_id = $1 AND service_id = $2",
+        doctor_id,
+        service_id
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound);
+    }
+    Ok(())
+}
+
+// The services in `service_ids` that the doctor does not provide
+pub async fn get_services_not_provided(
+    pool: &PgPool,
+    doctor_id: i32,
+    service_ids: &[i32],
+) -> Result<Vec<i32>, Error> {
+    sqlx::query_scalar::<_, i32>(
+        "SELECT DISTINCT s.id FROM unnest($2::int4[]) AS s(id)
+         WHERE NOT EXISTS (
+             SELECT 1 FROM tn_doctor_and_service ds WHERE ds.doctor_id = $1 AND ds.service_id = s.id)
+         ORDER BY s.id",
+    )
+    .bind(doctor_id)
+    .bind(service_ids)
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
@@ -0,0 +1,98 @@
+use crate::error::Error;
+use crate::models::Session;
+use chrono::{Duration, NaiveDateTime, Utc};
+use sqlx::PgPool;
+
+// How stale `last_seen_at` may get before a request updates it, so that not every
+// request writes to the database
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+pub async fn create(
+    pool: &PgPool,
+    user_id: i32,
+    user_agent: Option<&str>,
+    ip: &str,
+    expires_at: NaiveDateTime,
+) -> Result<i32, Error> {
+    let now = Utc::now().naive_utc();
+    sqlx::query_scalar!(
+        "INSERT INTO tn_sessions (user_id, user_agent, ip, create_at, last_seen_at, expires_at)
+         VALUES ($1, $2, $3, $4, $4, $5) RETURNING id",
+        user_id,
+        user_agent,
+        ip,
+        now,
+        expires_at
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// The user's sessions that can still be used, most recently active first
+pub async fn list(pool: &PgPool, user_id: i32, current: Option<i32>) -> Result<Vec<Session>, Error> {
+    sqlx::query_as!(
+        Session,
+        r#"SELECT id, user_agent, ip, create_at, last_seen_at, expires_at,
+                  (id = $2) IS TRUE as "current!"
+           FROM tn_sessions
+           WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > $3
+           ORDER BY last_seen_at DESC"#,
+        user_id,
+        current,
+        Utc::now().naive_utc()
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Ends one session of the user along with its refresh tokens; its access tokens are
+// turned away by `is_access_token_valid`. False when there is no such live session.
+pub async fn revoke(pool: &PgPool, user_id: i32, session_id: i32) -> Result<bool, Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let now = Utc::now().naive_utc();
+
+    let revoked = sqlx::query!(
+        "UPDATE tn_sessions SET revoked_at = $1 WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL",
+        now,
+        session_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .rows_affected()
+        > 0;
+    // Session ids are serial numbers, so another user's must not get any further
+    if !revoked {
+        return Ok(false);
+    }
+    sqlx::query!(
+        "UPDATE tn_refresh_tokens SET revoked_at = $1
+         WHERE session_id = $2 AND user_id = $3 AND revoked_at IS NULL",
+        now,
+        session_id,
+        user_id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(true)
+}
+
+pub async fn touch(pool: &PgPool, session_id: i32) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE tn_sessions SET last_seen_at = $1 WHERE id = $2 AND last_seen_at < $3",
+        now,
+        session_id,
+        now - Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS)
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
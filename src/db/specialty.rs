@@ -5,7 +5,7 @@ use sqlx::PgPool;
 pub async fn get_specialties(pool: &PgPool) -> Result<Vec<Speciality>, Error> {
     sqlx::query_as!(
         Speciality,
-        "SELECT id, name, description, image FROM tn_specialities"
+        "SELECT id, name, description, image, slot_duration FROM tn_specialities"
     )
     .fetch_all(pool)
     .await
@@ -15,7 +15,7 @@ pub async fn get_specialties(pool: &PgPool) -> Result<Vec<Speciality>, Error> {
 pub async fn get_specialty(pool: &PgPool, id: i32) -> Result<Speciality, Error> {
     sqlx::query_as!(
         Speciality,
-        "SELECT id, name, description, image FROM tn_specialities WHERE id = $1",
+        "SELECT id, name, description, image, slot_duration FROM tn_specialities WHERE id = $1",
         id
     )
     .fetch_one(pool)
@@ -28,10 +28,11 @@ pub async fn get_specialty(pool: &PgPool, id: i32) -> Result<Speciality, Error>
 
 pub async fn create_specialty(pool: &PgPool, speciality: &Speciality) -> Result<(), Error> {
     sqlx::query!(
-        "INSERT INTO tn_specialities (name, description, image) VALUES ($1, $2, $3)",
+        "INSERT INTO tn_specialities (name, description, image, slot_duration) VALUES ($1, $2, $3, $4)",
         speciality.name,
         speciality.description,
-        speciality.image
+        speciality.image,
+        speciality.slot_duration
     )
     .execute(pool)
     .await
@@ -41,10 +42,11 @@ pub async fn create_specialty(pool: &PgPool, speciality: &Speciality) -> Result<
 
 pub async fn update_specialty(pool: &PgPool, id: i32, speciality: &Speciality) -> Result<(), Error> {
     sqlx::query!(
-        "UPDATE tn_specialities SET name = $1, description = $2, image = $3 WHERE id = $4",
+        "UPDATE tn_specialities SET name = $1, description = $2, image = $3, slot_duration = $4 WHERE id = $5",
         speciality.name,
         speciality.description,
         speciality.image,
+        speciality.slot_duration,
         id
     )
     .execute(pool)
@@ -0,0 +1,223 @@
+use crate::error::Error;
+use chrono::{NaiveDateTime, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+
+pub async fn create_refresh_token(
+    pool: &PgPool,
+    user_id: i32,
+    session_id: i32,
+    token_hash: &str,
+    expires_at: NaiveDateTime,
+) -> Result<i32, Error> {
+    sqlx::query_scalar!(
+        "INSERT INTO tn_refresh_tokens (user_id, session_id, token_hash, expires_at, create_at) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        user_id,
+        session_id,
+        token_hash,
+        expires_at,
+        Utc::now().naive_utc()
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+// Swaps a live refresh token for a new one and returns its user and session, which now
+// lasts as long as the new token. Presenting a token that was already rotated means it
+// leaked, so every token of the user is revoked; one revoked on its own, e.g. with its
+// session, is just no longer valid.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    new_token_hash: &str,
+    expires_at: NaiveDateTime,
+    user_agent: Option<&str>,
+    ip: &str,
+) -> Result<(i32, i32), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    let now = Utc::now().naive_utc();
+
+    let current = sqlx::query!(
+        "SELECT id, user_id, session_id, expires_at, revoked_at, replaced_by FROM tn_refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+        token_hash
+    )
+    .fetch_optional(&mut tx)
+    .await
+    .map_err(Error::Database)?
+    .ok_or(Error::NotFound)?;
+
+    if current.revoked_at.is_some() {
+        if current.replaced_by.is_none() {
+            return Err(Error::NotFound);
+        }
+        revoke_all(&mut tx, current.user_id).await.map_err(Error::Database)?;
+        tx.commit().await.map_err(Error::Database)?;
+        return Err(Error::Conflict("refresh token was already used".to_string()));
+    }
+    if current.expires_at <= now {
+        return Err(Error::NotFound);
+    }
+
+    // Tokens from before sessions existed start one here
+    let session_id = match current.session_id {
+        Some(session_id) => {
+            sqlx::query!(
+                "UPDATE tn_sessions SET ip = $1, last_seen_at = $2, expires_at = $3 WHERE id = $4",
+                ip,
+                now,
+                expires_at,
+                session_id
+            )
+            .execute(&mut tx)
+            .await
+            .map_err(Error::Database)?;
+            session_id
+        }
+        None => sqlx::query_scalar!(
+            "INSERT INTO tn_sessions (user_id, user_agent, ip, create_at, last_seen_at, expires_at)
+             VALUES ($1, $2, $3, $4, $4, $5) RETURNING id",
+            current.user_id,
+            user_agent,
+            ip,
+            now,
+            expires_at
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(Error::Database)?,
+    };
+
+    let new_id = sqlx::query_scalar!(
+        "INSERT INTO tn_refresh_tokens (user_id, session_id, token_hash, expires_at, create_at) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        current.user_id,
+        session_id,
+        new_token_hash,
+        expires_at,
+        now
+    )
+    .fetch_one(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+    sqlx::query!(
+        "UPDATE tn_refresh_tokens SET revoked_at = $1, replaced_by = $2 WHERE id = $3",
+        now,
+        new_id,
+        current.id
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(Error::Database)?;
+
+    tx.commit().await.map_err(Error::Database)?;
+    Ok((current.user_id, session_id))
+}
+
+// Revokes one refresh token of the user; unknown or foreign tokens are ignored
+pub async fn revoke_refresh_token(pool: &PgPool, user_id: i32, token_hash: &str) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE tn_refresh_tokens SET revoked_at = $1 WHERE user_id = $2 AND token_hash = $3 AND revoked_at IS NULL",
+        Utc::now().naive_utc(),
+        user_id,
+        token_hash
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+// Puts an access token on the revocation list until it would have expired anyway
+pub async fn revoke_access_token(
+    pool: &PgPool,
+    jti: &str,
+    user_id: i32,
+    expires_at: NaiveDateTime,
+) -> Result<(), Error> {
+    let now = Utc::now().naive_utc();
+    sqlx::query!("DELETE FROM tn_revoked_tokens WHERE expires_at < $1", now)
+        .execute(pool)
+        .await
+        .map_err(Error::Database)?;
+    sqlx::query!(
+        "INSERT INTO tn_revoked_tokens (jti, user_id, expires_at, create_at) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (jti) DO NOTHING",
+        jti,
+        user_id,
+        expires_at,
+        now
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
+
+// Whether an access token may still be used: its account exists and is active, nothing
+// revoked the account's tokens after it was issued, the token is not on the list and its
+// session, if it has one, was not revoked.
+// `iat` has whole seconds only, so tokens from the second of a revocation count as revoked.
+pub async fn is_access_token_valid(
+    pool: &PgPool,
+    user_id: i32,
+    jti: &str,
+    issued_at: i64,
+    session_id: Option<i32>,
+) -> Result<bool, Error> {
+    let row = sqlx::query!(
+        r#"SELECT u.active, u.tokens_revoked_at,
+                  EXISTS (SELECT 1 FROM tn_revoked_tokens WHERE jti = $2) as "listed!",
+                  EXISTS (SELECT 1 FROM tn_sessions
+                          WHERE id = $3 AND (user_id <> u.id OR revoked_at IS NOT NULL)) as "session_revoked!"
+           FROM tn_users u WHERE u.id = $1"#,
+        user_id,
+        jti,
+        session_id
+    )
+    .fetch_optional(pool)
+    .await
+    .map_err(Error::Database)?;
+
+    Ok(match row {
+        Some(row) => {
+            row.active == 1
+                && !row.listed
+                && !row.session_revoked
+                && !matches!(row.tokens_revoked_at, Some(revoked_at) if issued_at <= revoked_at.and_utc().timestamp())
+        }
+        None => false,
+    })
+}
+
+// Logs the user out everywhere: refresh tokens die now, access tokens on their next use
+pub async fn revoke_user_tokens(pool: &PgPool, user_id: i32) -> Result<(), Error> {
+    let mut tx = pool.begin().await.map_err(Error::Database)?;
+    revoke_all(&mut tx, user_id).await.map_err(Error::Database)?;
+    tx.commit().await.map_err(Error::Database)?;
+    Ok(())
+}
+
+pub async fn revoke_all(tx: &mut Transaction<'_, Postgres>, user_id: i32) -> Result<(), sqlx::Error> {
+    let now = Utc::now().naive_utc();
+    sqlx::query!(
+        "UPDATE tn_users SET tokens_revoked_at = $1 WHERE id = $2",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE tn_refresh_tokens SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE tn_sessions SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL",
+        now,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use crate::models::{TwoFactorPolicy, UserRole};
+use chrono::Utc;
+use sqlx::PgPool;
+
+// Starts enrollment with a new secret; it only protects logins once `enable` confirmed it.
+// False when the account does not exist or already has a second factor.
+pub async fn start_enrollment(pool: &PgPool, user_id: i32, secret: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE tn_users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2 AND totp_enabled = 0",
+    )
+    .bind(secret)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// The secret and whether it is confirmed
+pub async fn get_secret(pool: &PgPool, user_id: i32) -> Result<Option<(String, bool)>, sqlx::Error> {
+    let row: Option<(Option<String>, i32)> =
+        sqlx::query_as("SELECT totp_secret, totp_enabled FROM tn_users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(row.and_then(|(secret, enabled)| secret.map(|secret| (secret, enabled == 1))))
+}
+
+// Takes up a code's time step; false when that step or a later one was already used
+pub async fn claim_step(pool: &PgPool, user_id: i32, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE tn_users SET totp_last_step = $1
+         WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+    )
+    .bind(step)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Turns the second factor on and replaces any recovery codes with the given hashes
+pub async fn enable(pool: &PgPool, user_id: i32, recovery_code_hashes: &[String]) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE tn_users SET totp_enabled = 1, update_at = $1 WHERE id = $2")
+        .bind(Utc::now().naive_utc())
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+    replace_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn set_recovery_codes(
+    pool: &PgPool,
+    user_id: i32,
+    recovery_code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    replace_recovery_codes(&mut tx, user_id, recovery_code_hashes).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn replace_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: i32,
+    recovery_code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM tn_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO tn_recovery_codes (user_id, code_hash, create_at)
+         SELECT $1, code_hash, $3 FROM UNNEST($2::varchar[]) AS code_hash",
+    )
+    .bind(user_id)
+    .bind(recovery_code_hashes)
+    .bind(Utc::now().naive_utc())
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+// Spends a recovery code; false when it is unknown or already used
+pub async fn use_recovery_code(pool: &PgPool, user_id: i32, code_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE tn_recovery_codes SET used_at = $1
+         WHERE user_id = $2 AND code_hash = $3 AND used_at IS NULL",
+    )
+    .bind(Utc::now().naive_utc())
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+// Removes the second factor and its recovery codes; false when the account does not exist
+pub async fn disable(pool: &PgPool, user_id: i32) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let result = sqlx::query(
+        "UPDATE tn_users SET totp_secret = NULL, totp_enabled = 0, totp_last_step = NULL, update_at = $1
+         WHERE id = $2",
+    )
+    .bind(Utc::now().naive_utc())
+    .bind(user_id)
+    .execute(&mut tx)
+    .await?;
+    sqlx::query("DELETE FROM tn_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn is_required(pool: &PgPool, role: UserRole) -> Result<bool, sqlx::Error> {
+    let required: Option<i32> = sqlx::query_scalar("SELECT required FROM tn_two_factor_policy WHERE role = $1")
+        .bind(role)
+        .fetch_optional(pool)
+        .await?;
+    Ok(required == Some(1))
+}
+
+pub async fn get_policies(pool: &PgPool) -> Result<Vec<TwoFactorPolicy>, sqlx::Error> {
+    sqlx::query_as::<_, TwoFactorPolicy>(
+        "SELECT role, required = 1 as required, update_at FROM tn_two_factor_policy ORDER BY role",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn set_policy(pool: &PgPool, role: UserRole, required: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO tn_two_factor_policy (role, required, update_at) VALUES ($1, $2, $3)
+         ON CONFLICT (role) DO UPDATE SET required = $2, update_at = $3",
+    )
+    .bind(role)
+    .bind(required as i32)
+    .bind(Utc::now().naive_utc())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
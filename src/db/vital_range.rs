@@ -0,0 +1,38 @@
+use crate::error::Error;
+use crate::models::{AgeGroup, Vital, VitalReferenceRange};
+use chrono::Utc;
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+
+pub async fn get_all(pool: &PgPool) -> Result<Vec<VitalReferenceRange>, Error> {
+    sqlx::query_as!(
+        VitalReferenceRange,
+        r#"SELECT age_group as "age_group: AgeGroup", vital as "vital: Vital", low, high, update_at
+           FROM tn_vital_reference_ranges ORDER BY age_group, vital"#
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(Error::Database)
+}
+
+pub async fn set(
+    pool: &PgPool,
+    age_group: AgeGroup,
+    vital: Vital,
+    low: Decimal,
+    high: Decimal,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "INSERT INTO tn_vital_reference_ranges (age_group, vital, low, high, update_at) VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (age_group, vital) DO UPDATE SET low = $3, high = $4, update_at = $5",
+        age_group as AgeGroup,
+        vital as Vital,
+        low,
+        high,
+        Utc::now().naive_utc()
+    )
+    .execute(pool)
+    .await
+    .map_err(Error::Database)?;
+    Ok(())
+}
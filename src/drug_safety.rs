@@ -0,0 +1,210 @@
+// Checking a prescription before it is stored: interactions between the medicines
+// prescribed and with those the patient takes long-term, the patient's recorded allergies,
+// and the same therapy given twice.
+use crate::models::{
+    AllergySeverity, ClinicalProfile, DrugInteraction, InteractionSeverity, Medicine,
+    MedicineIngredient, PrescriptionIssue, PrescriptionIssueKind, PrescriptionIssueLevel,
+};
+
+// What the knowledge base holds about the medicines prescribed and the patient's
+// long-term ones
+pub struct KnownFacts {
+    pub medicines: Vec<Medicine>,
+    pub interactions: Vec<DrugInteraction>,
+    pub ingredients: Vec<MedicineIngredient>,
+}
+
+// Allergies and major or contraindicated interactions block; the rest are warnings.
+// Without a profile only the medicines prescribed are checked against each other.
+pub fn check(
+    prescribed: &[i32],
+    facts: &KnownFacts,
+    profile: Option<&ClinicalProfile>,
+) -> Vec<PrescriptionIssue> {
+    let mut distinct: Vec<i32> = Vec::new();
+    for &id in prescribed {
+        if !distinct.contains(&id) {
+            distinct.push(id);
+        }
+    }
+    let mut long_term: Vec<i32> = Vec::new();
+    for medication in profile.map_or(&[][..], |p| &p.medications) {
+        if let Some(id) = medication.medicine_id {
+            if !long_term.contains(&id) {
+                long_term.push(id);
+            }
+        }
+    }
+    let name = |id: i32| {
+        facts
+            .medicines
+            .iter()
+            .find(|m| m.id == id)
+            .and_then(|m| m.name.clone())
+            .unwrap_or_else(|| format!("medicine {}", id))
+    };
+    // Medicines only taken long-term are named as such
+    let label = |id: i32| {
+        if distinct.contains(&id) {
+            name(id)
+        } else {
+            format!("{} (taken long-term)", name(id))
+        }
+    };
+    let ingredients_of = |id: i32| {
+        facts
+            .ingredients
+            .iter()
+            .filter(move |i| i.medicine_id == id)
+    };
+    let mut issues = Vec::new();
+
+    for interaction in &facts.interactions {
+        let (a, b) = (interaction.medicine_a, interaction.medicine_b);
+        if !distinct.contains(&a) && !distinct.contains(&b) {
+            continue;
+        }
+        let level = match interaction.severity {
+            InteractionSeverity::Major | InteractionSeverity::Contraindicated => {
+                PrescriptionIssueLevel::Blocking
+            }
+            InteractionSeverity::Minor | InteractionSeverity::Moderate => {
+                PrescriptionIssueLevel::Warning
+            }
+        };
+        let mut message = format!(
+            "{} and {}: {} interaction",
+            label(a),
+            label(b),
+            interaction.severity.as_str()
+        );
+        if let Some(note) = interaction.note.as_deref().filter(|n| !n.is_empty()) {
+            message = format!("{}. {}", message, note);
+        }
+        issues.push(PrescriptionIssue {
+            kind: PrescriptionIssueKind::Interaction,
+            level,
+            medicine_ids: vec![a, b],
+            message,
+        });
+    }
+
+    for allergy in profile.map_or(&[][..], |p| &p.allergies) {
+        let substance = words(&allergy.substance);
+        if substance.is_empty() {
+            continue;
+        }
+        for &id in &distinct {
+            // Formulary names often carry a strength or form, as in `Amoxicillin 500mg`,
+            // so the medicine's name is checked even without ingredient data
+            let contains = if mentions(&name(id), &substance) {
+                Some(allergy.substance.trim().to_string())
+            } else {
+                ingredients_of(id).find_map(|i| {
+                    if mentions(&i.ingredient, &substance) {
+                        Some(i.ingredient.clone())
+                    } else if i.allergen.as_deref().is_some_and(|a| mentions(a, &substance)) {
+                        Some(format!("{}, a {}", i.ingredient, allergy.substance.trim()))
+                    } else {
+                        None
+                    }
+                })
+            };
+            let Some(contains) = contains else {
+                continue;
+            };
+            let mut message = format!(
+                "{} contains {}; the patient has a {} allergy to {}",
+                name(id),
+                contains,
+                allergy_severity_name(allergy.severity),
+                allergy.substance.trim()
+            );
+            if let Some(reaction) = allergy.reaction.as_deref().filter(|r| !r.is_empty()) {
+                message = format!("{} ({})", message, reaction);
+            }
+            issues.push(PrescriptionIssue {
+                kind: PrescriptionIssueKind::Allergy,
+                level: PrescriptionIssueLevel::Blocking,
+                medicine_ids: vec![id],
+                message,
+            });
+        }
+    }
+
+    for &id in &distinct {
+        let message = if prescribed.iter().filter(|&&p| p == id).count() > 1 {
+            format!("{} is prescribed more than once", name(id))
+        } else if long_term.contains(&id) {
+            format!("{} is already taken long-term", name(id))
+        } else {
+            continue;
+        };
+        issues.push(PrescriptionIssue {
+            kind: PrescriptionIssueKind::DuplicateTherapy,
+            level: PrescriptionIssueLevel::Warning,
+            medicine_ids: vec![id],
+            message,
+        });
+    }
+    // Different medicines with the same active ingredient
+    let involved: Vec<i32> = distinct
+        .iter()
+        .chain(long_term.iter().filter(|id| !distinct.contains(id)))
+        .copied()
+        .collect();
+    for (index, &a) in involved.iter().enumerate() {
+        for &b in &involved[index + 1..] {
+            if !distinct.contains(&a) && !distinct.contains(&b) {
+                continue;
+            }
+            let shared: Vec<&str> = ingredients_of(a)
+                .filter(|i| {
+                    ingredients_of(b).any(|j| j.ingredient.eq_ignore_ascii_case(&i.ingredient))
+                })
+                .map(|i| i.ingredient.as_str())
+                .collect();
+            if shared.is_empty() {
+                continue;
+            }
+            issues.push(PrescriptionIssue {
+                kind: PrescriptionIssueKind::DuplicateTherapy,
+                level: PrescriptionIssueLevel::Warning,
+                medicine_ids: vec![a, b],
+                message: format!(
+                    "{} and {} both contain {}",
+                    label(a),
+                    label(b),
+                    shared.join(", ")
+                ),
+            });
+        }
+    }
+
+    issues
+}
+
+// Lowercase words of letters and digits, so case and punctuation do not matter
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Whether `text` has the words of `substance` in a row: `Amoxicillin 500mg` and
+// `amoxicillin trihydrate` mention amoxicillin, `Amoxicillinase` does not
+fn mentions(text: &str, substance: &[String]) -> bool {
+    words(text)
+        .windows(substance.len())
+        .any(|window| window == substance)
+}
+
+fn allergy_severity_name(severity: AllergySeverity) -> &'static str {
+    match severity {
+        AllergySeverity::Mild => "mild",
+        AllergySeverity::Moderate => "moderate",
+        AllergySeverity::Severe => "severe",
+        AllergySeverity::LifeThreatening => "life-threatening",
+    }
+}
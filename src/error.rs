@@ -7,6 +7,8 @@ pub enum Error {
     Database(#[from] sqlx::Error),
     #[error("not found")]
     NotFound,
+    #[error("conflict: {0}")]
+    Conflict(String),
 }
 
 impl Reject for Error {}
@@ -0,0 +1,220 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use simple_asn1::ASN1Block;
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+
+// The `iss` of every token we sign
+pub const ISSUER: &str = "hospital-management-system";
+
+const RSA_ENCRYPTION: [u64; 7] = [1, 2, 840, 113549, 1, 1, 1];
+const ED25519: [u64; 4] = [1, 3, 101, 112];
+
+#[derive(Error, Debug)]
+#[error("JWT key error: {0}")]
+pub struct JwtKeyError(pub String);
+
+struct VerifyingKey {
+    algorithm: Algorithm,
+    key: DecodingKey,
+    jwk: Value,
+}
+
+// Keys for signing and checking our tokens. Tokens carry the id of their signing key as
+// `kid`, so several keys can be trusted at once and the signing key can be rotated
+// without logging anyone out.
+pub struct JwtKeys {
+    signing_kid: Option<String>,
+    signing_algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    verifying_keys: BTreeMap<String, VerifyingKey>,
+    // Development fallback without a key directory: HS256 with JWT_SECRET and no `kid`
+    shared_secret: Option<DecodingKey>,
+}
+
+impl JwtKeys {
+    // Reads the keys from JWT_KEYS_DIR, where every trusted key has a `<kid>.pub.pem`
+    // (SPKI, RSA or Ed25519) and the signing key named by JWT_SIGNING_KID also has its
+    // private key as `<kid>.pem`. Rotation: add the new public key everywhere, switch
+    // JWT_SIGNING_KID, and delete the old public key once its last tokens have expired.
+    pub fn from_env() -> Result<Self, JwtKeyError> {
+        match std::env::var("JWT_KEYS_DIR") {
+            Ok(dir) => {
+                let signing_kid = std::env::var("JWT_SIGNING_KID")
+                    .map_err(|_| JwtKeyError("JWT_SIGNING_KID must be set with JWT_KEYS_DIR".to_string()))?;
+                Self::from_dir(Path::new(&dir), &signing_kid)
+            }
+            Err(_) => {
+                let secret = std::env::var("JWT_SECRET")
+                    .map_err(|_| JwtKeyError("JWT_KEYS_DIR or JWT_SECRET must be set".to_string()))?;
+                eprintln!("JWT_KEYS_DIR is not set; signing tokens with JWT_SECRET (HS256), which other services cannot verify");
+                Ok(Self::from_secret(secret.as_bytes()))
+            }
+        }
+    }
+
+    pub fn from_secret(secret: &[u8]) -> Self {
+        Self {
+            signing_kid: None,
+            signing_algorithm: Algorithm::HS256,
+            encoding_key: EncodingKey::from_secret(secret),
+            verifying_keys: BTreeMap::new(),
+            shared_secret: Some(DecodingKey::from_secret(secret)),
+        }
+    }
+
+    pub fn from_dir(dir: &Path, signing_kid: &str) -> Result<Self, JwtKeyError> {
+        let entries = std::fs::read_dir(dir)
+            .map_err(|e| JwtKeyError(format!("cannot read {}: {}", dir.display(), e)))?;
+        let mut verifying_keys = BTreeMap::new();
+        for entry in entries {
+            let path = entry.map_err(|e| JwtKeyError(e.to_string()))?.path();
+            let Some(kid) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".pub.pem"))
+            else {
+                continue;
+            };
+            let pem = std::fs::read(&path)
+                .map_err(|e| JwtKeyError(format!("cannot read {}: {}", path.display(), e)))?;
+            let key = verifying_key(kid, &pem)
+                .map_err(|e| JwtKeyError(format!("{}: {}", path.display(), e.0)))?;
+            verifying_keys.insert(kid.to_string(), key);
+        }
+
+        let signing_algorithm = verifying_keys
+            .get(signing_kid)
+            .map(|key| key.algorithm)
+            .ok_or_else(|| JwtKeyError(format!("no {}.pub.pem for the signing key", signing_kid)))?;
+        let private_path = dir.join(format!("{}.pem", signing_kid));
+        let pem = std::fs::read(&private_path)
+            .map_err(|e| JwtKeyError(format!("cannot read {}: {}", private_path.display(), e)))?;
+        let encoding_key = match signing_algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+            _ => EncodingKey::from_rsa_pem(&pem),
+        }
+        .map_err(|e| JwtKeyError(format!("{}: {}", private_path.display(), e)))?;
+
+        let keys = Self {
+            signing_kid: Some(signing_kid.to_string()),
+            signing_algorithm,
+            encoding_key,
+            verifying_keys,
+            shared_secret: None,
+        };
+        keys.check_signing_key()?;
+        Ok(keys)
+    }
+
+    // `typ` goes in the header and names what the token is for; the claims should carry
+    // the matching `aud` and ISSUER as `iss`
+    pub fn sign<T: Serialize>(&self, claims: &T, typ: &str) -> Result<String, jsonwebtoken::errors::Error> {
+        let mut header = Header::new(self.signing_algorithm);
+        header.kid = self.signing_kid.clone();
+        header.typ = Some(typ.to_string());
+        encode(&header, claims, &self.encoding_key)
+    }
+
+    // Claims of a token signed by one of the trusted keys, checked with that key's own
+    // algorithm so a token cannot pick a weaker one. Every key signs tokens of several
+    // kinds, so the `typ`, `aud` and `iss` must be the ones expected here as well.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str, typ: &str, audience: &str) -> Option<T> {
+        let header = decode_header(token).ok()?;
+        if header.typ.as_deref() != Some(typ) {
+            return None;
+        }
+        let (algorithm, key) = match header.kid {
+            Some(kid) => {
+                let key = self.verifying_keys.get(&kid)?;
+                (key.algorithm, &key.key)
+            }
+            None => (Algorithm::HS256, self.shared_secret.as_ref()?),
+        };
+        let mut validation = Validation::new(algorithm);
+        validation.set_audience(&[audience]);
+        validation.set_issuer(&[ISSUER]);
+        decode::<T>(token, key, &validation)
+            .ok()
+            .map(|token| token.claims)
+    }
+
+    // The trusted public keys as a JSON Web Key Set, for services verifying our tokens
+    pub fn jwks(&self) -> Value {
+        let keys: Vec<&Value> = self.verifying_keys.values().map(|key| &key.jwk).collect();
+        json!({ "keys": keys })
+    }
+
+    // A private key that does not match its public key would only show up as every
+    // token being rejected, so try one at startup
+    fn check_signing_key(&self) -> Result<(), JwtKeyError> {
+        let probe = json!({
+            "iss": ISSUER,
+            "aud": "probe",
+            "exp": (Utc::now() + Duration::minutes(1)).timestamp()
+        });
+        let token = self
+            .sign(&probe, "probe")
+            .map_err(|e| JwtKeyError(format!("cannot sign with the signing key: {}", e)))?;
+        match self.verify::<Value>(&token, "probe", "probe") {
+            Some(_) => Ok(()),
+            None => Err(JwtKeyError(
+                "the signing key does not match its public key".to_string(),
+            )),
+        }
+    }
+}
+
+// Reads an SPKI public key; the algorithm follows from the key type
+fn verifying_key(kid: &str, pem: &[u8]) -> Result<VerifyingKey, JwtKeyError> {
+    let pem = pem::parse(pem).map_err(|e| JwtKeyError(e.to_string()))?;
+    if pem.tag != "PUBLIC KEY" {
+        return Err(JwtKeyError("expected a PUBLIC KEY".to_string()));
+    }
+    let (oid, public_key) = match simple_asn1::from_der(&pem.contents).as_deref() {
+        Ok([ASN1Block::Sequence(_, spki)]) => match spki.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, public_key)] => {
+                match algorithm.first() {
+                    Some(ASN1Block::ObjectIdentifier(_, oid)) => {
+                        (oid.as_vec::<u64>().unwrap_or_default(), public_key.clone())
+                    }
+                    _ => return Err(JwtKeyError("malformed public key".to_string())),
+                }
+            }
+            _ => return Err(JwtKeyError("malformed public key".to_string())),
+        },
+        _ => return Err(JwtKeyError("malformed public key".to_string())),
+    };
+
+    if oid == RSA_ENCRYPTION {
+        let (n, e) = match simple_asn1::from_der(&public_key).as_deref() {
+            Ok([ASN1Block::Sequence(_, parts)]) => match parts.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                    (URL_SAFE_NO_PAD.encode(n.to_bytes_be().1), URL_SAFE_NO_PAD.encode(e.to_bytes_be().1))
+                }
+                _ => return Err(JwtKeyError("malformed RSA key".to_string())),
+            },
+            _ => return Err(JwtKeyError("malformed RSA key".to_string())),
+        };
+        Ok(VerifyingKey {
+            algorithm: Algorithm::RS256,
+            key: DecodingKey::from_rsa_components(&n, &e).map_err(|e| JwtKeyError(e.to_string()))?,
+            jwk: json!({ "kty": "RSA", "kid": kid, "use": "sig", "alg": "RS256", "n": n, "e": e }),
+        })
+    } else if oid == ED25519 {
+        let x = URL_SAFE_NO_PAD.encode(&public_key);
+        Ok(VerifyingKey {
+            algorithm: Algorithm::EdDSA,
+            key: DecodingKey::from_ed_components(&x).map_err(|e| JwtKeyError(e.to_string()))?,
+            jwk: json!({ "kty": "OKP", "crv": "Ed25519", "kid": kid, "use": "sig", "alg": "EdDSA", "x": x }),
+        })
+    } else {
+        Err(JwtKeyError("only RSA and Ed25519 keys are supported".to_string()))
+    }
+}
@@ -0,0 +1,120 @@
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[error("mail error: {0}")]
+pub struct MailError(pub String);
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Outgoing mail. Sending may block, so async callers should go through `web::block`.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+// Picks the transport from MAIL_TRANSPORT: `smtp`, `file` or `log` (the default)
+pub fn from_env() -> Result<Arc<dyn Mailer>, MailError> {
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "log".to_string());
+    Ok(match transport.as_str() {
+        "smtp" => Arc::new(SmtpMailer::from_env()?),
+        "file" => Arc::new(FileMailer::new(
+            std::env::var("MAIL_FILE").unwrap_or_else(|_| "mail.log".to_string()),
+        )),
+        "log" => Arc::new(LogMailer),
+        other => return Err(MailError(format!("unknown MAIL_TRANSPORT {}", other))),
+    })
+}
+
+// Sends through an SMTP relay configured by SMTP_HOST, SMTP_PORT, SMTP_USERNAME,
+// SMTP_PASSWORD and MAIL_FROM
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<Self, MailError> {
+        let host = std::env::var("SMTP_HOST").map_err(|_| MailError("SMTP_HOST must be set".to_string()))?;
+        let from = std::env::var("MAIL_FROM").map_err(|_| MailError("MAIL_FROM must be set".to_string()))?;
+
+        let mut builder = SmtpTransport::relay(&host).map_err(|e| MailError(e.to_string()))?;
+        if let Some(port) = std::env::var("SMTP_PORT").ok().and_then(|port| port.parse().ok()) {
+            builder = builder.port(port);
+        }
+        if let (Ok(username), Ok(password)) = (std::env::var("SMTP_USERNAME"), std::env::var("SMTP_PASSWORD")) {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let message = Message::builder()
+            .from(self.from.parse().map_err(|_| MailError(format!("invalid sender {}", self.from)))?)
+            .to(email.to.parse().map_err(|_| MailError(format!("invalid recipient {}", email.to)))?)
+            .subject(email.subject.clone())
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())
+            .map_err(|e| MailError(e.to_string()))?;
+
+        self.transport
+            .send(&message)
+            .map(|_| ())
+            .map_err(|e| MailError(e.to_string()))
+    }
+}
+
+// Appends every message to a file, for development and end-to-end tests
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let _guard = self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| MailError(e.to_string()))?;
+        writeln!(file, "To: {}\nSubject: {}\n\n{}\n", email.to, email.subject, email.body)
+            .map_err(|e| MailError(e.to_string()))
+    }
+}
+
+// Prints who would get which message instead of sending it. Bodies hold reset codes and
+// verification links, so they are left out; use `file` to read them in development.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        println!("mail to {}: {} (body not logged)", email.to, email.subject);
+        Ok(())
+    }
+}
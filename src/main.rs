@@ -3,33 +3,72 @@ use actix_web::middleware::Logger;
 use actix_web::web::service;
 use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
+use db::login_attempt::LockoutPolicy;
+use jwt_keys::JwtKeys;
 use middleware::auth::AuthMiddleware;
+use models::QueueEvent;
 use routes::{
     appointment, authentication, doctor, medical_record, medicine, patient, payment, service,
-    specialty,admin,
+    specialty,admin, queue, receptionest, booking, two_factor, icd10, clinical_profile,
+    drug_interaction,
 };
 use serde::ser;
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
 use warp::Filter;
 
 mod db;
+mod drug_safety;
 mod error;
+mod jwt_keys;
+mod mailer;
 mod middleware;
 mod models;
 mod routes;
+mod totp;
+mod vitals;
 
 pub struct AppState {
     db: PgPool,
-    jwt_secret: String,
+    jwt_keys: Arc<JwtKeys>,
+    access_token_ttl_minutes: i64,
+    refresh_token_ttl_days: i64,
+    // Patients cannot cancel or reschedule closer than this to the appointment
+    appointment_change_cutoff_hours: i64,
+    queue_events: broadcast::Sender<QueueEvent>,
+    queue_display_token: Option<String>,
+    mailer: Arc<dyn mailer::Mailer>,
+    password_reset_ttl_minutes: i64,
+    // Frontend page that takes the reset code as `?token=`, linked in reset emails
+    password_reset_url: Option<String>,
+    email_verification_ttl_hours: i64,
+    // Where verification links point, e.g. this API's /api/verify-email or a frontend page
+    email_verification_url: String,
+    lockout_policy: LockoutPolicy,
+    // Take the client address from X-Forwarded-For; only safe behind a proxy that sets it
+    trust_forwarded_for: bool,
+    // Name authenticator apps show next to the account
+    totp_issuer: String,
 }
 
-fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
-    cfg.service(
+fn configure_app(cfg: &mut web::ServiceConfig, jwt_keys: Arc<JwtKeys>) {
+    cfg.service(authentication::jwks)
+    .service(
         web::scope("/api/patient")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(patient::get_self_patient)
             .service(patient::get_patients)
+            .service(patient::get_vitals_trend)
+            .service(clinical_profile::get_clinical_profile)
+            .service(clinical_profile::update_clinical_profile)
+            .service(clinical_profile::add_allergy)
+            .service(clinical_profile::remove_allergy)
+            .service(clinical_profile::add_condition)
+            .service(clinical_profile::remove_condition)
+            .service(clinical_profile::add_medication)
+            .service(clinical_profile::remove_medication)
             .service(patient::get_patient_by_id)
             .service(patient::update_patient)
             .service(patient::get_patient_by_phone)
@@ -38,17 +77,50 @@ fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
     )
     .service(
         web::scope("/api/appointment")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(appointment::create_appointment)
+            .service(appointment::get_available_slots)
             .service(appointment::get_appointments_of_patient)
             .service(appointment::get_appointments_by_specialty)
             .service(appointment::update_appointment_status)
             .service(appointment::update_appointment_treatment_status)
+            .service(appointment::get_appointment_status_history)
+            .service(appointment::cancel_appointment)
+            .service(appointment::reschedule_appointment)
+            .service(appointment::assign_doctor)
             .service(appointment::get_self_appointments),
     )
+    .service(
+        web::scope("/api/queue")
+            .service(queue::get_specialty_queue)
+            .service(queue::get_room_queue)
+            .service(queue::stream_specialty_queue)
+            .service(queue::stream_room_queue),
+    )
+    .service(
+        web::scope("/api/reception")
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
+            .service(receptionest::search_patient_by_phone)
+            .service(receptionest::find_or_create_patient)
+            .service(receptionest::check_in_appointment)
+            .service(receptionest::register_walk_in)
+            .service(receptionest::get_reception_queue),
+    )
+    .service(
+        web::scope("/api/booking")
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
+            .service(booking::create_booking)
+            .service(booking::get_self_bookings)
+            .service(booking::get_bookings)
+            .service(booking::get_booking_by_id)
+            .service(booking::add_booking_photo)
+            .service(booking::confirm_booking)
+            .service(booking::reject_booking)
+            .service(booking::cancel_booking),
+    )
     .service(
         web::scope("/api/payment")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(payment::get_invoices_of_medical_record)
             .service(payment::create_invoice)
             .service(payment::get_self_invoices),
@@ -56,7 +128,7 @@ fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
     .service(
         web::scope("/api/specialty")
             .service(specialty::get_specialties)
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(specialty::get_specialty_by_id)
             .service(specialty::create_speciality)
             .service(specialty::update_speciality)
@@ -64,15 +136,16 @@ fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
     )
     .service(
         web::scope("/api/service")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(service::get_services)
             .service(service::get_service_by_id)
+            .service(service::get_doctors_of_service)
             .service(service::create_service)
             .service(service::update_service),
     )
     .service(
         web::scope("/api/medicine")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(medicine::get_medicines)
             .service(medicine::get_medicine_by_id)
             .service(medicine::create_medicine)
@@ -82,7 +155,7 @@ fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
     )
     .service(
         web::scope("/api/medical-record")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(medical_record::get_self_medical_records)
             .service(medical_record::update_payment_status)
             .service(medical_record::create_medical_record)
@@ -90,33 +163,100 @@ fn configure_app(cfg: &mut web::ServiceConfig, jwt_secret: String) {
             .service(medical_record::update_diagnosis)
             .service(medical_record::is_medical_record_exist)
             .service(medical_record::get_vital_signs)
-            .service(medical_record::create_vital_sign),
+            .service(medical_record::create_vital_sign)
+            .service(medical_record::update_note)
+            .service(medical_record::get_amendments)
+            .service(medical_record::add_diagnosis)
+            .service(medical_record::remove_diagnosis)
+            // Last, so `/{id}` does not shadow the fixed paths above
+            .service(medical_record::get_medical_record),
+    )
+    .service(
+        web::scope("/api/icd10")
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
+            // Full code lists are several megabytes of text
+            .app_data(web::PayloadConfig::new(32 * 1024 * 1024))
+            .service(icd10::search_codes)
+            .service(icd10::import_codes),
+    )
+    .service(
+        web::scope("/api/drug-interactions")
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
+            .app_data(web::PayloadConfig::new(8 * 1024 * 1024))
+            .service(drug_interaction::import_interactions)
+            .service(drug_interaction::import_ingredients),
     )
     .service(
         web::scope("/api/doctor")
-            .wrap(AuthMiddleware::new(jwt_secret.clone()))
+            .wrap(AuthMiddleware::new(jwt_keys.clone()))
             .service(doctor::get_self_doctor)
+            .service(doctor::get_worklist)
+            .service(doctor::get_services_of_doctor)
     )
     .service(
             web::scope("/api/admin")
-                .wrap(AuthMiddleware::new(jwt_secret.clone()))
+                .wrap(AuthMiddleware::new(jwt_keys.clone()))
                 .service(admin::get_doctors)
                 .service(admin::get_doctor_by_id)
                 .service(admin::create_doctor)
                 .service(admin::update_doctor)
-                .service(admin::delete_doctor),
+                .service(admin::delete_doctor)
+                .service(admin::get_doctor_working_hours)
+                .service(admin::update_doctor_working_hours)
+                .service(admin::get_doctor_schedule_exceptions)
+                .service(admin::create_doctor_schedule_exception)
+                .service(admin::delete_doctor_schedule_exception)
+                .service(admin::reassign_doctor_appointments)
+                .service(admin::attach_doctor_service)
+                .service(admin::detach_doctor_service)
+                .service(admin::get_rooms)
+                .service(admin::get_room_by_id)
+                .service(admin::create_room)
+                .service(admin::update_room)
+                .service(admin::delete_room)
+                .service(admin::get_room_shifts)
+                .service(admin::create_room_shift)
+                .service(admin::delete_room_shift)
+                .service(admin::set_doctor_room)
+                .service(admin::set_user_active)
+                .service(admin::unlock_user)
+                .service(admin::get_security_events)
+                .service(admin::reset_user_two_factor)
+                .service(admin::get_two_factor_policy)
+                .service(admin::set_two_factor_policy)
+                .service(admin::delete_user)
+                .service(admin::get_vital_reference_ranges)
+                .service(admin::set_vital_reference_range),
     )
     .service(
         web::scope("/api")
             .service(authentication::login)
             .service(authentication::register)
             .service(authentication::reset_password)
+            .service(authentication::confirm_reset_password)
+            .service(authentication::verify_email)
+            .service(authentication::resend_verification_email)
             .service(authentication::get_role)
+            // Before the /auth scope: refreshing works without a live access token
+            .service(authentication::refresh)
+            // Reachable with the token a password earns before the second factor
+            .service(
+                web::scope("/2fa")
+                    .wrap(AuthMiddleware::allowing_second_factor_pending(jwt_keys.clone()))
+                    .service(two_factor::enroll)
+                    .service(two_factor::enable)
+                    .service(two_factor::verify),
+            )
             .service(
                 web::scope("/auth")
-                    .wrap(AuthMiddleware::new(jwt_secret.clone()))
+                    .wrap(AuthMiddleware::new(jwt_keys.clone()))
+                    .service(authentication::logout)
+                    .service(authentication::list_sessions)
+                    .service(authentication::revoke_session)
                     .service(authentication::update_password)
-                    .service(authentication::admin_create_user),
+                    .service(authentication::admin_create_user)
+                    .service(two_factor::disable)
+                    .service(two_factor::regenerate_recovery_codes),
             ),
     );
 }
@@ -130,7 +270,52 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to connect to Postgres");
 
-    let jwt_secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+    let jwt_keys = Arc::new(JwtKeys::from_env().expect("Failed to load JWT keys"));
+    let access_token_ttl_minutes = std::env::var("ACCESS_TOKEN_TTL_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(15);
+    let refresh_token_ttl_days = std::env::var("REFRESH_TOKEN_TTL_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(30);
+    let appointment_change_cutoff_hours = std::env::var("APPOINTMENT_CHANGE_CUTOFF_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse().ok())
+        .unwrap_or(24);
+    let queue_display_token = std::env::var("QUEUE_DISPLAY_TOKEN").ok();
+    let mailer = mailer::from_env().expect("Failed to configure mail transport");
+    let password_reset_ttl_minutes = std::env::var("PASSWORD_RESET_TTL_MINUTES")
+        .ok()
+        .and_then(|minutes| minutes.parse().ok())
+        .unwrap_or(30);
+    let password_reset_url = std::env::var("PASSWORD_RESET_URL").ok();
+    let email_verification_ttl_hours = std::env::var("EMAIL_VERIFICATION_TTL_HOURS")
+        .ok()
+        .and_then(|hours| hours.parse().ok())
+        .unwrap_or(24);
+    let lockout_policy = LockoutPolicy {
+        max_failures: std::env::var("LOGIN_MAX_FAILURES")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(5),
+        max_ip_failures: std::env::var("LOGIN_MAX_IP_FAILURES")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(20),
+        lockout_minutes: std::env::var("LOGIN_LOCKOUT_MINUTES")
+            .ok()
+            .and_then(|minutes| minutes.parse().ok())
+            .unwrap_or(15),
+    };
+    let trust_forwarded_for = std::env::var("TRUST_FORWARDED_FOR").is_ok_and(|value| value == "true");
+    let totp_issuer =
+        std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "Hospital Management System".to_string());
+    let (queue_events, _) = broadcast::channel(100);
+    let server_address =
+        std::env::var("SERVER_ADDRESS").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let email_verification_url = std::env::var("EMAIL_VERIFICATION_URL")
+        .unwrap_or_else(|_| format!("http://{}/api/verify-email", server_address));
 
     HttpServer::new(move || {
         App::new()
@@ -143,11 +328,24 @@ async fn main() -> std::io::Result<()> {
             )
             .app_data(web::Data::new(AppState {
                 db: pool.clone(),
-                jwt_secret: jwt_secret.clone(),
+                jwt_keys: jwt_keys.clone(),
+                access_token_ttl_minutes,
+                refresh_token_ttl_days,
+                appointment_change_cutoff_hours,
+                queue_events: queue_events.clone(),
+                queue_display_token: queue_display_token.clone(),
+                mailer: mailer.clone(),
+                password_reset_ttl_minutes,
+                password_reset_url: password_reset_url.clone(),
+                email_verification_ttl_hours,
+                email_verification_url: email_verification_url.clone(),
+                lockout_policy,
+                trust_forwarded_for,
+                totp_issuer: totp_issuer.clone(),
             }))
-            .configure(|cfg| configure_app(cfg, jwt_secret.clone()))
+            .configure(|cfg| configure_app(cfg, jwt_keys.clone()))
     })
-    .bind("127.0.0.1:8080")?
+    .bind(server_address)?
     .run()
     .await
 }
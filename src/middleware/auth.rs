@@ -1,25 +1,41 @@
-use crate::authentication::Claims;
+use crate::authentication::{Claims, TokenKind};
+use crate::db::{session, token};
+use crate::jwt_keys::JwtKeys;
 use actix_web::{
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error, HttpMessage,
+    web, Error, HttpMessage,
 };
 use futures_util::future::LocalBoxFuture;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct AuthMiddleware {
-    jwt_secret: String,
+    jwt_keys: Arc<JwtKeys>,
+    allow_second_factor_pending: bool,
 }
 
 impl AuthMiddleware {
-    pub fn new(jwt_secret: String) -> Self {
-        Self { jwt_secret }
+    pub fn new(jwt_keys: Arc<JwtKeys>) -> Self {
+        Self {
+            jwt_keys,
+            allow_second_factor_pending: false,
+        }
+    }
+
+    // Also lets through tokens still waiting for the second factor, for the routes that
+    // finish signing in
+    pub fn allowing_second_factor_pending(jwt_keys: Arc<JwtKeys>) -> Self {
+        Self {
+            jwt_keys,
+            allow_second_factor_pending: true,
+        }
     }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -31,20 +47,22 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareService {
-            service,
-            jwt_secret: self.jwt_secret.clone(),
+            service: Rc::new(service),
+            jwt_keys: Arc::clone(&self.jwt_keys),
+            allow_second_factor_pending: self.allow_second_factor_pending,
         }))
     }
 }
 
 pub struct AuthMiddlewareService<S> {
-    service: S,
-    jwt_secret: String,
+    service: Rc<S>,
+    jwt_keys: Arc<JwtKeys>,
+    allow_second_factor_pending: bool,
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareService<S>
 where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -56,35 +74,45 @@ where
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let auth_header = req.headers().get("Authorization");
-        let jwt_secret = self.jwt_secret.clone();
-
-        if let Some(auth_str) = auth_header {
-            let token = auth_str.to_str().unwrap().replace("Bearer ", "");
+        let token = auth_header
+            .and_then(|auth_str| auth_str.to_str().ok())
+            .map(|auth_str| auth_str.replace("Bearer ", ""));
+        let verify = |kind: TokenKind| {
+            token
+                .as_deref()
+                .and_then(|token| self.jwt_keys.verify::<Claims>(token, kind.typ(), kind.audience()))
+                .filter(|claims| claims.kind == kind)
+        };
+        let claims = verify(TokenKind::Access).or_else(|| {
+            self.allow_second_factor_pending
+                .then(|| verify(TokenKind::SecondFactorPending))
+                .flatten()
+        });
+        let data = req.app_data::<web::Data<crate::AppState>>().cloned();
+        let service = Rc::clone(&self.service);
 
-            // Decode the token
-            if let Ok(token_data) = decode::<Claims>(
-                &token,
-                &DecodingKey::from_secret(jwt_secret.as_bytes()),
-                &Validation::default(),
-            ) {
-                req.extensions_mut().insert(token_data.claims);
-                let fut = self.service.call(req);
-                return Box::pin(async move {
-                    let res = fut.await?;
-                    Ok(res)
-                });
+        Box::pin(async move {
+            // A well-signed token still has to survive logout, session revocation, deactivation
+            // and deletion
+            if let (Some(claims), Some(data)) = (claims, data) {
+                let valid = match claims.sub.parse::<i32>() {
+                    Ok(user_id) => {
+                        token::is_access_token_valid(&data.db, user_id, &claims.jti, claims.iat, claims.sid)
+                            .await
+                            .map_err(actix_web::error::ErrorInternalServerError)?
+                    }
+                    Err(_) => false,
+                };
+                if valid {
+                    if let Some(session_id) = claims.sid {
+                        let _ = session::touch(&data.db, session_id).await;
+                    }
+                    req.extensions_mut().insert(claims);
+                    return service.call(req).await;
+                }
             }
-        }
 
-        Box::pin(async move {
-            Err(Error::from(actix_web::error::ErrorUnauthorized(
-                "Invalid token",
-            )))
+            Err(actix_web::error::ErrorUnauthorized("Invalid token"))
         })
     }
 }
-
-// Helper function to verify admin role
-pub fn verify_admin(claims: &Claims) -> bool {
-    claims.role == "admin"
-}
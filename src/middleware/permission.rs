@@ -0,0 +1,263 @@
+use crate::authentication::Claims;
+use crate::models::UserRole;
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    error::InternalError,
+    Error, HttpMessage, HttpResponse,
+};
+use futures_util::future::LocalBoxFuture;
+use serde_json::json;
+use std::future::{ready, Ready};
+
+// Something a route may require of the caller. Which roles hold which permission is
+// decided in one place, `UserRole::permissions`, instead of in every handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    PatientReadSelf,
+    PatientRead,
+    PatientAny,
+    PatientCreate,
+    PatientUpdate,
+    AppointmentCreate,
+    AppointmentReadOwn,
+    AppointmentRead,
+    AppointmentReadHistory,
+    AppointmentUpdateStatus,
+    AppointmentUpdateTreatmentStatus,
+    AppointmentChange,
+    AppointmentAssignDoctor,
+    ReceptionDesk,
+    BookingCreate,
+    BookingReadOwn,
+    BookingRead,
+    BookingUpdate,
+    BookingManage,
+    MedicalRecordReadOwn,
+    MedicalRecordRead,
+    MedicalRecordWrite,
+    Icd10Import,
+    VitalRangeManage,
+    DrugInteractionImport,
+    PrescriptionRead,
+    PrescriptionWrite,
+    InvoiceReadOwn,
+    InvoiceRead,
+    InvoiceCreate,
+    MedicineWrite,
+    SpecialtyWrite,
+    ServiceWrite,
+    DoctorReadSelf,
+    DoctorManage,
+    ScheduleManage,
+    RoomManage,
+    UserManage,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::PatientReadSelf => "patient:read_self",
+            Permission::PatientRead => "patient:read",
+            Permission::PatientAny => "patient:any",
+            Permission::PatientCreate => "patient:create",
+            Permission::PatientUpdate => "patient:update",
+            Permission::AppointmentCreate => "appointment:create",
+            Permission::AppointmentReadOwn => "appointment:read_own",
+            Permission::AppointmentRead => "appointment:read",
+            Permission::AppointmentReadHistory => "appointment:read_history",
+            Permission::AppointmentUpdateStatus => "appointment:update_status",
+            Permission::AppointmentUpdateTreatmentStatus => "appointment:update_treatment_status",
+            Permission::AppointmentChange => "appointment:change",
+            Permission::AppointmentAssignDoctor => "appointment:assign_doctor",
+            Permission::ReceptionDesk => "reception:desk",
+            Permission::BookingCreate => "booking:create",
+            Permission::BookingReadOwn => "booking:read_own",
+            Permission::BookingRead => "booking:read",
+            Permission::BookingUpdate => "booking:update",
+            Permission::BookingManage => "booking:manage",
+            Permission::MedicalRecordReadOwn => "medical_record:read_own",
+            Permission::MedicalRecordRead => "medical_record:read",
+            Permission::MedicalRecordWrite => "medical_record:write",
+            Permission::Icd10Import => "icd10:import",
+            Permission::VitalRangeManage => "vital_range:manage",
+            Permission::DrugInteractionImport => "drug_interaction:import",
+            Permission::PrescriptionRead => "prescription:read",
+            Permission::PrescriptionWrite => "prescription:write",
+            Permission::InvoiceReadOwn => "invoice:read_own",
+            Permission::InvoiceRead => "invoice:read",
+            Permission::InvoiceCreate => "invoice:create",
+            Permission::MedicineWrite => "medicine:write",
+            Permission::SpecialtyWrite => "specialty:write",
+            Permission::ServiceWrite => "service:write",
+            Permission::DoctorReadSelf => "doctor:read_self",
+            Permission::DoctorManage => "doctor:manage",
+            Permission::ScheduleManage => "schedule:manage",
+            Permission::RoomManage => "room:manage",
+            Permission::UserManage => "user:manage",
+        }
+    }
+}
+
+impl UserRole {
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+        match self {
+            UserRole::Patient => &[
+                PatientReadSelf,
+                PatientUpdate,
+                AppointmentCreate,
+                AppointmentReadOwn,
+                AppointmentReadHistory,
+                AppointmentChange,
+                BookingCreate,
+                BookingReadOwn,
+                BookingRead,
+                BookingUpdate,
+                MedicalRecordReadOwn,
+                MedicalRecordRead,
+                PrescriptionRead,
+                InvoiceReadOwn,
+            ],
+            UserRole::Doctor => &[
+                PatientRead,
+                AppointmentRead,
+                AppointmentReadHistory,
+                AppointmentUpdateTreatmentStatus,
+                MedicalRecordRead,
+                MedicalRecordWrite,
+                PrescriptionRead,
+                PrescriptionWrite,
+                InvoiceCreate,
+                DoctorReadSelf,
+            ],
+            UserRole::Receptionist => &[
+                PatientRead,
+                PatientAny,
+                PatientCreate,
+                PatientUpdate,
+                AppointmentCreate,
+                AppointmentRead,
+                AppointmentReadHistory,
+                AppointmentUpdateStatus,
+                AppointmentUpdateTreatmentStatus,
+                AppointmentChange,
+                AppointmentAssignDoctor,
+                ReceptionDesk,
+                BookingRead,
+                BookingUpdate,
+                BookingManage,
+                PrescriptionRead,
+                InvoiceRead,
+            ],
+            UserRole::Staff => &[
+                PatientRead,
+                PatientAny,
+                PatientCreate,
+                PatientUpdate,
+                AppointmentCreate,
+                AppointmentRead,
+                AppointmentReadHistory,
+                AppointmentUpdateStatus,
+                AppointmentUpdateTreatmentStatus,
+                AppointmentChange,
+                AppointmentAssignDoctor,
+                BookingRead,
+                BookingUpdate,
+                BookingManage,
+                MedicineWrite,
+            ],
+            UserRole::Admin => &[
+                PatientRead,
+                PatientAny,
+                PatientCreate,
+                PatientUpdate,
+                AppointmentRead,
+                AppointmentReadHistory,
+                AppointmentUpdateStatus,
+                AppointmentChange,
+                AppointmentAssignDoctor,
+                BookingRead,
+                BookingUpdate,
+                BookingManage,
+                MedicineWrite,
+                SpecialtyWrite,
+                ServiceWrite,
+                Icd10Import,
+                VitalRangeManage,
+                DrugInteractionImport,
+                DoctorManage,
+                ScheduleManage,
+                RoomManage,
+                UserManage,
+            ],
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+// Rejects the request with 403 unless the caller's role holds the permission. Meant for
+// single routes, e.g. `#[get("/all", wrap = "Require(Permission::PatientRead)")]`, inside
+// a scope wrapped in `AuthMiddleware`, which puts the claims in the request.
+pub struct Require(pub Permission);
+
+impl<S, B> Transform<S, ServiceRequest> for Require
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireService {
+            service,
+            permission: self.0,
+        }))
+    }
+}
+
+pub struct RequireService<S> {
+    service: S,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = req
+            .extensions()
+            .get::<Claims>()
+            .is_some_and(|claims| claims.role.has(self.permission));
+
+        if !allowed {
+            let permission = self.permission.as_str();
+            let response = HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "message": format!("Permission denied: {}", permission)
+            }));
+            return Box::pin(ready(Err(InternalError::from_response(
+                permission, response,
+            )
+            .into())));
+        }
+
+        Box::pin(self.service.call(req))
+    }
+}
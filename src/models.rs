@@ -1,4 +1,5 @@
-use chrono::{NaiveDate, NaiveDateTime};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
@@ -9,6 +10,7 @@ pub struct Service {
     pub image: Option<String>,
     pub description: Option<String>,
     pub price: Option<i32>,
+    pub speciality_id: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,6 +19,7 @@ pub struct ServiceCreateForm {
     pub price: Option<i32>,
     pub description: Option<String>,
     pub image: Option<String>,
+    pub speciality_id: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -26,12 +29,49 @@ pub struct Room {
     pub location: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomForm {
+    pub name: String,
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct RoomShift {
+    pub id: i32,
+    pub doctor_id: i32,
+    pub doctor_name: Option<String>,
+    pub room_id: i32,
+    pub date: NaiveDate,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoomShiftForm {
+    pub doctor_id: i32,
+    pub date: NaiveDate,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoomShiftQuery {
+    pub date: Option<NaiveDate>,
+}
+
+// Default room of a doctor, used whenever no shift says otherwise
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoctorRoomForm {
+    pub room_id: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Speciality {
     pub id: i32,
     pub name: Option<String>,
     pub description: Option<String>,
     pub image: Option<String>,
+    pub slot_duration: Option<i32>, // Minutes per appointment slot
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -39,7 +79,6 @@ pub struct Doctor {
     pub id: i32,
     pub email: Option<String>,
     pub phone: Option<String>,
-    pub password: Option<String>,
     pub name: Option<String>,
     pub description: Option<String>,
     pub role: Option<String>,
@@ -49,7 +88,7 @@ pub struct Doctor {
     pub update_at: Option<NaiveDateTime>,
     pub speciality_id: Option<i32>,
     pub room_id: Option<i32>,
-    pub recovery_token: Option<String>,
+    pub user_id: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -57,7 +96,6 @@ pub struct DoctorResponse {
     pub id: i32,
     pub email: Option<String>,
     pub phone: Option<String>,
-    pub password: Option<String>,
     pub name: Option<String>,
     pub description: Option<String>,
     pub role: Option<String>,
@@ -68,14 +106,25 @@ pub struct DoctorResponse {
     pub speciality: Option<String>,
     pub speciality_id: Option<i32>,
     pub room_id: Option<i32>,
-    pub recovery_token: Option<String>,
+    pub user_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DoctorServiceForm {
+    pub service_id: i32,
 }
 
+// A doctor performing a service, with where to find them
 #[derive(Debug, Serialize, Deserialize, FromRow)]
-pub struct DoctorAndService {
+pub struct ServiceDoctor {
     pub id: i32,
-    pub service_id: i32,
-    pub doctor_id: i32,
+    pub name: Option<String>,
+    pub avatar: Option<String>,
+    pub speciality_id: Option<i32>,
+    pub speciality_name: Option<String>,
+    pub room_id: Option<i32>,
+    pub room_name: Option<String>,
+    pub room_location: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -83,7 +132,6 @@ pub struct Patient {
     pub id: i32,
     pub email: Option<String>,
     pub phone: Option<String>,
-    pub password: Option<String>,
     pub name: Option<String>,
     pub gender: Option<i32>,
     pub birthday: Option<String>,
@@ -91,6 +139,130 @@ pub struct Patient {
     pub avatar: Option<String>,
     pub create_at: Option<NaiveDateTime>,
     pub update_at: Option<NaiveDateTime>,
+    pub user_id: Option<i32>,
+}
+
+// The patient with their clinical profile, as `/api/patient/self` returns it
+#[derive(Debug, Serialize)]
+pub struct PatientWithProfile {
+    #[serde(flatten)]
+    pub patient: Patient,
+    pub clinical_profile: ClinicalProfile,
+}
+
+// Allergies, chronic conditions, long-term medications, blood type and family history;
+// only current entries, oldest first
+#[derive(Debug, Serialize)]
+pub struct ClinicalProfile {
+    pub blood_type: Option<BloodType>,
+    pub family_history: Option<String>,
+    pub allergies: Vec<Allergy>,
+    pub conditions: Vec<ChronicCondition>,
+    pub medications: Vec<LongTermMedication>,
+    pub update_at: Option<NaiveDateTime>,
+}
+
+// Stored in tn_patient_clinical_profiles.blood_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "varchar")]
+pub enum BloodType {
+    #[serde(rename = "A+")]
+    #[sqlx(rename = "A+")]
+    APositive,
+    #[serde(rename = "A-")]
+    #[sqlx(rename = "A-")]
+    ANegative,
+    #[serde(rename = "B+")]
+    #[sqlx(rename = "B+")]
+    BPositive,
+    #[serde(rename = "B-")]
+    #[sqlx(rename = "B-")]
+    BNegative,
+    #[serde(rename = "AB+")]
+    #[sqlx(rename = "AB+")]
+    AbPositive,
+    #[serde(rename = "AB-")]
+    #[sqlx(rename = "AB-")]
+    AbNegative,
+    #[serde(rename = "O+")]
+    #[sqlx(rename = "O+")]
+    OPositive,
+    #[serde(rename = "O-")]
+    #[sqlx(rename = "O-")]
+    ONegative,
+}
+
+// Stored in tn_patient_allergies.severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum AllergySeverity {
+    Mild,
+    Moderate,
+    Severe,
+    LifeThreatening,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Allergy {
+    pub id: i32,
+    pub substance: String,
+    pub reaction: Option<String>,
+    pub severity: AllergySeverity,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ChronicCondition {
+    pub id: i32,
+    pub name: String,
+    pub icd10_code: Option<String>,
+    pub diagnosed_on: Option<NaiveDate>,
+    pub note: Option<String>,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct LongTermMedication {
+    pub id: i32,
+    pub medicine_id: Option<i32>, // tn_medicine.id, for medicines in the formulary
+    pub name: String,
+    pub dose: Option<String>,
+    pub frequency: Option<String>,
+    pub started_on: Option<NaiveDate>,
+    pub note: Option<String>,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClinicalProfileForm {
+    pub blood_type: Option<BloodType>,
+    pub family_history: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AllergyForm {
+    pub substance: String,
+    pub reaction: Option<String>,
+    pub severity: AllergySeverity,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChronicConditionForm {
+    pub name: String,
+    pub icd10_code: Option<String>,
+    pub diagnosed_on: Option<NaiveDate>,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LongTermMedicationForm {
+    pub medicine_id: Option<i32>,
+    pub name: String,
+    pub dose: Option<String>,
+    pub frequency: Option<String>,
+    pub started_on: Option<NaiveDate>,
+    pub note: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -116,9 +288,23 @@ pub struct Booking {
     pub reason: Option<String>,
     pub appointment_date: String,
     pub appointment_time: String,
-    pub status: Option<String>,
+    pub status: BookingStatus,
     pub create_at: Option<NaiveDateTime>,
     pub update_at: Option<NaiveDateTime>,
+    pub appointment_id: Option<i32>,
+    pub reject_reason: Option<String>,
+    pub doctor_id: Option<i32>,
+}
+
+// Stored in tn_booking.status; only pending bookings can be confirmed, rejected or cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum BookingStatus {
+    Pending,
+    Confirmed,
+    Rejected,
+    Cancelled,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -128,6 +314,53 @@ pub struct BookingPhoto {
     pub booking_id: i32,
 }
 
+#[derive(Debug, Serialize)]
+pub struct BookingDetail {
+    #[serde(flatten)]
+    pub booking: Booking,
+    pub photos: Vec<BookingPhoto>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookingForm {
+    pub service_id: i32,
+    pub booking_name: Option<String>,
+    pub booking_phone: Option<String>,
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub birthday: Option<String>,
+    pub address: Option<String>,
+    pub reason: Option<String>,
+    pub appointment_date: NaiveDate,
+    pub appointment_time: String, // "HH:MM"
+    pub photos: Option<Vec<String>>,
+    pub doctor_id: Option<i32>, // must provide the service
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookingPhotoForm {
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BookingListQuery {
+    pub status: Option<BookingStatus>,
+}
+
+// Overrides for the appointment created on confirmation; the booking's own date and
+// time are used when omitted
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfirmBookingRequest {
+    pub date: Option<NaiveDate>,
+    pub appointment_time: Option<String>,
+    pub doctor_id: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RejectBookingRequest {
+    pub reason: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct Treatment {
     pub id: i32,
@@ -152,24 +385,299 @@ pub struct Appointment {
     pub patient_phone: Option<String>,
     pub numerical_order: Option<i32>,
     pub appointment_time: String,
-    pub status: Option<String>,
-    pub treatment_status: Option<String>,
+    pub status: Option<AppointmentStatus>,
+    pub treatment_status: Option<TreatmentStatus>,
     pub create_at: Option<NaiveDateTime>,
     pub update_at: Option<NaiveDateTime>,
     pub date: Option<NaiveDate>,
+    pub arrived_at: Option<NaiveDateTime>,
+    pub is_walk_in: Option<i32>,
+    pub priority: Option<QueuePriority>,
+    pub doctor_id: Option<i32>,
+}
+
+// Payment state of an appointment, stored in tn_appointments.status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "varchar")]
+pub enum AppointmentStatus {
+    Unpaid,
+    Paid,
+    Refunded,
+}
+
+impl AppointmentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AppointmentStatus::Unpaid => "Unpaid",
+            AppointmentStatus::Paid => "Paid",
+            AppointmentStatus::Refunded => "Refunded",
+        }
+    }
+
+    pub fn can_transition_to(&self, next: AppointmentStatus) -> bool {
+        matches!(
+            (self, next),
+            (AppointmentStatus::Unpaid, AppointmentStatus::Paid)
+                | (AppointmentStatus::Paid, AppointmentStatus::Refunded)
+        )
+    }
+}
+
+// Visit lifecycle of an appointment, stored in tn_appointments.treatment_status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "kebab-case")]
+#[sqlx(type_name = "varchar", rename_all = "kebab-case")]
+pub enum TreatmentStatus {
+    Scheduled,
+    CheckedIn,
+    InProgress,
+    Completed,
+    Cancelled,
+    NoShow,
 }
 
+impl TreatmentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TreatmentStatus::Scheduled => "scheduled",
+            TreatmentStatus::CheckedIn => "checked-in",
+            TreatmentStatus::InProgress => "in-progress",
+            TreatmentStatus::Completed => "completed",
+            TreatmentStatus::Cancelled => "cancelled",
+            TreatmentStatus::NoShow => "no-show",
+        }
+    }
+
+    // scheduled -> checked-in -> in-progress -> completed; cancelled and no-show
+    // are only reachable before the visit starts
+    pub fn can_transition_to(&self, next: TreatmentStatus) -> bool {
+        use TreatmentStatus::*;
+        matches!(
+            (self, next),
+            (Scheduled, CheckedIn)
+                | (Scheduled, Cancelled)
+                | (Scheduled, NoShow)
+                | (CheckedIn, InProgress)
+                | (CheckedIn, Cancelled)
+                | (CheckedIn, NoShow)
+                | (InProgress, Completed)
+        )
+    }
+}
+
+// Set at reception; higher priorities are called before lower ones regardless of
+// numerical_order, stored in tn_appointments.priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum QueuePriority {
+    Normal,
+    Elderly,
+    Pregnant,
+    Emergency,
+}
+
+// Stored in the units of `vitals::UNITS`, whatever the reading was entered in
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct VitalSign {
     pub id: i32,
     pub medical_record_id: Option<i32>,
-    pub temperature: Option<i32>,
+    pub temperature: Option<Decimal>,
+    pub blood_pressure_systolic: Option<i32>,
+    pub blood_pressure_diastolic: Option<i32>,
+    pub heart_rate: Option<i32>,
+    pub spo2: Option<i32>,
+    pub weight: Option<Decimal>,
+    pub height: Option<Decimal>,
+    pub recorded_at: NaiveDateTime,
+    pub recorded_by: Option<i32>, // tn_users.id
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VitalSignForm {
+    pub medical_record_id: i32,
+    pub temperature: Option<Decimal>,
+    pub temperature_unit: Option<TemperatureUnit>, // celsius unless given
     pub blood_pressure_systolic: Option<i32>,
     pub blood_pressure_diastolic: Option<i32>,
     pub heart_rate: Option<i32>,
     pub spo2: Option<i32>,
-    pub weight: Option<i32>,
-    pub height: Option<i32>,
+    pub weight: Option<Decimal>,
+    pub weight_unit: Option<WeightUnit>, // kg unless given
+    pub height: Option<Decimal>,
+    pub height_unit: Option<HeightUnit>, // cm unless given
+    pub recorded_at: Option<NaiveDateTime>, // now unless given
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightUnit {
+    Kg,
+    Lb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeightUnit {
+    Cm,
+    In,
+}
+
+// A reading as the API returns it: the stored values with their units, the BMI and how
+// they compare to the reference ranges for the patient's age
+#[derive(Debug, Serialize)]
+pub struct VitalSignReading {
+    #[serde(flatten)]
+    pub vital_sign: VitalSign,
+    pub bmi: Option<Decimal>,
+    pub units: VitalSignUnits,
+    pub age_group: AgeGroup,
+    pub flags: Vec<VitalFlag>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct VitalSignUnits {
+    pub temperature: &'static str,
+    pub blood_pressure: &'static str,
+    pub heart_rate: &'static str,
+    pub spo2: &'static str,
+    pub weight: &'static str,
+    pub height: &'static str,
+    pub bmi: &'static str,
+}
+
+// Which reference ranges apply, by the patient's age when the reading was taken; stored
+// in tn_vital_reference_ranges.age_group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum AgeGroup {
+    Adult,
+    Pediatric,
+}
+
+// Stored in tn_vital_reference_ranges.vital
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum Vital {
+    Temperature,
+    BloodPressureSystolic,
+    BloodPressureDiastolic,
+    HeartRate,
+    Spo2,
+    Bmi,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VitalFlagLevel {
+    Low,
+    High,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VitalFlag {
+    pub vital: Vital,
+    pub value: Decimal,
+    pub flag: VitalFlagLevel,
+    pub low: Decimal,
+    pub high: Decimal,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct VitalReferenceRange {
+    pub age_group: AgeGroup,
+    pub vital: Vital,
+    pub low: Decimal,
+    pub high: Decimal,
+    pub update_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VitalReferenceRangeRequest {
+    pub low: Decimal,
+    pub high: Decimal,
+}
+
+// What `GET /api/patient/{id}/vitals/trend` can chart; BMI is worked out per reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrendMetric {
+    Temperature,
+    BloodPressureSystolic,
+    BloodPressureDiastolic,
+    HeartRate,
+    Spo2,
+    Weight,
+    Height,
+    Bmi,
+}
+
+impl TrendMetric {
+    pub const ALL: [TrendMetric; 8] = [
+        TrendMetric::Temperature,
+        TrendMetric::BloodPressureSystolic,
+        TrendMetric::BloodPressureDiastolic,
+        TrendMetric::HeartRate,
+        TrendMetric::Spo2,
+        TrendMetric::Weight,
+        TrendMetric::Height,
+        TrendMetric::Bmi,
+    ];
+}
+
+impl std::str::FromStr for TrendMetric {
+    type Err = ();
+
+    fn from_str(metric: &str) -> Result<Self, Self::Err> {
+        match metric {
+            "temperature" => Ok(TrendMetric::Temperature),
+            "blood_pressure_systolic" => Ok(TrendMetric::BloodPressureSystolic),
+            "blood_pressure_diastolic" => Ok(TrendMetric::BloodPressureDiastolic),
+            "heart_rate" => Ok(TrendMetric::HeartRate),
+            "spo2" => Ok(TrendMetric::Spo2),
+            "weight" => Ok(TrendMetric::Weight),
+            "height" => Ok(TrendMetric::Height),
+            "bmi" => Ok(TrendMetric::Bmi),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VitalTrendQuery {
+    pub metric: Option<String>, // comma separated, every metric unless given
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>, // inclusive
+}
+
+#[derive(Debug, Serialize)]
+pub struct VitalTrend {
+    pub metric: TrendMetric,
+    pub unit: &'static str,
+    pub count: usize,
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+    pub avg: Option<Decimal>,
+    pub points: Vec<VitalTrendPoint>,
+}
+
+// One reading of a metric; `delta` is the change from the last reading of the previous
+// visit, None on the first visit
+#[derive(Debug, Serialize)]
+pub struct VitalTrendPoint {
+    pub recorded_at: NaiveDateTime,
+    pub medical_record_id: Option<i32>,
+    pub value: Decimal,
+    pub delta: Option<Decimal>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -181,6 +689,123 @@ pub struct AppointmentCreateForm {
     pub patient_reason: String,
     pub speciality_id: i32,
     pub date: Option<NaiveDate>,
+    pub appointment_time: Option<String>, // "HH:MM" of a free slot, first free slot if omitted
+    pub doctor_id: Option<i32>,           // staff only; least-loaded free doctor if omitted
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct DoctorWorkingHour {
+    pub id: i32,
+    pub doctor_id: i32,
+    pub weekday: i32, // 1 = Monday ... 7 = Sunday
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkingHourForm {
+    pub weekday: i32,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ScheduleException {
+    pub id: i32,
+    pub doctor_id: i32,
+    pub date: NaiveDate,
+    pub start_time: Option<NaiveTime>, // None together with end_time means the whole day
+    pub end_time: Option<NaiveTime>,
+    pub reason: Option<String>,
+    pub create_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduleExceptionForm {
+    pub date: NaiveDate,
+    pub start_time: Option<NaiveTime>,
+    pub end_time: Option<NaiveTime>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AvailableSlot {
+    pub time: String,
+    pub capacity: i32,
+    pub booked: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvailableSlotQuery {
+    pub speciality_id: i32,
+    pub date: NaiveDate,
+}
+
+// Published whenever the queue of a specialty changes on a given day. Without a specialty,
+// doctors moved between rooms, which may change what any room's queue shows.
+#[derive(Debug, Clone)]
+pub struct QueueEvent {
+    pub speciality_id: Option<i32>,
+    pub date: NaiveDate,
+}
+
+#[derive(Debug, FromRow)]
+pub struct QueueEntry {
+    pub numerical_order: Option<i32>,
+    pub treatment_status: TreatmentStatus,
+}
+
+// The doctors sitting in a room at a moment, and what their queue needs to know of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoomOccupants {
+    pub doctor_ids: Vec<i32>,
+    pub speciality_ids: Vec<i32>,
+    pub slot_minutes: i64, // Average over the doctors
+}
+
+// What a lobby display shows; numbers only, no patient details
+#[derive(Debug, Serialize)]
+pub struct QueueSnapshot {
+    pub date: NaiveDate,
+    pub now_serving: Vec<i32>,
+    pub next_up: Vec<i32>,
+    pub waiting: i64,
+    pub estimated_wait_minutes: i64, // For a patient checking in now
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisplayTokenQuery {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhoneSearchQuery {
+    pub phone: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckInRequest {
+    pub priority: Option<QueuePriority>,
+}
+
+// A patient without an appointment; patient_id, or else the phone number, identifies
+// the patient, who is created from the remaining fields if the phone is unknown
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WalkInForm {
+    pub patient_id: Option<i32>,
+    pub phone: Option<String>,
+    pub name: Option<String>,
+    pub gender: Option<i32>,
+    pub birthday: Option<String>,
+    pub address: Option<String>,
+    pub speciality_id: i32,
+    pub reason: Option<String>,
+    pub priority: Option<QueuePriority>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReceptionQueueQuery {
+    pub date: Option<NaiveDate>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -198,11 +823,16 @@ pub struct Notification {
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct AppointmentRecord {
     pub id: i32,
-    pub appointment_id: i32,
+    pub appointment_id: Option<i32>,
+    pub doctor_id: Option<i32>,
+    pub room_id: Option<i32>,
     pub reason: Option<String>,
     pub description: Option<String>,
+    pub status_type: Option<String>, // "status", "treatment_status" or "schedule"
     pub status_before: Option<String>,
     pub status_after: Option<String>,
+    pub actor_id: Option<i32>,
+    pub actor_role: Option<String>,
     pub create_at: Option<NaiveDateTime>,
     pub update_at: Option<NaiveDateTime>,
 }
@@ -213,9 +843,106 @@ pub struct Drug {
     pub name: String,
 }
 
+// Who an account belongs to; each role keeps its profile in its own table
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum UserRole {
+    Patient,
+    Doctor,
+    Receptionist,
+    Staff,
+    Admin,
+}
+
+impl std::str::FromStr for UserRole {
+    type Err = ();
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "patient" => Ok(UserRole::Patient),
+            "doctor" => Ok(UserRole::Doctor),
+            "receptionist" => Ok(UserRole::Receptionist),
+            "staff" => Ok(UserRole::Staff),
+            "admin" => Ok(UserRole::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+impl UserRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserRole::Patient => "patient",
+            UserRole::Doctor => "doctor",
+            UserRole::Receptionist => "receptionist",
+            UserRole::Staff => "staff",
+            UserRole::Admin => "admin",
+        }
+    }
+
+    // Table holding the profile of accounts with this role
+    pub fn profile_table(&self) -> &'static str {
+        match self {
+            UserRole::Patient => "tn_patients",
+            UserRole::Doctor => "tn_doctors",
+            UserRole::Receptionist => "tn_receptionist",
+            UserRole::Staff => "tn_staffs",
+            UserRole::Admin => "tn_admins",
+        }
+    }
+}
+
+// Login credentials, one row per account in tn_users
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct User {
+    pub id: i32,
+    pub email: String,
+    #[serde(skip_serializing)]
+    pub password: String,
+    pub role: UserRole,
+    pub active: i32,
+    pub verified: i32,
+    pub create_at: Option<NaiveDateTime>,
+    pub update_at: Option<NaiveDateTime>,
+    pub tokens_revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    pub refresh_token: Option<String>,
+    pub all: Option<bool>, // also end every other session of the account
+}
+
 #[derive(Debug, Deserialize)]
+pub struct UserActiveRequest {
+    pub active: bool,
+}
+
+// An account joined with its profile, as needed to log in
+#[derive(Debug, FromRow)]
+pub struct Account {
+    pub user_id: i32,
+    pub password: String,
+    pub role: UserRole,
+    pub active: i32,
+    pub verified: i32,
+    pub locked_until: Option<NaiveDateTime>,
+    pub totp_enabled: i32,
+    pub profile_id: i32,
+    pub name: Option<String>,
+    pub speciality_id: Option<i32>,
+}
+
+// No Debug: the request carries a password
+#[derive(Deserialize)]
 pub struct LoginRequest {
-    pub login_type: String,
+    pub login_type: UserRole,
     pub email: String,
     pub password: String,
 }
@@ -233,6 +960,8 @@ pub struct LoginResponse {
 pub struct AppointmentResponse {
     pub appointment_time: String,
     pub numerical_order: i32,
+    pub doctor_id: Option<i32>,
+    pub room: Option<Room>, // where the patient should go
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -250,6 +979,8 @@ pub struct TokenData {
     pub access_token: String,
     pub token_type: String,
     pub expires_in: i64,
+    pub refresh_token: String,
+    pub refresh_expires_in: i64,
 }
 
 #[derive(Deserialize)]
@@ -257,14 +988,24 @@ pub struct RegisterRequest {
     pub email: String,
     pub password: String,
     pub name: String,
-    pub role: String,
+    pub role: UserRole,
     pub speciality_id: Option<i32>,
 }
 
 #[derive(Deserialize)]
 pub struct PasswordResetRequest {
     pub email: String,
-    pub role: String,
+    pub role: UserRole,
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResendVerificationRequest {
+    pub email: String,
 }
 
 #[derive(Deserialize)]
@@ -303,6 +1044,12 @@ pub struct MedicalRecordResponse {
     pub patient_id: Option<i32>,
     pub doctor_name: Option<String>,
     pub diagnosis: Option<String>,
+    pub chief_complaint: Option<String>,
+    pub history: Option<String>,
+    pub examination: Option<String>,
+    pub assessment: Option<String>,
+    pub plan: Option<String>,
+    pub update_at: Option<NaiveDateTime>,
 }
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct MedicalRecord {
@@ -311,7 +1058,103 @@ pub struct MedicalRecord {
     pub payment_status: Option<i32>,
     pub patient_id: Option<i32>,
     pub doctor_id: Option<i32>,
-    pub diagnosis: Option<String>,
+    pub diagnosis: Option<String>, // free-text summary; coded diagnoses are added separately
+    pub chief_complaint: Option<String>,
+    pub history: Option<String>,
+    pub examination: Option<String>,
+    pub assessment: Option<String>,
+    pub plan: Option<String>,
+}
+
+// A record with its coded diagnoses and the patient's clinical profile
+#[derive(Debug, Serialize)]
+pub struct MedicalRecordDetail {
+    #[serde(flatten)]
+    pub record: MedicalRecordResponse,
+    pub diagnoses: Vec<RecordDiagnosis>,
+    pub clinical_profile: Option<ClinicalProfile>, // None for records without a patient
+}
+
+// Part of a medical record an amendment changed. `Diagnosis` is the free-text summary,
+// `CodedDiagnosis` an ICD-10 diagnosis being added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum RecordSection {
+    ChiefComplaint,
+    History,
+    Examination,
+    Assessment,
+    Plan,
+    Diagnosis,
+    CodedDiagnosis,
+}
+
+// New text for some sections of a note; sections left out stay as they are. Replacing
+// text that was already written needs a reason, which goes into the amendment trail.
+#[derive(Debug, Deserialize)]
+pub struct ClinicalNoteForm {
+    pub chief_complaint: Option<String>,
+    pub history: Option<String>,
+    pub examination: Option<String>,
+    pub assessment: Option<String>,
+    pub plan: Option<String>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Amendment {
+    pub id: i32,
+    pub section: RecordSection,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub reason: Option<String>,
+    pub amended_by: Option<i32>, // tn_users.id
+    pub amended_by_name: Option<String>,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Icd10Code {
+    pub code: String,
+    pub description: String,
+}
+
+#[derive(Deserialize)]
+pub struct Icd10Query {
+    pub q: Option<String>, // code prefix or words of the description
+    pub limit: Option<i64>,
+}
+
+// Stored in tn_medical_record_diagnoses.kind; a record has at most one primary diagnosis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "varchar", rename_all = "lowercase")]
+pub enum DiagnosisKind {
+    Primary,
+    Secondary,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct RecordDiagnosis {
+    pub id: i32,
+    pub icd10_code: String,
+    pub description: String,
+    pub kind: DiagnosisKind,
+    pub note: Option<String>,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiagnosisForm {
+    pub code: String,
+    pub kind: Option<DiagnosisKind>, // secondary unless given
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RemoveDiagnosisQuery {
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -354,23 +1197,145 @@ pub struct MedicineOfPrescription {
     pub medical_record_id: Option<i32>,
     pub medicine_ids: Option<Vec<i32>>,
     pub quantity: Option<i32>,
+    pub override_reason: Option<String>,
+    pub overridden_issues: Option<Vec<String>>, // messages of the blocking issues overridden
+    pub overridden_by: Option<i32>,
+    pub create_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrescriptionForm {
+    pub medical_record_id: i32,
+    pub medicine_ids: Vec<i32>,
+    pub quantity: Option<i32>,
+    // Required to prescribe despite blocking safety issues
+    pub override_reason: Option<String>,
+}
+
+// Stored in tn_drug_interactions.severity; major and contraindicated block prescribing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum InteractionSeverity {
+    Minor,
+    Moderate,
+    Major,
+    Contraindicated,
+}
+
+impl InteractionSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InteractionSeverity::Minor => "minor",
+            InteractionSeverity::Moderate => "moderate",
+            InteractionSeverity::Major => "major",
+            InteractionSeverity::Contraindicated => "contraindicated",
+        }
+    }
+}
+
+impl std::str::FromStr for InteractionSeverity {
+    type Err = ();
+
+    fn from_str(severity: &str) -> Result<Self, Self::Err> {
+        match severity.to_ascii_lowercase().as_str() {
+            "minor" => Ok(InteractionSeverity::Minor),
+            "moderate" => Ok(InteractionSeverity::Moderate),
+            "major" => Ok(InteractionSeverity::Major),
+            "contraindicated" => Ok(InteractionSeverity::Contraindicated),
+            _ => Err(()),
+        }
+    }
+}
+
+// medicine_a is the lower id of the pair
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct DrugInteraction {
+    pub medicine_a: i32,
+    pub medicine_b: i32,
+    pub severity: InteractionSeverity,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct MedicineIngredient {
+    pub medicine_id: i32,
+    pub ingredient: String,
+    pub allergen: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrescriptionIssueKind {
+    Interaction,
+    Allergy,
+    DuplicateTherapy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrescriptionIssueLevel {
+    Blocking,
+    Warning,
+}
+
+// Something found when checking a prescription, about the medicines listed
+#[derive(Debug, Serialize)]
+pub struct PrescriptionIssue {
+    pub kind: PrescriptionIssueKind,
+    pub level: PrescriptionIssueLevel,
+    pub medicine_ids: Vec<i32>,
+    pub message: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateTreatmentStatusRequest {
-    pub treatment_status: String,
+    pub treatment_status: TreatmentStatus,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelAppointmentRequest {
+    pub reason: Option<String>,
+}
+
+// doctor_id None picks the least-loaded other doctor free at the appointment's slot
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AssignDoctorRequest {
+    pub doctor_id: Option<i32>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReassignDoctorRequest {
+    pub date: NaiveDate,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorklistQuery {
+    pub date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RescheduleAppointmentRequest {
+    pub date: NaiveDate,
+    pub appointment_time: Option<String>, // First free slot of the new date if omitted
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateStatusRequest {
-    pub status: String,
+    pub status: AppointmentStatus,
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserData {
-    pub id: i32,
+    pub id: i32,      // id in the role's profile table
+    pub user_id: i32, // account id, unique across roles
     pub name: String,
-    pub role: String,
+    pub role: UserRole,
     pub speciality_id: Option<i32>, // Optional since only doctors have this
 }
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -388,4 +1353,67 @@ pub struct UpdatePasswordRequest {
     pub current_password: String,
     pub new_password: String,
     pub email: String,
+    pub role: Option<UserRole>, // admins only: which of the email's accounts to update
+}
+
+// What happened in a security event, stored in tn_security_events.event_type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "varchar", rename_all = "snake_case")]
+pub enum SecurityEventType {
+    LoginFailed,
+    LoginBlocked,
+    AccountLocked,
+    IpLocked,
+    AccountUnlocked,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SecurityEvent {
+    pub id: i32,
+    pub event_type: SecurityEventType,
+    pub user_id: Option<i32>,
+    pub email: Option<String>,
+    pub ip: Option<String>,
+    pub detail: Option<String>,
+    pub create_at: NaiveDateTime,
+}
+
+#[derive(Deserialize)]
+pub struct SecurityEventQuery {
+    pub user_id: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+// A login as the user sees it in their list of devices
+#[derive(Debug, Serialize, FromRow)]
+pub struct Session {
+    pub id: i32,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub create_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime, // when the refresh token runs out unless used
+    pub current: bool,             // the session of the token asking
+}
+
+// A TOTP code from the authenticator or, instead, one of the recovery codes
+#[derive(Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TwoFactorPolicy {
+    pub role: UserRole,
+    pub required: bool,
+    pub update_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorPolicyRequest {
+    pub required: bool,
 }
@@ -0,0 +1,67 @@
+use crate::authentication::Claims;
+use crate::db::{medical_record, patient};
+use crate::error::Error;
+use crate::middleware::permission::Permission;
+use crate::models::UserRole;
+use actix_web::HttpResponse;
+use serde_json::json;
+
+// Ownership rule for all data tied to a patient, checked after the route's permission:
+// roles holding `patient:any` reach every patient, patients only themselves and doctors
+// only patients they have an appointment or a medical record with.
+pub async fn check_patient_access(
+    data: &crate::AppState,
+    claims: &Claims,
+    patient_id: Option<i32>,
+) -> Result<(), HttpResponse> {
+    let allowed = if claims.role.has(Permission::PatientAny) {
+        true
+    } else {
+        match (claims.role, patient_id) {
+            (UserRole::Patient, Some(patient_id)) => patient_id == claims.profile_id,
+            (UserRole::Doctor, Some(patient_id)) => {
+                patient::is_patient_of_doctor(&data.db, patient_id, claims.profile_id)
+                    .await
+                    .map_err(|e| {
+                        HttpResponse::InternalServerError().json(json!({
+                            "success": false,
+                            "message": format!("Failed to check access: {}", e)
+                        }))
+                    })?
+            }
+            _ => false,
+        }
+    };
+
+    if !allowed {
+        return Err(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "message": "You don't have access to this patient's data"
+        })));
+    }
+    Ok(())
+}
+
+// Same rule for a medical record, looked up by id; 404 if it does not exist
+pub async fn check_medical_record_access(
+    data: &crate::AppState,
+    claims: &Claims,
+    medical_record_id: i32,
+) -> Result<(), HttpResponse> {
+    let patient_id = match medical_record::get_patient_id(&data.db, medical_record_id).await {
+        Ok(patient_id) => patient_id,
+        Err(Error::NotFound) => {
+            return Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Medical record not found"
+            })));
+        }
+        Err(e) => {
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to check access: {}", e)
+            })));
+        }
+    };
+    check_patient_access(data, claims, patient_id).await
+}
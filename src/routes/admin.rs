@@ -1,7 +1,19 @@
 use crate::authentication::Claims;
-use crate::db::doctor;
-use crate::models::Doctor;
+use crate::db::{
+    appointment, authentication, doctor, login_attempt, room, schedule, security_event, service,
+    two_factor, vital_range,
+};
+use crate::error::Error;
+use crate::models::{
+    Doctor, DoctorRoomForm, DoctorServiceForm, ReassignDoctorRequest, RoomForm, RoomShiftForm,
+    RoomShiftQuery, ScheduleExceptionForm, SecurityEventQuery, SecurityEventType,
+    TwoFactorPolicyRequest, UserActiveRequest, UserRole, WorkingHourForm, AgeGroup, Vital,
+    VitalReferenceRangeRequest,
+};
+use crate::routes::queue;
+use crate::middleware::permission::{Permission, Require};
 use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::{Local, NaiveTime};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -12,18 +24,11 @@ pub struct DoctorQuery {
     order_dir: Option<String>,
 }
 
-#[get("/doctors")]
+#[get("/doctors", wrap = "Require(Permission::DoctorManage)")]
 pub async fn get_doctors(
     data: web::Data<crate::AppState>,
     query: web::Query<DoctorQuery>,
-    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    if claims.role != "admin" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Admin access required"
-        }));
-    }
     match doctor::get_doctors(
         &data.db,
         query.search.clone(),
@@ -45,7 +50,7 @@ pub async fn get_doctors(
 }
 
 // Lấy thông tin chi tiết một bác sĩ
-#[get("/doctors/{id}")]
+#[get("/doctors/{id}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn get_doctor_by_id(
     data: web::Data<crate::AppState>,
     id: web::Path<i32>,
@@ -64,7 +69,7 @@ pub async fn get_doctor_by_id(
 }
 
 // Tạo bác sĩ mới
-#[post("/doctors")]
+#[post("/doctors", wrap = "Require(Permission::DoctorManage)")]
 pub async fn create_doctor(
     data: web::Data<crate::AppState>,
     doctor: web::Json<Doctor>,
@@ -82,7 +87,7 @@ pub async fn create_doctor(
 }
 
 // Cập nhật thông tin bác sĩ
-#[put("/doctors/{email}")]
+#[put("/doctors/{email}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn update_doctor(
     data: web::Data<crate::AppState>,
     email: web::Path<String>,
@@ -101,7 +106,7 @@ pub async fn update_doctor(
 }
 
 // Xóa bác sĩ
-#[delete("/doctors/{id}")]
+#[delete("/doctors/{id}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn delete_doctor(data: web::Data<crate::AppState>, id: web::Path<i32>) -> HttpResponse {
     // Thêm hàm delete_doctor vào module db/doctor.rs
     match doctor::delete_doctor(&data.db, &id).await {
@@ -115,3 +120,688 @@ pub async fn delete_doctor(data: web::Data<crate::AppState>, id: web::Path<i32>)
         })),
     }
 }
+
+// Lịch làm việc hàng tuần của bác sĩ
+#[get("/doctors/{id}/working-hours", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn get_doctor_working_hours(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    match schedule::get_working_hours(&data.db, id.into_inner()).await {
+        Ok(hours) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": hours,
+            "message": "Working hours retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve working hours: {}", e)
+        })),
+    }
+}
+
+#[put("/doctors/{id}/working-hours", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn update_doctor_working_hours(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<Vec<WorkingHourForm>>,
+) -> HttpResponse {
+    if let Err(message) = schedule::validate_working_hours(&body) {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": message
+        }));
+    }
+    match schedule::replace_working_hours(&data.db, id.into_inner(), &body).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Working hours updated successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update working hours: {}", e)
+        })),
+    }
+}
+
+// Ngày nghỉ, nghỉ phép của bác sĩ
+#[get("/doctors/{id}/exceptions", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn get_doctor_schedule_exceptions(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    match schedule::get_exceptions(&data.db, id.into_inner()).await {
+        Ok(exceptions) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": exceptions,
+            "message": "Schedule exceptions retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve schedule exceptions: {}", e)
+        })),
+    }
+}
+
+#[post("/doctors/{id}/exceptions", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn create_doctor_schedule_exception(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<ScheduleExceptionForm>,
+) -> HttpResponse {
+    if let (Some(start), Some(end)) = (body.start_time, body.end_time) {
+        if start >= end {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Exception must start before it ends"
+            }));
+        }
+    }
+    match schedule::create_exception(&data.db, id.into_inner(), &body).await {
+        Ok(exception_id) => HttpResponse::Created().json(json!({
+            "success": true,
+            "data": exception_id,
+            "message": "Schedule exception created successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to create schedule exception: {}", e)
+        })),
+    }
+}
+
+#[delete("/doctors/{id}/exceptions/{exception_id}", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn delete_doctor_schedule_exception(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+) -> HttpResponse {
+    let (doctor_id, exception_id) = path.into_inner();
+    match schedule::delete_exception(&data.db, doctor_id, exception_id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Schedule exception deleted successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Schedule exception not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to delete schedule exception: {}", e)
+        })),
+    }
+}
+
+// Moves a doctor's open appointments on a day to other free doctors of the specialty,
+// e.g. after an unplanned absence. Appointments nobody can take are left unassigned.
+#[post("/doctors/{id}/reassign-appointments", wrap = "Require(Permission::ScheduleManage)")]
+pub async fn reassign_doctor_appointments(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<ReassignDoctorRequest>,
+) -> HttpResponse {
+    let doctor_id = id.into_inner();
+    let appointments =
+        match appointment::get_open_appointments_of_doctor(&data.db, doctor_id, body.date).await {
+            Ok(appointments) => appointments,
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to reassign appointments: {}", e)
+                }));
+            }
+        };
+
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    let mut reassigned = Vec::new();
+    let mut unassigned = Vec::new();
+    for current in appointments {
+        let (Some(appointment_id), Some(speciality_id)) = (current.id, current.speciality_id)
+        else {
+            continue;
+        };
+        let slot_start = NaiveTime::parse_from_str(&current.appointment_time, "%H:%M")
+            .ok()
+            .filter(|_| current.is_walk_in != Some(1));
+        let result = match schedule::get_working_doctors(
+            &data.db,
+            speciality_id,
+            body.date,
+            slot_start,
+        )
+        .await
+        {
+            Ok(doctors) => {
+                let doctors: Vec<i32> = doctors.into_iter().filter(|id| *id != doctor_id).collect();
+                appointment::assign_doctor(
+                    &data.db,
+                    appointment_id,
+                    appointment::Assignment::LeastLoadedOrNone,
+                    &doctors,
+                    &actor,
+                    body.reason.as_deref(),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(updated) if updated.doctor_id.is_some() => reassigned.push(json!({
+                "appointment_id": appointment_id,
+                "doctor_id": updated.doctor_id
+            })),
+            Ok(_) => unassigned.push(appointment_id),
+            // Started or cancelled in the meantime
+            Err(Error::Conflict(_)) | Err(Error::NotFound) => {}
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to reassign appointments: {}", e)
+                }));
+            }
+        }
+        queue::notify(&data, current.speciality_id, current.date);
+    }
+
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": {
+            "reassigned": reassigned,
+            "unassigned": unassigned
+        },
+        "message": "Appointments reassigned successfully"
+    }))
+}
+
+#[post("/doctors/{id}/services", wrap = "Require(Permission::DoctorManage)")]
+pub async fn attach_doctor_service(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<DoctorServiceForm>,
+) -> HttpResponse {
+    match service::attach_service_to_doctor(&data.db, id.into_inner(), body.service_id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Service attached to doctor successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Doctor or service not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to attach service: {}", e)
+        })),
+    }
+}
+
+#[delete("/doctors/{id}/services/{service_id}", wrap = "Require(Permission::DoctorManage)")]
+pub async fn detach_doctor_service(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+) -> HttpResponse {
+    let (doctor_id, service_id) = path.into_inner();
+    match service::detach_service_from_doctor(&data.db, doctor_id, service_id).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Service detached from doctor successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Doctor does not provide this service"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to detach service: {}", e)
+        })),
+    }
+}
+
+#[get("/rooms", wrap = "Require(Permission::RoomManage)")]
+pub async fn get_rooms(data: web::Data<crate::AppState>) -> HttpResponse {
+    match room::get_rooms(&data.db).await {
+        Ok(rooms) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": rooms,
+            "message": "Rooms retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve rooms: {}", e)
+        })),
+    }
+}
+
+#[get("/rooms/{id}", wrap = "Require(Permission::RoomManage)")]
+pub async fn get_room_by_id(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    match room::get_room_by_id(&data.db, id.into_inner()).await {
+        Ok(room) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": room,
+            "message": "Room retrieved successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve room: {}", e)
+        })),
+    }
+}
+
+#[post("/rooms", wrap = "Require(Permission::RoomManage)")]
+pub async fn create_room(
+    data: web::Data<crate::AppState>,
+    body: web::Json<RoomForm>,
+) -> HttpResponse {
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Room name is required"
+        }));
+    }
+    match room::create_room(&data.db, &body).await {
+        Ok(room_id) => HttpResponse::Created().json(json!({
+            "success": true,
+            "data": room_id,
+            "message": "Room created successfully"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to create room: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to create room: {}", e)
+        })),
+    }
+}
+
+#[put("/rooms/{id}", wrap = "Require(Permission::RoomManage)")]
+pub async fn update_room(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<RoomForm>,
+) -> HttpResponse {
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Room name is required"
+        }));
+    }
+    match room::update_room(&data.db, id.into_inner(), &body).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Room updated successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to update room: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update room: {}", e)
+        })),
+    }
+}
+
+#[delete("/rooms/{id}", wrap = "Require(Permission::RoomManage)")]
+pub async fn delete_room(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    match room::delete_room(&data.db, id.into_inner()).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Room deleted successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to delete room: {}", e)
+        })),
+    }
+}
+
+// Who sits in the room on a given day, defaulting to today
+#[get("/rooms/{id}/shifts", wrap = "Require(Permission::RoomManage)")]
+pub async fn get_room_shifts(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    query: web::Query<RoomShiftQuery>,
+) -> HttpResponse {
+    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+    match room::get_room_shifts(&data.db, id.into_inner(), date).await {
+        Ok(shifts) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": shifts,
+            "message": "Room shifts retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve room shifts: {}", e)
+        })),
+    }
+}
+
+#[post("/rooms/{id}/shifts", wrap = "Require(Permission::RoomManage)")]
+pub async fn create_room_shift(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<RoomShiftForm>,
+) -> HttpResponse {
+    if body.start_time >= body.end_time {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Shift must start before it ends"
+        }));
+    }
+    match room::create_room_shift(&data.db, id.into_inner(), &body).await {
+        Ok(shift_id) => {
+            queue::notify_rooms(&data, body.date);
+            HttpResponse::Created().json(json!({
+                "success": true,
+                "data": shift_id,
+                "message": "Room shift created successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room or doctor not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to create room shift: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to create room shift: {}", e)
+        })),
+    }
+}
+
+#[delete("/rooms/{id}/shifts/{shift_id}", wrap = "Require(Permission::RoomManage)")]
+pub async fn delete_room_shift(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+) -> HttpResponse {
+    let (room_id, shift_id) = path.into_inner();
+    match room::delete_room_shift(&data.db, room_id, shift_id).await {
+        Ok(date) => {
+            queue::notify_rooms(&data, date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Room shift deleted successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room shift not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to delete room shift: {}", e)
+        })),
+    }
+}
+
+// Sets or clears (room_id null) the doctor's default room
+#[put("/doctors/{id}/room", wrap = "Require(Permission::RoomManage)")]
+pub async fn set_doctor_room(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<DoctorRoomForm>,
+) -> HttpResponse {
+    match room::set_doctor_room(&data.db, id.into_inner(), body.room_id).await {
+        Ok(_) => {
+            queue::notify_rooms(&data, Local::now().date_naive());
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Doctor room updated successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Doctor or room not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to update doctor room: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update doctor room: {}", e)
+        })),
+    }
+}
+
+// Deactivating an account revokes its tokens at once; reactivating does not bring them back
+#[put("/users/{id}/active", wrap = "Require(Permission::UserManage)")]
+pub async fn set_user_active(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+    body: web::Json<UserActiveRequest>,
+) -> HttpResponse {
+    match authentication::set_user_active(&data.db, id.into_inner(), body.active).await {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "User updated successfully"
+        })),
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update user: {}", e)
+        })),
+    }
+}
+
+// Lifts a login lockout before it runs out
+#[post("/users/{id}/unlock", wrap = "Require(Permission::UserManage)")]
+pub async fn unlock_user(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    match login_attempt::unlock_user(&data.db, id).await {
+        Ok(true) => {
+            let detail = format!("unlocked by user {}", claims.sub);
+            let _ = security_event::record(
+                &data.db,
+                SecurityEventType::AccountUnlocked,
+                Some(id),
+                None,
+                None,
+                Some(&detail),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "User unlocked successfully"
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to unlock user: {}", e)
+        })),
+    }
+}
+
+#[get("/security-events", wrap = "Require(Permission::UserManage)")]
+pub async fn get_security_events(
+    data: web::Data<crate::AppState>,
+    query: web::Query<SecurityEventQuery>,
+) -> HttpResponse {
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    match security_event::list(&data.db, query.user_id, limit).await {
+        Ok(events) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": events
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to get security events: {}", e)
+        })),
+    }
+}
+
+// Clears a user's second factor, e.g. after a lost phone; they can enroll again
+#[delete("/users/{id}/two-factor", wrap = "Require(Permission::UserManage)")]
+pub async fn reset_user_two_factor(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    match two_factor::disable(&data.db, id).await {
+        Ok(true) => {
+            let detail = format!("reset by user {}", claims.sub);
+            let _ = security_event::record(
+                &data.db,
+                SecurityEventType::TwoFactorDisabled,
+                Some(id),
+                None,
+                None,
+                Some(&detail),
+            )
+            .await;
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Two-factor authentication reset successfully"
+            }))
+        }
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to reset two-factor authentication: {}", e)
+        })),
+    }
+}
+
+#[get("/two-factor-policy", wrap = "Require(Permission::UserManage)")]
+pub async fn get_two_factor_policy(data: web::Data<crate::AppState>) -> HttpResponse {
+    match two_factor::get_policies(&data.db).await {
+        Ok(policies) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": policies
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to get two-factor policy: {}", e)
+        })),
+    }
+}
+
+// Makes a second factor mandatory, or optional again, for every account of a role
+#[put("/two-factor-policy/{role}", wrap = "Require(Permission::UserManage)")]
+pub async fn set_two_factor_policy(
+    data: web::Data<crate::AppState>,
+    role: web::Path<String>,
+    body: web::Json<TwoFactorPolicyRequest>,
+) -> HttpResponse {
+    let Ok(role) = role.parse::<UserRole>() else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Unknown role"
+        }));
+    };
+    match two_factor::set_policy(&data.db, role, body.required).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Two-factor policy updated successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update two-factor policy: {}", e)
+        })),
+    }
+}
+
+#[delete("/users/{id}", wrap = "Require(Permission::UserManage)")]
+pub async fn delete_user(
+    data: web::Data<crate::AppState>,
+    id: web::Path<i32>,
+) -> HttpResponse {
+    match authentication::delete_user(&data.db, id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "User deleted successfully"
+        })),
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "User not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to delete user: {}", e)
+        })),
+    }
+}
+
+#[get("/vital-reference-ranges", wrap = "Require(Permission::VitalRangeManage)")]
+pub async fn get_vital_reference_ranges(data: web::Data<crate::AppState>) -> HttpResponse {
+    match vital_range::get_all(&data.db).await {
+        Ok(ranges) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": ranges
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to get vital reference ranges: {}", e)
+        })),
+    }
+}
+
+// Sets the normal range readings of one vital sign are flagged against for an age group,
+// e.g. `PUT /vital-reference-ranges/adult/heart_rate`. Applies to readings already taken too.
+#[put("/vital-reference-ranges/{age_group}/{vital}", wrap = "Require(Permission::VitalRangeManage)")]
+pub async fn set_vital_reference_range(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(AgeGroup, Vital)>,
+    body: web::Json<VitalReferenceRangeRequest>,
+) -> HttpResponse {
+    let (age_group, vital) = path.into_inner();
+    if body.low >= body.high {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "low must be below high"
+        }));
+    }
+    match vital_range::set(&data.db, age_group, vital, body.low, body.high).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Vital reference range updated successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update vital reference range: {}", e)
+        })),
+    }
+}
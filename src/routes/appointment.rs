@@ -1,11 +1,15 @@
 use crate::authentication::Claims;
-use crate::db::{appointment, patient};
+use crate::db::{appointment, patient, room, schedule};
+use crate::error::Error;
+use crate::routes::{access, queue};
 use crate::models::{
-    Appointment, AppointmentCreateForm, AppointmentResponse, Patient, UpdateStatusRequest,
-    UpdateTreatmentStatusRequest,
+    Appointment, AppointmentCreateForm, AssignDoctorRequest, AvailableSlot, AppointmentResponse, AppointmentStatus,
+    AvailableSlotQuery, CancelAppointmentRequest, Patient, QueuePriority, RescheduleAppointmentRequest,
+    TreatmentStatus, UpdateStatusRequest, UpdateTreatmentStatusRequest, UserRole,
 };
+use crate::middleware::permission::{Permission, Require};
 use actix_web::{get, post, put, web, HttpResponse};
-use chrono::Utc;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
 use serde_json::json;
 
 // #[get("/patient/{id}")]
@@ -17,41 +21,41 @@ use serde_json::json;
 
 // }
 
-#[post("")]
+#[post("", wrap = "Require(Permission::AppointmentCreate)")]
 pub async fn create_appointment(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
     body: web::Json<AppointmentCreateForm>,
 ) -> HttpResponse {
-    if claims.role != "staff" && claims.role != "patient" {
+    let appointment_form = body.into_inner();
+    if appointment_form.doctor_id.is_some() && claims.role == UserRole::Patient {
         return HttpResponse::Forbidden().json(json!({
             "success": false,
-            "message": "Staff access required"
+            "message": "Only staff can choose the doctor"
         }));
     }
+    if claims.role == UserRole::Patient {
+        let patient_id = Some(appointment_form.patient_id);
+        if let Err(response) = access::check_patient_access(&data, &claims, patient_id).await {
+            return response;
+        }
+    }
+    let date = appointment_form
+        .date
+        .unwrap_or_else(|| Local::now().date_naive());
 
-    let appointment_form = body.into_inner();
-    let (numerical_order, appointment_time) = match appointment::calculate_appointment_time(
-        &data.db,
-        appointment_form.date,
-        Some(appointment_form.speciality_id),
+    let (slot, doctors) = match find_slot(
+        &data,
+        appointment_form.speciality_id,
+        date,
+        appointment_form.appointment_time.as_deref(),
     )
     .await
     {
-        Ok(result) => result,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "success": false,
-                "message": format!("Failed to calculate appointment time: {}", e)
-            }));
-        }
+        Ok(found) => found,
+        Err(response) => return response,
     };
-
-    println!(
-        "appointment_time: {:?}",
-        appointment_time.format("%H:%M").to_string()
-    );
-    println!("numerical_order: {:?}", Some(numerical_order as i32));
+    let (appointment_time, slot_capacity) = (slot.time, slot.capacity);
 
     let appointment = Appointment {
         id: None,
@@ -61,23 +65,28 @@ pub async fn create_appointment(
         patient_phone: Some(appointment_form.patient_phone),
         patient_reason: Some(appointment_form.patient_reason),
         speciality_id: Some(appointment_form.speciality_id),
-        numerical_order: Some(numerical_order as i32),
-        appointment_time: appointment_time.format("%H:%M").to_string(),
-        status: Some("Unpaid".to_string()),
-        treatment_status: Some("scheduled".to_string()),
+        numerical_order: None,
+        appointment_time: appointment_time.clone(),
+        status: Some(AppointmentStatus::Unpaid),
+        treatment_status: Some(TreatmentStatus::Scheduled),
         create_at: Some(Utc::now().naive_utc()),
         update_at: Some(Utc::now().naive_utc()),
-        date: appointment_form.date,
+        date: Some(date),
+        arrived_at: None,
+        is_walk_in: Some(0),
+        priority: Some(QueuePriority::Normal),
+        doctor_id: appointment_form.doctor_id,
     };
     let pool = &data.db;
-    match appointment::create_appointment(pool, appointment).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
+    match appointment::create_appointment(pool, appointment, slot_capacity, &doctors).await {
+        Ok(created) => HttpResponse::Ok().json(json!({
             "success": true,
             "message": "Appointment created successfully",
-            "data": AppointmentResponse {
-                appointment_time: appointment_time.format("%H:%M").to_string(),
-                numerical_order: numerical_order as i32
-            }
+            "data": appointment_response(&data, &created).await
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to create appointment: {}", message)
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
             "success": false,
@@ -86,20 +95,41 @@ pub async fn create_appointment(
     }
 }
 
-#[get("/{id}")]
-pub async fn get_appointments_of_patient(
+#[get("/available-slots")]
+pub async fn get_available_slots(
     data: web::Data<crate::AppState>,
-    claims: web::ReqData<Claims>,
-    path: web::Path<i32>,
+    query: web::Query<AvailableSlotQuery>,
 ) -> HttpResponse {
-    if claims.role != "patient" {
-        return HttpResponse::Forbidden().json(json!({
+    if query.date < Local::now().date_naive() {
+        return past_date();
+    }
+    match schedule::get_available_slots(&data.db, query.speciality_id, query.date).await {
+        Ok(slots) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": slots,
+            "message": "Available slots fetched successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
             "success": false,
-            "message": "Patient access required"
-        }));
+            "message": "Specialty not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to fetch available slots: {}", e)
+        })),
     }
+}
 
+#[get("/{id}", wrap = "Require(Permission::AppointmentReadOwn)")]
+pub async fn get_appointments_of_patient(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
     let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
     match appointment::get_appointments_of_patient(&data.db, patient_id).await {
         Ok(appointments) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -113,19 +143,11 @@ pub async fn get_appointments_of_patient(
     }
 }
 
-#[get("/specialty/{specialityId}")]
+#[get("/specialty/{specialityId}", wrap = "Require(Permission::AppointmentRead)")]
 pub async fn get_appointments_by_specialty(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
-    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    if claims.role != "doctor" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Doctor access required"
-        }));
-    }
-
     let specialty_id = path.into_inner();
     let query = "SELECT DISTINCT a.* FROM tn_appointments a 
                 INNER JOIN tn_doctors d ON d.speciality_id = a.speciality_id 
@@ -153,27 +175,41 @@ pub async fn get_appointments_by_specialty(
     }))
 }
 
-#[put("/status/{id}")]
+#[put("/status/{id}", wrap = "Require(Permission::AppointmentUpdateStatus)")]
 pub async fn update_appointment_status(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
     claims: web::ReqData<Claims>,
     body: web::Json<UpdateStatusRequest>,
 ) -> HttpResponse {
-    // if claims.role != "doctor" {
-    //     return HttpResponse::Forbidden().json(json!({
-    //         "success": false,
-    //         "message": "Doctor access required"
-    //     }));
-    // }
-    let new_status = body.into_inner().status;
+    let body = body.into_inner();
     let appointment_id = path.into_inner();
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
 
-    match appointment::update_appointment_status(&data.db, appointment_id, new_status).await {
+    match appointment::update_appointment_status(
+        &data.db,
+        appointment_id,
+        body.status,
+        &actor,
+        body.reason.as_deref(),
+    )
+    .await
+    {
         Ok(_) => HttpResponse::Ok().json(json!({
             "success": true,
             "message": "Status updated successfully"
         })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Appointment not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to update status: {}", message)
+        })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
             "success": false,
             "message": format!("Failed to update status: {}", e)
@@ -181,32 +217,43 @@ pub async fn update_appointment_status(
     }
 }
 
-#[put("/treatment-status/{id}")]
+#[put("/treatment-status/{id}", wrap = "Require(Permission::AppointmentUpdateTreatmentStatus)")]
 pub async fn update_appointment_treatment_status(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
     body: web::Json<UpdateTreatmentStatusRequest>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    // if claims.role != "doctor" {
-    //     return HttpResponse::Forbidden().json(json!({
-    //         "success": false,
-    //         "message": "Doctor access required"
-    //     }));
-    // }
-
+    let body = body.into_inner();
     let appointment_id = path.into_inner();
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
 
     match appointment::update_appointment_treatment_status(
         &data.db,
         appointment_id,
-        body.treatment_status.clone(),
+        body.treatment_status,
+        &actor,
+        body.reason.as_deref(),
     )
     .await
     {
-        Ok(_) => HttpResponse::Ok().json(json!({
-            "success": true,
-            "message": "Treatment status updated successfully"
+        Ok(updated) => {
+            queue::notify(&data, updated.speciality_id, updated.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Treatment status updated successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Appointment not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to update treatment status: {}", message)
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
             "success": false,
@@ -214,14 +261,56 @@ pub async fn update_appointment_treatment_status(
         })),
     }
 }
-#[get("/history/self")]
-pub async fn get_self_appointments(
+
+#[get("/{id}/history", wrap = "Require(Permission::AppointmentReadHistory)")]
+pub async fn get_appointment_status_history(
     data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    let patient_id = claims.sub.parse::<i32>().unwrap();
-    println!("patient_id at get_self_appointments: {:?}", patient_id);
+    let appointment_id = path.into_inner();
+
+    let appointment = match appointment::get_appointment_by_id(&data.db, appointment_id).await {
+        Ok(appointment) => appointment,
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Appointment not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to fetch appointment history: {}", e)
+            }));
+        }
+    };
+
+    if let Err(response) =
+        access::check_patient_access(&data, &claims, Some(appointment.patient_id)).await
+    {
+        return response;
+    }
+
+    match appointment::get_appointment_records(&data.db, appointment_id).await {
+        Ok(records) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Appointment history fetched successfully",
+            "data": records
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to fetch appointment history: {}", e)
+        })),
+    }
+}
 
+#[get("/history/self", wrap = "Require(Permission::AppointmentReadOwn)")]
+pub async fn get_self_appointments(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let patient_id = claims.profile_id;
     match appointment::get_appointment_history(&data.db, patient_id).await {
         Ok(appointments) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -234,3 +323,421 @@ pub async fn get_self_appointments(
         })),
     }
 }
+
+#[post("/{id}/cancel", wrap = "Require(Permission::AppointmentChange)")]
+pub async fn cancel_appointment(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<CancelAppointmentRequest>,
+) -> HttpResponse {
+    let appointment_id = path.into_inner();
+    let appointment = match appointment::get_appointment_by_id(&data.db, appointment_id).await {
+        Ok(appointment) => appointment,
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Appointment not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to cancel appointment: {}", e)
+            }));
+        }
+    };
+    if let Err(response) = check_can_change(&data, &claims, &appointment).await {
+        return response;
+    }
+
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    match appointment::update_appointment_treatment_status(
+        &data.db,
+        appointment_id,
+        TreatmentStatus::Cancelled,
+        &actor,
+        body.reason.as_deref(),
+    )
+    .await
+    {
+        Ok(updated) => {
+            queue::notify(&data, updated.speciality_id, updated.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Appointment cancelled successfully"
+            }))
+        }
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to cancel appointment: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to cancel appointment: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/reschedule", wrap = "Require(Permission::AppointmentChange)")]
+pub async fn reschedule_appointment(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<RescheduleAppointmentRequest>,
+) -> HttpResponse {
+    let appointment_id = path.into_inner();
+    let body = body.into_inner();
+    let appointment = match appointment::get_appointment_by_id(&data.db, appointment_id).await {
+        Ok(appointment) => appointment,
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Appointment not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to reschedule appointment: {}", e)
+            }));
+        }
+    };
+    if let Err(response) = check_can_change(&data, &claims, &appointment).await {
+        return response;
+    }
+    let Some(speciality_id) = appointment.speciality_id else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Appointment has no specialty to reschedule within"
+        }));
+    };
+
+    let (slot, doctors) =
+        match find_slot(&data, speciality_id, body.date, body.appointment_time.as_deref()).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        };
+    // The old time was checked above; the new one must not dodge the same rules
+    if let Ok(time) = NaiveTime::parse_from_str(&slot.time, "%H:%M") {
+        let cutoff_hours =
+            (claims.role == UserRole::Patient).then_some(data.appointment_change_cutoff_hours);
+        let new_time =
+            check_new_time(body.date.and_time(time), Local::now().naive_local(), cutoff_hours);
+        if let Err(message) = new_time {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": message
+            }));
+        }
+    }
+
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    match appointment::reschedule_appointment(
+        &data.db,
+        appointment_id,
+        body.date,
+        &slot,
+        &doctors,
+        &actor,
+        body.reason.as_deref(),
+    )
+    .await
+    {
+        Ok(updated) => {
+            queue::notify(&data, appointment.speciality_id, appointment.date);
+            queue::notify(&data, appointment.speciality_id, Some(body.date));
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Appointment rescheduled successfully",
+                "data": appointment_response(&data, &updated).await
+            }))
+        }
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to reschedule appointment: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to reschedule appointment: {}", e)
+        })),
+    }
+}
+
+// Assigns the appointment to a doctor, or with no doctor_id moves it to the least-loaded
+// other doctor free at its slot, e.g. when the assigned doctor is unavailable
+#[put("/{id}/doctor", wrap = "Require(Permission::AppointmentAssignDoctor)")]
+pub async fn assign_doctor(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<AssignDoctorRequest>,
+) -> HttpResponse {
+    let appointment_id = path.into_inner();
+    let appointment = match appointment::get_appointment_by_id(&data.db, appointment_id).await {
+        Ok(appointment) => appointment,
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Appointment not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to assign doctor: {}", e)
+            }));
+        }
+    };
+    let (Some(speciality_id), Some(date)) = (appointment.speciality_id, appointment.date) else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Appointment has no specialty or date"
+        }));
+    };
+
+    // Walk-ins have no slot of their own, any doctor working that day will do
+    let slot = Some(appointment.appointment_time.as_str())
+        .filter(|_| appointment.is_walk_in != Some(1));
+    let doctors = match schedule::get_working_doctors(
+        &data.db,
+        speciality_id,
+        date,
+        slot.and_then(|time| NaiveTime::parse_from_str(time, "%H:%M").ok()),
+    )
+    .await
+    {
+        // Asking for someone else means not the current doctor
+        Ok(doctors) => doctors
+            .into_iter()
+            .filter(|id| body.doctor_id.is_some() || Some(*id) != appointment.doctor_id)
+            .collect::<Vec<i32>>(),
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to assign doctor: {}", e)
+            }));
+        }
+    };
+
+    let actor = appointment::Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    let assignment = match body.doctor_id {
+        Some(doctor_id) => appointment::Assignment::To(doctor_id),
+        None => appointment::Assignment::LeastLoaded,
+    };
+    match appointment::assign_doctor(
+        &data.db,
+        appointment_id,
+        assignment,
+        &doctors,
+        &actor,
+        body.reason.as_deref(),
+    )
+    .await
+    {
+        Ok(updated) => {
+            queue::notify(&data, updated.speciality_id, updated.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": updated,
+                "message": "Doctor assigned successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Appointment not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to assign doctor: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to assign doctor: {}", e)
+        })),
+    }
+}
+
+// The requested free slot, or the earliest one when no time is given, together with
+// the doctors working it. Past dates have none.
+pub async fn find_slot(
+    data: &crate::AppState,
+    speciality_id: i32,
+    date: NaiveDate,
+    appointment_time: Option<&str>,
+) -> Result<(AvailableSlot, Vec<i32>), HttpResponse> {
+    if date < Local::now().date_naive() {
+        return Err(past_date());
+    }
+    let slots = match schedule::get_available_slots(&data.db, speciality_id, date).await {
+        Ok(slots) => slots,
+        Err(Error::NotFound) => {
+            return Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Specialty not found"
+            })));
+        }
+        Err(e) => {
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to calculate appointment time: {}", e)
+            })));
+        }
+    };
+
+    let slot = match appointment_time {
+        Some(time) => slots.into_iter().find(|slot| slot.time == time),
+        None => slots.into_iter().next(),
+    };
+    let Some(slot) = slot else {
+        return Err(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "No free slot within working hours for the requested date and time"
+        })));
+    };
+
+    let slot_start = NaiveTime::parse_from_str(&slot.time, "%H:%M").ok();
+    match schedule::get_working_doctors(&data.db, speciality_id, date, slot_start).await {
+        Ok(doctors) => Ok((slot, doctors)),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to calculate appointment time: {}", e)
+        }))),
+    }
+}
+
+fn past_date() -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": "Appointments cannot be made for past dates"
+    }))
+}
+
+// Patients may only change their own appointments and not later than the configured
+// cutoff before the visit; the other roles allowed on the route may change any appointment.
+async fn check_can_change(
+    data: &crate::AppState,
+    claims: &Claims,
+    appointment: &Appointment,
+) -> Result<(), HttpResponse> {
+    access::check_patient_access(data, claims, Some(appointment.patient_id)).await?;
+    if claims.role != UserRole::Patient {
+        return Ok(());
+    }
+
+    let time = NaiveTime::parse_from_str(&appointment.appointment_time, "%H:%M").ok();
+    if let (Some(date), Some(time)) = (appointment.date, time) {
+        let hours = data.appointment_change_cutoff_hours;
+        if within_cutoff(date.and_time(time), Local::now().naive_local(), hours) {
+            return Err(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": cutoff_message(hours)
+            })));
+        }
+    }
+    Ok(())
+}
+
+// Whether an appointment may be moved to `start`: not into the past, and with
+// `cutoff_hours`, i.e. for patients, not to within the cutoff either
+fn check_new_time(
+    start: NaiveDateTime,
+    now: NaiveDateTime,
+    cutoff_hours: Option<i64>,
+) -> Result<(), String> {
+    if start <= now {
+        return Err("Appointments cannot be moved into the past".to_string());
+    }
+    match cutoff_hours {
+        Some(hours) if within_cutoff(start, now, hours) => Err(cutoff_message(hours)),
+        _ => Ok(()),
+    }
+}
+
+fn within_cutoff(start: NaiveDateTime, now: NaiveDateTime, cutoff_hours: i64) -> bool {
+    start - now < Duration::hours(cutoff_hours)
+}
+
+fn cutoff_message(cutoff_hours: i64) -> String {
+    format!(
+        "Appointments can only be changed up to {} hours in advance",
+        cutoff_hours
+    )
+}
+
+// The slot, number and room the patient should go to. The room is informational, so a
+// failed lookup leaves it empty rather than failing the request.
+async fn appointment_response(
+    data: &web::Data<crate::AppState>,
+    appointment: &Appointment,
+) -> AppointmentResponse {
+    let room = match (
+        appointment.doctor_id,
+        appointment.date,
+        NaiveTime::parse_from_str(&appointment.appointment_time, "%H:%M"),
+    ) {
+        (Some(doctor_id), Some(date), Ok(time)) => room::get_doctor_room_at(&data.db, doctor_id, date, time)
+            .await
+            .ok()
+            .flatten(),
+        _ => None,
+    };
+    AppointmentResponse {
+        appointment_time: appointment.appointment_time.clone(),
+        numerical_order: appointment.numerical_order.unwrap_or_default(),
+        doctor_id: appointment.doctor_id,
+        room,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn appointments_are_not_moved_into_the_past() {
+        let now = at("2026-10-18 10:00");
+        for start in ["2026-10-17 15:00", "2026-10-18 09:30", "2026-10-18 10:00"] {
+            assert_eq!(
+                check_new_time(at(start), now, None),
+                Err("Appointments cannot be moved into the past".to_string()),
+                "{}",
+                start
+            );
+        }
+        assert_eq!(check_new_time(at("2026-10-18 10:30"), now, None), Ok(()));
+    }
+
+    #[test]
+    fn patients_cannot_move_appointments_to_within_the_cutoff() {
+        let now = at("2026-10-18 10:00");
+        assert_eq!(
+            check_new_time(at("2026-10-19 09:30"), now, Some(24)),
+            Err("Appointments can only be changed up to 24 hours in advance".to_string())
+        );
+        assert_eq!(check_new_time(at("2026-10-19 10:00"), now, Some(24)), Ok(()));
+        // Staff pass no cutoff
+        assert_eq!(check_new_time(at("2026-10-18 10:30"), now, None), Ok(()));
+    }
+
+    #[test]
+    fn the_cutoff_is_measured_from_now() {
+        let now = at("2026-10-18 10:00");
+        assert!(within_cutoff(at("2026-10-18 12:00"), now, 3));
+        assert!(!within_cutoff(at("2026-10-18 13:00"), now, 3));
+        assert!(within_cutoff(at("2026-10-17 13:00"), now, 0));
+    }
+}
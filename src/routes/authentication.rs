@@ -1,69 +1,161 @@
 use std::ptr::null;
 
-use crate::db::{authentication, doctor, patient};
+use crate::db::{
+    authentication, doctor, login_attempt, patient, security_event, session, token, two_factor,
+};
+use crate::error::Error;
+use crate::jwt_keys::ISSUER;
+use crate::mailer::{Email, MailError};
+use crate::middleware::permission::{Permission, Require};
 use crate::models::{
-    LoginRequest, LoginResponse, PasswordResetRequest, RegisterRequest, TokenData,
-    UpdatePasswordRequest, UserData,
+    Account, LoginRequest, LoginResponse, LogoutRequest, PasswordResetConfirm,
+    PasswordResetRequest, RefreshRequest, RegisterRequest, ResendVerificationRequest,
+    SecurityEventType, TokenData, UpdatePasswordRequest, UserData, UserRole, VerifyEmailQuery,
 };
-use actix_web::{get, post, put, web, HttpResponse};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 use bcrypt::{hash, DEFAULT_COST};
-use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use lettre::message::header::ContentType;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
 use rand::distributions::{Alphanumeric, DistString};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
-    pub sub: String,
+    pub sub: String, // account id in tn_users, unique across roles
     pub name: String,
-    pub role: String,
+    pub role: UserRole,
+    pub profile_id: i32, // id in the role's profile table, e.g. tn_patients.id
+    pub jti: String,     // token id, what logout puts on the revocation list
+    pub kind: TokenKind,
+    pub iss: String,
+    pub aud: String, // follows from `kind`, for services that only look at standard claims
+    // Session the token belongs to; second-factor-pending tokens have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<i32>,
+    pub iat: i64,
     pub exp: i64,
 }
 
+// What a token is good for. A password alone only earns a second-factor-pending token
+// when the account uses two-factor authentication, and `AuthMiddleware` turns those away
+// everywhere but the /api/2fa routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenKind {
+    Access,
+    SecondFactorPending,
+}
+
+impl TokenKind {
+    // The header `typ` of tokens of this kind
+    pub fn typ(self) -> &'static str {
+        match self {
+            TokenKind::Access => "at+jwt",
+            TokenKind::SecondFactorPending => "2fa-pending+jwt",
+        }
+    }
+
+    pub fn audience(self) -> &'static str {
+        match self {
+            TokenKind::Access => "hms-api",
+            TokenKind::SecondFactorPending => "hms-2fa",
+        }
+    }
+}
+
+// How long a user has to enter the second factor after the password
+const SECOND_FACTOR_TTL_MINUTES: i64 = 5;
+
+// Where a pair of tokens comes from: a login, which starts a new session, or a refresh
+// token, which continues its session
+pub enum SessionStart<'a> {
+    Login(&'a HttpRequest),
+    Refresh { session_id: i32, refresh_token: String },
+}
+
 fn verify_password(password: &str, hash: &str) -> bool {
     bcrypt::verify(password, hash).unwrap_or(false)
 }
 
 #[post("/login")]
 pub async fn login(
+    req: HttpRequest,
     data: web::Data<crate::AppState>,
     login_req: web::Json<LoginRequest>,
 ) -> HttpResponse {
     let pool = &data.db;
+    let ip = client_ip(&req, data.trust_forwarded_for);
 
-    println!("login request: {:?}", login_req);
+    // Locked addresses and accounts are turned away before the password is looked at
+    match login_attempt::ip_locked_until(pool, &ip).await {
+        Ok(Some(locked_until)) => {
+            let _ = security_event::record(
+                pool,
+                SecurityEventType::LoginBlocked,
+                None,
+                Some(&login_req.email),
+                Some(&ip),
+                Some("address locked"),
+            )
+            .await;
+            return too_many_attempts(locked_until);
+        }
+        Ok(None) => {}
+        Err(_) => return login_database_error(),
+    }
 
     // Query the database using the authentication module
-    let credentials: (i32, String, String, Option<i32>) =
-        match authentication::get_user_credentials(pool, &login_req).await {
-            Ok(Some(creds)) => creds,
-            Ok(None) => {
-                return HttpResponse::Unauthorized().json(LoginResponse {
-                    success: false,
-                    message: "Wrong email or password".to_string(),
-                    data: None,
-                    user_data: None,
-                });
-            }
-            Err(_) => {
-                return HttpResponse::InternalServerError().json(LoginResponse {
-                    success: false,
-                    message: "Database error".to_string(),
-                    data: None,
-                    user_data: None,
-                });
+    let account = match authentication::get_user_credentials(
+        pool,
+        &login_req.email,
+        login_req.login_type,
+    )
+    .await
+    {
+        Ok(Some(account)) => account,
+        Ok(None) => {
+            if record_failed_login(&data, None, Some(&login_req.email), &ip, "unknown account")
+                .await
+                .is_err()
+            {
+                return login_database_error();
             }
-        };
+            return HttpResponse::Unauthorized().json(LoginResponse {
+                success: false,
+                message: "Wrong email or password".to_string(),
+                data: None,
+                user_data: None,
+            });
+        }
+        Err(_) => return login_database_error(),
+    };
 
-    // Check if user exists
-    let (id, hashed_password, name, speciality_id) = credentials;
+    if let Some(locked_until) = account
+        .locked_until
+        .filter(|locked_until| *locked_until > Utc::now().naive_utc())
+    {
+        let _ = security_event::record(
+            pool,
+            SecurityEventType::LoginBlocked,
+            Some(account.user_id),
+            Some(&login_req.email),
+            Some(&ip),
+            Some("account locked"),
+        )
+        .await;
+        return too_many_attempts(locked_until);
+    }
 
     // Verify password
-    if !verify_password(&login_req.password, &hashed_password) {
+    if !verify_password(&login_req.password, &account.password) {
+        if record_failed_login(&data, Some(account.user_id), Some(&login_req.email), &ip, "wrong password")
+            .await
+            .is_err()
+        {
+            return login_database_error();
+        }
         return HttpResponse::Unauthorized().json(LoginResponse {
             success: false,
             message: "Invalid credentials".to_string(),
@@ -71,52 +163,443 @@ pub async fn login(
             user_data: None,
         });
     }
+    if account.active == 0 {
+        return HttpResponse::Forbidden().json(LoginResponse {
+            success: false,
+            message: "Account is disabled".to_string(),
+            data: None,
+            user_data: None,
+        });
+    }
+    if account.verified == 0 {
+        return HttpResponse::Forbidden().json(LoginResponse {
+            success: false,
+            message: "Email address is not verified".to_string(),
+            data: None,
+            user_data: None,
+        });
+    }
 
-    // Generate JWT token
-    let claims = Claims {
-        sub: id.to_string(),
-        name: name.clone(),
-        role: login_req.login_type.clone(),
-        exp: (Utc::now() + Duration::hours(24)).timestamp(),
+    // With a second factor, or one the role must set up, the password only earns a
+    // short-lived token for the /api/2fa routes
+    let second_factor_required = match two_factor::is_required(pool, account.role).await {
+        Ok(required) => required,
+        Err(_) => return login_database_error(),
+    };
+    if account.totp_enabled == 1 || second_factor_required {
+        let enrolled = account.totp_enabled == 1;
+        let token = sign_token(
+            &data,
+            &account,
+            TokenKind::SecondFactorPending,
+            None,
+            Duration::minutes(SECOND_FACTOR_TTL_MINUTES),
+        );
+        return HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": if enrolled {
+                "Enter the code from your authenticator app"
+            } else {
+                "Two-factor authentication is required; set it up to finish signing in"
+            },
+            "second_factor_required": true,
+            "enrollment_required": !enrolled,
+            "second_factor_token": token,
+            "expires_in": SECOND_FACTOR_TTL_MINUTES * 60
+        }));
+    }
+
+    // Failures only start over once the whole sign-in has succeeded, so the password
+    // alone cannot reset the count of wrong second factors
+    if login_attempt::clear_account_failures(pool, account.user_id).await.is_err() {
+        return login_database_error();
+    }
+    match issue_tokens(&data, &account, SessionStart::Login(&req)).await {
+        Ok(response) => response,
+        Err(e) => HttpResponse::InternalServerError().json(LoginResponse {
+            success: false,
+            message: format!("Failed to issue tokens: {}", e),
+            data: None,
+            user_data: None,
+        }),
+    }
+}
+
+// Behind a reverse proxy every request comes from the proxy, so the client address is
+// taken from X-Forwarded-For; only when told to, since clients can set that header.
+pub fn client_ip(req: &HttpRequest, trust_forwarded_for: bool) -> String {
+    let ip = if trust_forwarded_for {
+        req.connection_info().realip_remote_addr().map(str::to_string)
+    } else {
+        req.peer_addr().map(|addr| addr.ip().to_string())
     };
+    ip.unwrap_or_else(|| "unknown".to_string())
+}
 
-    println!("{:?}", claims);
+// The User-Agent header, cut to fit the sessions table; `to_str` only passes ASCII, so
+// any byte is a valid place to cut
+fn user_agent(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| &user_agent[..user_agent.len().min(512)])
+}
 
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(data.jwt_secret.as_ref()),
+// Counts the failure against the address and, when known, the account, and records what
+// happened, including any lockout it started
+pub async fn record_failed_login(
+    data: &crate::AppState,
+    user_id: Option<i32>,
+    email: Option<&str>,
+    ip: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    let pool = &data.db;
+    security_event::record(
+        pool,
+        SecurityEventType::LoginFailed,
+        user_id,
+        email,
+        Some(ip),
+        Some(reason),
     )
-    .unwrap();
+    .await?;
 
-    HttpResponse::Ok().json(LoginResponse {
+    if let Some(locked_until) = login_attempt::record_ip_failure(pool, ip, &data.lockout_policy).await? {
+        let detail = format!("locked until {}", locked_until);
+        security_event::record(pool, SecurityEventType::IpLocked, None, email, Some(ip), Some(&detail))
+            .await?;
+    }
+    if let Some(user_id) = user_id {
+        if let Some(locked_until) =
+            login_attempt::record_account_failure(pool, user_id, &data.lockout_policy).await?
+        {
+            let detail = format!("locked until {}", locked_until);
+            security_event::record(
+                pool,
+                SecurityEventType::AccountLocked,
+                Some(user_id),
+                email,
+                Some(ip),
+                Some(&detail),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+pub fn too_many_attempts(locked_until: NaiveDateTime) -> HttpResponse {
+    let retry_after = (locked_until - Utc::now().naive_utc()).num_seconds().max(1);
+    HttpResponse::TooManyRequests()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(LoginResponse {
+            success: false,
+            message: "Too many failed login attempts; try again later".to_string(),
+            data: None,
+            user_data: None,
+        })
+}
+
+pub fn login_database_error() -> HttpResponse {
+    HttpResponse::InternalServerError().json(LoginResponse {
+        success: false,
+        message: "Database error".to_string(),
+        data: None,
+        user_data: None,
+    })
+}
+
+// Trades a refresh token for a new access token and a new refresh token. The old refresh
+// token stops working; using it again revokes every session of the account.
+#[post("/auth/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    data: web::Data<crate::AppState>,
+    body: web::Json<RefreshRequest>,
+) -> HttpResponse {
+    let refresh_token = new_token();
+    let expires_at = (Utc::now() + Duration::days(data.refresh_token_ttl_days)).naive_utc();
+    let (user_id, session_id) = match token::rotate_refresh_token(
+        &data.db,
+        &hash_token(&body.refresh_token),
+        &hash_token(&refresh_token),
+        expires_at,
+        user_agent(&req),
+        &client_ip(&req, data.trust_forwarded_for),
+    )
+    .await
+    {
+        Ok(rotated) => rotated,
+        Err(Error::NotFound) | Err(Error::Conflict(_)) => {
+            return HttpResponse::Unauthorized().json(LoginResponse {
+                success: false,
+                message: "Invalid or expired refresh token".to_string(),
+                data: None,
+                user_data: None,
+            });
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(LoginResponse {
+                success: false,
+                message: format!("Failed to refresh token: {}", e),
+                data: None,
+                user_data: None,
+            });
+        }
+    };
+
+    let account = match authentication::get_account_by_user_id(&data.db, user_id).await {
+        Ok(Some(account)) if account.active == 1 => account,
+        Ok(_) => {
+            return HttpResponse::Unauthorized().json(LoginResponse {
+                success: false,
+                message: "Account is disabled".to_string(),
+                data: None,
+                user_data: None,
+            });
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(LoginResponse {
+                success: false,
+                message: "Database error".to_string(),
+                data: None,
+                user_data: None,
+            });
+        }
+    };
+
+    match issue_tokens(
+        &data,
+        &account,
+        SessionStart::Refresh {
+            session_id,
+            refresh_token,
+        },
+    )
+    .await
+    {
+        Ok(response) => response,
+        Err(e) => HttpResponse::InternalServerError().json(LoginResponse {
+            success: false,
+            message: format!("Failed to issue tokens: {}", e),
+            data: None,
+            user_data: None,
+        }),
+    }
+}
+
+// Revokes the calling access token and ends its session along with the session's refresh
+// token. With `all` every session of the account ends.
+#[post("/logout")]
+pub async fn logout(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: Option<web::Json<LogoutRequest>>,
+) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "Invalid token"
+        }));
+    };
+    let expires_at = DateTime::from_timestamp(claims.exp, 0)
+        .unwrap_or_else(Utc::now)
+        .naive_utc();
+
+    let body = body.map(|body| body.into_inner());
+    let result = async {
+        token::revoke_access_token(&data.db, &claims.jti, user_id, expires_at).await?;
+        if let Some(session_id) = claims.sid {
+            session::revoke(&data.db, user_id, session_id).await?;
+        }
+        if let Some(refresh_token) = body.as_ref().and_then(|body| body.refresh_token.as_deref()) {
+            token::revoke_refresh_token(&data.db, user_id, &hash_token(refresh_token)).await?;
+        }
+        if body.as_ref().and_then(|body| body.all) == Some(true) {
+            token::revoke_user_tokens(&data.db, user_id).await?;
+        }
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Logged out successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to log out: {}", e)
+        })),
+    }
+}
+
+// Where the user is logged in: one entry per login that has not ended or expired
+#[get("/sessions")]
+pub async fn list_sessions(data: web::Data<crate::AppState>, claims: web::ReqData<Claims>) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "Invalid token"
+        }));
+    };
+    match session::list(&data.db, user_id, claims.sid).await {
+        Ok(sessions) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": sessions
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to get sessions: {}", e)
+        })),
+    }
+}
+
+// Logs one of the user's own sessions out, e.g. a lost phone; its tokens stop working
+// right away
+#[delete("/sessions/{id}")]
+pub async fn revoke_session(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    session_id: web::Path<i32>,
+) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "Invalid token"
+        }));
+    };
+    match session::revoke(&data.db, user_id, session_id.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Session revoked"
+        })),
+        Ok(false) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Session not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to revoke session: {}", e)
+        })),
+    }
+}
+
+// Signs a short-lived access token for the account and pairs it with a refresh token.
+// A login records a new session and stores its refresh token; a refresh already rotated it.
+pub async fn issue_tokens(
+    data: &crate::AppState,
+    account: &Account,
+    start: SessionStart<'_>,
+) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(create_session(data, account, start).await?))
+}
+
+pub async fn create_session(
+    data: &crate::AppState,
+    account: &Account,
+    start: SessionStart<'_>,
+) -> Result<LoginResponse, Error> {
+    let (session_id, refresh_token) = match start {
+        SessionStart::Refresh {
+            session_id,
+            refresh_token,
+        } => (session_id, refresh_token),
+        SessionStart::Login(req) => {
+            let refresh_token = new_token();
+            let expires_at = (Utc::now() + Duration::days(data.refresh_token_ttl_days)).naive_utc();
+            let ip = client_ip(req, data.trust_forwarded_for);
+            let session_id =
+                session::create(&data.db, account.user_id, user_agent(req), &ip, expires_at).await?;
+            token::create_refresh_token(
+                &data.db,
+                account.user_id,
+                session_id,
+                &hash_token(&refresh_token),
+                expires_at,
+            )
+            .await?;
+            (session_id, refresh_token)
+        }
+    };
+
+    let access_token = sign_token(
+        data,
+        account,
+        TokenKind::Access,
+        Some(session_id),
+        Duration::minutes(data.access_token_ttl_minutes),
+    );
+
+    Ok(LoginResponse {
         success: true,
         message: "Login successful".to_string(),
         data: Some(TokenData {
-            access_token: token,
+            access_token,
             token_type: "Bearer".to_string(),
-            expires_in: 86400, // 24 hours in seconds
+            expires_in: data.access_token_ttl_minutes * 60,
+            refresh_token,
+            refresh_expires_in: data.refresh_token_ttl_days * 86400,
         }),
         user_data: Some(UserData {
-            id,
-            name,
-            role: login_req.login_type.clone(),
-            speciality_id,
+            id: account.profile_id,
+            user_id: account.user_id,
+            name: account.name.clone().unwrap_or_default(),
+            role: account.role,
+            speciality_id: account.speciality_id,
         }),
     })
 }
 
+fn sign_token(
+    data: &crate::AppState,
+    account: &Account,
+    kind: TokenKind,
+    session_id: Option<i32>,
+    ttl: Duration,
+) -> String {
+    let now = Utc::now();
+    let claims = Claims {
+        sub: account.user_id.to_string(),
+        name: account.name.clone().unwrap_or_default(),
+        role: account.role,
+        profile_id: account.profile_id,
+        jti: Alphanumeric.sample_string(&mut rand::thread_rng(), 32),
+        kind,
+        iss: ISSUER.to_string(),
+        aud: kind.audience().to_string(),
+        sid: session_id,
+        iat: now.timestamp(),
+        exp: (now + ttl).timestamp(),
+    };
+
+    data.jwt_keys.sign(&claims, kind.typ()).unwrap()
+}
+
+// Random secret for refresh and password reset tokens
+fn new_token() -> String {
+    Alphanumeric.sample_string(&mut rand::thread_rng(), 64)
+}
+
+// Refresh and reset tokens are stored hashed, so a database leak does not hand out accounts
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Public sign-up, for patients only; other roles are created by an administrator through
+// `admin_create_user`. The account stays locked until the emailed link is opened.
 #[post("/register")]
 pub async fn register(
     data: web::Data<crate::AppState>,
     register_req: web::Json<RegisterRequest>,
 ) -> HttpResponse {
-    // if register_req.role != "patient" {
-    //     return HttpResponse::BadRequest().json(json!({
-    //         "success": false,
-    //         "message": "Only patient is supported for registration"
-    //     }));
-    // }
+    if register_req.role != UserRole::Patient {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Only patients can register; other accounts are created by an administrator"
+        }));
+    }
 
     let pool = &data.db;
     // Hash the password
@@ -133,111 +616,245 @@ pub async fn register(
     };
 
     // Attempt to create the user
-    match authentication::create_user(
+    let user_id = match authentication::create_user(
         pool,
         &register_req.email,
         &hashed_password,
         &register_req.name,
-        &register_req.role,
+        UserRole::Patient,
+        false,
     )
     .await
     {
-        Ok(_) => HttpResponse::Ok().json(LoginResponse {
-            success: true,
-            message: "User registered successfully".to_string(),
-            data: None,
-            user_data: None,
-        }),
+        Ok((user_id, _)) => user_id,
         Err(e) => {
             // You might want to handle different error types differently
-            HttpResponse::BadRequest().json(LoginResponse {
+            return HttpResponse::BadRequest().json(LoginResponse {
                 success: false,
                 message: format!("Registration failed: {}", e),
                 data: None,
                 user_data: None,
-            })
+            });
         }
+    };
+
+    let message = match send_verification_email(&data, user_id, &register_req.email).await {
+        Ok(()) => "User registered successfully; check your email to verify the account",
+        Err(_) => "User registered, but the verification email could not be sent; request a new one",
+    };
+    HttpResponse::Ok().json(LoginResponse {
+        success: true,
+        message: message.to_string(),
+        data: None,
+        user_data: None,
+    })
+}
+
+// Payload of an email verification link. Its own `typ` and `aud` keep it from being
+// mistaken for any other token signed with the same key.
+#[derive(Debug, Serialize, Deserialize)]
+struct VerificationClaims {
+    sub: String,
+    iss: String,
+    aud: String,
+    exp: i64,
+}
+
+const VERIFY_EMAIL_TYP: &str = "email-verification+jwt";
+const VERIFY_EMAIL_AUDIENCE: &str = "hms-email-verification";
+
+async fn send_verification_email(
+    data: &crate::AppState,
+    user_id: i32,
+    to: &str,
+) -> Result<(), MailError> {
+    let claims = VerificationClaims {
+        sub: user_id.to_string(),
+        iss: ISSUER.to_string(),
+        aud: VERIFY_EMAIL_AUDIENCE.to_string(),
+        exp: (Utc::now() + Duration::hours(data.email_verification_ttl_hours)).timestamp(),
+    };
+    let token = data
+        .jwt_keys
+        .sign(&claims, VERIFY_EMAIL_TYP)
+        .map_err(|e| MailError(e.to_string()))?;
+
+    let email = Email {
+        to: to.to_string(),
+        subject: "Verify your email".to_string(),
+        body: format!(
+            "Open this link to activate your account:\n{}?token={}\nThe link expires in {} hours.",
+            data.email_verification_url, token, data.email_verification_ttl_hours
+        ),
+    };
+    let mailer = data.mailer.clone();
+    web::block(move || mailer.send(&email))
+        .await
+        .map_err(|e| MailError(e.to_string()))?
+}
+
+#[get("/verify-email")]
+pub async fn verify_email(
+    data: web::Data<crate::AppState>,
+    query: web::Query<VerifyEmailQuery>,
+) -> HttpResponse {
+    let user_id = data
+        .jwt_keys
+        .verify::<VerificationClaims>(&query.token, VERIFY_EMAIL_TYP, VERIFY_EMAIL_AUDIENCE)
+        .and_then(|claims| claims.sub.parse::<i32>().ok());
+    let Some(user_id) = user_id else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Invalid or expired verification link"
+        }));
+    };
+
+    match authentication::mark_verified(&data.db, user_id).await {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Email verified; you can now log in"
+        })),
+        Ok(false) => HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Invalid or expired verification link"
+        })),
+        Err(_) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": "Failed to verify email"
+        })),
     }
 }
 
+// Sends a fresh link to a patient that has not verified yet. Like the password reset,
+// the answer does not reveal whether such an account exists.
+#[post("/verify-email/resend")]
+pub async fn resend_verification_email(
+    data: web::Data<crate::AppState>,
+    body: web::Json<ResendVerificationRequest>,
+) -> HttpResponse {
+    match authentication::get_unverified_patient(&data.db, &body.email).await {
+        Ok(Some(user_id)) => {
+            if send_verification_email(&data, user_id, &body.email).await.is_err() {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": "Failed to send email"
+                }));
+            }
+        }
+        Ok(None) => {}
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": "Failed to send verification email"
+            }));
+        }
+    }
+
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": "If the account is waiting for verification, a new link has been sent"
+    }))
+}
+
+// Mails a single-use reset token. The answer is the same, and as quick, whether or not
+// the account exists, so the endpoint cannot be used to probe for emails.
 #[post("/reset-password")]
 pub async fn reset_password(
     data: web::Data<crate::AppState>,
     reset_req: web::Json<PasswordResetRequest>,
 ) -> HttpResponse {
-    let pool = &data.db;
+    let reset_req = reset_req.into_inner();
+    let token = new_token();
+    let expires_at =
+        (Utc::now() + Duration::minutes(data.password_reset_ttl_minutes)).naive_utc();
 
-    match authentication::user_exists(pool, &reset_req.email, &reset_req.role).await {
-        Ok(true) => (),
-        Ok(false) => {
-            return HttpResponse::BadRequest().json(json!({
-                "success": false,
-                "message": "User not found"
-            }));
+    match authentication::set_recovery_token(
+        &data.db,
+        &reset_req.email,
+        reset_req.role,
+        &hash_token(&token),
+        expires_at,
+    )
+    .await
+    {
+        Ok(true) => {
+            let link = data
+                .password_reset_url
+                .as_ref()
+                .map(|url| format!("\n{}?token={}", url, token))
+                .unwrap_or_default();
+            let email = Email {
+                to: reset_req.email,
+                subject: "Password Reset".to_string(),
+                body: format!(
+                    "Reset code: {}{}\nThe code expires in {} minutes and works once. If you did not ask for a reset, ignore this email.",
+                    token, link, data.password_reset_ttl_minutes
+                ),
+            };
+            // Sent in the background: waiting on the mail server, or failing when it is
+            // down, would tell existing accounts apart from unknown ones
+            let mailer = data.mailer.clone();
+            actix_web::rt::spawn(async move {
+                let to = email.to.clone();
+                let sent = web::block(move || mailer.send(&email))
+                    .await
+                    .map_err(|e| MailError(e.to_string()))
+                    .and_then(|sent| sent);
+                if let Err(e) = sent {
+                    eprintln!("failed to send password reset email to {}: {}", to, e);
+                }
+            });
         }
+        Ok(false) => {}
         Err(_) => {
             return HttpResponse::InternalServerError().json(json!({
                 "success": false,
-                "message": "Failed to check user existence"
+                "message": "Failed to reset password"
             }));
         }
     }
 
-    // Generate a temporary password
-    let temp_password = Alphanumeric.sample_string(&mut rand::thread_rng(), 12);
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "message": "If the account exists, a reset code has been sent to its email"
+    }))
+}
 
-    // Hash the temporary password
-    let hashed_password = match hash(&temp_password, DEFAULT_COST) {
+#[post("/reset-password/confirm")]
+pub async fn confirm_reset_password(
+    data: web::Data<crate::AppState>,
+    confirm_req: web::Json<PasswordResetConfirm>,
+) -> HttpResponse {
+    if confirm_req.new_password.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "New password must not be empty"
+        }));
+    }
+    let hashed_password = match hash(&confirm_req.new_password, DEFAULT_COST) {
         Ok(hashed) => hashed,
         Err(_) => {
             return HttpResponse::InternalServerError().json(json!({
                 "success": false,
-                "message": "Password generation failed"
+                "message": "Password hashing failed"
             }));
         }
     };
 
-    // Update password in database
-    match authentication::update_password(pool, &reset_req.role, &reset_req.email, &hashed_password)
-        .await
+    match authentication::reset_password_with_token(
+        &data.db,
+        &hash_token(&confirm_req.token),
+        &hashed_password,
+    )
+    .await
     {
-        Ok(true) => {
-            // Send email with new password
-            let email = Message::builder()
-                .from("your-email@domain.com".parse().unwrap())
-                .to(reset_req.email.parse().unwrap())
-                .subject("Password Reset")
-                .header(ContentType::TEXT_PLAIN)
-                .body(format!(
-                    "Your new temporary password is: {}\nPlease change it after logging in.",
-                    temp_password
-                ))
-                .unwrap();
-
-            let creds = Credentials::new(
-                "minhtr090102@gmail.com".to_string(),
-                "zkfc hzzy jfza cxex".to_string(),
-            );
-
-            let mailer = SmtpTransport::relay("smtp.gmail.com")
-                .unwrap()
-                .credentials(creds)
-                .build();
-
-            match mailer.send(&email) {
-                Ok(_) => HttpResponse::Ok().json(json!({
-                    "success": true,
-                    "message": "New password has been sent to your email"
-                })),
-                Err(_) => HttpResponse::InternalServerError().json(json!({
-                    "success": false,
-                    "message": "Failed to send email"
-                })),
-            }
-        }
-        Ok(false) => HttpResponse::BadRequest().json(json!({
+        Ok(Some(_)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Password has been reset"
+        })),
+        Ok(None) => HttpResponse::BadRequest().json(json!({
             "success": false,
-            "message": "Email not found"
+            "message": "Invalid or expired reset code"
         })),
         Err(_) => HttpResponse::InternalServerError().json(json!({
             "success": false,
@@ -246,6 +863,15 @@ pub async fn reset_password(
     }
 }
 
+// Public keys our tokens are signed with, so other services can verify them locally.
+// Short caching keeps a newly added key from going unnoticed for long.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(data: web::Data<crate::AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(data.jwt_keys.jwks())
+}
+
 #[get("/role/{email}")]
 pub async fn get_role(data: web::Data<crate::AppState>, email: web::Path<String>) -> HttpResponse {
     let pool = &data.db;
@@ -267,8 +893,46 @@ pub async fn update_password(
 ) -> HttpResponse {
     let pool = &data.db;
 
-    // For non-admin users, verify they're changing their own password
-    if claims.role != "admin" {
+    // User managers may pick which of the email's accounts to update; everyone else updates their own
+    let manages_users = claims.role.has(Permission::UserManage);
+    let user_id = if manages_users {
+        match authentication::get_user_ids_by_email(pool, &new_password.email, new_password.role)
+            .await
+        {
+            Ok(user_ids) => match user_ids[..] {
+                [user_id] => user_id,
+                [] => {
+                    return HttpResponse::NotFound().json(json!({
+                        "success": false,
+                        "message": "User not found"
+                    }));
+                }
+                _ => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "success": false,
+                        "message": "Several accounts use this email; give the role of the one to update"
+                    }));
+                }
+            },
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": "Failed to verify user"
+                }));
+            }
+        }
+    } else {
+        let Ok(user_id) = claims.sub.parse::<i32>() else {
+            return HttpResponse::Unauthorized().json(json!({
+                "success": false,
+                "message": "Invalid token"
+            }));
+        };
+        user_id
+    };
+
+    // Everyone else has to be changing their own password
+    if !manages_users {
         match authentication::get_user_email(pool, &claims.sub).await {
             Ok(Some(user_email)) => {
                 if user_email != new_password.email {
@@ -295,8 +959,7 @@ pub async fn update_password(
         // Verify current password for non-admin users
         match authentication::verify_current_password(
             pool,
-            &new_password.email,
-            &claims.role,
+            &claims.sub,
             &new_password.current_password
         ).await {
             Ok(true) => (),  // Password verified, continue
@@ -327,7 +990,7 @@ pub async fn update_password(
     };
 
     // Update the password in the database
-    match authentication::update_password(pool, &claims.role, &new_password.email, &hashed_password).await {
+    match authentication::update_password(pool, user_id, &hashed_password).await {
         Ok(_) => HttpResponse::Ok().json(json!({
             "success": true,
             "message": "Password updated successfully"
@@ -339,19 +1002,21 @@ pub async fn update_password(
     }
 }
 
-#[post("/register-with-admin")]
+#[post("/register-with-admin", wrap = "Require(Permission::UserManage)")]
 pub async fn admin_create_user(
     data: web::Data<crate::AppState>,
-    claims: web::ReqData<Claims>,
     register_req: web::Json<RegisterRequest>,
 ) -> HttpResponse {
     let pool = &data.db;
-    if claims.role != "admin" {
-        return HttpResponse::BadRequest().json(json!({
-            "success": false,
-            "message": "Only admin can create user"
-        }));
-    }
+    let speciality_id = match (register_req.role, register_req.speciality_id) {
+        (UserRole::Doctor, None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Doctors need a speciality_id"
+            }));
+        }
+        (_, speciality_id) => speciality_id,
+    };
     // Hash the password
     let hashed_password = match hash(&register_req.password, DEFAULT_COST) {
         Ok(hashed) => hashed,
@@ -365,13 +1030,14 @@ pub async fn admin_create_user(
         }
     };
 
-    // Attempt to create the user
+    // Accounts made by an administrator need no email verification
     match authentication::create_user(
         pool,
         &register_req.email,
         &hashed_password,
         &register_req.name,
-        &register_req.role,
+        register_req.role,
+        true,
     )
     .await
     {
@@ -384,14 +1050,8 @@ pub async fn admin_create_user(
         }
     }
 
-    if register_req.role == "doctor" {
-        match doctor::update_doctor_speciality(
-            pool,
-            register_req.email.clone(),
-            &register_req.speciality_id.unwrap(),
-        )
-        .await
-        {
+    if let (UserRole::Doctor, Some(speciality_id)) = (register_req.role, speciality_id) {
+        match doctor::update_doctor_speciality(pool, register_req.email.clone(), &speciality_id).await {
             Ok(_) => HttpResponse::Ok().json(json!({
                 "success": true,
                 "message": "Doctor registered successfully"
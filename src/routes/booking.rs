@@ -0,0 +1,386 @@
+use crate::authentication::Claims;
+use crate::db::{booking, service};
+use crate::error::Error;
+use crate::models::{
+    Appointment, AppointmentStatus, Booking, BookingDetail, BookingForm, BookingListQuery,
+    BookingPhotoForm, BookingStatus, ConfirmBookingRequest, QueuePriority,
+    RejectBookingRequest, TreatmentStatus,
+};
+use crate::routes::{access, appointment, queue};
+use crate::middleware::permission::{Permission, Require};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{Local, NaiveDate, NaiveTime, Utc};
+use serde_json::json;
+
+#[post("", wrap = "Require(Permission::BookingCreate)")]
+pub async fn create_booking(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<BookingForm>,
+) -> HttpResponse {
+    let patient_id = claims.profile_id;
+
+    if NaiveTime::parse_from_str(&body.appointment_time, "%H:%M").is_err() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "appointment_time must be HH:MM"
+        }));
+    }
+    if body.appointment_date < Local::now().date_naive() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Bookings cannot be made for past dates"
+        }));
+    }
+    match service::get_service_by_id(&data.db, body.service_id).await {
+        Ok(_) => {}
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Service not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to create booking: {}", e)
+            }));
+        }
+    }
+    if let Some(doctor_id) = body.doctor_id {
+        if let Err(response) = check_doctor_provides(&data, doctor_id, body.service_id).await {
+            return response;
+        }
+    }
+
+    match booking::create_booking(&data.db, patient_id, &body).await {
+        Ok(id) => HttpResponse::Created().json(json!({
+            "success": true,
+            "data": id,
+            "message": "Booking created successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to create booking: {}", e)
+        })),
+    }
+}
+
+#[get("/self", wrap = "Require(Permission::BookingReadOwn)")]
+pub async fn get_self_bookings(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let patient_id = claims.profile_id;
+    let bookings = match booking::get_bookings_of_patient(&data.db, patient_id).await {
+        Ok(bookings) => bookings,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve bookings: {}", e)
+            }));
+        }
+    };
+
+    let mut details = Vec::with_capacity(bookings.len());
+    for booking in bookings {
+        match with_photos(&data, booking).await {
+            Ok(detail) => details.push(detail),
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to retrieve bookings: {}", e)
+                }));
+            }
+        }
+    }
+    HttpResponse::Ok().json(json!({
+        "success": true,
+        "data": details,
+        "message": "Bookings retrieved successfully"
+    }))
+}
+
+#[get("", wrap = "Require(Permission::BookingManage)")]
+pub async fn get_bookings(
+    data: web::Data<crate::AppState>,
+    query: web::Query<BookingListQuery>,
+) -> HttpResponse {
+    match booking::get_bookings(&data.db, query.status).await {
+        Ok(bookings) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": bookings,
+            "message": "Bookings retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve bookings: {}", e)
+        })),
+    }
+}
+
+#[get("/{id}", wrap = "Require(Permission::BookingRead)")]
+pub async fn get_booking_by_id(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let booking = match load_booking(&data, &claims, path.into_inner()).await {
+        Ok(booking) => booking,
+        Err(response) => return response,
+    };
+
+    match with_photos(&data, booking).await {
+        Ok(detail) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": detail,
+            "message": "Booking retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve booking: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/photos", wrap = "Require(Permission::BookingUpdate)")]
+pub async fn add_booking_photo(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<BookingPhotoForm>,
+) -> HttpResponse {
+    let booking = match load_booking(&data, &claims, path.into_inner()).await {
+        Ok(booking) => booking,
+        Err(response) => return response,
+    };
+
+    match booking::add_booking_photo(&data.db, booking.id, &body.url).await {
+        Ok(id) => HttpResponse::Created().json(json!({
+            "success": true,
+            "data": id,
+            "message": "Photo added successfully"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to add photo: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to add photo: {}", e)
+        })),
+    }
+}
+
+// Books the appointment the booking asked for and marks the booking confirmed
+#[post("/{id}/confirm", wrap = "Require(Permission::BookingManage)")]
+pub async fn confirm_booking(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: Option<web::Json<ConfirmBookingRequest>>,
+) -> HttpResponse {
+    let booking = match load_booking(&data, &claims, path.into_inner()).await {
+        Ok(booking) => booking,
+        Err(response) => return response,
+    };
+    // Checked again under lock when the appointment is created
+    if booking.status != BookingStatus::Pending {
+        return HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": "Failed to confirm booking: booking is no longer pending"
+        }));
+    }
+    let overrides = body.map(|body| body.into_inner());
+
+    let speciality_id = match service::get_service_by_id(&data.db, booking.service_id).await {
+        Ok(service) => service.speciality_id,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to confirm booking: {}", e)
+            }));
+        }
+    };
+    let Some(speciality_id) = speciality_id else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "The booked service is not linked to a specialty"
+        }));
+    };
+    let date = match overrides.as_ref().and_then(|o| o.date) {
+        Some(date) => date,
+        None => match NaiveDate::parse_from_str(&booking.appointment_date, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "message": "Booking has an invalid date, pass one explicitly"
+                }));
+            }
+        },
+    };
+    let appointment_time = overrides
+        .as_ref()
+        .and_then(|o| o.appointment_time.clone())
+        .unwrap_or_else(|| booking.appointment_time.clone());
+
+    // The doctor the patient asked for, unless staff pick another one
+    let doctor_id = overrides.as_ref().and_then(|o| o.doctor_id).or(booking.doctor_id);
+    if let Some(doctor_id) = doctor_id {
+        if let Err(response) = check_doctor_provides(&data, doctor_id, booking.service_id).await {
+            return response;
+        }
+    }
+
+    let (slot, doctors) =
+        match appointment::find_slot(&data, speciality_id, date, Some(&appointment_time)).await {
+            Ok(found) => found,
+            Err(response) => return response,
+        };
+
+    let now = Utc::now().naive_utc();
+    let new_appointment = Appointment {
+        id: None,
+        patient_id: booking.patient_id,
+        patient_name: booking.name.clone(),
+        patient_birthday: booking.birthday.clone(),
+        patient_phone: booking.booking_phone.clone(),
+        patient_reason: booking.reason.clone(),
+        speciality_id: Some(speciality_id),
+        numerical_order: None,
+        appointment_time: slot.time.clone(),
+        status: Some(AppointmentStatus::Unpaid),
+        treatment_status: Some(TreatmentStatus::Scheduled),
+        create_at: Some(now),
+        update_at: Some(now),
+        date: Some(date),
+        arrived_at: None,
+        is_walk_in: Some(0),
+        priority: Some(QueuePriority::Normal),
+        doctor_id,
+    };
+
+    match booking::confirm_booking(&data.db, booking.id, new_appointment, slot.capacity, &doctors)
+        .await
+    {
+        Ok(created) => {
+            queue::notify(&data, created.speciality_id, created.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": created,
+                "message": "Booking confirmed successfully"
+            }))
+        }
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to confirm booking: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to confirm booking: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/reject", wrap = "Require(Permission::BookingManage)")]
+pub async fn reject_booking(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<RejectBookingRequest>,
+) -> HttpResponse {
+    close_booking(
+        &data,
+        &claims,
+        path.into_inner(),
+        BookingStatus::Rejected,
+        body.reason.as_deref(),
+    )
+    .await
+}
+
+#[post("/{id}/cancel", wrap = "Require(Permission::BookingUpdate)")]
+pub async fn cancel_booking(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    close_booking(&data, &claims, path.into_inner(), BookingStatus::Cancelled, None).await
+}
+
+async fn close_booking(
+    data: &crate::AppState,
+    claims: &Claims,
+    id: i32,
+    status: BookingStatus,
+    reason: Option<&str>,
+) -> HttpResponse {
+    if let Err(response) = load_booking(data, claims, id).await {
+        return response;
+    }
+
+    match booking::close_booking(&data.db, id, status, reason).await {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Booking updated successfully"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to update booking: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update booking: {}", e)
+        })),
+    }
+}
+
+// Bookings follow the ownership rule of the patient they belong to
+async fn load_booking(
+    data: &crate::AppState,
+    claims: &Claims,
+    id: i32,
+) -> Result<Booking, HttpResponse> {
+    let booking = match booking::get_booking_by_id(&data.db, id).await {
+        Ok(booking) => booking,
+        Err(Error::NotFound) => {
+            return Err(HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Booking not found"
+            })));
+        }
+        Err(e) => {
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve booking: {}", e)
+            })));
+        }
+    };
+
+    access::check_patient_access(data, claims, Some(booking.patient_id)).await?;
+    Ok(booking)
+}
+
+async fn check_doctor_provides(
+    data: &crate::AppState,
+    doctor_id: i32,
+    service_id: i32,
+) -> Result<(), HttpResponse> {
+    match service::get_services_not_provided(&data.db, doctor_id, &[service_id]).await {
+        Ok(missing) if missing.is_empty() => Ok(()),
+        Ok(_) => Err(HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "The chosen doctor does not provide this service"
+        }))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to check doctor services: {}", e)
+        }))),
+    }
+}
+
+async fn with_photos(data: &crate::AppState, booking: Booking) -> Result<BookingDetail, Error> {
+    let photos = booking::get_booking_photos(&data.db, booking.id).await?;
+    Ok(BookingDetail { booking, photos })
+}
@@ -0,0 +1,297 @@
+use crate::authentication::Claims;
+use crate::db::{clinical_profile, icd10};
+use crate::error::Error;
+use crate::middleware::permission::{Permission, Require};
+use crate::models::{
+    AllergyForm, ChronicConditionForm, ClinicalProfileForm, LongTermMedicationForm,
+};
+use crate::routes::{access, icd10::normalize_code};
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde_json::json;
+
+#[get("/{id}/clinical-profile", wrap = "Require(Permission::MedicalRecordRead)")]
+pub async fn get_clinical_profile(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    match clinical_profile::get(&data.db, patient_id).await {
+        Ok(profile) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": profile,
+            "message": "Clinical profile retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve clinical profile: {}", e)
+        })),
+    }
+}
+
+// Sets blood type and family history; the lists are edited entry by entry below
+#[put("/{id}/clinical-profile", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn update_clinical_profile(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    form: web::Json<ClinicalProfileForm>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    match clinical_profile::set_basics(&data.db, patient_id, &form, user_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Clinical profile updated successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to update clinical profile: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/allergies", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn add_allergy(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    form: web::Json<AllergyForm>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    if form.substance.trim().is_empty() {
+        return missing_field("substance");
+    }
+    match clinical_profile::add_allergy(&data.db, patient_id, &form, user_id).await {
+        Ok(id) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": id,
+            "message": "Allergy added successfully"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": message
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to add allergy: {}", e)
+        })),
+    }
+}
+
+#[delete("/{id}/allergies/{allergy_id}", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn remove_allergy(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let (patient_id, allergy_id) = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    match clinical_profile::remove_allergy(&data.db, patient_id, allergy_id, user_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Allergy removed successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Allergy not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to remove allergy: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/conditions", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn add_condition(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    form: web::Json<ChronicConditionForm>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    if form.name.trim().is_empty() {
+        return missing_field("name");
+    }
+    let icd10_code = match form
+        .icd10_code
+        .as_deref()
+        .filter(|code| !code.trim().is_empty())
+    {
+        None => None,
+        Some(code) => {
+            let known = match normalize_code(code) {
+                Some(code) => icd10::get(&data.db, &code).await,
+                None => Ok(None),
+            };
+            match known {
+                Ok(Some(known)) => Some(known.code),
+                Ok(None) => {
+                    return HttpResponse::BadRequest().json(json!({
+                        "success": false,
+                        "message": format!("Unknown ICD-10 code {}", code)
+                    }));
+                }
+                Err(e) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "message": format!("Failed to add condition: {}", e)
+                    }));
+                }
+            }
+        }
+    };
+
+    match clinical_profile::add_condition(
+        &data.db,
+        patient_id,
+        &form,
+        icd10_code.as_deref(),
+        user_id,
+    )
+    .await
+    {
+        Ok(id) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": id,
+            "message": "Condition added successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to add condition: {}", e)
+        })),
+    }
+}
+
+#[delete("/{id}/conditions/{condition_id}", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn remove_condition(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let (patient_id, condition_id) = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    match clinical_profile::remove_condition(&data.db, patient_id, condition_id, user_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Condition removed successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Condition not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to remove condition: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/medications", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn add_medication(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    form: web::Json<LongTermMedicationForm>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    if form.name.trim().is_empty() {
+        return missing_field("name");
+    }
+    match clinical_profile::add_medication(&data.db, patient_id, &form, user_id).await {
+        Ok(id) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": id,
+            "message": "Medication added successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Unknown medicine"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to add medication: {}", e)
+        })),
+    }
+}
+
+// For when the patient stops taking it
+#[delete("/{id}/medications/{medication_id}", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn remove_medication(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+    claims: web::ReqData<Claims>,
+) -> HttpResponse {
+    let (patient_id, medication_id) = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    match clinical_profile::remove_medication(&data.db, patient_id, medication_id, user_id).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Medication removed successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Medication not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to remove medication: {}", e)
+        })),
+    }
+}
+
+fn missing_field(field: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({
+        "success": false,
+        "message": format!("{} is required", field)
+    }))
+}
+
+fn invalid_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "success": false,
+        "message": "Invalid token"
+    }))
+}
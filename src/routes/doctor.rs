@@ -1,7 +1,10 @@
 use crate::authentication::Claims;
-use crate::db::doctor;
-use crate::models::Doctor;
+use crate::db::{appointment, doctor, service};
+use crate::error::Error;
+use crate::models::{Doctor, WorklistQuery};
+use crate::middleware::permission::{Permission, Require};
 use actix_web::{delete, get, post, put, web, HttpResponse};
+use chrono::Local;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -12,12 +15,12 @@ pub struct DoctorQuery {
     order_dir: Option<String>,
 }
 
-#[get("/self")]
+#[get("/self", wrap = "Require(Permission::DoctorReadSelf)")]
 pub async fn get_self_doctor(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    let doctor_id = claims.sub.parse::<i32>().unwrap();
+    let doctor_id = claims.profile_id;
 
     match doctor::get_doctor_by_id(&data.db, &doctor_id).await {
         Ok(doctor) => HttpResponse::Ok().json(json!({
@@ -32,7 +35,66 @@ pub async fn get_self_doctor(
     }
 }
 
-#[get("/admin/doctors")]
+// The signed-in doctor's own patients for a day, in call order
+#[get("/worklist", wrap = "Require(Permission::DoctorReadSelf)")]
+pub async fn get_worklist(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    query: web::Query<WorklistQuery>,
+) -> HttpResponse {
+    let doctor_id = claims.profile_id;
+    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+
+    match appointment::get_appointments_of_doctor(&data.db, doctor_id, date).await {
+        Ok(appointments) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": appointments,
+            "message": "Worklist retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve worklist: {}", e)
+        })),
+    }
+}
+
+// Catalogue of services a doctor performs
+#[get("/{id}/services")]
+pub async fn get_services_of_doctor(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    let doctor_id = path.into_inner();
+    match doctor::get_doctor_by_id(&data.db, &doctor_id).await {
+        Ok(_) => {}
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Doctor not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve services: {}", e)
+            }));
+        }
+    }
+
+    match service::get_services_of_doctor(&data.db, doctor_id).await {
+        Ok(services) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": services,
+            "message": "Services retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve services: {}", e)
+        })),
+    }
+}
+
+#[get("/admin/doctors", wrap = "Require(Permission::DoctorManage)")]
 pub async fn get_doctors(
     data: web::Data<crate::AppState>,
     query: web::Query<DoctorQuery>,
@@ -58,7 +120,7 @@ pub async fn get_doctors(
 }
 
 // Lấy thông tin chi tiết một bác sĩ
-#[get("/admin/doctors/{id}")]
+#[get("/admin/doctors/{id}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn get_doctor_by_id(
     data: web::Data<crate::AppState>,
     id: web::Path<i32>,
@@ -77,7 +139,7 @@ pub async fn get_doctor_by_id(
 }
 
 // Tạo bác sĩ mới
-#[post("/admin/doctors")]
+#[post("/admin/doctors", wrap = "Require(Permission::DoctorManage)")]
 pub async fn create_doctor(
     data: web::Data<crate::AppState>,
     doctor: web::Json<Doctor>,
@@ -95,7 +157,7 @@ pub async fn create_doctor(
 }
 
 // Cập nhật thông tin bác sĩ
-#[put("/admin/doctors/{email}")]
+#[put("/admin/doctors/{email}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn update_doctor(
     data: web::Data<crate::AppState>,
     email: web::Path<String>,
@@ -114,7 +176,7 @@ pub async fn update_doctor(
 }
 
 // Xóa bác sĩ
-#[delete("/admin/doctors/{id}")]
+#[delete("/admin/doctors/{id}", wrap = "Require(Permission::DoctorManage)")]
 pub async fn delete_doctor(
     data: web::Data<crate::AppState>,
     id: web::Path<i32>,
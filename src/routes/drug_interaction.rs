@@ -0,0 +1,250 @@
+use crate::db::{drug_interaction, medicine};
+use crate::middleware::permission::{Permission, Require};
+use crate::models::{DrugInteraction, InteractionSeverity, Medicine, MedicineIngredient};
+use actix_web::{post, web, HttpResponse};
+use serde_json::json;
+
+// Loads interacting medicine pairs sent as CSV, one pair per line:
+// `medicine_a,medicine_b,severity,note`, with medicines named as in the formulary and a
+// severity of minor, moderate, major or contraindicated. Nothing is written unless every
+// line can be read.
+#[post("/import", wrap = "Require(Permission::DrugInteractionImport)")]
+pub async fn import_interactions(data: web::Data<crate::AppState>, body: String) -> HttpResponse {
+    let medicines = match medicine::get_medicines(&data.db).await {
+        Ok(medicines) => medicines,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to import drug interactions: {}", e)
+            }));
+        }
+    };
+    let interactions = match parse_interactions(&body, &medicines) {
+        Ok(interactions) if !interactions.is_empty() => interactions,
+        Ok(_) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "The interaction list is empty"
+            }));
+        }
+        Err(lines) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Some lines are not two medicines of the formulary, a severity and a note",
+                "lines": lines
+            }));
+        }
+    };
+
+    match drug_interaction::import_interactions(&data.db, &interactions).await {
+        Ok(imported) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Drug interactions imported",
+            "data": { "imported": imported }
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to import drug interactions: {}", e)
+        })),
+    }
+}
+
+// Loads the active ingredients of medicines sent as CSV, one per line:
+// `medicine,ingredient,allergen`, the allergen being the class a patient may be allergic
+// to, e.g. `Amoxicillin 500mg,amoxicillin,penicillin`. It may be left empty.
+#[post("/ingredients/import", wrap = "Require(Permission::DrugInteractionImport)")]
+pub async fn import_ingredients(data: web::Data<crate::AppState>, body: String) -> HttpResponse {
+    let medicines = match medicine::get_medicines(&data.db).await {
+        Ok(medicines) => medicines,
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to import medicine ingredients: {}", e)
+            }));
+        }
+    };
+    let ingredients = match parse_ingredients(&body, &medicines) {
+        Ok(ingredients) if !ingredients.is_empty() => ingredients,
+        Ok(_) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "The ingredient list is empty"
+            }));
+        }
+        Err(lines) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Some lines are not a medicine of the formulary, an ingredient and an allergen",
+                "lines": lines
+            }));
+        }
+    };
+
+    match drug_interaction::import_ingredients(&data.db, &ingredients).await {
+        Ok(imported) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Medicine ingredients imported",
+            "data": { "imported": imported }
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to import medicine ingredients: {}", e)
+        })),
+    }
+}
+
+// A header line is skipped, and of a pair listed twice the first line counts. A name
+// shared by several medicines in the formulary applies to each of them. On failure, the
+// numbers of the lines that could not be read, at most 20 of them.
+fn parse_interactions(
+    text: &str,
+    medicines: &[Medicine],
+) -> Result<Vec<DrugInteraction>, Vec<usize>> {
+    let mut interactions = Vec::new();
+    let mut bad_lines = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = csv_fields(line).unwrap_or_default();
+        if index == 0
+            && fields
+                .get(2)
+                .is_some_and(|f| f.eq_ignore_ascii_case("severity"))
+        {
+            continue;
+        }
+        let (a, b, severity) = match fields.as_slice() {
+            [a, b, severity] | [a, b, severity, _] => (
+                medicine_ids(medicines, a),
+                medicine_ids(medicines, b),
+                severity.parse::<InteractionSeverity>(),
+            ),
+            _ => {
+                bad_lines.push(index + 1);
+                continue;
+            }
+        };
+        let note = fields.get(3).filter(|n| !n.is_empty()).cloned();
+        match severity {
+            Ok(severity) if !a.is_empty() && !b.is_empty() && a != b => {
+                for &medicine_a in &a {
+                    for &medicine_b in &b {
+                        if medicine_a != medicine_b {
+                            interactions.push(DrugInteraction {
+                                medicine_a: medicine_a.min(medicine_b),
+                                medicine_b: medicine_a.max(medicine_b),
+                                severity,
+                                note: note.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+            _ => bad_lines.push(index + 1),
+        }
+    }
+
+    if bad_lines.is_empty() {
+        // A pair listed twice would hit the same row twice in one insert
+        interactions.sort_by_key(|i| (i.medicine_a, i.medicine_b));
+        interactions.dedup_by_key(|i| (i.medicine_a, i.medicine_b));
+        Ok(interactions)
+    } else {
+        bad_lines.truncate(20);
+        Err(bad_lines)
+    }
+}
+
+// Like `parse_interactions`; of an ingredient listed twice for a medicine the first line
+// counts
+fn parse_ingredients(
+    text: &str,
+    medicines: &[Medicine],
+) -> Result<Vec<MedicineIngredient>, Vec<usize>> {
+    let mut ingredients = Vec::new();
+    let mut bad_lines = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields = csv_fields(line).unwrap_or_default();
+        if index == 0
+            && fields
+                .get(1)
+                .is_some_and(|f| f.eq_ignore_ascii_case("ingredient"))
+        {
+            continue;
+        }
+        let (ids, ingredient, allergen) = match fields.as_slice() {
+            [medicine, ingredient] => (medicine_ids(medicines, medicine), ingredient, None),
+            [medicine, ingredient, allergen] => (
+                medicine_ids(medicines, medicine),
+                ingredient,
+                Some(allergen.clone()).filter(|a| !a.is_empty()),
+            ),
+            _ => {
+                bad_lines.push(index + 1);
+                continue;
+            }
+        };
+        if ids.is_empty() || ingredient.is_empty() {
+            bad_lines.push(index + 1);
+            continue;
+        }
+        for medicine_id in ids {
+            ingredients.push(MedicineIngredient {
+                medicine_id,
+                ingredient: ingredient.clone(),
+                allergen: allergen.clone(),
+            });
+        }
+    }
+
+    if bad_lines.is_empty() {
+        ingredients.sort_by_key(|i| (i.medicine_id, i.ingredient.to_lowercase()));
+        ingredients.dedup_by_key(|i| (i.medicine_id, i.ingredient.to_lowercase()));
+        Ok(ingredients)
+    } else {
+        bad_lines.truncate(20);
+        Err(bad_lines)
+    }
+}
+
+// Medicines of the formulary with this name, ignoring case
+fn medicine_ids(medicines: &[Medicine], name: &str) -> Vec<i32> {
+    medicines
+        .iter()
+        .filter(|m| {
+            m.name
+                .as_deref()
+                .is_some_and(|n| n.trim().eq_ignore_ascii_case(name))
+        })
+        .map(|m| m.id)
+        .collect()
+}
+
+// Fields of one CSV line, trimmed; a quoted field may hold commas and doubled quotes.
+// None if a quote is left open.
+fn csv_fields(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field.trim().to_string());
+    Some(fields)
+}
@@ -0,0 +1,134 @@
+use crate::db::icd10;
+use crate::middleware::permission::{Permission, Require};
+use crate::models::{Icd10Code, Icd10Query};
+use actix_web::{get, post, web, HttpResponse};
+use serde_json::json;
+
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+// Lookup for coding diagnoses: by code, with or without the dot, or by words of the
+// description
+#[get("", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn search_codes(
+    data: web::Data<crate::AppState>,
+    query: web::Query<Icd10Query>,
+) -> HttpResponse {
+    let q = query.q.as_deref().unwrap_or("").trim();
+    let code_prefix = escape_like(&q.replace('.', "").to_uppercase());
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    match icd10::search(&data.db, &code_prefix, &escape_like(q), limit).await {
+        Ok(codes) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": codes
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to search ICD-10 codes: {}", e)
+        })),
+    }
+}
+
+// Loads a code list sent as plain text, e.g. the CMS ICD-10-CM codes file. Nothing is
+// written unless every line can be read.
+#[post("/import", wrap = "Require(Permission::Icd10Import)")]
+pub async fn import_codes(data: web::Data<crate::AppState>, body: String) -> HttpResponse {
+    let codes = match parse_code_list(&body) {
+        Ok(codes) if !codes.is_empty() => codes,
+        Ok(_) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "The code list is empty"
+            }));
+        }
+        Err(lines) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Some lines are not a code followed by a description",
+                "lines": lines
+            }));
+        }
+    };
+
+    match icd10::import(&data.db, &codes).await {
+        Ok(imported) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "ICD-10 codes imported",
+            "data": { "imported": imported }
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to import ICD-10 codes: {}", e)
+        })),
+    }
+}
+
+// Canonical form of a code, upper case with the dot after the category, e.g. `j45909`
+// becomes `J45.909`. None if it cannot be an ICD-10 code.
+pub fn normalize_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| *c != '.' && !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let bytes = code.as_bytes();
+    let valid = (3..=7).contains(&bytes.len())
+        && bytes[0].is_ascii_uppercase()
+        && bytes[1].is_ascii_digit()
+        && bytes[2..].iter().all(u8::is_ascii_alphanumeric);
+    if !valid {
+        return None;
+    }
+    Some(match code.split_at(3) {
+        (category, "") => category.to_string(),
+        (category, rest) => format!("{}.{}", category, rest),
+    })
+}
+
+// One code per line and then its description, separated by a tab, a comma or spaces;
+// quotes around either are dropped. Blank lines and a header line are skipped, and of a
+// code listed twice the first description counts. On failure, the numbers of the lines
+// that could not be read, at most 20 of them.
+fn parse_code_list(text: &str) -> Result<Vec<Icd10Code>, Vec<usize>> {
+    let mut codes = Vec::new();
+    let mut bad_lines = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (code, description) = line.split_once(['\t', ',', ' ']).unwrap_or((line, ""));
+        let code = code.trim_matches('"');
+        let description = description.trim().trim_matches('"').trim();
+        if index == 0 && code.eq_ignore_ascii_case("code") {
+            continue;
+        }
+        match normalize_code(code) {
+            Some(code) if !description.is_empty() => codes.push(Icd10Code {
+                code,
+                description: description.to_string(),
+            }),
+            _ => bad_lines.push(index + 1),
+        }
+    }
+
+    if bad_lines.is_empty() {
+        // A code listed twice would hit the same row twice in one insert
+        codes.sort_by(|a, b| a.code.cmp(&b.code));
+        codes.dedup_by(|a, b| a.code == b.code);
+        Ok(codes)
+    } else {
+        bad_lines.truncate(20);
+        Err(bad_lines)
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
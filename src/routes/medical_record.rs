@@ -1,25 +1,26 @@
+use crate::db::icd10;
+use crate::db::medical_record::Author;
 use crate::error::Error;
-use crate::models::MedicalRecord;
-use crate::{db::medical_record, models::VitalSign};
-use actix_web::{get, post, put, web, HttpResponse, Responder};
+use crate::models::{
+    ClinicalNoteForm, DiagnosisForm, DiagnosisKind, MedicalRecord, MedicalRecordDetail,
+    MedicalRecordResponse, RecordSection, RemoveDiagnosisQuery, VitalSignForm,
+};
+use crate::db::{appointment, clinical_profile, medical_record, vital_range};
+use crate::vitals;
+use crate::middleware::permission::{Permission, Require};
+use crate::routes::{access, icd10::normalize_code};
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use chrono::Utc;
 use serde_json::json;
 use sqlx::PgPool;
 
 use super::authentication::Claims;
 
-#[post("")]
+#[post("", wrap = "Require(Permission::MedicalRecordWrite)")]
 pub async fn create_medical_record(
     data: web::Data<crate::AppState>,
-    claims: web::ReqData<Claims>,
     update_req: web::Json<MedicalRecord>,
 ) -> impl Responder {
-    if claims.role != "doctor" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Only doctor can create medical record"
-        }));
-    }
-
     match medical_record::create(&data.db, &update_req.into_inner()).await {
         Ok(medical_record_id) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -33,12 +34,12 @@ pub async fn create_medical_record(
     }
 }
 
-#[get("/self")]
+#[get("/self", wrap = "Require(Permission::MedicalRecordReadOwn)")]
 pub async fn get_self_medical_records(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
 ) -> impl Responder {
-    let patient_id = claims.sub.parse::<i32>().unwrap();
+    let patient_id = claims.profile_id;
     println!("patient_id at get_self_medical_records: {}", patient_id);
 
     match medical_record::get_by_patient_id(&data.db, patient_id).await {
@@ -54,20 +55,16 @@ pub async fn get_self_medical_records(
     }
 }
 
-#[put("/payment-status/{id}")]
+#[put("/payment-status/{id}", wrap = "Require(Permission::MedicalRecordWrite)")]
 pub async fn update_payment_status(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
     claims: web::ReqData<Claims>,
 ) -> impl Responder {
-    if claims.role != "doctor" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Only doctor can update payment status"
-        }));
-    }
-
     let id = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
     match medical_record::update_payment_status(&data.db, id).await {
         Ok(_) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -80,16 +77,39 @@ pub async fn update_payment_status(
     }
 }
 
-#[get("/vital-signs/{medical_record_id}")]
+#[get("/vital-signs/{medical_record_id}", wrap = "Require(Permission::MedicalRecordRead)")]
 pub async fn get_vital_signs(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
 ) -> impl Responder {
     let medical_record_id = path.into_inner();
-    match medical_record::get_vital_signs(&data.db, medical_record_id).await {
-        Ok(vital_signs) => HttpResponse::Ok().json(json!({
+    if let Err(response) =
+        access::check_medical_record_access(&data, &claims, medical_record_id).await
+    {
+        return response;
+    }
+    let readings = async {
+        let vital_signs = medical_record::get_vital_signs(&data.db, medical_record_id).await?;
+        let birthday = medical_record::get_patient_birthday(&data.db, medical_record_id).await?;
+        let ranges = vital_range::get_all(&data.db).await?;
+        Ok::<_, Error>(
+            vital_signs
+                .into_iter()
+                .map(|vital_sign| {
+                    let age_group =
+                        vitals::age_group(birthday.as_deref(), vital_sign.recorded_at.date());
+                    vitals::reading(vital_sign, age_group, &ranges)
+                })
+                .collect::<Vec<_>>(),
+        )
+    }
+    .await;
+
+    match readings {
+        Ok(readings) => HttpResponse::Ok().json(json!({
             "success": true,
-            "data": vital_signs,
+            "data": readings,
             "message": "Vital signs retrieved successfully"
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
@@ -99,22 +119,49 @@ pub async fn get_vital_signs(
     }
 }
 
-#[post("/vital-signs")]
+// Takes a reading in any of the accepted units and stores it in the standard ones.
+// Readings no patient could have are refused; readings outside the reference ranges for
+// the patient's age are stored and come back flagged.
+#[post("/vital-signs", wrap = "Require(Permission::MedicalRecordWrite)")]
 pub async fn create_vital_sign(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
-    vital_sign: web::Json<VitalSign>,
+    form: web::Json<VitalSignForm>,
 ) -> impl Responder {
-    if claims.role != "doctor" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Only doctor can create vital sign"
-        }));
+    if let Err(response) =
+        access::check_medical_record_access(&data, &claims, form.medical_record_id).await
+    {
+        return response;
     }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let now = Utc::now().naive_utc();
+    let vital_sign = match vitals::measure(&form, form.recorded_at.unwrap_or(now), user_id, now) {
+        Ok(vital_sign) => vital_sign,
+        Err(errors) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Invalid vital signs",
+                "errors": errors
+            }));
+        }
+    };
 
-    match medical_record::create_vital_sign(&data.db, &vital_sign.into_inner()).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
+    let reading = async {
+        let vital_sign = medical_record::create_vital_sign(&data.db, &vital_sign).await?;
+        let birthday =
+            medical_record::get_patient_birthday(&data.db, form.medical_record_id).await?;
+        let ranges = vital_range::get_all(&data.db).await?;
+        let age_group = vitals::age_group(birthday.as_deref(), vital_sign.recorded_at.date());
+        Ok::<_, Error>(vitals::reading(vital_sign, age_group, &ranges))
+    }
+    .await;
+
+    match reading {
+        Ok(reading) => HttpResponse::Ok().json(json!({
             "success": true,
+            "data": reading,
             "message": "Vital sign created successfully"
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
@@ -124,39 +171,296 @@ pub async fn create_vital_sign(
     }
 }
 
-#[put("/diagnosis/{id}")]
+// The free-text diagnosis summary. Changes go through the amendment trail like the note
+// sections, so replacing an earlier diagnosis needs a `reason`.
+#[put("/diagnosis/{id}", wrap = "Require(Permission::MedicalRecordWrite)")]
 pub async fn update_diagnosis(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
     claims: web::ReqData<Claims>,
     update_req: web::Json<serde_json::Value>,
 ) -> impl Responder {
-    if claims.role != "doctor" {
-        return HttpResponse::Forbidden().json(json!({
+    let id = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    // A body without the key would otherwise wipe the diagnosis
+    let Some(diagnosis) = update_req.get("diagnosis").and_then(|v| v.as_str()) else {
+        return HttpResponse::BadRequest().json(json!({
             "success": false,
-            "message": "Only doctor can update diagnosis"
+            "message": "diagnosis is required"
         }));
+    };
+    let reason = update_req.get("reason").and_then(|v| v.as_str());
+    let author = Author {
+        user_id,
+        name: &claims.name,
+    };
+
+    match medical_record::amend(
+        &data.db,
+        id,
+        &[(RecordSection::Diagnosis, diagnosis)],
+        reason,
+        &author,
+    )
+    .await
+    {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Diagnosis updated successfully"
+        })),
+        Err(e) => amendment_error(e, "Failed to update diagnosis"),
     }
+}
 
+// Writes the SOAP sections of the note: chief complaint and history (subjective),
+// examination (objective), assessment and plan. Every change lands in the amendment trail.
+#[put("/{id}/note", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn update_note(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    note: web::Json<ClinicalNoteForm>,
+) -> impl Responder {
     let id = path.into_inner();
-    let diagnosis = update_req
-        .get("diagnosis")
-        .and_then(|v| v.as_str())
-        .unwrap_or("");
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let changes: Vec<(RecordSection, &str)> = [
+        (RecordSection::ChiefComplaint, &note.chief_complaint),
+        (RecordSection::History, &note.history),
+        (RecordSection::Examination, &note.examination),
+        (RecordSection::Assessment, &note.assessment),
+        (RecordSection::Plan, &note.plan),
+    ]
+    .into_iter()
+    .filter_map(|(section, text)| text.as_deref().map(|text| (section, text)))
+    .collect();
+    let author = Author {
+        user_id,
+        name: &claims.name,
+    };
 
-    match medical_record::update_diagnosis(&data.db, id, diagnosis).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
+    match medical_record::amend(&data.db, id, &changes, note.reason.as_deref(), &author).await {
+        Ok(changed) => HttpResponse::Ok().json(json!({
             "success": true,
-            "message": "Diagnosis updated successfully"
+            "message": "Note updated successfully",
+            "data": { "changed": changed }
+        })),
+        Err(e) => amendment_error(e, "Failed to update note"),
+    }
+}
+
+// Every change made to the record after it was written, oldest first
+#[get("/{id}/amendments", wrap = "Require(Permission::MedicalRecordRead)")]
+pub async fn get_amendments(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    match medical_record::get_amendments(&data.db, id).await {
+        Ok(amendments) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": amendments
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve amendments: {}", e)
+        })),
+    }
+}
+
+#[post("/{id}/diagnoses", wrap = "Require(Permission::MedicalRecordWrite)")]
+pub async fn add_diagnosis(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    diagnosis: web::Json<DiagnosisForm>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let code = match normalize_code(&diagnosis.code) {
+        Some(code) => icd10::get(&data.db, &code).await,
+        None => Ok(None),
+    };
+    let code = match code {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": format!("Unknown ICD-10 code {}", diagnosis.code)
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to add diagnosis: {}", e)
+            }));
+        }
+    };
+    let author = Author {
+        user_id,
+        name: &claims.name,
+    };
+
+    match medical_record::add_diagnosis(
+        &data.db,
+        id,
+        &code,
+        diagnosis.kind.unwrap_or(DiagnosisKind::Secondary),
+        diagnosis.note.as_deref(),
+        &author,
+    )
+    .await
+    {
+        Ok(diagnosis_id) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Diagnosis added successfully",
+            "data": diagnosis_id
+        })),
+        Err(e) => amendment_error(e, "Failed to add diagnosis"),
+    }
+}
+
+#[delete(
+    "/{id}/diagnoses/{diagnosis_id}",
+    wrap = "Require(Permission::MedicalRecordWrite)"
+)]
+pub async fn remove_diagnosis(
+    data: web::Data<crate::AppState>,
+    path: web::Path<(i32, i32)>,
+    claims: web::ReqData<Claims>,
+    query: web::Query<RemoveDiagnosisQuery>,
+) -> impl Responder {
+    let (id, diagnosis_id) = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let Some(reason) = query.reason.as_deref().map(str::trim).filter(|r| !r.is_empty()) else {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "A reason is required to remove a diagnosis"
+        }));
+    };
+    let author = Author {
+        user_id,
+        name: &claims.name,
+    };
+
+    match medical_record::remove_diagnosis(
+        &data.db,
+        id,
+        diagnosis_id,
+        Some(reason),
+        &author,
+    )
+    .await
+    {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Diagnosis removed successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Diagnosis not found"
+        })),
+        Err(e) => amendment_error(e, "Failed to remove diagnosis"),
+    }
+}
+
+// The whole record: note sections, free-text summary, coded diagnoses and the patient's
+// clinical profile
+#[get("/{id}", wrap = "Require(Permission::MedicalRecordRead)")]
+pub async fn get_medical_record(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(response) = access::check_medical_record_access(&data, &claims, id).await {
+        return response;
+    }
+    let detail = match medical_record::get_by_id(&data.db, id).await {
+        Ok(record) => record_detail(&data.db, record).await,
+        Err(e) => Err(e),
+    };
+
+    match detail {
+        Ok(detail) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": detail,
+            "message": "Medical record retrieved successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Medical record not found"
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
             "success": false,
-            "message": format!("Failed to update diagnosis: {}", e)
+            "message": format!("Failed to retrieve medical record: {}", e)
         })),
     }
 }
 
-#[get("/appointment/{appointment_id}")]
+async fn record_detail(
+    pool: &PgPool,
+    record: MedicalRecordResponse,
+) -> Result<MedicalRecordDetail, Error> {
+    let clinical_profile = match record.patient_id {
+        Some(patient_id) => Some(clinical_profile::get(pool, patient_id).await?),
+        None => None,
+    };
+    Ok(MedicalRecordDetail {
+        diagnoses: medical_record::get_diagnoses(pool, record.id).await?,
+        record,
+        clinical_profile,
+    })
+}
+
+fn amendment_error(e: Error, context: &str) -> HttpResponse {
+    match e {
+        Error::NotFound => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Medical record not found"
+        })),
+        Error::Conflict(message) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": message
+        })),
+        e => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("{}: {}", context, e)
+        })),
+    }
+}
+
+fn invalid_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "success": false,
+        "message": "Invalid token"
+    }))
+}
+
+#[get("/appointment/{appointment_id}", wrap = "Require(Permission::MedicalRecordRead)")]
 pub async fn get_medical_record_by_appointment(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
@@ -166,22 +470,21 @@ pub async fn get_medical_record_by_appointment(
 
     match medical_record::get_by_appointment_id(&data.db, appointment_id).await {
         Ok(record) => {
-            // Kiểm tra quyền truy cập - chỉ cho phép bác sĩ hoặc bệnh nhân của record này
-            if claims.role == "doctor"
-                || record
-                    .patient_id
-                    .map_or(false, |pid| claims.sub.parse::<i32>().unwrap() == pid)
+            if let Err(response) =
+                access::check_patient_access(&data, &claims, record.patient_id).await
             {
-                HttpResponse::Ok().json(json!({
+                return response;
+            }
+            match record_detail(&data.db, record).await {
+                Ok(detail) => HttpResponse::Ok().json(json!({
                     "success": true,
-                    "data": record,
+                    "data": detail,
                     "message": "Medical record retrieved successfully"
-                }))
-            } else {
-                HttpResponse::Forbidden().json(json!({
+                })),
+                Err(e) => HttpResponse::InternalServerError().json(json!({
                     "success": false,
-                    "message": "You don't have permission to access this medical record"
-                }))
+                    "message": format!("Failed to retrieve medical record: {}", e)
+                })),
             }
         }
         Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
@@ -195,13 +498,36 @@ pub async fn get_medical_record_by_appointment(
     }
 }
 
-#[get("/is-medical-record-exist/{appointment_id}")]
+#[get("/is-medical-record-exist/{appointment_id}", wrap = "Require(Permission::MedicalRecordRead)")]
 pub async fn is_medical_record_exist(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
 ) -> impl Responder {
     let appointment_id = path.into_inner();
 
+    // Whether a record exists already says something about the patient's visit
+    let appointment = match appointment::get_appointment_by_id(&data.db, appointment_id).await {
+        Ok(appointment) => appointment,
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Appointment not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve medical record: {}", e)
+            }));
+        }
+    };
+    if let Err(response) =
+        access::check_patient_access(&data, &claims, Some(appointment.patient_id)).await
+    {
+        return response;
+    }
+
     match medical_record::is_medical_record_exist(&data.db, appointment_id).await {
         Ok(medical_record) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -1,8 +1,16 @@
-use crate::db::medicine;
-use crate::models::{Medicine, MedicineCreateForm, MedicineOfPrescription};
+use crate::authentication::Claims;
+use crate::db::{clinical_profile, drug_interaction, medical_record, medicine};
+use crate::drug_safety::{self, KnownFacts};
+use crate::error::Error;
+use crate::models::{
+    Medicine, MedicineCreateForm, PrescriptionForm, PrescriptionIssue, PrescriptionIssueLevel,
+};
 use crate::AppState;
+use crate::middleware::permission::{Permission, Require};
+use crate::routes::access;
 use actix_web::{delete, get, post, put, web, HttpResponse};
 use serde_json::json;
+use sqlx::PgPool;
 
 #[get("/all")]
 pub async fn get_medicines(data: web::Data<AppState>) -> HttpResponse {
@@ -20,7 +28,7 @@ pub async fn get_medicine_by_id(data: web::Data<AppState>, path: web::Path<i32>)
     }
 }
 
-#[post("")]
+#[post("", wrap = "Require(Permission::MedicineWrite)")]
 pub async fn create_medicine(
     data: web::Data<AppState>,
     body: web::Json<MedicineCreateForm>,
@@ -31,7 +39,7 @@ pub async fn create_medicine(
     }
 }
 
-#[put("/{id}")]
+#[put("/{id}", wrap = "Require(Permission::MedicineWrite)")]
 pub async fn update_medicine(
     data: web::Data<AppState>,
     path: web::Path<i32>,
@@ -43,7 +51,7 @@ pub async fn update_medicine(
     }
 }
 
-#[delete("/{id}")]
+#[delete("/{id}", wrap = "Require(Permission::MedicineWrite)")]
 pub async fn delete_medicine(data: web::Data<AppState>, path: web::Path<i32>) -> HttpResponse {
     match medicine::delete_medicine(&data.db, path.into_inner()).await {
         Ok(_) => HttpResponse::Ok().json("Medicine deleted successfully"),
@@ -51,12 +59,20 @@ pub async fn delete_medicine(data: web::Data<AppState>, path: web::Path<i32>) ->
     }
 }
 
-#[get("/prescription/{medical_record_id}")]
+#[get("/prescription/{medical_record_id}", wrap = "Require(Permission::PrescriptionRead)")]
 pub async fn get_medicine_of_prescription(
     data: web::Data<AppState>,
     path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    match medicine::get_medicine_of_prescription(&data.db, Some(path.into_inner())).await {
+    let medical_record_id = path.into_inner();
+    if let Err(response) =
+        access::check_medical_record_access(&data, &claims, medical_record_id).await
+    {
+        return response;
+    }
+
+    match medicine::get_medicine_of_prescription(&data.db, Some(medical_record_id)).await {
         Ok(medicines_prescription) => HttpResponse::Ok().json(json!({
             "success": true,
             "data": medicines_prescription,
@@ -69,15 +85,81 @@ pub async fn get_medicine_of_prescription(
     }
 }
 
-#[post("/prescription")]
+// Checks the prescription against the interaction knowledge base and the patient's
+// clinical profile first. Blocking issues are refused with 409 unless the body gives an
+// `override_reason`; warnings come back with the created prescription.
+#[post("/prescription", wrap = "Require(Permission::PrescriptionWrite)")]
 pub async fn create_medicine_of_prescription(
     data: web::Data<AppState>,
-    body: web::Json<MedicineOfPrescription>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<PrescriptionForm>,
 ) -> HttpResponse {
-    let medicine_of_prescription = body.into_inner();
-    match medicine::create_medicine_of_prescription(&data.db, &medicine_of_prescription).await {
-        Ok(_) => HttpResponse::Ok().json(json!({
+    let prescription = body.into_inner();
+    if let Err(response) =
+        access::check_medical_record_access(&data, &claims, prescription.medical_record_id).await
+    {
+        return response;
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "Invalid token"
+        }));
+    };
+    if prescription.medicine_ids.is_empty() {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "medicine_ids is required"
+        }));
+    }
+
+    let issues = match safety_issues(&data.db, &prescription).await {
+        Ok(issues) => issues,
+        Err(Error::NotFound) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Unknown medicine"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to check medicine of prescription: {}", e)
+            }));
+        }
+    };
+    let (blocking, warnings): (Vec<_>, Vec<_>) = issues
+        .into_iter()
+        .partition(|issue| issue.level == PrescriptionIssueLevel::Blocking);
+    let overridden = prescription
+        .override_reason
+        .as_deref()
+        .is_some_and(|reason| !reason.trim().is_empty());
+    if !blocking.is_empty() && !overridden {
+        return HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": "The prescription has safety issues; give an override_reason to prescribe anyway",
+            "errors": blocking,
+            "warnings": warnings
+        }));
+    }
+
+    let overridden_issues: Vec<String> = blocking.iter().map(|i| i.message.clone()).collect();
+    match medicine::create_medicine_of_prescription(
+        &data.db,
+        &prescription,
+        &overridden_issues,
+        user_id,
+    )
+    .await
+    {
+        Ok(id) => HttpResponse::Ok().json(json!({
             "success": true,
+            "data": {
+                "id": id,
+                "overridden": blocking,
+                "warnings": warnings
+            },
             "message": "Medicine of prescription created successfully"
         })),
         Err(e) => HttpResponse::InternalServerError().json(json!({
@@ -86,3 +168,40 @@ pub async fn create_medicine_of_prescription(
         })),
     }
 }
+
+// NotFound if a prescribed medicine is not in the formulary
+async fn safety_issues(
+    pool: &PgPool,
+    prescription: &PrescriptionForm,
+) -> Result<Vec<PrescriptionIssue>, Error> {
+    let patient_id = medical_record::get_patient_id(pool, prescription.medical_record_id).await?;
+    let profile = match patient_id {
+        Some(patient_id) => Some(clinical_profile::get(pool, patient_id).await?),
+        None => None,
+    };
+    let mut involved = prescription.medicine_ids.clone();
+    if let Some(profile) = &profile {
+        involved.extend(profile.medications.iter().filter_map(|m| m.medicine_id));
+    }
+    involved.sort_unstable();
+    involved.dedup();
+
+    let medicines = medicine::get_medicines_by_ids(pool, &involved).await?;
+    if prescription
+        .medicine_ids
+        .iter()
+        .any(|id| !medicines.iter().any(|m| m.id == *id))
+    {
+        return Err(Error::NotFound);
+    }
+    let facts = KnownFacts {
+        medicines,
+        interactions: drug_interaction::get_interactions(pool, &involved).await?,
+        ingredients: drug_interaction::get_ingredients(pool, &involved).await?,
+    };
+    Ok(drug_safety::check(
+        &prescription.medicine_ids,
+        &facts,
+        profile.as_ref(),
+    ))
+}
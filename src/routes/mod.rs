@@ -4,9 +4,17 @@ pub mod nurse;
 pub mod patient;
 pub mod payment;
 pub mod staff;
+pub mod icd10;
+pub mod drug_interaction;
+pub mod clinical_profile;
 pub mod authentication;
 pub mod specialty;
 pub mod medicine;
 pub mod service;
 pub mod admin;
 pub mod medical_record;
+pub mod queue;
+pub mod receptionest;
+pub mod booking;
+pub mod access;
+pub mod two_factor;
@@ -1,23 +1,20 @@
-use crate::db::patient;
-use crate::models::{Patient, PatientForm, PatientQuery};
+use crate::db::{clinical_profile, medical_record, patient};
+use crate::error::Error;
+use crate::models::{
+    Patient, PatientForm, PatientQuery, PatientWithProfile, TrendMetric, VitalTrendQuery,
+};
+use crate::vitals;
 use crate::{authentication::Claims, models::UpdatePatientForm};
+use crate::middleware::permission::{Permission, Require};
+use crate::routes::access;
 use actix_web::{get, post, put, web, HttpResponse};
 use serde_json::json;
 
-#[get("/all")]
+#[get("/all", wrap = "Require(Permission::PatientAny)")]
 pub async fn get_patients(
     data: web::Data<crate::AppState>,
     query: web::Query<PatientQuery>,
-    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    // Check if user has doctor role
-    if claims.role != "staff" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Staff access required"
-        }));
-    }
-
     match patient::get_patients(
         &data.db,
         query.search.clone(),
@@ -40,14 +37,24 @@ pub async fn get_patients(
     }
 }
 
-#[get("/self")]
+// The patient's own details together with their clinical profile
+#[get("/self", wrap = "Require(Permission::PatientReadSelf)")]
 pub async fn get_self_patient(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    let patient_id = claims.sub.parse::<i32>().unwrap();
+    let patient_id = claims.profile_id;
+    let patient = async {
+        Ok::<_, Error>(PatientWithProfile {
+            patient: patient::get_patient_by_id(&data.db, &patient_id)
+                .await
+                .map_err(Error::Database)?,
+            clinical_profile: clinical_profile::get(&data.db, patient_id).await?,
+        })
+    }
+    .await;
 
-    match patient::get_patient_by_id(&data.db, &patient_id).await {
+    match patient {
         Ok(patient) => HttpResponse::Ok().json(json!({
             "success": true,
             "data": patient,
@@ -60,13 +67,18 @@ pub async fn get_self_patient(
     }
 }
 
-#[get("/{id}")]
+#[get("/{id}", wrap = "Require(Permission::PatientRead)")]
 pub async fn get_patient_by_id(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    match patient::get_patient_by_id(&data.db, &path.into_inner()).await {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+
+    match patient::get_patient_by_id(&data.db, &patient_id).await {
         Ok(patients) => HttpResponse::Ok().json(json!({
             "success": true,
             "data": patients,
@@ -79,17 +91,83 @@ pub async fn get_patient_by_id(
     }
 }
 
-#[get("email/{email}")]
+// Readings of a patient across all visits as one series per metric, for charting, e.g.
+// `?metric=blood_pressure_systolic,blood_pressure_diastolic&from=2026-01-01`
+#[get("/{id}/vitals/trend", wrap = "Require(Permission::MedicalRecordRead)")]
+pub async fn get_vitals_trend(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    query: web::Query<VitalTrendQuery>,
+) -> HttpResponse {
+    let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
+    let metrics = match query.metric.as_deref() {
+        None | Some("") => TrendMetric::ALL.to_vec(),
+        Some(metric) => match metric.split(',').map(|m| m.trim().parse()).collect() {
+            Ok(metrics) => metrics,
+            Err(()) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "message": "Unknown metric",
+                    "metrics": TrendMetric::ALL
+                }));
+            }
+        },
+    };
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "from must not be after to"
+            }));
+        }
+    }
+    let from = query.from.and_then(|from| from.and_hms_opt(0, 0, 0));
+    let to = query
+        .to
+        .and_then(|to| to.succ_opt())
+        .and_then(|to| to.and_hms_opt(0, 0, 0));
+
+    match medical_record::get_patient_vital_signs(&data.db, patient_id, from, to).await {
+        Ok(readings) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": {
+                "patient_id": patient_id,
+                "from": query.from,
+                "to": query.to,
+                "series": vitals::trend(&readings, &metrics)
+            },
+            "message": "Vital signs trend retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve vital signs trend: {}", e)
+        })),
+    }
+}
+
+#[get("email/{email}", wrap = "Require(Permission::PatientRead)")]
 pub async fn get_patient_id_by_email(
     data: web::Data<crate::AppState>,
     email: web::Path<String>,
+    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
     match patient::get_patient_id_by_email(&data.db, email.into_inner()).await {
-        Ok(patient_id) => HttpResponse::Ok().json(json!({
-            "success": true,
-            "data": patient_id,
-            "message": "Patient ID retrieved successfully"
-        })),
+        Ok(patient_id) => {
+            if let Err(response) =
+                access::check_patient_access(&data, &claims, Some(patient_id)).await
+            {
+                return response;
+            }
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": patient_id,
+                "message": "Patient ID retrieved successfully"
+            }))
+        }
         Err(e) => HttpResponse::InternalServerError().json(json!({
             "success": false,
             "message": format!("Failed to retrieve patient ID: {}", e)
@@ -97,7 +175,7 @@ pub async fn get_patient_id_by_email(
     }
 }
 
-#[put("/{id}")]
+#[put("/{id}", wrap = "Require(Permission::PatientUpdate)")]
 pub async fn update_patient(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
@@ -105,6 +183,9 @@ pub async fn update_patient(
     update_req: web::Json<UpdatePatientForm>,
 ) -> HttpResponse {
     let patient_id = path.into_inner();
+    if let Err(response) = access::check_patient_access(&data, &claims, Some(patient_id)).await {
+        return response;
+    }
 
     match patient::update_patient(&data.db, update_req.into_inner(), patient_id).await {
         Ok(updated_patient) => HttpResponse::Ok().json(json!({
@@ -119,20 +200,11 @@ pub async fn update_patient(
     }
 }
 
-#[post("")]
+#[post("", wrap = "Require(Permission::PatientCreate)")]
 pub async fn create_patient(
     data: web::Data<crate::AppState>,
-    claims: web::ReqData<Claims>,
     body: web::Json<PatientForm>,
 ) -> HttpResponse {
-    // Check if user has staff role
-    if claims.role != "staff" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Staff access required"
-        }));
-    }
-
     let create_at = chrono::Utc::now().naive_utc();
     let update_at = chrono::Utc::now().naive_utc();
 
@@ -148,20 +220,11 @@ pub async fn create_patient(
     }
 }
 
-#[get("/{phone}")]
+#[get("/{phone}", wrap = "Require(Permission::PatientAny)")]
 pub async fn get_patient_by_phone(
     data: web::Data<crate::AppState>,
     path: web::Path<String>,
-    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    // Check if user has doctor role
-    if claims.role != "staff" {
-        return HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Staff access required"
-        }));
-    }
-
     match patient::get_patient_by_phone(&data.db, path.into_inner()).await {
         Ok(patient) => HttpResponse::Ok().json(json!({
             "success": true,
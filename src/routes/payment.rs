@@ -1,15 +1,17 @@
 use crate::authentication::Claims;
-use crate::db::payment;
-use crate::models::Invoice;
+use crate::db::{medical_record, payment, service};
+use crate::error::Error;
+use crate::models::{Invoice, UserRole};
+use crate::middleware::permission::{Permission, Require};
 use actix_web::{get, post, web, HttpResponse};
 use serde_json::json;
 
-#[get("/self-invoices")]
+#[get("/self-invoices", wrap = "Require(Permission::InvoiceReadOwn)")]
 pub async fn get_self_invoices(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    let user_id = claims.sub.parse::<i32>().unwrap();
+    let user_id = claims.profile_id;
     match payment::get_invoices_of_user(&data.db, user_id).await {
         Ok(invoices) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -23,15 +25,11 @@ pub async fn get_self_invoices(
     }
 }
 
-#[get("/invoices/{id}")]
+#[get("/invoices/{id}", wrap = "Require(Permission::InvoiceRead)")]
 pub async fn get_invoices_of_medical_record(
     data: web::Data<crate::AppState>,
     path: web::Path<i32>,
-    claims: web::ReqData<Claims>,
 ) -> HttpResponse {
-    // Check if user has receptionist role
-    check_receptionist(&claims);
-
     match payment::get_invoices_of_medical_record(&data.db, path.into_inner()).await {
         Ok(invoices) => HttpResponse::Ok().json(json!({
             "success": true,
@@ -45,20 +43,63 @@ pub async fn get_invoices_of_medical_record(
     }
 }
 
-#[post("/invoices")]
+#[post("/invoices", wrap = "Require(Permission::InvoiceCreate)")]
 pub async fn create_invoice(
     data: web::Data<crate::AppState>,
     claims: web::ReqData<Claims>,
     body: web::Json<Invoice>,
 ) -> HttpResponse {
-    // Check if user has receptionist role
-    if claims.role != "doctor" {
-        return HttpResponse::Unauthorized().json(json!({
-            "success": false,
-            "message": "You are not authorized to create an invoice"
-        }));
+    // Only services the treating doctor provides can be billed
+    let invoice = body.into_inner();
+    if let Some(service_ids) = invoice.service_ids.as_deref().filter(|ids| !ids.is_empty()) {
+        let record_doctor = match invoice.medical_record_id {
+            Some(record_id) => match medical_record::get_doctor_id(&data.db, record_id).await {
+                Ok(doctor_id) => doctor_id,
+                Err(Error::NotFound) => {
+                    return HttpResponse::NotFound().json(json!({
+                        "success": false,
+                        "message": "Medical record not found"
+                    }));
+                }
+                Err(e) => {
+                    return HttpResponse::InternalServerError().json(json!({
+                        "success": false,
+                        "message": format!("Failed to create invoice: {}", e)
+                    }));
+                }
+            },
+            None => None,
+        };
+        // Without a doctor on the record, services are only billed as the caller's own
+        let doctor_id = match record_doctor {
+            Some(doctor_id) => doctor_id,
+            None if claims.role == UserRole::Doctor => claims.profile_id,
+            None => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "message": "The medical record has no doctor to bill services for"
+                }));
+            }
+        };
+        match service::get_services_not_provided(&data.db, doctor_id, service_ids).await {
+            Ok(missing) if missing.is_empty() => {}
+            Ok(missing) => {
+                return HttpResponse::BadRequest().json(json!({
+                    "success": false,
+                    "data": missing,
+                    "message": "The doctor does not provide some of the billed services"
+                }));
+            }
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to create invoice: {}", e)
+                }));
+            }
+        }
     }
-    match payment::create_invoice(&data.db, body.into_inner()).await {
+
+    match payment::create_invoice(&data.db, invoice).await {
         Ok(_) => HttpResponse::Ok().json(json!({
             "success": true,
             "message": "Invoice created successfully"
@@ -69,13 +110,3 @@ pub async fn create_invoice(
         })),
     }
 }
-
-fn check_receptionist(claims: &Claims) -> Result<(), HttpResponse> {
-    if claims.role != "receptionist" {
-        return Err(HttpResponse::Forbidden().json(json!({
-            "success": false,
-            "message": "Receptionist access required"
-        })));
-    }
-    Ok(())
-}
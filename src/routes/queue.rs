@@ -0,0 +1,275 @@
+use crate::db::queue;
+use crate::error::Error;
+use crate::models::{DisplayTokenQuery, QueueEvent, QueueSnapshot, RoomOccupants};
+use crate::AppState;
+use actix_web::web::Bytes;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use chrono::{Local, NaiveDate};
+use futures_util::stream;
+use serde_json::json;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+const KEEP_ALIVE_SECONDS: u64 = 15;
+
+// Tells connected displays that the queue of a specialty changed on a given day
+pub fn notify(data: &AppState, speciality_id: Option<i32>, date: Option<NaiveDate>) {
+    if let (Some(speciality_id), Some(date)) = (speciality_id, date) {
+        // An error only means no display is connected
+        let _ = data.queue_events.send(QueueEvent {
+            speciality_id: Some(speciality_id),
+            date,
+        });
+    }
+}
+
+// Tells connected room displays that doctors moved between rooms on a given day
+pub fn notify_rooms(data: &AppState, date: NaiveDate) {
+    let _ = data.queue_events.send(QueueEvent {
+        speciality_id: None,
+        date,
+    });
+}
+
+#[derive(Clone)]
+enum QueueTarget {
+    Specialty(i32),
+    // Who sat in the room at the last snapshot
+    Room(i32, Option<RoomOccupants>),
+}
+
+impl QueueTarget {
+    fn matches(&self, event: &QueueEvent) -> bool {
+        match (self, event.speciality_id) {
+            (QueueTarget::Specialty(id), Some(speciality_id)) => *id == speciality_id,
+            (QueueTarget::Specialty(_), None) => false,
+            (QueueTarget::Room(_, Some(occupants)), Some(speciality_id)) => {
+                occupants.speciality_ids.contains(&speciality_id)
+            }
+            (QueueTarget::Room(..), _) => true,
+        }
+    }
+
+    // Whether the doctors in the room changed since the last snapshot, as shifts start
+    // and end without any event
+    async fn occupants_changed(&self, data: &AppState) -> bool {
+        match self {
+            QueueTarget::Specialty(_) => false,
+            QueueTarget::Room(id, occupants) => {
+                let now = Local::now().naive_local();
+                queue::get_room_occupants(&data.db, *id, now).await.ok() != *occupants
+            }
+        }
+    }
+
+    // A room's doctors are looked up again each time, as they may have moved
+    async fn snapshot(&mut self, data: &AppState, date: NaiveDate) -> Result<QueueSnapshot, Error> {
+        match self {
+            QueueTarget::Specialty(id) => queue::get_specialty_queue(&data.db, *id, date).await,
+            QueueTarget::Room(id, occupants) => {
+                let now = Local::now().naive_local();
+                let current = queue::get_room_occupants(&data.db, *id, now).await?;
+                let snapshot = queue::get_room_queue(&data.db, &current, date).await;
+                *occupants = Some(current);
+                snapshot
+            }
+        }
+    }
+}
+
+#[get("/specialty/{id}")]
+pub async fn get_specialty_queue(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+    query: web::Query<DisplayTokenQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = check_display_token(&data, &query, &req) {
+        return response;
+    }
+    snapshot_response(&data, QueueTarget::Specialty(path.into_inner())).await
+}
+
+#[get("/room/{id}")]
+pub async fn get_room_queue(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+    query: web::Query<DisplayTokenQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = check_display_token(&data, &query, &req) {
+        return response;
+    }
+    snapshot_response(&data, QueueTarget::Room(path.into_inner(), None)).await
+}
+
+#[get("/specialty/{id}/stream")]
+pub async fn stream_specialty_queue(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+    query: web::Query<DisplayTokenQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = check_display_token(&data, &query, &req) {
+        return response;
+    }
+    event_stream(data, QueueTarget::Specialty(path.into_inner()))
+}
+
+#[get("/room/{id}/stream")]
+pub async fn stream_room_queue(
+    data: web::Data<AppState>,
+    path: web::Path<i32>,
+    query: web::Query<DisplayTokenQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    if let Err(response) = check_display_token(&data, &query, &req) {
+        return response;
+    }
+    let room_id = path.into_inner();
+    match queue::get_room_occupants(&data.db, room_id, Local::now().naive_local()).await {
+        Ok(occupants) => event_stream(data, QueueTarget::Room(room_id, Some(occupants))),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve queue: {}", e)
+        })),
+    }
+}
+
+async fn snapshot_response(data: &AppState, mut target: QueueTarget) -> HttpResponse {
+    match target.snapshot(data, Local::now().date_naive()).await {
+        Ok(snapshot) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": snapshot,
+            "message": "Queue retrieved successfully"
+        })),
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Room not found"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve queue: {}", e)
+        })),
+    }
+}
+
+struct StreamState {
+    data: web::Data<AppState>,
+    target: QueueTarget,
+    events: Receiver<QueueEvent>,
+    keep_alive: tokio::time::Interval,
+    send_snapshot: bool,
+}
+
+// Server-sent events: the current snapshot on connect, a new one after every relevant
+// change to today's queue, and a comment line every few seconds to keep proxies from
+// closing an idle connection. A room also gets a new snapshot when a shift starts or ends,
+// noticed at the next keep-alive.
+fn event_stream(data: web::Data<AppState>, target: QueueTarget) -> HttpResponse {
+    let mut keep_alive = tokio::time::interval(Duration::from_secs(KEEP_ALIVE_SECONDS));
+    keep_alive.reset();
+    let state = StreamState {
+        events: data.queue_events.subscribe(),
+        data,
+        target,
+        keep_alive,
+        send_snapshot: true,
+    };
+
+    let body = stream::unfold(state, |mut state| async move {
+        loop {
+            if state.send_snapshot {
+                state.send_snapshot = false;
+                let today = Local::now().date_naive();
+                let chunk = match state.target.snapshot(&state.data, today).await {
+                    Ok(snapshot) => format!(
+                        "event: queue\ndata: {}\n\n",
+                        serde_json::to_string(&snapshot).unwrap_or_default()
+                    ),
+                    Err(e) => format!("event: error\ndata: {}\n\n", e),
+                };
+                return Some((Ok::<_, Infallible>(Bytes::from(chunk)), state));
+            }
+
+            tokio::select! {
+                event = state.events.recv() => match event {
+                    Ok(event) => {
+                        state.send_snapshot = state.target.matches(&event)
+                            && event.date == Local::now().date_naive();
+                    }
+                    // Missed some events; the next snapshot covers them
+                    Err(RecvError::Lagged(_)) => state.send_snapshot = true,
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = state.keep_alive.tick() => {
+                    if state.target.occupants_changed(&state.data).await {
+                        state.send_snapshot = true;
+                        continue;
+                    }
+                    return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), state));
+                }
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
+
+// Lobby screens authenticate with a shared display token instead of a user JWT, passed
+// as ?token= (EventSource cannot set headers) or in the X-Display-Token header.
+fn check_display_token(
+    data: &AppState,
+    query: &DisplayTokenQuery,
+    req: &HttpRequest,
+) -> Result<(), HttpResponse> {
+    let provided = query.token.clone().or_else(|| {
+        req.headers()
+            .get("X-Display-Token")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    });
+
+    match (&data.queue_display_token, provided) {
+        (Some(expected), Some(provided))
+            if constant_time_eq(expected.as_bytes(), provided.as_bytes()) =>
+        {
+            Ok(())
+        }
+        (None, _) => Err(HttpResponse::Forbidden().json(json!({
+            "success": false,
+            "message": "Queue display is not enabled"
+        }))),
+        _ => Err(HttpResponse::Unauthorized().json(json!({
+            "success": false,
+            "message": "Invalid display token"
+        }))),
+    }
+}
+
+// Compares every byte, so the time taken does not tell how much of a guess was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_tokens_must_match_exactly() {
+        assert!(constant_time_eq(b"lobby-screen", b"lobby-screen"));
+        assert!(!constant_time_eq(b"lobby-screen", b"lobby-screeN"));
+        assert!(!constant_time_eq(b"lobby-screen", b"lobby"));
+        assert!(!constant_time_eq(b"", b"lobby"));
+    }
+}
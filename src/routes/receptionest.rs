@@ -0,0 +1,259 @@
+use crate::authentication::Claims;
+use crate::db::appointment::Actor;
+use crate::db::{patient, receptionest, schedule, specialty};
+use crate::error::Error;
+use crate::models::{
+    CheckInRequest, Patient, PatientForm, PhoneSearchQuery, QueuePriority, ReceptionQueueQuery,
+    WalkInForm,
+};
+use crate::routes::queue;
+use crate::middleware::permission::{Permission, Require};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{Local, Utc};
+use serde_json::json;
+
+#[get("/patients", wrap = "Require(Permission::ReceptionDesk)")]
+pub async fn search_patient_by_phone(
+    data: web::Data<crate::AppState>,
+    query: web::Query<PhoneSearchQuery>,
+) -> HttpResponse {
+    match find_patient_by_phone(&data, query.into_inner().phone).await {
+        Ok(Some(patient)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": patient,
+            "message": "Patient retrieved successfully"
+        })),
+        Ok(None) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "No patient with this phone number"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve patient: {}", e)
+        })),
+    }
+}
+
+// Returns the patient registered with the phone number, creating them if there is none
+#[post("/patients", wrap = "Require(Permission::ReceptionDesk)")]
+pub async fn find_or_create_patient(
+    data: web::Data<crate::AppState>,
+    body: web::Json<PatientForm>,
+) -> HttpResponse {
+    match find_or_create(&data, body.into_inner()).await {
+        Ok((patient, created)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": {
+                "patient": patient,
+                "created": created
+            },
+            "message": if created { "Patient created successfully" } else { "Patient already registered" }
+        })),
+        Err(response) => response,
+    }
+}
+
+#[post("/appointments/{id}/check-in", wrap = "Require(Permission::ReceptionDesk)")]
+pub async fn check_in_appointment(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    claims: web::ReqData<Claims>,
+    body: Option<web::Json<CheckInRequest>>,
+) -> HttpResponse {
+    let actor = Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    let priority = body.and_then(|body| body.into_inner().priority);
+    match receptionest::check_in_appointment(&data.db, path.into_inner(), priority, &actor).await {
+        Ok(appointment) => {
+            queue::notify(&data, appointment.speciality_id, appointment.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": appointment,
+                "message": "Patient checked in successfully"
+            }))
+        }
+        Err(Error::NotFound) => HttpResponse::NotFound().json(json!({
+            "success": false,
+            "message": "Appointment not found"
+        })),
+        Err(Error::Conflict(message)) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": format!("Failed to check in: {}", message)
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to check in: {}", e)
+        })),
+    }
+}
+
+#[post("/walk-ins", wrap = "Require(Permission::ReceptionDesk)")]
+pub async fn register_walk_in(
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<WalkInForm>,
+) -> HttpResponse {
+    let form = body.into_inner();
+    match specialty::get_specialty(&data.db, form.speciality_id).await {
+        Ok(_) => {}
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Specialty not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to register walk-in: {}", e)
+            }));
+        }
+    }
+
+    let patient = match form.patient_id {
+        Some(patient_id) => match patient::get_patient_by_id(&data.db, &patient_id).await {
+            Ok(patient) => patient,
+            Err(sqlx::Error::RowNotFound) => {
+                return HttpResponse::NotFound().json(json!({
+                    "success": false,
+                    "message": "Patient not found"
+                }));
+            }
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to register walk-in: {}", e)
+                }));
+            }
+        },
+        None => {
+            let patient_form = PatientForm {
+                phone: form.phone,
+                name: form.name,
+                gender: form.gender,
+                birthday: form.birthday,
+                address: form.address,
+            };
+            match find_or_create(&data, patient_form).await {
+                Ok((patient, _)) => patient,
+                Err(response) => return response,
+            }
+        }
+    };
+
+    let today = Local::now().date_naive();
+    let doctors =
+        match schedule::get_working_doctors(&data.db, form.speciality_id, today, None).await {
+            Ok(doctors) => doctors,
+            Err(e) => {
+                return HttpResponse::InternalServerError().json(json!({
+                    "success": false,
+                    "message": format!("Failed to register walk-in: {}", e)
+                }));
+            }
+        };
+
+    let actor = Actor {
+        id: Some(claims.profile_id),
+        role: claims.role.as_str(),
+    };
+    match receptionest::create_walk_in(
+        &data.db,
+        &patient,
+        form.speciality_id,
+        form.reason,
+        form.priority.unwrap_or(QueuePriority::Normal),
+        &doctors,
+        &actor,
+    )
+    .await
+    {
+        Ok(appointment) => {
+            queue::notify(&data, appointment.speciality_id, appointment.date);
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "data": appointment,
+                "message": "Walk-in registered successfully"
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to register walk-in: {}", e)
+        })),
+    }
+}
+
+// Full queue of a specialty with patient details, for the reception desk
+#[get("/queue/{speciality_id}", wrap = "Require(Permission::ReceptionDesk)")]
+pub async fn get_reception_queue(
+    data: web::Data<crate::AppState>,
+    path: web::Path<i32>,
+    query: web::Query<ReceptionQueueQuery>,
+) -> HttpResponse {
+    let date = query.date.unwrap_or_else(|| Local::now().date_naive());
+    match receptionest::get_reception_queue(&data.db, path.into_inner(), date).await {
+        Ok(appointments) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": appointments,
+            "message": "Queue retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve queue: {}", e)
+        })),
+    }
+}
+
+async fn find_patient_by_phone(
+    data: &crate::AppState,
+    phone: String,
+) -> Result<Option<Patient>, sqlx::Error> {
+    match patient::get_patient_by_phone(&data.db, phone).await {
+        Ok(patient) => Ok(Some(patient)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+async fn find_or_create(
+    data: &crate::AppState,
+    form: PatientForm,
+) -> Result<(Patient, bool), HttpResponse> {
+    let phone = match form.phone.as_deref().map(str::trim) {
+        Some(phone) if !phone.is_empty() => phone.to_string(),
+        _ => {
+            return Err(HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Phone number is required"
+            })));
+        }
+    };
+
+    match find_patient_by_phone(data, phone.clone()).await {
+        Ok(Some(patient)) => return Ok((patient, false)),
+        Ok(None) => {}
+        Err(e) => {
+            return Err(HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve patient: {}", e)
+            })));
+        }
+    }
+
+    let now = Utc::now().naive_utc();
+    let form = PatientForm {
+        phone: Some(phone),
+        ..form
+    };
+    patient::create_patient(&data.db, form, now, now)
+        .await
+        .map(|patient| (patient, true))
+        .map_err(|e| {
+            HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to create patient: {}", e)
+            }))
+        })
+}
@@ -2,6 +2,8 @@ use actix_web::{get, post, put, delete, web, HttpResponse};
 use crate::AppState;
 use crate::models::{Service, ServiceCreateForm};
 use crate::db::service;
+use crate::error::Error;
+use crate::middleware::permission::{Permission, Require};
 use serde_json::json;
 
 #[get("/all")]
@@ -37,7 +39,43 @@ pub async fn get_service_by_id(
     }
 }
 
-#[post("")]
+// Doctors who perform the service, with their specialty and room
+#[get("/{id}/doctors")]
+pub async fn get_doctors_of_service(
+    data: web::Data<AppState>,
+    path: web::Path<i32>
+) -> HttpResponse {
+    let service_id = path.into_inner();
+    match service::get_service_by_id(&data.db, service_id).await {
+        Ok(_) => {}
+        Err(Error::NotFound) => {
+            return HttpResponse::NotFound().json(json!({
+                "success": false,
+                "message": "Service not found"
+            }));
+        }
+        Err(e) => {
+            return HttpResponse::InternalServerError().json(json!({
+                "success": false,
+                "message": format!("Failed to retrieve doctors: {}", e)
+            }));
+        }
+    }
+
+    match service::get_doctors_of_service(&data.db, service_id).await {
+        Ok(doctors) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": doctors,
+            "message": "Doctors retrieved successfully"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to retrieve doctors: {}", e)
+        })),
+    }
+}
+
+#[post("", wrap = "Require(Permission::ServiceWrite)")]
 pub async fn create_service(
     data: web::Data<AppState>,
     body: web::Json<ServiceCreateForm>
@@ -55,7 +93,7 @@ pub async fn create_service(
     }
 }
 
-#[put("/{id}")]
+#[put("/{id}", wrap = "Require(Permission::ServiceWrite)")]
 pub async fn update_service(
     data: web::Data<AppState>,
     path: web::Path<i32>,
@@ -73,7 +111,7 @@ pub async fn update_service(
     }
 }
 
-#[delete("/{id}")]
+#[delete("/{id}", wrap = "Require(Permission::ServiceWrite)")]
 pub async fn delete_service(
     data: web::Data<AppState>,
     path: web::Path<i32>
@@ -1,6 +1,7 @@
 use crate::db::specialty;
 use crate::models::Speciality;
 use crate::AppState;
+use crate::middleware::permission::{Permission, Require};
 use actix_web::{get, post, put, delete, web, HttpResponse};
 use crate::error::Error;
 
@@ -22,7 +23,7 @@ pub async fn get_specialty_by_id(data: web::Data<AppState>, path: web::Path<i32>
     }
 }
 
-#[post("")]
+#[post("", wrap = "Require(Permission::SpecialtyWrite)")]
 pub async fn create_speciality(
     data: web::Data<AppState>,
     body: web::Json<Speciality>,
@@ -33,7 +34,7 @@ pub async fn create_speciality(
     }
 }
 
-#[put("/update")]
+#[put("/update", wrap = "Require(Permission::SpecialtyWrite)")]
 pub async fn update_speciality(
     data: web::Data<AppState>,
     path: web::Path<i32>,
@@ -45,7 +46,7 @@ pub async fn update_speciality(
     }
 }
 
-#[delete("/delete")]
+#[delete("/delete", wrap = "Require(Permission::SpecialtyWrite)")]
 pub async fn delete_specialty(data: web::Data<AppState>, path: web::Path<i32>) -> HttpResponse {
     match specialty::delete_specialty(&data.db, path.into_inner()).await {
         Ok(_) => HttpResponse::Ok().json("Specialty deleted successfully"),
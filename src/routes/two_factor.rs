@@ -0,0 +1,375 @@
+use crate::authentication::{
+    client_ip, create_session, hash_token, issue_tokens, login_database_error, record_failed_login,
+    too_many_attempts, Claims, SessionStart, TokenKind,
+};
+use crate::db::{authentication, login_attempt, security_event, token, two_factor};
+use crate::models::{SecurityEventType, TwoFactorCodeRequest};
+use crate::totp;
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use rand::distributions::{Alphanumeric, DistString};
+use serde_json::json;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// Starts enrollment: a new secret, returned as is and as the otpauth URI for QR codes.
+// Nothing changes for logins until `enable` confirms a code from the app.
+#[post("/enroll")]
+pub async fn enroll(data: web::Data<crate::AppState>, claims: web::ReqData<Claims>) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let user = match authentication::get_user_by_id(&data.db, user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return invalid_token(),
+        Err(e) => return database_error(e),
+    };
+
+    let secret = totp::generate_secret();
+    match two_factor::start_enrollment(&data.db, user_id, &secret).await {
+        Ok(true) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "data": {
+                "secret": secret,
+                "otpauth_uri": totp::otpauth_uri(&data.totp_issuer, &user.email, &secret)
+            }
+        })),
+        Ok(false) => HttpResponse::Conflict().json(json!({
+            "success": false,
+            "message": "Two-factor authentication is already enabled"
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+// Confirms enrollment with a code from the app and hands out the recovery codes, shown
+// only this once. A login that was waiting for the enrollment is finished here as well.
+#[post("/enable")]
+pub async fn enable(
+    req: HttpRequest,
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<TwoFactorCodeRequest>,
+) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let ip = client_ip(&req, data.trust_forwarded_for);
+    if let Err(response) = check_locks(&data, user_id, &ip).await {
+        return response;
+    }
+    let secret = match two_factor::get_secret(&data.db, user_id).await {
+        Ok(Some((_, true))) => {
+            return HttpResponse::Conflict().json(json!({
+                "success": false,
+                "message": "Two-factor authentication is already enabled"
+            }));
+        }
+        Ok(Some((secret, false))) => secret,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(json!({
+                "success": false,
+                "message": "Start enrollment first"
+            }));
+        }
+        Err(e) => return database_error(e),
+    };
+
+    let Some(step) = body
+        .code
+        .as_deref()
+        .and_then(|code| totp::matching_step(&secret, code, Utc::now().timestamp()))
+    else {
+        return wrong_code(&data, user_id, &ip).await;
+    };
+    match two_factor::claim_step(&data.db, user_id, step).await {
+        Ok(true) => {}
+        Ok(false) => return wrong_code(&data, user_id, &ip).await,
+        Err(e) => return database_error(e),
+    }
+
+    let recovery_codes = new_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    if let Err(e) = two_factor::enable(&data.db, user_id, &hashes).await {
+        return database_error(e);
+    }
+    let _ = security_event::record(
+        &data.db,
+        SecurityEventType::TwoFactorEnabled,
+        Some(user_id),
+        None,
+        None,
+        None,
+    )
+    .await;
+
+    if claims.kind != TokenKind::SecondFactorPending {
+        return HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Two-factor authentication enabled",
+            "recovery_codes": recovery_codes
+        }));
+    }
+
+    let session = async {
+        end_pending_token(&data, &claims, user_id).await?;
+        login_attempt::clear_account_failures(&data.db, user_id).await?;
+        match authentication::get_account_by_user_id(&data.db, user_id).await? {
+            Some(account) => create_session(&data, &account, SessionStart::Login(&req)).await.map(Some),
+            None => Ok(None),
+        }
+    }
+    .await;
+    match session {
+        Ok(Some(session)) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "message": "Two-factor authentication enabled",
+            "recovery_codes": recovery_codes,
+            "data": session.data,
+            "user_data": session.user_data
+        })),
+        Ok(None) => invalid_token(),
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to issue tokens: {}", e)
+        })),
+    }
+}
+
+// Second step of a login: trades the pending token and a code, or a recovery code, for
+// the real tokens
+#[post("/verify")]
+pub async fn verify(
+    req: HttpRequest,
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<TwoFactorCodeRequest>,
+) -> HttpResponse {
+    if claims.kind != TokenKind::SecondFactorPending {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "No sign-in is waiting for a second factor"
+        }));
+    }
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let account = match authentication::get_account_by_user_id(&data.db, user_id).await {
+        Ok(Some(account)) if account.active == 1 => account,
+        Ok(_) => return invalid_token(),
+        Err(_) => return login_database_error(),
+    };
+    let ip = client_ip(&req, data.trust_forwarded_for);
+    if let Err(response) = check_locks(&data, user_id, &ip).await {
+        return response;
+    }
+    if account.totp_enabled == 0 {
+        return HttpResponse::BadRequest().json(json!({
+            "success": false,
+            "message": "Two-factor authentication is not set up; enroll first"
+        }));
+    }
+
+    match check_second_factor(&data, user_id, &body).await {
+        Ok(true) => {}
+        Ok(false) => return wrong_code(&data, user_id, &ip).await,
+        Err(_) => return login_database_error(),
+    }
+
+    if end_pending_token(&data, &claims, user_id).await.is_err()
+        || login_attempt::clear_account_failures(&data.db, user_id).await.is_err()
+    {
+        return login_database_error();
+    }
+    match issue_tokens(&data, &account, SessionStart::Login(&req)).await {
+        Ok(response) => response,
+        Err(e) => HttpResponse::InternalServerError().json(json!({
+            "success": false,
+            "message": format!("Failed to issue tokens: {}", e)
+        })),
+    }
+}
+
+// Turns the second factor off, confirmed with a current code or a recovery code. Not
+// possible while the role requires it.
+#[post("/2fa/disable")]
+pub async fn disable(
+    req: HttpRequest,
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<TwoFactorCodeRequest>,
+) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let ip = client_ip(&req, data.trust_forwarded_for);
+    if let Err(response) = check_locks(&data, user_id, &ip).await {
+        return response;
+    }
+    match two_factor::is_required(&data.db, claims.role).await {
+        Ok(false) => {}
+        Ok(true) => {
+            return HttpResponse::Forbidden().json(json!({
+                "success": false,
+                "message": "Two-factor authentication is required for your role"
+            }));
+        }
+        Err(e) => return database_error(e),
+    }
+    match check_second_factor(&data, user_id, &body).await {
+        Ok(true) => {}
+        Ok(false) => return wrong_code(&data, user_id, &ip).await,
+        Err(e) => return database_error(e),
+    }
+
+    match two_factor::disable(&data.db, user_id).await {
+        Ok(_) => {
+            let _ = security_event::record(
+                &data.db,
+                SecurityEventType::TwoFactorDisabled,
+                Some(user_id),
+                None,
+                None,
+                None,
+            )
+            .await;
+            HttpResponse::Ok().json(json!({
+                "success": true,
+                "message": "Two-factor authentication disabled"
+            }))
+        }
+        Err(e) => database_error(e),
+    }
+}
+
+// New recovery codes, confirmed with a current code; the old ones stop working
+#[post("/2fa/recovery-codes")]
+pub async fn regenerate_recovery_codes(
+    req: HttpRequest,
+    data: web::Data<crate::AppState>,
+    claims: web::ReqData<Claims>,
+    body: web::Json<TwoFactorCodeRequest>,
+) -> HttpResponse {
+    let Ok(user_id) = claims.sub.parse::<i32>() else {
+        return invalid_token();
+    };
+    let ip = client_ip(&req, data.trust_forwarded_for);
+    if let Err(response) = check_locks(&data, user_id, &ip).await {
+        return response;
+    }
+    match check_second_factor(&data, user_id, &body).await {
+        Ok(true) => {}
+        Ok(false) => return wrong_code(&data, user_id, &ip).await,
+        Err(e) => return database_error(e),
+    }
+
+    let recovery_codes = new_recovery_codes();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| hash_recovery_code(code)).collect();
+    match two_factor::set_recovery_codes(&data.db, user_id, &hashes).await {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "success": true,
+            "recovery_codes": recovery_codes
+        })),
+        Err(e) => database_error(e),
+    }
+}
+
+// Whether the request carries a valid code from the enabled authenticator or an unused
+// recovery code
+async fn check_second_factor(
+    data: &crate::AppState,
+    user_id: i32,
+    body: &TwoFactorCodeRequest,
+) -> Result<bool, sqlx::Error> {
+    if let Some(code) = body.code.as_deref() {
+        let Some((secret, true)) = two_factor::get_secret(&data.db, user_id).await? else {
+            return Ok(false);
+        };
+        return match totp::matching_step(&secret, code, Utc::now().timestamp()) {
+            Some(step) => two_factor::claim_step(&data.db, user_id, step).await,
+            None => Ok(false),
+        };
+    }
+    if let Some(recovery_code) = body.recovery_code.as_deref() {
+        return two_factor::use_recovery_code(&data.db, user_id, &hash_recovery_code(recovery_code))
+            .await;
+    }
+    Ok(false)
+}
+
+// Locked addresses and accounts are turned away before any code is looked at, as at login
+async fn check_locks(data: &crate::AppState, user_id: i32, ip: &str) -> Result<(), HttpResponse> {
+    let locked_until = async {
+        let ip_locked_until = login_attempt::ip_locked_until(&data.db, ip).await?;
+        let account_locked_until = login_attempt::account_locked_until(&data.db, user_id).await?;
+        Ok::<_, sqlx::Error>(ip_locked_until.max(account_locked_until))
+    };
+    match locked_until.await {
+        Ok(Some(locked_until)) => Err(too_many_attempts(locked_until)),
+        Ok(None) => Ok(()),
+        Err(e) => Err(database_error(e)),
+    }
+}
+
+// Wrong codes count towards the lockouts like wrong passwords, whichever handler they are
+// sent to, so a stolen session cannot guess its way through the six digits
+async fn wrong_code(data: &crate::AppState, user_id: i32, ip: &str) -> HttpResponse {
+    match record_failed_login(data, Some(user_id), None, ip, "wrong second factor").await {
+        Ok(()) => invalid_code(),
+        Err(e) => database_error(e),
+    }
+}
+
+// The pending token has done its job; it must not be traded in a second time
+async fn end_pending_token(
+    data: &crate::AppState,
+    claims: &Claims,
+    user_id: i32,
+) -> Result<(), crate::error::Error> {
+    let expires_at = DateTime::from_timestamp(claims.exp, 0)
+        .unwrap_or_else(Utc::now)
+        .naive_utc();
+    token::revoke_access_token(&data.db, &claims.jti, user_id, expires_at).await
+}
+
+// Codes look like `abcde-12345`; dashes, spaces and case do not matter when entered
+fn new_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = Alphanumeric
+                .sample_string(&mut rand::thread_rng(), 10)
+                .to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+fn invalid_code() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "success": false,
+        "message": "Invalid code"
+    }))
+}
+
+fn invalid_token() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({
+        "success": false,
+        "message": "Invalid token"
+    }))
+}
+
+fn database_error(e: sqlx::Error) -> HttpResponse {
+    HttpResponse::InternalServerError().json(json!({
+        "success": false,
+        "message": format!("Database error: {}", e)
+    }))
+}
@@ -0,0 +1,65 @@
+// Time-based one-time passwords (RFC 6238) as authenticator apps expect them: HMAC-SHA1,
+// six digits, 30 second steps, secrets shared as unpadded base32.
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+// Codes from one step before or after are accepted too, for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    base32::encode(ALPHABET, &secret)
+}
+
+// The time step `code` belongs to, if it is valid at `unix_time`. Only exactly six digits
+// count; `parse` alone would also take `+12345` or `0000123456`.
+pub fn matching_step(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let key = base32::decode(ALPHABET, secret)?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = unix_time / STEP_SECONDS;
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS).find(|&step| code_at(&key, step) == code)
+}
+
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    (value & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+// What authenticator apps scan, usually shown as a QR code
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_component(issuer),
+        encode_component(account),
+        secret,
+        encode_component(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn encode_component(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,320 @@
+// Vital sign readings: converting entered units to the stored ones, rejecting values no
+// living patient can have, BMI, flagging readings outside the reference ranges, and
+// series across visits for charting.
+use crate::models::{
+    AgeGroup, HeightUnit, TemperatureUnit, TrendMetric, Vital, VitalFlag, VitalFlagLevel,
+    VitalReferenceRange, VitalSign, VitalSignForm, VitalSignReading, VitalSignUnits, VitalTrend,
+    VitalTrendPoint, WeightUnit,
+};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+
+pub const UNITS: VitalSignUnits = VitalSignUnits {
+    temperature: "°C",
+    blood_pressure: "mmHg",
+    heart_rate: "bpm",
+    spo2: "%",
+    weight: "kg",
+    height: "cm",
+    bmi: "kg/m²",
+};
+
+// Patients younger than this when a reading is taken are judged by the pediatric ranges
+const PEDIATRIC_BELOW_AGE: i32 = 18;
+// Readings may carry a time slightly ahead of the server's clock
+const ALLOWED_CLOCK_SKEW_MINUTES: i64 = 5;
+
+// The reading in stored units, or what is wrong with it. The id is assigned on insert.
+pub fn measure(
+    form: &VitalSignForm,
+    recorded_at: NaiveDateTime,
+    recorded_by: i32,
+    now: NaiveDateTime,
+) -> Result<VitalSign, Vec<String>> {
+    let mut errors = Vec::new();
+    // Values too large to convert are reported rather than left to overflow
+    let mut convert = |name: &str, value: Option<Decimal>, converted: Option<Decimal>| {
+        if value.is_some() && converted.is_none() {
+            errors.push(format!("{} is too large", name));
+        }
+        converted
+    };
+    let temperature = convert(
+        "temperature",
+        form.temperature,
+        form.temperature.and_then(|t| match form.temperature_unit {
+            Some(TemperatureUnit::Fahrenheit) => t
+                .checked_sub(Decimal::from(32))
+                .and_then(|t| t.checked_mul(Decimal::from(5)))
+                .map(|t| t / Decimal::from(9)),
+            _ => Some(t),
+        }),
+    );
+    let weight = convert(
+        "weight",
+        form.weight,
+        form.weight.and_then(|w| match form.weight_unit {
+            Some(WeightUnit::Lb) => w.checked_mul(Decimal::new(45359237, 8)),
+            _ => Some(w),
+        }),
+    );
+    let height = convert(
+        "height",
+        form.height,
+        form.height.and_then(|h| match form.height_unit {
+            Some(HeightUnit::In) => h.checked_mul(Decimal::new(254, 2)),
+            _ => Some(h),
+        }),
+    );
+    let vital_sign = VitalSign {
+        id: 0,
+        medical_record_id: Some(form.medical_record_id),
+        temperature: temperature.map(|t| t.round_dp(1)),
+        blood_pressure_systolic: form.blood_pressure_systolic,
+        blood_pressure_diastolic: form.blood_pressure_diastolic,
+        heart_rate: form.heart_rate,
+        spo2: form.spo2,
+        weight: weight.map(|w| w.round_dp(2)),
+        height: height.map(|h| h.round_dp(1)),
+        recorded_at,
+        recorded_by: Some(recorded_by),
+    };
+
+    let mut check = |name: &str, value: Option<Decimal>, min: Decimal, max: Decimal, unit: &str| {
+        if let Some(value) = value {
+            if value < min || value > max {
+                errors.push(format!(
+                    "{} must be between {} and {} {}",
+                    name, min, max, unit
+                ));
+            }
+        }
+    };
+    check(
+        "temperature",
+        vital_sign.temperature,
+        Decimal::new(250, 1),
+        Decimal::new(450, 1),
+        UNITS.temperature,
+    );
+    check(
+        "blood_pressure_systolic",
+        vital_sign.blood_pressure_systolic.map(Decimal::from),
+        Decimal::from(40),
+        Decimal::from(300),
+        UNITS.blood_pressure,
+    );
+    check(
+        "blood_pressure_diastolic",
+        vital_sign.blood_pressure_diastolic.map(Decimal::from),
+        Decimal::from(20),
+        Decimal::from(200),
+        UNITS.blood_pressure,
+    );
+    check(
+        "heart_rate",
+        vital_sign.heart_rate.map(Decimal::from),
+        Decimal::from(20),
+        Decimal::from(300),
+        UNITS.heart_rate,
+    );
+    check(
+        "spo2",
+        vital_sign.spo2.map(Decimal::from),
+        Decimal::from(50),
+        Decimal::from(100),
+        UNITS.spo2,
+    );
+    check(
+        "weight",
+        vital_sign.weight,
+        Decimal::new(3, 1),
+        Decimal::from(500),
+        UNITS.weight,
+    );
+    check(
+        "height",
+        vital_sign.height,
+        Decimal::from(20),
+        Decimal::from(280),
+        UNITS.height,
+    );
+    if let (Some(systolic), Some(diastolic)) = (
+        vital_sign.blood_pressure_systolic,
+        vital_sign.blood_pressure_diastolic,
+    ) {
+        if diastolic >= systolic {
+            errors.push(
+                "blood_pressure_diastolic must be lower than blood_pressure_systolic".to_string(),
+            );
+        }
+    }
+    let measured = [
+        form.temperature.is_some(),
+        form.blood_pressure_systolic.is_some(),
+        form.blood_pressure_diastolic.is_some(),
+        form.heart_rate.is_some(),
+        form.spo2.is_some(),
+        form.weight.is_some(),
+        form.height.is_some(),
+    ];
+    if !measured.contains(&true) {
+        errors.push("at least one measurement is required".to_string());
+    }
+    if recorded_at > now + Duration::minutes(ALLOWED_CLOCK_SKEW_MINUTES) {
+        errors.push("recorded_at cannot be in the future".to_string());
+    }
+
+    if errors.is_empty() {
+        Ok(vital_sign)
+    } else {
+        Err(errors)
+    }
+}
+
+// Weight over height squared, when both were measured and the height is above zero
+pub fn bmi(vital_sign: &VitalSign) -> Option<Decimal> {
+    let weight = vital_sign.weight?;
+    let height = vital_sign.height.filter(|h| *h > Decimal::ZERO)? / Decimal::from(100);
+    let bmi = weight.checked_div(height.checked_mul(height)?)?;
+    Some(bmi.round_dp(1))
+}
+
+// By age on the day of the reading; adult when the birthday (YYYY-MM-DD) is unknown
+pub fn age_group(birthday: Option<&str>, on: NaiveDate) -> AgeGroup {
+    let Some(birthday) = birthday.and_then(|b| NaiveDate::parse_from_str(b, "%Y-%m-%d").ok())
+    else {
+        return AgeGroup::Adult;
+    };
+    let mut age = on.year() - birthday.year();
+    if (on.month(), on.day()) < (birthday.month(), birthday.day()) {
+        age -= 1;
+    }
+    if age < PEDIATRIC_BELOW_AGE {
+        AgeGroup::Pediatric
+    } else {
+        AgeGroup::Adult
+    }
+}
+
+pub fn reading(
+    vital_sign: VitalSign,
+    age_group: AgeGroup,
+    ranges: &[VitalReferenceRange],
+) -> VitalSignReading {
+    let bmi = bmi(&vital_sign);
+    let values = [
+        (Vital::Temperature, vital_sign.temperature),
+        (
+            Vital::BloodPressureSystolic,
+            vital_sign.blood_pressure_systolic.map(Decimal::from),
+        ),
+        (
+            Vital::BloodPressureDiastolic,
+            vital_sign.blood_pressure_diastolic.map(Decimal::from),
+        ),
+        (Vital::HeartRate, vital_sign.heart_rate.map(Decimal::from)),
+        (Vital::Spo2, vital_sign.spo2.map(Decimal::from)),
+        (Vital::Bmi, bmi),
+    ];
+    let flags = values
+        .into_iter()
+        .filter_map(|(vital, value)| {
+            let value = value?;
+            let range = ranges
+                .iter()
+                .find(|r| r.age_group == age_group && r.vital == vital)?;
+            let flag = if value < range.low {
+                VitalFlagLevel::Low
+            } else if value > range.high {
+                VitalFlagLevel::High
+            } else {
+                return None;
+            };
+            Some(VitalFlag {
+                vital,
+                value,
+                flag,
+                low: range.low,
+                high: range.high,
+            })
+        })
+        .collect();
+
+    VitalSignReading {
+        vital_sign,
+        bmi,
+        units: UNITS,
+        age_group,
+        flags,
+    }
+}
+
+// One time series per metric from readings in order of time, skipping readings that did
+// not measure it
+pub fn trend(readings: &[VitalSign], metrics: &[TrendMetric]) -> Vec<VitalTrend> {
+    metrics
+        .iter()
+        .map(|&metric| {
+            let mut points: Vec<VitalTrendPoint> = Vec::new();
+            let mut previous_visit_value = None;
+            for reading in readings {
+                let Some(value) = value_of(reading, metric) else {
+                    continue;
+                };
+                if let Some(last) = points.last() {
+                    if last.medical_record_id != reading.medical_record_id {
+                        previous_visit_value = Some(last.value);
+                    }
+                }
+                points.push(VitalTrendPoint {
+                    recorded_at: reading.recorded_at,
+                    medical_record_id: reading.medical_record_id,
+                    value,
+                    delta: previous_visit_value.map(|previous| value - previous),
+                });
+            }
+
+            let values = points.iter().map(|p| p.value);
+            let avg = (!points.is_empty()).then(|| {
+                (values.clone().sum::<Decimal>() / Decimal::from(points.len())).round_dp(1)
+            });
+            VitalTrend {
+                metric,
+                unit: unit_of(metric),
+                count: points.len(),
+                min: values.clone().min(),
+                max: values.max(),
+                avg,
+                points,
+            }
+        })
+        .collect()
+}
+
+fn value_of(reading: &VitalSign, metric: TrendMetric) -> Option<Decimal> {
+    match metric {
+        TrendMetric::Temperature => reading.temperature,
+        TrendMetric::BloodPressureSystolic => reading.blood_pressure_systolic.map(Decimal::from),
+        TrendMetric::BloodPressureDiastolic => reading.blood_pressure_diastolic.map(Decimal::from),
+        TrendMetric::HeartRate => reading.heart_rate.map(Decimal::from),
+        TrendMetric::Spo2 => reading.spo2.map(Decimal::from),
+        TrendMetric::Weight => reading.weight,
+        TrendMetric::Height => reading.height,
+        TrendMetric::Bmi => bmi(reading),
+    }
+}
+
+fn unit_of(metric: TrendMetric) -> &'static str {
+    match metric {
+        TrendMetric::Temperature => UNITS.temperature,
+        TrendMetric::BloodPressureSystolic | TrendMetric::BloodPressureDiastolic => {
+            UNITS.blood_pressure
+        }
+        TrendMetric::HeartRate => UNITS.heart_rate,
+        TrendMetric::Spo2 => UNITS.spo2,
+        TrendMetric::Weight => UNITS.weight,
+        TrendMetric::Height => UNITS.height,
+        TrendMetric::Bmi => UNITS.bmi,
+    }
+}
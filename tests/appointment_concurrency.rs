@@ -0,0 +1,177 @@
+// Fires concurrent POST /api/appointment requests at a running server backed by a local
+// Postgres and checks that numerical_order and slots are never handed out twice.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test appointment_concurrency -- --ignored
+
+mod common;
+
+use chrono::{Datelike, Duration, Utc};
+use common::{create_user, token, Server};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+const PORT: u16 = 18080;
+const REQUESTS: usize = 40;
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn concurrent_bookings_never_share_an_order_or_slot() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    // A fresh specialty keeps the run isolated from existing data
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("load-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active) VALUES ($1, 'Load Test', $2, 1) RETURNING id",
+    )
+    .bind(format!("load-{}@hospital.test", suffix))
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let user_id = create_user(&pool, &format!("load-{}@patient.test", suffix), "patient").await;
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Load Test', $2) RETURNING id",
+    )
+    .bind(format!("load-{}@patient.test", suffix))
+    .bind(user_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    // 07:00 - 12:00 gives ten 30-minute slots with a single doctor, fewer than the requests
+    let date = (Utc::now() + Duration::days(30)).date_naive();
+    sqlx::query(
+        "INSERT INTO tn_doctor_working_hours (doctor_id, weekday, start_time, end_time) VALUES ($1, $2, '07:00', '12:00')",
+    )
+    .bind(doctor_id)
+    .bind(date.weekday().number_from_monday() as i32)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let server = Arc::new(Server::start(&database_url, PORT).await);
+    let token = token(user_id, "patient", patient_id);
+
+    let body = serde_json::json!({
+        "patient_id": patient_id,
+        "patient_name": "Load Test",
+        "patient_birthday": "1990-01-01",
+        "patient_phone": "0000000000",
+        "patient_reason": "Concurrency test",
+        "speciality_id": speciality_id,
+        "date": date,
+    })
+    .to_string();
+
+    // Nothing can be booked in the past
+    let yesterday = (Utc::now() - Duration::days(1)).date_naive();
+    let past = body.replace(&date.to_string(), &yesterday.to_string());
+    let (status, _) = server.send("POST", "/api/appointment", &token, &past).await;
+    assert_eq!(status, 400, "appointments cannot be made for past dates");
+    let slots = format!(
+        "/api/appointment/available-slots?speciality_id={}&date={}",
+        speciality_id, yesterday
+    );
+    let (status, _) = server.send("GET", &slots, &token, "").await;
+    assert_eq!(status, 400, "past dates have no slots");
+
+    // A walk-in seen at a slot's time does not take the slot from pre-booked patients
+    sqlx::query(
+        "INSERT INTO tn_appointment_sequences (date, speciality_id, last_order) VALUES ($1, $2, 1)",
+    )
+    .bind(date)
+    .bind(speciality_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_appointments (patient_id, patient_name, speciality_id, doctor_id, date, numerical_order, appointment_time, status, treatment_status, is_walk_in)
+         VALUES ($1, 'Walk In', $2, $3, $4, 1, '07:00', 'Unpaid', 'checked-in', 1)",
+    )
+    .bind(patient_id)
+    .bind(speciality_id)
+    .bind(doctor_id)
+    .bind(date)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let slots = format!(
+        "/api/appointment/available-slots?speciality_id={}&date={}",
+        speciality_id, date
+    );
+    let (status, offered) = server.send("GET", &slots, &token, "").await;
+    assert_eq!(status, 200, "{}", offered);
+    assert_eq!(offered["data"][0]["time"], "07:00", "{}", offered);
+    assert_eq!(offered["data"][0]["booked"], 0, "{}", offered);
+    let mut at_seven: serde_json::Value = serde_json::from_str(&body).unwrap();
+    at_seven["appointment_time"] = "07:00".into();
+    let (status, booked) = server
+        .send("POST", "/api/appointment", &token, &at_seven.to_string())
+        .await;
+    assert_eq!(status, 200, "{}", booked);
+
+    let handles: Vec<_> = (0..REQUESTS)
+        .map(|_| {
+            let server = server.clone();
+            let token = token.clone();
+            let body = body.clone();
+            tokio::spawn(async move {
+                server
+                    .send("POST", "/api/appointment", &token, &body)
+                    .await
+                    .0
+            })
+        })
+        .collect();
+
+    let mut created = 0;
+    for handle in handles {
+        match handle.await.unwrap() {
+            200 => created += 1,
+            // Lost the race for a slot, or every slot was taken
+            400 | 409 => {}
+            status => panic!("unexpected status {}", status),
+        }
+    }
+
+    let stored: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM tn_appointments WHERE date = $1 AND speciality_id = $2 AND is_walk_in = 0",
+    )
+    .bind(date)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, created + 1);
+    assert!(created > 0, "no booking succeeded");
+    assert!(created <= 9, "{} bookings for the 9 slots left", created);
+
+    let duplicated_orders: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM (SELECT numerical_order FROM tn_appointments WHERE date = $1 AND speciality_id = $2 GROUP BY numerical_order HAVING COUNT(*) > 1) d",
+    )
+    .bind(date)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(duplicated_orders, 0);
+
+    let overbooked_slots: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM (SELECT appointment_time FROM tn_appointments WHERE date = $1 AND speciality_id = $2 AND is_walk_in = 0 GROUP BY appointment_time HAVING COUNT(*) > 1) d",
+    )
+    .bind(date)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(overbooked_slots, 0);
+}
@@ -0,0 +1,208 @@
+// Calls every authenticated route as each role, and without a token, against a running
+// server and checks the role policy: roles without the route's permission are turned away
+// by the guard, the others get past it. Ids that do not exist and empty bodies keep
+// handlers from changing anything, so an allowed call ends in 400, 403 from an ownership
+// check or 404 rather than 2xx. Ownership itself is covered in tests/ownership.rs.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test authorization -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, token, Server};
+use sqlx::PgPool;
+
+const PORT: u16 = 18081;
+
+const P: &str = "patient";
+const D: &str = "doctor";
+const R: &str = "receptionist";
+const S: &str = "staff";
+const A: &str = "admin";
+const ROLES: [&str; 5] = [P, D, R, S, A];
+const ANY: &[&str] = &ROLES;
+
+// (method, path, roles allowed through)
+#[rustfmt::skip]
+const ROUTES: &[(&str, &str, &[&str])] = &[
+    ("GET", "/api/patient/all", &[R, S, A]),
+    ("GET", "/api/patient/self", &[P]),
+    ("GET", "/api/patient/0", &[D, R, S, A]),
+    ("GET", "/api/patient/0/vitals/trend", &[P, D]),
+    ("GET", "/api/patient/0/clinical-profile", &[P, D]),
+    ("PUT", "/api/patient/0/clinical-profile", &[D]),
+    ("POST", "/api/patient/0/allergies", &[D]),
+    ("DELETE", "/api/patient/0/allergies/0", &[D]),
+    ("POST", "/api/patient/0/conditions", &[D]),
+    ("DELETE", "/api/patient/0/conditions/0", &[D]),
+    ("POST", "/api/patient/0/medications", &[D]),
+    ("DELETE", "/api/patient/0/medications/0", &[D]),
+    ("GET", "/api/patient/email/nobody@hospital.test", &[D, R, S, A]),
+    ("PUT", "/api/patient/0", &[P, R, S, A]),
+    ("POST", "/api/patient", &[R, S, A]),
+    ("POST", "/api/appointment", &[P, R, S]),
+    ("GET", "/api/appointment/available-slots", ANY),
+    ("GET", "/api/appointment/0", &[P]),
+    ("GET", "/api/appointment/specialty/0", &[D, R, S, A]),
+    ("PUT", "/api/appointment/status/0", &[R, S, A]),
+    ("PUT", "/api/appointment/treatment-status/0", &[D, R, S]),
+    ("GET", "/api/appointment/0/history", ANY),
+    ("GET", "/api/appointment/history/self", &[P]),
+    ("POST", "/api/appointment/0/cancel", &[P, R, S, A]),
+    ("POST", "/api/appointment/0/reschedule", &[P, R, S, A]),
+    ("PUT", "/api/appointment/0/doctor", &[R, S, A]),
+    ("GET", "/api/reception/patients", &[R]),
+    ("POST", "/api/reception/patients", &[R]),
+    ("POST", "/api/reception/appointments/0/check-in", &[R]),
+    ("POST", "/api/reception/walk-ins", &[R]),
+    ("GET", "/api/reception/queue/0", &[R]),
+    ("POST", "/api/booking", &[P]),
+    ("GET", "/api/booking/self", &[P]),
+    ("GET", "/api/booking", &[R, S, A]),
+    ("GET", "/api/booking/0", &[P, R, S, A]),
+    ("POST", "/api/booking/0/photos", &[P, R, S, A]),
+    ("POST", "/api/booking/0/confirm", &[R, S, A]),
+    ("POST", "/api/booking/0/reject", &[R, S, A]),
+    ("POST", "/api/booking/0/cancel", &[P, R, S, A]),
+    ("GET", "/api/payment/self-invoices", &[P]),
+    ("GET", "/api/payment/invoices/0", &[R]),
+    ("POST", "/api/payment/invoices", &[D]),
+    ("GET", "/api/specialty/all", ANY),
+    ("GET", "/api/specialty/0", ANY),
+    ("POST", "/api/specialty", &[A]),
+    ("PUT", "/api/specialty/update", &[A]),
+    ("DELETE", "/api/specialty/delete", &[A]),
+    ("GET", "/api/service/all", ANY),
+    ("GET", "/api/service/0", ANY),
+    ("GET", "/api/service/0/doctors", ANY),
+    ("POST", "/api/service", &[A]),
+    ("PUT", "/api/service/0", &[A]),
+    ("GET", "/api/medicine/all", ANY),
+    ("GET", "/api/medicine/0", ANY),
+    ("POST", "/api/medicine", &[S, A]),
+    ("DELETE", "/api/medicine/0", &[S, A]),
+    ("POST", "/api/medicine/prescription", &[D]),
+    ("GET", "/api/medicine/prescription/0", &[P, D, R]),
+    ("GET", "/api/medical-record/self", &[P]),
+    ("PUT", "/api/medical-record/payment-status/0", &[D]),
+    ("POST", "/api/medical-record", &[D]),
+    ("GET", "/api/medical-record/appointment/0", &[P, D]),
+    ("PUT", "/api/medical-record/diagnosis/0", &[D]),
+    ("GET", "/api/medical-record/is-medical-record-exist/0", &[P, D]),
+    ("GET", "/api/medical-record/vital-signs/0", &[P, D]),
+    ("POST", "/api/medical-record/vital-signs", &[D]),
+    ("PUT", "/api/medical-record/0/note", &[D]),
+    ("GET", "/api/medical-record/0/amendments", &[P, D]),
+    ("POST", "/api/medical-record/0/diagnoses", &[D]),
+    ("DELETE", "/api/medical-record/0/diagnoses/0", &[D]),
+    ("GET", "/api/medical-record/0", &[P, D]),
+    ("GET", "/api/icd10", &[D]),
+    ("POST", "/api/icd10/import", &[A]),
+    ("POST", "/api/drug-interactions/import", &[A]),
+    ("POST", "/api/drug-interactions/ingredients/import", &[A]),
+    ("GET", "/api/doctor/self", &[D]),
+    ("GET", "/api/doctor/worklist", &[D]),
+    ("GET", "/api/doctor/0/services", ANY),
+    ("GET", "/api/admin/doctors", &[A]),
+    ("GET", "/api/admin/doctors/0", &[A]),
+    ("POST", "/api/admin/doctors", &[A]),
+    ("PUT", "/api/admin/doctors/nobody@hospital.test", &[A]),
+    ("DELETE", "/api/admin/doctors/0", &[A]),
+    ("GET", "/api/admin/doctors/0/working-hours", &[A]),
+    ("PUT", "/api/admin/doctors/0/working-hours", &[A]),
+    ("GET", "/api/admin/doctors/0/exceptions", &[A]),
+    ("POST", "/api/admin/doctors/0/exceptions", &[A]),
+    ("DELETE", "/api/admin/doctors/0/exceptions/0", &[A]),
+    ("POST", "/api/admin/doctors/0/reassign-appointments", &[A]),
+    ("POST", "/api/admin/doctors/0/services", &[A]),
+    ("DELETE", "/api/admin/doctors/0/services/0", &[A]),
+    ("GET", "/api/admin/rooms", &[A]),
+    ("GET", "/api/admin/rooms/0", &[A]),
+    ("POST", "/api/admin/rooms", &[A]),
+    ("PUT", "/api/admin/rooms/0", &[A]),
+    ("DELETE", "/api/admin/rooms/0", &[A]),
+    ("GET", "/api/admin/rooms/0/shifts", &[A]),
+    ("POST", "/api/admin/rooms/0/shifts", &[A]),
+    ("DELETE", "/api/admin/rooms/0/shifts/0", &[A]),
+    ("PUT", "/api/admin/doctors/0/room", &[A]),
+    ("PUT", "/api/admin/users/0/active", &[A]),
+    ("POST", "/api/admin/users/0/unlock", &[A]),
+    ("GET", "/api/admin/security-events", &[A]),
+    ("DELETE", "/api/admin/users/0/two-factor", &[A]),
+    ("GET", "/api/admin/two-factor-policy", &[A]),
+    ("PUT", "/api/admin/two-factor-policy/patient", &[A]),
+    ("DELETE", "/api/admin/users/0", &[A]),
+    ("GET", "/api/admin/vital-reference-ranges", &[A]),
+    ("PUT", "/api/admin/vital-reference-ranges/adult/heart_rate", &[A]),
+    ("PUT", "/api/auth/update-password", ANY),
+    ("POST", "/api/auth/register-with-admin", &[A]),
+    ("POST", "/api/auth/logout", ANY),
+    ("GET", "/api/auth/sessions", ANY),
+    ("DELETE", "/api/auth/sessions/0", ANY),
+    ("POST", "/api/auth/2fa/disable", ANY),
+    ("POST", "/api/auth/2fa/recovery-codes", ANY),
+    ("POST", "/api/2fa/enable", ANY),
+    ("POST", "/api/2fa/verify", ANY),
+];
+
+// Status and whether the permission guard, rather than the handler, rejected the call
+async fn send(server: &Server, method: &str, path: &str, token: Option<&str>) -> (u16, bool) {
+    let response = server.request(method, path, token, &[], "").await;
+    (
+        response.status,
+        response.status == 403 && response.body().contains("Permission denied"),
+    )
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn every_route_enforces_the_role_policy() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    // One account per role; the guard only needs the account to exist and be active
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let mut accounts = Vec::new();
+    for role in ROLES {
+        let email = format!("authz-{}-{}@hospital.test", role, suffix);
+        let user_id = create_user(&pool, &email, role).await;
+        accounts.push((role, user_id));
+    }
+
+    let server = Server::start(&database_url, PORT).await;
+
+    let mut failures = Vec::new();
+    for &(method, path, allowed) in ROUTES {
+        let (status, _) = send(&server, method, path, None).await;
+        if status != 401 {
+            failures.push(format!("{} {} without a token: {}", method, path, status));
+        }
+
+        for &(role, user_id) in &accounts {
+            let (status, guarded) =
+                send(&server, method, path, Some(&token(user_id, role, 0))).await;
+            let ok = if allowed.contains(&role) {
+                status != 401 && !guarded
+            } else {
+                guarded
+            };
+            if !ok {
+                failures.push(format!("{} {} as {}: {}", method, path, role, status));
+            }
+        }
+    }
+
+    drop(server);
+    sqlx::query("DELETE FROM tn_users WHERE email LIKE $1")
+        .bind(format!("authz-%-{}@hospital.test", suffix))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert!(
+        failures.is_empty(),
+        "policy violations:\n{}",
+        failures.join("\n")
+    );
+}
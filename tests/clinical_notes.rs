@@ -0,0 +1,307 @@
+// Checks clinical notes against a running server: ICD-10 codes are imported and searched,
+// note sections are filled in and amended, coded diagnoses allow one primary, and every
+// change lands in an amendment trail that cannot be rewritten.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test clinical_notes -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, sign, Claims, Server};
+use serde_json::Value;
+use sqlx::PgPool;
+
+const DOCTOR_NAME: &str = "Clinical Notes Doctor";
+
+// In the layouts seen in the wild: CMS fixed width, CSV with quotes and tab separated
+const CODE_LIST: &str = "code\tdescription
+J45909  Unspecified asthma, uncomplicated
+E119,\"Type 2 diabetes mellitus without complications\"
+i10\tEssential (primary) hypertension
+J45.909\tListed twice
+";
+
+// Code lists are sent as plain text
+async fn import(server: &Server, token: &str, codes: &str) -> (u16, Value) {
+    let response = server
+        .request(
+            "POST",
+            "/api/icd10/import",
+            Some(token),
+            &[("Content-Type", "text/plain")],
+            codes,
+        )
+        .await;
+    (response.status, response.json())
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn notes_are_coded_and_amended_with_a_trail() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("notes-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let admin_email = format!("notes-admin-{}@hospital.test", suffix);
+    let admin_user = create_user(&pool, &admin_email, "admin").await;
+    let doctor_email = format!("notes-doctor-{}@hospital.test", suffix);
+    let doctor_user = create_user(&pool, &doctor_email, "doctor").await;
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, $2, $3, 1, $4) RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(DOCTOR_NAME)
+    .bind(speciality_id)
+    .bind(doctor_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let patient_email = format!("notes-patient-{}@hospital.test", suffix);
+    let patient_user = create_user(&pool, &patient_email, "patient").await;
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Notes Patient', $2) RETURNING id",
+    )
+    .bind(&patient_email)
+    .bind(patient_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let record_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id, chief_complaint, assessment)
+         VALUES ($1, $2, $3, 'Wheezing at night', 'Likely viral bronchitis') RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient_id)
+    .bind(doctor_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    let server = Server::start(&database_url, 18089).await;
+    let admin = sign(&Claims::access(admin_user, "Notes Admin", "admin", 0));
+    let doctor = sign(&Claims::access(
+        doctor_user,
+        DOCTOR_NAME,
+        "doctor",
+        doctor_id,
+    ));
+    let patient = sign(&Claims::access(
+        patient_user,
+        "Notes Patient",
+        "patient",
+        patient_id,
+    ));
+
+    // Importing: a list with an unreadable line writes nothing
+    let (status, body) = import(
+        &server,
+        &admin,
+        "J45909\tUnspecified asthma\nnot a code\nI10\n",
+    )
+    .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["lines"], serde_json::json!([2, 3]));
+    let (status, _) = import(&server, &doctor, CODE_LIST).await;
+    assert_eq!(status, 403, "only admins import codes");
+    let (status, body) = import(&server, &admin, CODE_LIST).await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["imported"], 3);
+
+    // Searching by code, with or without the dot, and by description
+    let (status, body) = server.send("GET", "/api/icd10?q=j45.90", &doctor, "").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"][0]["code"], "J45.909");
+    assert_eq!(
+        body["data"][0]["description"],
+        "Unspecified asthma, uncomplicated"
+    );
+    let (_, body) = server
+        .send("GET", "/api/icd10?q=HYPERTENSION", &doctor, "")
+        .await;
+    let codes: Vec<&str> = body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code["code"].as_str().unwrap())
+        .collect();
+    assert!(codes.contains(&"I10"), "{:?}", codes);
+
+    // Note sections: blank ones are filled freely, written ones need a reason to change
+    let note = format!("/api/medical-record/{}/note", record_id);
+    let (status, body) = server
+        .send(
+            "PUT",
+            &note,
+            &doctor,
+            r#"{"examination":"Expiratory wheeze, SpO2 96%","plan":"Salbutamol as needed"}"#,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["data"]["changed"],
+        serde_json::json!(["examination", "plan"])
+    );
+    let (status, _) = server
+        .send("PUT", &note, &doctor, r#"{"assessment":"Asthma"}"#)
+        .await;
+    assert_eq!(status, 409, "replacing written text needs a reason");
+    let (status, body) = server.send(
+        "PUT",
+        &note,
+        &doctor,
+        r#"{"assessment":"Asthma","plan":"Salbutamol as needed","reason":"Spirometry came back"}"#,
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        body["data"]["changed"],
+        serde_json::json!(["assessment"]),
+        "unchanged sections are not amended"
+    );
+    let (status, _) = server
+        .send("PUT", &note, &patient, r#"{"plan":"None"}"#)
+        .await;
+    assert_eq!(status, 403);
+
+    // Coded diagnoses: one primary at most, unknown codes refused
+    let diagnoses = format!("/api/medical-record/{}/diagnoses", record_id);
+    let (status, body) = server
+        .send(
+            "POST",
+            &diagnoses,
+            &doctor,
+            r#"{"code":"j45909","kind":"primary","note":"Mild intermittent"}"#,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let asthma = body["data"].as_i64().unwrap();
+    let (status, _) = server
+        .send(
+            "POST",
+            &diagnoses,
+            &doctor,
+            r#"{"code":"I10","kind":"primary"}"#,
+        )
+        .await;
+    assert_eq!(status, 409, "a record has one primary diagnosis");
+    let (status, _) = server
+        .send("POST", &diagnoses, &doctor, r#"{"code":"I10"}"#)
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send("POST", &diagnoses, &doctor, r#"{"code":"Z99.999"}"#)
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = server
+        .send("POST", &diagnoses, &doctor, r#"{"code":"asthma"}"#)
+        .await;
+    assert_eq!(status, 400);
+
+    let (status, _) = server
+        .send("DELETE", &format!("{}/{}", diagnoses, asthma), &doctor, "")
+        .await;
+    assert_eq!(status, 400, "removing a diagnosis needs a reason");
+    let (status, _) = server
+        .send(
+            "DELETE",
+            &format!("{}/{}?reason=%20", diagnoses, asthma),
+            &doctor,
+            "",
+        )
+        .await;
+    assert_eq!(status, 400, "a blank reason is no reason");
+    let remove = format!("{}/{}?reason=Ruled%20out", diagnoses, asthma);
+    let (status, _) = server.send("DELETE", &remove, &doctor, "").await;
+    assert_eq!(status, 200);
+    let (status, _) = server.send("DELETE", &remove, &doctor, "").await;
+    assert_eq!(status, 404, "a diagnosis is removed once");
+
+    // The patient sees the whole record and how it got there
+    let (status, body) = server
+        .send(
+            "GET",
+            &format!("/api/medical-record/{}", record_id),
+            &patient,
+            "",
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let record = &body["data"];
+    assert_eq!(record["chief_complaint"], "Wheezing at night");
+    assert_eq!(record["examination"], "Expiratory wheeze, SpO2 96%");
+    assert_eq!(record["assessment"], "Asthma");
+    assert_eq!(record["plan"], "Salbutamol as needed");
+    let coded = record["diagnoses"].as_array().unwrap();
+    assert_eq!(coded.len(), 1, "removed diagnoses are not listed");
+    assert_eq!(coded[0]["icd10_code"], "I10");
+    assert_eq!(coded[0]["kind"], "secondary");
+    assert_eq!(coded[0]["description"], "Essential (primary) hypertension");
+
+    let (status, body) = server
+        .send(
+            "GET",
+            &format!("/api/medical-record/{}/amendments", record_id),
+            &patient,
+            "",
+        )
+        .await;
+    assert_eq!(status, 200);
+    let trail = body["data"].as_array().unwrap();
+    let sections: Vec<&str> = trail
+        .iter()
+        .map(|a| a["section"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        sections,
+        [
+            "examination",
+            "plan",
+            "assessment",
+            "coded_diagnosis",
+            "coded_diagnosis",
+            "coded_diagnosis"
+        ]
+    );
+    let assessment = &trail[2];
+    assert_eq!(assessment["old_value"], "Likely viral bronchitis");
+    assert_eq!(assessment["new_value"], "Asthma");
+    assert_eq!(assessment["reason"], "Spirometry came back");
+    assert_eq!(assessment["amended_by"], doctor_user);
+    assert_eq!(assessment["amended_by_name"], DOCTOR_NAME);
+    assert!(trail[0]["old_value"].is_null());
+    assert_eq!(trail[5]["reason"], "Ruled out");
+    assert!(trail[5]["new_value"].is_null());
+
+    // Not even the database lets the trail be rewritten
+    let tampered = sqlx::query(
+        "UPDATE tn_medical_record_amendments SET reason = 'none' WHERE medical_record_id = $1",
+    )
+    .bind(record_id)
+    .execute(&pool)
+    .await;
+    assert!(tampered.is_err(), "amendments must be append-only");
+    let deleted =
+        sqlx::query("DELETE FROM tn_medical_record_amendments WHERE medical_record_id = $1")
+            .bind(record_id)
+            .execute(&pool)
+            .await;
+    assert!(deleted.is_err(), "amendments must be append-only");
+}
@@ -0,0 +1,229 @@
+// Checks the clinical profile against a running server: doctors record blood type, family
+// history, allergies, chronic conditions and long-term medications, and the profile shows
+// up for the patient on `/api/patient/self` and on every medical record opened for them.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test clinical_profile -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, token, Server};
+use sqlx::PgPool;
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn doctors_keep_the_profile_and_records_show_it() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("profile-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let doctor_email = format!("profile-doctor-{}@hospital.test", suffix);
+    let doctor_user = create_user(&pool, &doctor_email, "doctor").await;
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, 'Profile Doctor', $2, 1, $3) RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(speciality_id)
+    .bind(doctor_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let patient_email = format!("profile-patient-{}@hospital.test", suffix);
+    let patient_user = create_user(&pool, &patient_email, "patient").await;
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Profile Patient', $2) RETURNING id",
+    )
+    .bind(&patient_email)
+    .bind(patient_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let record_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient_id)
+    .bind(doctor_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let metformin: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_medicine (name, unit) VALUES ('Metformin 500 mg', 'tablet') RETURNING id",
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_icd10_codes (code, description) VALUES ('E11.9', 'Type 2 diabetes mellitus without complications')
+         ON CONFLICT (code) DO NOTHING",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let server = Server::start(&database_url, 18092).await;
+    let doctor = token(doctor_user, "doctor", doctor_id);
+    let patient = token(patient_user, "patient", patient_id);
+    let profile = format!("/api/patient/{}/clinical-profile", patient_id);
+    let allergies = format!("/api/patient/{}/allergies", patient_id);
+    let conditions = format!("/api/patient/{}/conditions", patient_id);
+    let medications = format!("/api/patient/{}/medications", patient_id);
+
+    let (status, _) = server
+        .send(
+            "PUT",
+            &profile,
+            &doctor,
+            r#"{"blood_type":"O-","family_history":"Father: myocardial infarction at 52"}"#,
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send("PUT", &profile, &doctor, r#"{"blood_type":"Z+"}"#)
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = server
+        .send("PUT", &profile, &patient, r#"{"blood_type":"A+"}"#)
+        .await;
+    assert_eq!(
+        status, 403,
+        "patients read their profile but do not edit it"
+    );
+
+    // Allergies: one entry per substance while it is current
+    let penicillin =
+        r#"{"substance":"Penicillin","reaction":"Anaphylaxis","severity":"life_threatening"}"#;
+    let (status, body) = server.send("POST", &allergies, &doctor, penicillin).await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, _) = server
+        .send(
+            "POST",
+            &allergies,
+            &doctor,
+            r#"{"substance":" PENICILLIN ","severity":"mild"}"#,
+        )
+        .await;
+    assert_eq!(status, 409);
+    let (status, _) = server
+        .send(
+            "POST",
+            &allergies,
+            &doctor,
+            r#"{"substance":"  ","severity":"mild"}"#,
+        )
+        .await;
+    assert_eq!(status, 400);
+    let (status, body) = server
+        .send(
+            "POST",
+            &allergies,
+            &doctor,
+            r#"{"substance":"Latex","severity":"moderate"}"#,
+        )
+        .await;
+    assert_eq!(status, 200);
+    let latex = format!("{}/{}", allergies, body["data"]);
+    let (status, _) = server.send("DELETE", &latex, &doctor, "").await;
+    assert_eq!(status, 200);
+    let (status, _) = server.send("DELETE", &latex, &doctor, "").await;
+    assert_eq!(status, 404);
+
+    // Conditions, coded or not
+    let (status, body) = server
+        .send(
+            "POST",
+            &conditions,
+            &doctor,
+            r#"{"name":"Type 2 diabetes","icd10_code":"e119","diagnosed_on":"2019-05-01"}"#,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, _) = server
+        .send(
+            "POST",
+            &conditions,
+            &doctor,
+            r#"{"name":"Unknown","icd10_code":"Z99.999"}"#,
+        )
+        .await;
+    assert_eq!(status, 400);
+
+    // Medications, from the formulary or not
+    let body = format!(
+        r#"{{"medicine_id":{},"name":"Metformin","dose":"500 mg","frequency":"twice daily"}}"#,
+        metformin
+    );
+    let (status, body) = server.send("POST", &medications, &doctor, &body).await;
+    assert_eq!(status, 200, "{}", body);
+    let (status, _) = server
+        .send(
+            "POST",
+            &medications,
+            &doctor,
+            r#"{"medicine_id":0,"name":"Nothing"}"#,
+        )
+        .await;
+    assert_eq!(status, 400);
+    let (status, body) = server
+        .send(
+            "POST",
+            &medications,
+            &doctor,
+            r#"{"name":"Lisinopril","dose":"10 mg"}"#,
+        )
+        .await;
+    assert_eq!(status, 200);
+    let stopped = format!("{}/{}", medications, body["data"]);
+    let (status, _) = server.send("DELETE", &stopped, &doctor, "").await;
+    assert_eq!(status, 200);
+
+    // The patient sees it all on their own details
+    let (status, body) = server.send("GET", "/api/patient/self", &patient, "").await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["name"], "Profile Patient");
+    let own = &body["data"]["clinical_profile"];
+    assert_eq!(own["blood_type"], "O-");
+    assert_eq!(own["family_history"], "Father: myocardial infarction at 52");
+    assert_eq!(
+        own["allergies"].as_array().unwrap().len(),
+        1,
+        "removed entries are not listed"
+    );
+    assert_eq!(own["allergies"][0]["severity"], "life_threatening");
+    assert_eq!(own["conditions"][0]["icd10_code"], "E11.9");
+    assert_eq!(own["conditions"][0]["diagnosed_on"], "2019-05-01");
+    let listed = own["medications"].as_array().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0]["medicine_id"], metformin);
+
+    // And the doctor sees it on the record, however it is opened
+    for path in [
+        format!("/api/medical-record/{}", record_id),
+        format!("/api/medical-record/appointment/{}", appointment_id),
+    ] {
+        let (status, body) = server.send("GET", &path, &doctor, "").await;
+        assert_eq!(status, 200, "{}", body);
+        let shown = &body["data"]["clinical_profile"];
+        assert_eq!(shown["allergies"][0]["substance"], "Penicillin", "{}", path);
+        assert_eq!(shown["blood_type"], "O-");
+        assert!(body["data"]["diagnoses"].is_array());
+    }
+}
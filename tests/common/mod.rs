@@ -0,0 +1,205 @@
+// Shared by the end-to-end suites. Each suite starts the server binary on a port of its own
+// against DATABASE_URL, talks plain HTTP/1.1 to it and mints its own access tokens with
+// JWT_SECRET. Not every suite uses every helper.
+#![allow(dead_code)]
+
+use chrono::{Duration, Utc};
+use jsonwebtoken::{encode, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use std::process::{Child, Command};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// The HS256 secret every test server signs with
+pub const JWT_SECRET: &str = "integration-test";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Claims {
+    pub sub: String,
+    pub name: String,
+    pub role: String,
+    pub profile_id: i32,
+    pub jti: String,
+    pub kind: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl Claims {
+    // An access token valid for five minutes, with an id of its own
+    pub fn access(user_id: i32, name: &str, role: &str, profile_id: i32) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub: user_id.to_string(),
+            name: name.to_string(),
+            role: role.to_string(),
+            profile_id,
+            jti: format!("test-{}", now.timestamp_nanos_opt().unwrap()),
+            kind: "access".to_string(),
+            iss: "hospital-management-system".to_string(),
+            aud: "hms-api".to_string(),
+            iat: now.timestamp(),
+            exp: (now + Duration::minutes(5)).timestamp(),
+        }
+    }
+}
+
+// The header of an access token, which the server checks along with the claims
+pub fn access_header(header: Header) -> Header {
+    Header {
+        typ: Some("at+jwt".to_string()),
+        ..header
+    }
+}
+
+pub fn sign(claims: &Claims) -> String {
+    encode(
+        &access_header(Header::default()),
+        claims,
+        &EncodingKey::from_secret(JWT_SECRET.as_bytes()),
+    )
+    .unwrap()
+}
+
+// A fresh access token per call, so revoking one in a test cannot affect the next
+pub fn token(user_id: i32, role: &str, profile_id: i32) -> String {
+    sign(&Claims::access(
+        user_id,
+        "Integration Test",
+        role,
+        profile_id,
+    ))
+}
+
+pub struct Server {
+    child: Child,
+    address: String,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    // Status line, headers and body as received
+    pub raw: String,
+}
+
+impl Response {
+    pub fn body(&self) -> &str {
+        self.raw
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body)
+            .unwrap_or("")
+    }
+
+    // Null when the body is not JSON
+    pub fn json(&self) -> Value {
+        serde_json::from_str(self.body()).unwrap_or(Value::Null)
+    }
+}
+
+impl Server {
+    pub async fn start(database_url: &str, port: u16) -> Server {
+        Server::start_with(database_url, port, |_| {}).await
+    }
+
+    // `configure` may set further environment or redirect output before the server starts
+    pub async fn start_with(
+        database_url: &str,
+        port: u16,
+        configure: impl FnOnce(&mut Command),
+    ) -> Server {
+        let address = format!("127.0.0.1:{}", port);
+        let mut command = Command::new(env!("CARGO_BIN_EXE_hospital_management_system_backend"));
+        command
+            .env("DATABASE_URL", database_url)
+            .env("JWT_SECRET", JWT_SECRET)
+            .env("SERVER_ADDRESS", &address);
+        configure(&mut command);
+        let child = command.spawn().expect("failed to start server");
+        let server = Server { child, address };
+
+        for _ in 0..100 {
+            if TcpStream::connect(&server.address).await.is_ok() {
+                return server;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        panic!("server did not start on {}", server.address);
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn child(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    // The body is sent as JSON unless `headers` name another Content-Type
+    pub async fn request(
+        &self,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Response {
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, self.address);
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {}\r\n", token));
+        }
+        if !headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            head.push_str("Content-Type: application/json\r\n");
+        }
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        let request = format!(
+            "{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            head,
+            body.len(),
+            body
+        );
+
+        let mut stream = TcpStream::connect(&self.address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut raw = String::new();
+        stream.read_to_string(&mut raw).await.unwrap();
+        let status = raw
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .expect("malformed HTTP response");
+        Response { status, raw }
+    }
+
+    // A JSON request with a bearer token; the body of the response as JSON
+    pub async fn send(&self, method: &str, path: &str, token: &str, body: &str) -> (u16, Value) {
+        let response = self.request(method, path, Some(token), &[], body).await;
+        (response.status, response.json())
+    }
+}
+
+// An account with no password, which can only be used through tokens minted here
+pub async fn create_user(pool: &PgPool, email: &str, role: &str) -> i32 {
+    sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, '', $2) RETURNING id",
+    )
+    .bind(email)
+    .bind(role)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
@@ -0,0 +1,308 @@
+// Checks prescribing against a running server: admins import medicine interactions and
+// ingredients from CSV, and prescriptions that interact, meet a recorded allergy or repeat
+// a therapy come back as blocking errors or warnings, the errors needing an override reason.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test drug_interactions -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, token, Server};
+use serde_json::Value;
+use sqlx::PgPool;
+
+async fn create_medicine(pool: &PgPool, name: &str) -> i32 {
+    sqlx::query_scalar("INSERT INTO tn_medicine (name, unit) VALUES ($1, 'tablet') RETURNING id")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+fn kinds(issues: &Value) -> Vec<String> {
+    issues
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| i["kind"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn prescriptions_are_checked_against_interactions_and_allergies() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("interactions-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let doctor_email = format!("interactions-doctor-{}@hospital.test", suffix);
+    let doctor_user = create_user(&pool, &doctor_email, "doctor").await;
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, 'Interactions Doctor', $2, 1, $3) RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(speciality_id)
+    .bind(doctor_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let admin_user = create_user(
+        &pool,
+        &format!("interactions-admin-{}@hospital.test", suffix),
+        "admin",
+    )
+    .await;
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name) VALUES ($1, 'Interactions Patient') RETURNING id",
+    )
+    .bind(format!("interactions-patient-{}@hospital.test", suffix))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let record_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient_id)
+    .bind(doctor_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    // Names are unique to the run since imports find medicines by name
+    let name = |medicine: &str| format!("{} {}", medicine, suffix);
+    let warfarin = create_medicine(&pool, &name("Warfarin")).await;
+    let aspirin = create_medicine(&pool, &name("Aspirin")).await;
+    let ibuprofen = create_medicine(&pool, &name("Ibuprofen")).await;
+    let amoxicillin = create_medicine(&pool, &name("Amoxicillin")).await;
+    let paracetamol = create_medicine(&pool, &name("Paracetamol")).await;
+    let cold_remedy = create_medicine(&pool, &name("Cold Remedy")).await;
+    let naproxen = create_medicine(&pool, &name("Naproxen 250mg")).await;
+
+    let server = Server::start(&database_url, 18093).await;
+    let doctor = token(doctor_user, "doctor", doctor_id);
+    let admin = token(admin_user, "admin", 0);
+
+    // Nothing is imported when a line names a medicine the formulary does not have
+    let interactions = format!(
+        "medicine_a,medicine_b,severity,note\n{},{},major,\"Bleeding risk, monitor INR\"\n{},Unobtainium,minor,\n",
+        name("Warfarin"),
+        name("Aspirin"),
+        name("Aspirin")
+    );
+    let (status, body) = server
+        .send(
+            "POST",
+            "/api/drug-interactions/import",
+            &admin,
+            &interactions,
+        )
+        .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["lines"], serde_json::json!([3]));
+    let interactions = format!(
+        "medicine_a,medicine_b,severity,note\n{},{},major,\"Bleeding risk, monitor INR\"\n{},{},Moderate,\n",
+        name("Warfarin"),
+        name("Aspirin"),
+        name("Ibuprofen"),
+        name("Aspirin")
+    );
+    let (status, body) = server
+        .send(
+            "POST",
+            "/api/drug-interactions/import",
+            &admin,
+            &interactions,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["imported"], 2);
+    let ingredients = format!(
+        "medicine,ingredient,allergen\n{},amoxicillin,penicillin\n{},paracetamol,\n{},Paracetamol,\n{},phenylephrine,\n",
+        name("Amoxicillin"),
+        name("Paracetamol"),
+        name("Cold Remedy"),
+        name("Cold Remedy")
+    );
+    let (status, body) = server
+        .send(
+            "POST",
+            "/api/drug-interactions/ingredients/import",
+            &admin,
+            &ingredients,
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["imported"], 4);
+
+    let prescribe = |medicine_ids: &[i32], override_reason: Option<&str>| {
+        serde_json::json!({
+            "medical_record_id": record_id,
+            "medicine_ids": medicine_ids,
+            "quantity": 10,
+            "override_reason": override_reason
+        })
+        .to_string()
+    };
+    let path = "/api/medicine/prescription";
+
+    // A prescription belongs to a medical record, whose patient it is checked against
+    let (status, _) = server
+        .send(
+            "POST",
+            path,
+            &doctor,
+            &serde_json::json!({ "medicine_ids": [aspirin, ibuprofen] }).to_string(),
+        )
+        .await;
+    assert_eq!(status, 400, "a prescription needs a medical record");
+    // Before the profile below, aspirin and ibuprofen only interact moderately
+    let (status, body) = server
+        .send(
+            "POST",
+            path,
+            &doctor,
+            &prescribe(&[aspirin, ibuprofen], None),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(kinds(&body["data"]["warnings"]), ["interaction"]);
+
+    // The patient is allergic to penicillins and naproxen, and takes warfarin
+    let (status, _) = server
+        .send(
+            "POST",
+            &format!("/api/patient/{}/allergies", patient_id),
+            &doctor,
+            r#"{"substance":"Penicillin","reaction":"hives","severity":"severe"}"#,
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send(
+            "POST",
+            &format!("/api/patient/{}/allergies", patient_id),
+            &doctor,
+            r#"{"substance":"naproxen","severity":"moderate"}"#,
+        )
+        .await;
+    assert_eq!(status, 200);
+    let (status, _) = server
+        .send(
+            "POST",
+            &format!("/api/patient/{}/medications", patient_id),
+            &doctor,
+            &format!(
+                r#"{{"medicine_id":{},"name":"Warfarin","dose":"5 mg"}}"#,
+                warfarin
+            ),
+        )
+        .await;
+    assert_eq!(status, 200);
+
+    // Aspirin with the warfarin taken long-term, and amoxicillin with the allergy, block
+    let (status, body) = server
+        .send("POST", path, &doctor, &prescribe(&[aspirin], None))
+        .await;
+    assert_eq!(status, 409, "{}", body);
+    assert_eq!(kinds(&body["errors"]), ["interaction"]);
+    let message = body["errors"][0]["message"].as_str().unwrap();
+    assert!(message.contains("taken long-term"), "{}", message);
+    assert!(
+        message.contains("Bleeding risk, monitor INR"),
+        "{}",
+        message
+    );
+    let (status, body) = server
+        .send("POST", path, &doctor, &prescribe(&[amoxicillin], None))
+        .await;
+    assert_eq!(status, 409);
+    assert_eq!(kinds(&body["errors"]), ["allergy"]);
+    assert_eq!(
+        body["errors"][0]["medicine_ids"],
+        serde_json::json!([amoxicillin])
+    );
+    let (status, _) = server
+        .send(
+            "POST",
+            path,
+            &doctor,
+            &prescribe(&[amoxicillin], Some("  ")),
+        )
+        .await;
+    assert_eq!(status, 409, "a blank reason is no reason");
+    // Naproxen has no ingredient data; its name alone gives it away
+    let (status, body) = server
+        .send("POST", path, &doctor, &prescribe(&[naproxen], None))
+        .await;
+    assert_eq!(status, 409, "{}", body);
+    assert_eq!(kinds(&body["errors"]), ["allergy"]);
+
+    // Duplicated therapy and moderate interactions only warn
+    let (status, body) = server
+        .send(
+            "POST",
+            path,
+            &doctor,
+            &prescribe(&[paracetamol, cold_remedy, ibuprofen, warfarin], None),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        kinds(&body["data"]["warnings"]),
+        ["duplicate_therapy", "duplicate_therapy"]
+    );
+    assert!(body["data"]["overridden"].as_array().unwrap().is_empty());
+
+    // With a reason the prescription is stored along with what was overridden
+    let (status, body) = server
+        .send(
+            "POST",
+            path,
+            &doctor,
+            &prescribe(&[aspirin, amoxicillin], Some("No alternative available")),
+        )
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(
+        kinds(&body["data"]["overridden"]),
+        ["interaction", "allergy"]
+    );
+    let (reason, issues, overridden_by): (Option<String>, Option<Vec<String>>, Option<i32>) =
+        sqlx::query_as(
+            "SELECT override_reason, overridden_issues, overridden_by FROM medicine_of_prescription WHERE id = $1",
+        )
+        .bind(body["data"]["id"].as_i64().unwrap() as i32)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(reason.as_deref(), Some("No alternative available"));
+    assert_eq!(issues.unwrap().len(), 2);
+    assert_eq!(overridden_by, Some(doctor_user));
+
+    let (status, _) = server
+        .send("POST", path, &doctor, &prescribe(&[0], None))
+        .await;
+    assert_eq!(status, 400, "unknown medicine");
+    let (status, _) = server
+        .send("POST", path, &doctor, &prescribe(&[], None))
+        .await;
+    assert_eq!(status, 400);
+}
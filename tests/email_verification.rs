@@ -0,0 +1,133 @@
+// Checks self-registration against a running server that writes its mail to a file: only
+// patients may sign up, they cannot log in until the emailed link is opened, and login
+// also refuses doctors whose profile is switched off.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test email_verification -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::Server;
+use sqlx::PgPool;
+use std::path::PathBuf;
+
+const PORT: u16 = 18084;
+
+async fn send(server: &Server, method: &str, path: &str, body: &str) -> u16 {
+    server.request(method, path, None, &[], body).await.status
+}
+
+// Path and query of every verification link mailed so far
+fn verification_links(mail_file: &PathBuf) -> Vec<String> {
+    let mail = std::fs::read_to_string(mail_file).unwrap_or_default();
+    let prefix = format!("http://127.0.0.1:{}", PORT);
+    mail.lines()
+        .filter_map(|line| line.strip_prefix(&prefix))
+        .map(str::to_string)
+        .collect()
+}
+
+fn register(email: &str, role: &str) -> String {
+    format!(
+        r#"{{"email":"{}","password":"secret","name":"Verification Test","role":"{}"}}"#,
+        email, role
+    )
+}
+
+fn login(email: &str, role: &str) -> String {
+    format!(
+        r#"{{"login_type":"{}","email":"{}","password":"secret"}}"#,
+        role, email
+    )
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn only_verified_and_active_accounts_log_in() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("verify-{}@hospital.test", suffix);
+    let mail_file = std::env::temp_dir().join(format!("email-verification-{}.log", suffix));
+    let server = Server::start_with(&database_url, PORT, |command| {
+        command
+            .env("MAIL_TRANSPORT", "file")
+            .env("MAIL_FILE", &mail_file);
+    })
+    .await;
+
+    for role in ["admin", "doctor", "receptionist", "staff"] {
+        let status = send(&server, "POST", "/api/register", &register(&email, role)).await;
+        assert_eq!(status, 400, "registering as {} must be refused", role);
+    }
+
+    let status = send(
+        &server,
+        "POST",
+        "/api/register",
+        &register(&email, "patient"),
+    )
+    .await;
+    assert_eq!(status, 200, "registration failed");
+    let status = send(&server, "POST", "/api/login", &login(&email, "patient")).await;
+    assert_eq!(status, 403, "an unverified account must not log in");
+
+    let links = verification_links(&mail_file);
+    assert_eq!(links.len(), 1, "registration must mail one link");
+    let status = send(&server, "GET", "/api/verify-email?token=not-a-token", "").await;
+    assert_eq!(status, 400);
+    let tampered = format!("{}x", links[0]);
+    let status = send(&server, "GET", &tampered, "").await;
+    assert_eq!(status, 400, "a tampered link must be rejected");
+
+    let resend = format!(r#"{{"email":"{}"}}"#, email);
+    let status = send(&server, "POST", "/api/verify-email/resend", &resend).await;
+    assert_eq!(status, 200);
+    let links = verification_links(&mail_file);
+    assert_eq!(links.len(), 2, "resending must mail a new link");
+
+    let status = send(&server, "GET", &links[1], "").await;
+    assert_eq!(status, 200, "the link must verify the account");
+    let status = send(&server, "POST", "/api/login", &login(&email, "patient")).await;
+    assert_eq!(status, 200, "a verified account must log in");
+
+    let status = send(&server, "POST", "/api/verify-email/resend", &resend).await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        verification_links(&mail_file).len(),
+        2,
+        "verified accounts get no more links"
+    );
+
+    // A verified doctor account whose doctor profile is switched off
+    let doctor_email = format!("verify-doctor-{}@hospital.test", suffix);
+    let password = bcrypt::hash("secret", 4).unwrap();
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, 'doctor') RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(&password)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_doctors (email, name, active, user_id) VALUES ($1, 'Inactive', 0, $2)",
+    )
+    .bind(&doctor_email)
+    .bind(user_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let status = send(
+        &server,
+        "POST",
+        "/api/login",
+        &login(&doctor_email, "doctor"),
+    )
+    .await;
+    assert_eq!(status, 403, "an inactive doctor must not log in");
+
+    let _ = std::fs::remove_file(&mail_file);
+}
@@ -0,0 +1,185 @@
+// Checks token signing against a server using the key directory in tests/fixtures/jwt:
+// tokens name their key, verify with nothing but the published key set, and every
+// trusted key is accepted while unknown keys and the shared secret are not.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test jwks -- --ignored
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{access_header, Claims, Server, JWT_SECRET};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde_json::Value;
+use sqlx::PgPool;
+
+const PORT: u16 = 18087;
+const KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jwt");
+const PASSWORD: &str = "rotating-keys";
+
+// Status and body
+async fn send(
+    server: &Server,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, String) {
+    let response = server.request(method, path, token, &[], body).await;
+    (response.status, response.body().to_string())
+}
+
+// The same claims under a fresh id, signed by a key of the test's choosing
+fn resign(claims: &Claims, header: Header, key: &EncodingKey) -> String {
+    let mut claims = claims.clone();
+    claims.jti = format!("jwks-{}", Utc::now().timestamp_nanos_opt().unwrap());
+    claims.iat = Utc::now().timestamp();
+    claims.exp = (Utc::now() + Duration::minutes(5)).timestamp();
+    encode(&header, &claims, key).unwrap()
+}
+
+fn private_key(kid: &str) -> Vec<u8> {
+    std::fs::read(format!("{}/{}.pem", KEYS_DIR, kid)).unwrap()
+}
+
+fn with_kid(algorithm: Algorithm, kid: &str) -> Header {
+    let mut header = access_header(Header::new(algorithm));
+    header.kid = Some(kid.to_string());
+    header
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn tokens_verify_against_the_published_keys() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let email = format!(
+        "jwks-{}@hospital.test",
+        Utc::now().timestamp_nanos_opt().unwrap()
+    );
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, 'patient') RETURNING id",
+    )
+    .bind(&email)
+    .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Key Test', $2)")
+        .bind(&email)
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let server = Server::start_with(&database_url, PORT, |command| {
+        command
+            .env("JWT_KEYS_DIR", KEYS_DIR)
+            .env("JWT_SIGNING_KID", "rsa-2026-10");
+    })
+    .await;
+
+    let (status, response) = send(&server, "GET", "/.well-known/jwks.json", None, "").await;
+    assert_eq!(status, 200);
+    let jwks: JwkSet = serde_json::from_str(&response).unwrap();
+    let mut kids: Vec<&str> = jwks
+        .keys
+        .iter()
+        .filter_map(|key| key.common.key_id.as_deref())
+        .collect();
+    kids.sort();
+    assert_eq!(kids, ["ed-2026-10", "rsa-2026-04", "rsa-2026-10"]);
+    assert!(
+        !&response.contains("\"d\""),
+        "no private key material may be published"
+    );
+
+    // A login token names its key and verifies with the key set alone
+    let login = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, PASSWORD
+    );
+    let (status, response) = send(&server, "POST", "/api/login", None, &login).await;
+    assert_eq!(status, 200);
+    let login: Value = serde_json::from_str(&response).unwrap();
+    let access_token = login["data"]["access_token"].as_str().unwrap().to_string();
+    let header = decode_header(&access_token).unwrap();
+    assert_eq!(header.alg, Algorithm::RS256);
+    assert_eq!(header.kid.as_deref(), Some("rsa-2026-10"));
+    assert_eq!(header.typ.as_deref(), Some("at+jwt"));
+    let jwk = jwks.find("rsa-2026-10").unwrap();
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_audience(&["hms-api"]);
+    validation.set_issuer(&["hospital-management-system"]);
+    let claims = decode::<Claims>(
+        &access_token,
+        &DecodingKey::from_jwk(jwk).unwrap(),
+        &validation,
+    )
+    .unwrap()
+    .claims;
+    assert_eq!(claims.sub, user_id.to_string());
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&access_token), "").await;
+    assert_eq!(status, 200);
+
+    // Every trusted key is accepted, not just the one signing today
+    let ed_key = EncodingKey::from_ed_pem(&private_key("ed-2026-10")).unwrap();
+    let token = resign(&claims, with_kid(Algorithm::EdDSA, "ed-2026-10"), &ed_key);
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(status, 200, "a token from the Ed25519 key must be accepted");
+    let retired_key = EncodingKey::from_rsa_pem(&private_key("rsa-2026-04")).unwrap();
+    let token = resign(
+        &claims,
+        with_kid(Algorithm::RS256, "rsa-2026-04"),
+        &retired_key,
+    );
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(
+        status, 200,
+        "a token from the previous key must be accepted"
+    );
+
+    // Unknown keys, mismatched algorithms and the shared secret are not
+    let signing_key = EncodingKey::from_rsa_pem(&private_key("rsa-2026-10")).unwrap();
+    let token = resign(
+        &claims,
+        with_kid(Algorithm::RS256, "rsa-2025-01"),
+        &signing_key,
+    );
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(status, 401, "a token from an unknown key must be refused");
+    let token = resign(
+        &claims,
+        with_kid(Algorithm::RS256, "ed-2026-10"),
+        &signing_key,
+    );
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(status, 401, "a token must be signed by the key it names");
+
+    // The signing key also signs other kinds of token, told apart by `typ` and `aud`
+    let mut header = with_kid(Algorithm::RS256, "rsa-2026-10");
+    header.typ = Some("JWT".to_string());
+    let token = resign(&claims, header, &signing_key);
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(status, 401, "a token must be typed as an access token");
+    let mut other_audience = claims.clone();
+    other_audience.aud = "hms-email-verification".to_string();
+    let token = resign(
+        &other_audience,
+        with_kid(Algorithm::RS256, "rsa-2026-10"),
+        &signing_key,
+    );
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(status, 401, "a token for another audience must be refused");
+    let secret = EncodingKey::from_secret(JWT_SECRET.as_bytes());
+    let token = resign(&claims, access_header(Header::default()), &secret);
+    let (status, _) = send(&server, "GET", "/api/patient/self", Some(&token), "").await;
+    assert_eq!(
+        status, 401,
+        "HS256 tokens must be refused once keys are configured"
+    );
+}
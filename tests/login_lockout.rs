@@ -0,0 +1,191 @@
+// Checks login throttling against a running server: repeated wrong passwords lock the
+// account until an administrator unlocks it, repeated failures from one address lock the
+// address, every step leaves a security event, and passwords never reach the log.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test login_lockout -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, token, Server};
+use sqlx::PgPool;
+use std::io::Read;
+use std::process::Stdio;
+
+const PORT: u16 = 18085;
+const PASSWORD: &str = "correct-horse";
+
+// Status and the whole response; `ip` is sent as X-Forwarded-For so each case gets its own
+// address
+async fn send(
+    server: &Server,
+    method: &str,
+    path: &str,
+    ip: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, String) {
+    let headers = [("X-Forwarded-For", ip)];
+    let response = server.request(method, path, token, &headers, body).await;
+    (response.status, response.raw)
+}
+
+async fn login(server: &Server, ip: &str, email: &str, password: &str) -> (u16, String) {
+    let body = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, password
+    );
+    send(server, "POST", "/api/login", ip, None, &body).await
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn failed_logins_lock_accounts_and_addresses() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("lockout-{}@hospital.test", suffix);
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, 'patient') RETURNING id",
+    )
+    .bind(&email)
+    .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Lockout Test', $2)")
+        .bind(&email)
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let admin_email = format!("lockout-admin-{}@hospital.test", suffix);
+    let admin_id = create_user(&pool, &admin_email, "admin").await;
+
+    // Addresses unique to this run, so earlier runs cannot have locked them
+    let address = |n: i64| format!("10.{}.{}.{}", n, (suffix / 256) % 256, suffix % 256);
+
+    let mut server = Server::start_with(&database_url, PORT, |command| {
+        command
+            .env("LOGIN_MAX_FAILURES", "3")
+            .env("LOGIN_MAX_IP_FAILURES", "5")
+            .env("TRUST_FORWARDED_FOR", "true")
+            .stdout(Stdio::piped());
+    })
+    .await;
+
+    // Account lockout, from one address that stays under its own limit
+    for attempt in 1..=3 {
+        let (status, _) = login(&server, &address(1), &email, "wrong-password").await;
+        assert_eq!(status, 401, "wrong password, attempt {}", attempt);
+    }
+    let (status, response) = login(&server, &address(2), &email, PASSWORD).await;
+    assert_eq!(status, 429, "a locked account must be refused");
+    assert!(
+        response.contains("retry-after"),
+        "429 must carry Retry-After"
+    );
+
+    let token = token(admin_id, "admin", 0);
+    let unlock = format!("/api/admin/users/{}/unlock", user_id);
+    let (status, _) = send(&server, "POST", &unlock, &address(3), Some(&token), "").await;
+    assert_eq!(status, 200);
+    let (status, _) = login(&server, &address(2), &email, PASSWORD).await;
+    assert_eq!(status, 200, "an unlocked account must log in");
+
+    let events = format!("/api/admin/security-events?user_id={}", user_id);
+    let (status, response) = send(&server, "GET", &events, &address(3), Some(&token), "").await;
+    assert_eq!(status, 200);
+    for event in [
+        "login_failed",
+        "account_locked",
+        "login_blocked",
+        "account_unlocked",
+    ] {
+        assert!(response.contains(event), "missing {} event", event);
+    }
+
+    // Failures far apart do not add up to a lockout
+    for attempt in 1..=2 {
+        let (status, _) = login(&server, &address(6), &email, "wrong-password").await;
+        assert_eq!(status, 401, "wrong password, attempt {}", attempt);
+    }
+    sqlx::query(
+        "UPDATE tn_users SET last_failed_login_at = last_failed_login_at - interval '1 day' WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let (status, _) = login(&server, &address(6), &email, "wrong-password").await;
+    assert_eq!(status, 401);
+    let (status, _) = login(&server, &address(6), &email, PASSWORD).await;
+    assert_eq!(status, 200, "old failures must be forgotten");
+
+    // Lockouts that ended long ago no longer double the next one
+    sqlx::query(
+        "UPDATE tn_users SET lockout_count = 3, locked_until = now() - interval '1 day',
+         last_failed_login_at = now() - interval '1 day' WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    for _ in 1..=3 {
+        login(&server, &address(7), &email, "wrong-password").await;
+    }
+    let lockouts: i32 = sqlx::query_scalar("SELECT lockout_count FROM tn_users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(lockouts, 1, "the account's old lockouts must be forgotten");
+    let (status, _) = send(&server, "POST", &unlock, &address(3), Some(&token), "").await;
+    assert_eq!(status, 200);
+
+    // Address lockout, spread over accounts that do not exist
+    for attempt in 1..=5 {
+        let other = format!("nobody-{}-{}@hospital.test", attempt, suffix);
+        let (status, _) = login(&server, &address(4), &other, "wrong-password").await;
+        assert_eq!(status, 401, "unknown account, attempt {}", attempt);
+    }
+    let (status, _) = login(&server, &address(4), &email, PASSWORD).await;
+    assert_eq!(status, 429, "a locked address must be refused");
+    let (status, _) = login(&server, &address(5), &email, PASSWORD).await;
+    assert_eq!(status, 200, "other addresses must not be affected");
+
+    // The same for addresses, which may be shared by a whole ward
+    sqlx::query(
+        "UPDATE tn_login_ip_attempts SET lockout_count = 6, locked_until = now() - interval '1 day',
+         last_failed_at = now() - interval '1 day' WHERE ip = $1",
+    )
+    .bind(address(4))
+    .execute(&pool)
+    .await
+    .unwrap();
+    for attempt in 1..=5 {
+        let other = format!("nobody-again-{}-{}@hospital.test", attempt, suffix);
+        login(&server, &address(4), &other, "wrong-password").await;
+    }
+    let lockouts: i32 =
+        sqlx::query_scalar("SELECT lockout_count FROM tn_login_ip_attempts WHERE ip = $1")
+            .bind(address(4))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(lockouts, 1, "the address's old lockouts must be forgotten");
+
+    server.child().kill().unwrap();
+    let mut output = String::new();
+    server
+        .child()
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(!output.contains(PASSWORD), "the password was logged");
+    assert!(!output.contains("wrong-password"), "a password was logged");
+}
@@ -0,0 +1,255 @@
+// Checks the ownership rule on patient data against a running server: patients only reach
+// their own records, doctors only patients they have an appointment or record with, and
+// receptionists every patient.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test ownership -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, Server};
+use sqlx::PgPool;
+
+const PORT: u16 = 18082;
+
+struct Account {
+    user_id: i32,
+    profile_id: i32,
+    role: &'static str,
+}
+
+async fn create_account(
+    pool: &PgPool,
+    suffix: i64,
+    name: &str,
+    role: &'static str,
+    profile_table: &str,
+    speciality_id: i32,
+) -> Account {
+    let email = format!("owner-{}-{}@hospital.test", name, suffix);
+    let user_id = create_user(pool, &email, role).await;
+    let profile_id: i32 = if profile_table == "tn_doctors" {
+        sqlx::query_scalar(
+            "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, $2, $3, 1, $4) RETURNING id",
+        )
+        .bind(&email)
+        .bind(name)
+        .bind(speciality_id)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    } else {
+        sqlx::query_scalar(&format!(
+            "INSERT INTO {} (email, name, user_id) VALUES ($1, $2, $3) RETURNING id",
+            profile_table
+        ))
+        .bind(&email)
+        .bind(name)
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    };
+    Account {
+        user_id,
+        profile_id,
+        role,
+    }
+}
+
+fn token(account: &Account) -> String {
+    common::token(account.user_id, account.role, account.profile_id)
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn patient_data_is_only_reachable_by_its_owners() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("owner-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let patient = create_account(
+        &pool,
+        suffix,
+        "patient",
+        "patient",
+        "tn_patients",
+        speciality_id,
+    )
+    .await;
+    let other_patient = create_account(
+        &pool,
+        suffix,
+        "other",
+        "patient",
+        "tn_patients",
+        speciality_id,
+    )
+    .await;
+    let doctor = create_account(
+        &pool,
+        suffix,
+        "doctor",
+        "doctor",
+        "tn_doctors",
+        speciality_id,
+    )
+    .await;
+    let other_doctor = create_account(
+        &pool,
+        suffix,
+        "stranger",
+        "doctor",
+        "tn_doctors",
+        speciality_id,
+    )
+    .await;
+    let receptionist = create_account(
+        &pool,
+        suffix,
+        "desk",
+        "receptionist",
+        "tn_receptionist",
+        speciality_id,
+    )
+    .await;
+
+    // The patient has one appointment with the doctor and the record written during it
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE + 30, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient.profile_id)
+    .bind(doctor.profile_id)
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let record_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient.profile_id)
+    .bind(doctor.profile_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+
+    let server = Server::start(&database_url, PORT).await;
+
+    let own_appointments = format!("/api/appointment/{}", patient.profile_id);
+    let other_appointments = format!("/api/appointment/{}", other_patient.profile_id);
+    let history = format!("/api/appointment/{}/history", appointment_id);
+    let record = format!("/api/medical-record/appointment/{}", appointment_id);
+    let record_exists = format!(
+        "/api/medical-record/is-medical-record-exist/{}",
+        appointment_id
+    );
+    let vital_signs = format!("/api/medical-record/vital-signs/{}", record_id);
+    let prescription = format!("/api/medicine/prescription/{}", record_id);
+    let diagnosis = format!("/api/medical-record/diagnosis/{}", record_id);
+    let record_detail = format!("/api/medical-record/{}", record_id);
+    let amendments = format!("/api/medical-record/{}/amendments", record_id);
+    let note = format!("/api/medical-record/{}/note", record_id);
+    let trend = format!("/api/patient/{}/vitals/trend", patient.profile_id);
+    let clinical_profile = format!("/api/patient/{}/clinical-profile", patient.profile_id);
+    let allergies = format!("/api/patient/{}/allergies", patient.profile_id);
+    let patient_profile = format!("/api/patient/{}", patient.profile_id);
+    let other_profile = format!("/api/patient/{}", other_patient.profile_id);
+    let diagnosis_body = r#"{"diagnosis":"Ownership test"}"#;
+    let note_body = r#"{"plan":"Ownership test"}"#;
+    let allergy_body = r#"{"substance":"Ownership test","severity":"mild"}"#;
+    let booking_body = format!(
+        r#"{{"patient_id":{},"patient_name":"Ownership Test","patient_birthday":"1990-01-01","patient_phone":"0000000000","patient_reason":"Ownership test","speciality_id":{}}}"#,
+        other_patient.profile_id, speciality_id
+    );
+
+    let cases: &[(&Account, &str, &str, &str, u16)] = &[
+        (&patient, "GET", &own_appointments, "", 200),
+        (&patient, "GET", &other_appointments, "", 403),
+        (&patient, "POST", "/api/appointment", &booking_body, 403),
+        (&patient, "GET", &history, "", 200),
+        (&other_patient, "GET", &history, "", 403),
+        (&patient, "GET", &record, "", 200),
+        (&other_patient, "GET", &record, "", 403),
+        (&patient, "GET", &record_exists, "", 200),
+        (&other_patient, "GET", &record_exists, "", 403),
+        (&patient, "GET", &vital_signs, "", 200),
+        (&other_patient, "GET", &vital_signs, "", 403),
+        (&patient, "GET", &prescription, "", 200),
+        (&other_patient, "GET", &prescription, "", 403),
+        (&patient, "GET", &record_detail, "", 200),
+        (&other_patient, "GET", &record_detail, "", 403),
+        (&patient, "GET", &amendments, "", 200),
+        (&other_patient, "GET", &amendments, "", 403),
+        (&patient, "GET", &trend, "", 200),
+        (&other_patient, "GET", &trend, "", 403),
+        (&patient, "GET", &clinical_profile, "", 200),
+        (&other_patient, "GET", &clinical_profile, "", 403),
+        (
+            &patient,
+            "PUT",
+            &patient_profile,
+            r#"{"name":"Ownership Test"}"#,
+            200,
+        ),
+        (
+            &other_patient,
+            "PUT",
+            &patient_profile,
+            r#"{"name":"Ownership Test"}"#,
+            403,
+        ),
+        (&doctor, "GET", &patient_profile, "", 200),
+        (&doctor, "GET", &other_profile, "", 403),
+        (&doctor, "GET", &history, "", 200),
+        (&doctor, "GET", &record, "", 200),
+        (&doctor, "GET", &vital_signs, "", 200),
+        (&doctor, "PUT", &diagnosis, "{}", 400),
+        (&doctor, "PUT", &diagnosis, diagnosis_body, 200),
+        (&doctor, "PUT", &note, note_body, 200),
+        (&doctor, "GET", &record_detail, "", 200),
+        (&doctor, "GET", &trend, "", 200),
+        (&doctor, "GET", &clinical_profile, "", 200),
+        (&doctor, "POST", &allergies, allergy_body, 200),
+        (&other_doctor, "GET", &patient_profile, "", 403),
+        (&other_doctor, "GET", &history, "", 403),
+        (&other_doctor, "GET", &record, "", 403),
+        (&other_doctor, "GET", &vital_signs, "", 403),
+        (&other_doctor, "PUT", &diagnosis, diagnosis_body, 403),
+        (&other_doctor, "PUT", &note, note_body, 403),
+        (&other_doctor, "GET", &record_detail, "", 403),
+        (&other_doctor, "GET", &record_exists, "", 403),
+        (&other_doctor, "GET", &trend, "", 403),
+        (&other_doctor, "GET", &clinical_profile, "", 403),
+        (&other_doctor, "POST", &allergies, allergy_body, 403),
+        (&receptionist, "GET", &other_profile, "", 200),
+        (&receptionist, "GET", &history, "", 200),
+    ];
+
+    let mut failures = Vec::new();
+    for &(account, method, path, body, expected) in cases {
+        let (status, _) = server.send(method, path, &token(account), body).await;
+        if status != expected {
+            failures.push(format!(
+                "{} {} as {} {}: {} instead of {}",
+                method, path, account.role, account.profile_id, status, expected
+            ));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "ownership violations:\n{}",
+        failures.join("\n")
+    );
+}
@@ -0,0 +1,121 @@
+// Walks the password reset flow against a running server that writes its mail to a file:
+// the emailed code resets the password once, stops working after that or once expired,
+// and unknown accounts get the same answer as known ones.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test password_reset -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::Server;
+use sqlx::PgPool;
+use std::path::PathBuf;
+
+const PORT: u16 = 18083;
+
+// Status and body
+async fn post(server: &Server, path: &str, body: &str) -> (u16, String) {
+    let response = server.request("POST", path, None, &[], body).await;
+    (response.status, response.body().to_string())
+}
+
+// The code from the `count`th reset email. Mail goes out in the background, so this waits
+// for it to arrive.
+async fn reset_code(mail_file: &PathBuf, count: usize) -> String {
+    for _ in 0..50 {
+        let mail = std::fs::read_to_string(mail_file).unwrap_or_default();
+        let codes: Vec<&str> = mail
+            .lines()
+            .filter_map(|line| line.strip_prefix("Reset code: "))
+            .collect();
+        if codes.len() >= count {
+            return codes[count - 1].to_string();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("reset email {} was not sent", count);
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn reset_codes_work_once_and_expire() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("reset-{}@hospital.test", suffix);
+    let mail_file = std::env::temp_dir().join(format!("password-reset-{}.log", suffix));
+    let server = Server::start_with(&database_url, PORT, |command| {
+        command
+            .env("MAIL_TRANSPORT", "file")
+            .env("MAIL_FILE", &mail_file);
+    })
+    .await;
+
+    let (status, _) = post(
+        &server,
+        "/api/register",
+        &format!(
+            r#"{{"email":"{}","password":"old-password","name":"Reset Test","role":"patient"}}"#,
+            email
+        ),
+    )
+    .await;
+    assert_eq!(status, 200, "registration failed");
+    sqlx::query("UPDATE tn_users SET verified = 1 WHERE email = $1")
+        .bind(&email)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let reset_request = format!(r#"{{"email":"{}","role":"patient"}}"#, email);
+    let (status, known) = post(&server, "/api/reset-password", &reset_request).await;
+    assert_eq!(status, 200);
+    let (status, unknown) = post(
+        &server,
+        "/api/reset-password",
+        &format!(
+            r#"{{"email":"nobody-{}@hospital.test","role":"patient"}}"#,
+            suffix
+        ),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(known, unknown, "unknown accounts must get the same answer");
+
+    let code = reset_code(&mail_file, 1).await;
+    let confirm = format!(r#"{{"token":"{}","new_password":"new-password"}}"#, code);
+    let (status, _) = post(&server, "/api/reset-password/confirm", &confirm).await;
+    assert_eq!(status, 200, "a fresh code must reset the password");
+    let (status, _) = post(&server, "/api/reset-password/confirm", &confirm).await;
+    assert_eq!(status, 400, "a code must only work once");
+
+    let login = |password: &str| {
+        format!(
+            r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+            email, password
+        )
+    };
+    let (status, _) = post(&server, "/api/login", &login("new-password")).await;
+    assert_eq!(status, 200, "the new password must work");
+    let (status, _) = post(&server, "/api/login", &login("old-password")).await;
+    assert_ne!(status, 200, "the old password must stop working");
+
+    post(&server, "/api/reset-password", &reset_request).await;
+    let code = reset_code(&mail_file, 2).await;
+    sqlx::query("UPDATE tn_users SET recovery_token_expires_at = NOW() - INTERVAL '1 minute' WHERE email = $1")
+        .bind(&email)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (status, _) = post(
+        &server,
+        "/api/reset-password/confirm",
+        &format!(r#"{{"token":"{}","new_password":"other-password"}}"#, code),
+    )
+    .await;
+    assert_eq!(status, 400, "an expired code must be rejected");
+
+    let _ = std::fs::remove_file(&mail_file);
+}
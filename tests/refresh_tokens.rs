@@ -0,0 +1,138 @@
+// Checks refresh token rotation against a running server: a refresh token works once,
+// presenting a rotated one again ends every session of the account, and logging out ends
+// the tokens it names.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test refresh_tokens -- --ignored
+
+mod common;
+
+use chrono::{NaiveDateTime, Utc};
+use common::Server;
+use serde_json::Value;
+use sqlx::PgPool;
+
+const PORT: u16 = 18094;
+const PASSWORD: &str = "rotate-me";
+
+// Access and refresh token of a new login
+async fn login(server: &Server, email: &str) -> (String, String) {
+    let body = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, PASSWORD
+    );
+    let response = server.request("POST", "/api/login", None, &[], &body).await;
+    assert_eq!(response.status, 200, "login failed");
+    let body = response.json();
+    (
+        body["data"]["access_token"].as_str().unwrap().to_string(),
+        body["data"]["refresh_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn refresh(server: &Server, refresh_token: &str) -> (u16, Value) {
+    let body = format!(r#"{{"refresh_token":"{}"}}"#, refresh_token);
+    let response = server
+        .request("POST", "/api/auth/refresh", None, &[], &body)
+        .await;
+    (response.status, response.json())
+}
+
+async fn status_with(server: &Server, token: &str) -> u16 {
+    server.send("GET", "/api/auth/sessions", token, "").await.0
+}
+
+// Revocations are recorded to the second and cover tokens issued in that second, so a
+// login right after one would be refused
+async fn next_second() {
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn refresh_tokens_work_once() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let email = format!(
+        "refresh-{}@hospital.test",
+        Utc::now().timestamp_nanos_opt().unwrap()
+    );
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, 'patient') RETURNING id",
+    )
+    .bind(&email)
+    .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Refresh Test', $2)")
+        .bind(&email)
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let tokens_revoked_at = || async {
+        sqlx::query_scalar::<_, Option<NaiveDateTime>>(
+            "SELECT tokens_revoked_at FROM tn_users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+    };
+
+    let server = Server::start(&database_url, PORT).await;
+
+    // Rotation hands out a new pair and retires the old refresh token
+    let (first_access, first_refresh) = login(&server, &email).await;
+    let (status, body) = refresh(&server, &first_refresh).await;
+    assert_eq!(status, 200, "{}", body);
+    let second_access = body["data"]["access_token"].as_str().unwrap().to_string();
+    let second_refresh = body["data"]["refresh_token"].as_str().unwrap().to_string();
+    assert_ne!(second_refresh, first_refresh);
+    assert_eq!(status_with(&server, &second_access).await, 200);
+    assert_eq!(tokens_revoked_at().await, None);
+
+    // Presenting the retired token again means it leaked: everything of the account ends
+    let (status, _) = refresh(&server, &first_refresh).await;
+    assert_eq!(status, 401, "a refresh token works once");
+    assert!(
+        tokens_revoked_at().await.is_some(),
+        "reuse must revoke the account's tokens"
+    );
+    let (status, _) = refresh(&server, &second_refresh).await;
+    assert_eq!(status, 401, "the rotated token must die with the rest");
+    assert_eq!(status_with(&server, &first_access).await, 401);
+    assert_eq!(status_with(&server, &second_access).await, 401);
+
+    // Logging out ends the access token and the refresh token it names
+    next_second().await;
+    let (access, refresh_token) = login(&server, &email).await;
+    assert_eq!(
+        status_with(&server, &access).await,
+        200,
+        "a new login works"
+    );
+    let body = format!(r#"{{"refresh_token":"{}"}}"#, refresh_token);
+    let (status, _) = server
+        .send("POST", "/api/auth/logout", &access, &body)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(status_with(&server, &access).await, 401);
+    let (status, _) = refresh(&server, &refresh_token).await;
+    assert_eq!(status, 401, "a logged out refresh token must stop working");
+
+    // Logging out everywhere ends the other logins too
+    let (access, _) = login(&server, &email).await;
+    let (other_access, other_refresh) = login(&server, &email).await;
+    let before = tokens_revoked_at().await;
+    let (status, _) = server
+        .send("POST", "/api/auth/logout", &access, r#"{"all":true}"#)
+        .await;
+    assert_eq!(status, 200);
+    assert!(tokens_revoked_at().await > before);
+    assert_eq!(status_with(&server, &other_access).await, 401);
+    let (status, _) = refresh(&server, &other_refresh).await;
+    assert_eq!(status, 401);
+}
@@ -0,0 +1,260 @@
+// Checks rooms against a running server: a room shows the queue of the doctors sitting in
+// it now, through a shift or as their default room, a connected display gets a new
+// snapshot when a shift moves a doctor in or out, and a room is never given to two doctors
+// at once through a shift and a default room.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test room_queue -- --ignored
+
+mod common;
+
+use chrono::{Datelike, Local, Utc};
+use common::{create_user, token, Server};
+use sqlx::PgPool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const PORT: u16 = 18095;
+const DISPLAY_TOKEN: &str = "lobby-screen";
+
+// The next server-sent event of the stream, as received
+async fn next_event(stream: &mut TcpStream) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let read =
+            tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buffer))
+                .await
+                .expect("no event within five seconds")
+                .unwrap();
+        assert!(read > 0, "the stream closed");
+        received.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&received);
+        if let Some(start) = text.find("event: queue") {
+            if let Some(end) = text[start..].find("\n\n") {
+                return text[start..start + end].to_string();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn rooms_follow_shifts_and_default_rooms() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let today = Local::now().date_naive();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("rooms-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let room_id: i32 = sqlx::query_scalar("INSERT INTO tn_rooms (name) VALUES ($1) RETURNING id")
+        .bind(format!("R{}", suffix % 1_000_000))
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    // No default room; the doctor only sits in the room during a shift
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active) VALUES ($1, 'Room Test', $2, 1) RETURNING id",
+    )
+    .bind(format!("rooms-doctor-{}@hospital.test", suffix))
+    .bind(speciality_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name) VALUES ($1, 'Room Patient') RETURNING id",
+    )
+    .bind(format!("rooms-patient-{}@hospital.test", suffix))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status, treatment_status, numerical_order)
+         VALUES ($1, $2, $3, $4, '09:00', 'Unpaid', 'checked-in', 1)",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .bind(today)
+    .execute(&pool)
+    .await
+    .unwrap();
+    // Next door, a doctor of the same specialty in their default room with a patient of
+    // their own, who must not show up on this room's display
+    let other_room_id: i32 =
+        sqlx::query_scalar("INSERT INTO tn_rooms (name) VALUES ($1) RETURNING id")
+            .bind(format!("N{}", suffix % 1_000_000))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    let neighbour_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, room_id) VALUES ($1, 'Neighbour', $2, 1, $3) RETURNING id",
+    )
+    .bind(format!("rooms-neighbour-{}@hospital.test", suffix))
+    .bind(speciality_id)
+    .bind(other_room_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status, treatment_status, numerical_order)
+         VALUES ($1, $2, $3, $4, '09:30', 'Unpaid', 'checked-in', 2)",
+    )
+    .bind(patient_id)
+    .bind(neighbour_id)
+    .bind(speciality_id)
+    .bind(today)
+    .execute(&pool)
+    .await
+    .unwrap();
+    let admin_email = format!("rooms-admin-{}@hospital.test", suffix);
+    let admin = token(create_user(&pool, &admin_email, "admin").await, "admin", 0);
+
+    let server = Server::start_with(&database_url, PORT, |command| {
+        command.env("QUEUE_DISPLAY_TOKEN", DISPLAY_TOKEN);
+    })
+    .await;
+    let room_queue = format!("/api/queue/room/{}?token={}", room_id, DISPLAY_TOKEN);
+    let waiting = |body: serde_json::Value| body["data"]["waiting"].as_i64().unwrap();
+
+    let response = server.request("GET", &room_queue, None, &[], "").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(waiting(response.json()), 0, "nobody sits in the room yet");
+    let other_queue = format!("/api/queue/room/{}?token={}", other_room_id, DISPLAY_TOKEN);
+    let response = server.request("GET", &other_queue, None, &[], "").await;
+    assert_eq!(response.json()["data"]["next_up"], serde_json::json!([2]));
+    let wrong_token = format!("/api/queue/room/{}?token=lobby-screeN", room_id);
+    let response = server.request("GET", &wrong_token, None, &[], "").await;
+    assert_eq!(response.status, 401);
+
+    // A display connected before the shift is created
+    let mut stream = TcpStream::connect(server.address()).await.unwrap();
+    let request = format!(
+        "GET /api/queue/room/{}/stream?token={} HTTP/1.1\r\nHost: {}\r\n\r\n",
+        room_id,
+        DISPLAY_TOKEN,
+        server.address()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let event = next_event(&mut stream).await;
+    assert!(event.contains(r#""waiting":0"#), "{}", event);
+
+    let shift = serde_json::json!({
+        "doctor_id": doctor_id,
+        "date": today,
+        "start_time": "00:00:00",
+        "end_time": "23:59:00",
+    })
+    .to_string();
+    let shifts = format!("/api/admin/rooms/{}/shifts", room_id);
+    let (status, body) = server.send("POST", &shifts, &admin, &shift).await;
+    assert_eq!(status, 201, "{}", body);
+    let shift_id = body["data"].as_i64().unwrap();
+
+    let event = next_event(&mut stream).await;
+    assert!(
+        event.contains(r#""waiting":1"#),
+        "the display must pick up the doctor's queue: {}",
+        event
+    );
+    let response = server.request("GET", &room_queue, None, &[], "").await;
+    let body = response.json();
+    assert_eq!(
+        waiting(body.clone()),
+        1,
+        "a shift puts the doctor in the room"
+    );
+    assert_eq!(
+        body["data"]["next_up"],
+        serde_json::json!([1]),
+        "the room shows its own doctors' patients, not the whole specialty's"
+    );
+
+    let (status, _) = server
+        .send("DELETE", &format!("{}/{}", shifts, shift_id), &admin, "")
+        .await;
+    assert_eq!(status, 200);
+    let event = next_event(&mut stream).await;
+    assert!(
+        event.contains(r#""waiting":0"#),
+        "the display must drop the doctor's queue: {}",
+        event
+    );
+
+    // A doctor whose default room it is holds the room while they work
+    let weekday = today.weekday().number_from_monday() as i32;
+    let mut doctors = Vec::new();
+    for (name, start, end) in [
+        ("Occupant", "08:00", "12:00"),
+        ("Newcomer", "12:30", "17:00"),
+    ] {
+        let id: i32 = sqlx::query_scalar(
+            "INSERT INTO tn_doctors (email, name, speciality_id, active) VALUES ($1, $2, $3, 1) RETURNING id",
+        )
+        .bind(format!("rooms-{}-{}@hospital.test", name.to_lowercase(), suffix))
+        .bind(name)
+        .bind(speciality_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO tn_doctor_working_hours (doctor_id, weekday, start_time, end_time) VALUES ($1, $2, $3::time, $4::time)",
+        )
+        .bind(id)
+        .bind(weekday)
+        .bind(start)
+        .bind(end)
+        .execute(&pool)
+        .await
+        .unwrap();
+        doctors.push(id);
+    }
+    let (occupant, newcomer) = (doctors[0], doctors[1]);
+    let doctor_room = |id: i32| format!("/api/admin/doctors/{}/room", id);
+    let room = format!(r#"{{"room_id":{}}}"#, room_id);
+    let (status, body) = server
+        .send("PUT", &doctor_room(occupant), &admin, &room)
+        .await;
+    assert_eq!(status, 200, "{}", body);
+
+    let shift_at = |start: &str, end: &str| {
+        serde_json::json!({
+            "doctor_id": doctor_id,
+            "date": today,
+            "start_time": start,
+            "end_time": end,
+        })
+        .to_string()
+    };
+    let (status, body) = server
+        .send("POST", &shifts, &admin, &shift_at("09:00:00", "10:00:00"))
+        .await;
+    assert_eq!(status, 409, "the room's own doctor works then");
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("default room of Occupant"));
+    let (status, body) = server
+        .send("POST", &shifts, &admin, &shift_at("13:00:00", "14:00:00"))
+        .await;
+    assert_eq!(status, 201, "{}", body);
+
+    // And a shift holds the room against a new default occupant
+    let (status, body) = server
+        .send("PUT", &doctor_room(newcomer), &admin, &room)
+        .await;
+    assert_eq!(
+        status, 409,
+        "the newcomer would share the room with the shift"
+    );
+    assert!(body["message"]
+        .as_str()
+        .unwrap()
+        .contains("has a shift in this room"));
+}
@@ -0,0 +1,216 @@
+// Checks session management against a running server: every login shows up as a session
+// with its device, a revoked session's access and refresh tokens stop working at once,
+// and other sessions carry on.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test sessions -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::Server;
+use serde_json::Value;
+use sqlx::PgPool;
+
+const PORT: u16 = 18088;
+const PASSWORD: &str = "many-devices";
+const LAPTOP: &str = "Mozilla/5.0 (X11; Linux x86_64) Firefox/131.0";
+const PHONE: &str = "HospitalApp/2.3 (iPhone; iOS 18.0)";
+
+async fn send(
+    server: &Server,
+    method: &str,
+    path: &str,
+    user_agent: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, Value) {
+    let headers = [("User-Agent", user_agent)];
+    let response = server.request(method, path, token, &headers, body).await;
+    (response.status, response.json())
+}
+
+// Access and refresh token of a new login from the given device
+async fn login(server: &Server, email: &str, user_agent: &str) -> (String, String) {
+    let body = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, PASSWORD
+    );
+    let (status, body) = send(server, "POST", "/api/login", user_agent, None, &body).await;
+    assert_eq!(status, 200, "login failed");
+    (
+        body["data"]["access_token"].as_str().unwrap().to_string(),
+        body["data"]["refresh_token"].as_str().unwrap().to_string(),
+    )
+}
+
+async fn refresh(server: &Server, user_agent: &str, refresh_token: &str) -> (u16, Value) {
+    let body = format!(r#"{{"refresh_token":"{}"}}"#, refresh_token);
+    send(server, "POST", "/api/auth/refresh", user_agent, None, &body).await
+}
+
+async fn sessions(server: &Server, user_agent: &str, token: &str) -> Vec<Value> {
+    let (status, body) = send(
+        server,
+        "GET",
+        "/api/auth/sessions",
+        user_agent,
+        Some(token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 200);
+    body["data"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn revoked_sessions_lose_their_tokens() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let mut emails = Vec::new();
+    for owner in ["owner", "other"] {
+        let email = format!("sessions-{}-{}@hospital.test", owner, suffix);
+        let user_id: i32 = sqlx::query_scalar(
+            "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, 'patient') RETURNING id",
+        )
+        .bind(&email)
+        .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO tn_patients (email, name, user_id) VALUES ($1, 'Session Test', $2)",
+        )
+        .bind(&email)
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+        emails.push(email);
+    }
+
+    let server = Server::start(&database_url, PORT).await;
+
+    let (laptop_token, laptop_refresh) = login(&server, &emails[0], LAPTOP).await;
+    let (phone_token, phone_refresh) = login(&server, &emails[0], PHONE).await;
+    let (other_token, _) = login(&server, &emails[1], LAPTOP).await;
+
+    let listed = sessions(&server, LAPTOP, &laptop_token).await;
+    assert_eq!(listed.len(), 2, "one session per login");
+    let current: Vec<&Value> = listed.iter().filter(|s| s["current"] == true).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["user_agent"], LAPTOP);
+    let phone = listed
+        .iter()
+        .find(|s| s["user_agent"] == PHONE)
+        .expect("the phone session must be listed");
+    assert!(phone["ip"].is_string() && phone["last_seen_at"].is_string());
+    let phone_session = format!("/api/auth/sessions/{}", phone["id"]);
+
+    // Sessions of other users are out of reach
+    let (status, _) = send(
+        &server,
+        "DELETE",
+        &phone_session,
+        LAPTOP,
+        Some(&other_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 404);
+    let (status, _) = send(
+        &server,
+        "GET",
+        "/api/patient/self",
+        PHONE,
+        Some(&phone_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, body) = refresh(&server, PHONE, &phone_refresh).await;
+    assert_eq!(
+        status, 200,
+        "another user's attempt must leave the refresh token alive"
+    );
+    let phone_refresh = body["data"]["refresh_token"].as_str().unwrap().to_string();
+
+    let (status, _) = send(
+        &server,
+        "DELETE",
+        &phone_session,
+        LAPTOP,
+        Some(&laptop_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, _) = send(
+        &server,
+        "DELETE",
+        &phone_session,
+        LAPTOP,
+        Some(&laptop_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 404, "a session can only be revoked once");
+    let (status, _) = send(
+        &server,
+        "GET",
+        "/api/patient/self",
+        PHONE,
+        Some(&phone_token),
+        "",
+    )
+    .await;
+    assert_eq!(
+        status, 401,
+        "the revoked session's access token must stop working"
+    );
+    let (status, _) = refresh(&server, PHONE, &phone_refresh).await;
+    assert_eq!(
+        status, 401,
+        "the revoked session's refresh token must stop working"
+    );
+
+    // The laptop is not affected, not even by the phone's dead refresh token
+    let (status, _) = send(
+        &server,
+        "GET",
+        "/api/patient/self",
+        LAPTOP,
+        Some(&laptop_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (status, body) = refresh(&server, LAPTOP, &laptop_refresh).await;
+    assert_eq!(status, 200);
+    let laptop_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let listed = sessions(&server, LAPTOP, &laptop_token).await;
+    assert_eq!(listed.len(), 1, "refreshing continues the session");
+    assert_eq!(listed[0]["current"], true);
+
+    // Logging out ends the session too
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/auth/logout",
+        LAPTOP,
+        Some(&laptop_token),
+        "",
+    )
+    .await;
+    assert_eq!(status, 200);
+    let (_, other_refresh) = login(&server, &emails[0], PHONE).await;
+    let (status, body) = refresh(&server, PHONE, &other_refresh).await;
+    assert_eq!(status, 200);
+    let phone_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let listed = sessions(&server, PHONE, &phone_token).await;
+    assert_eq!(listed.len(), 1, "the logged out session must be gone");
+    assert_eq!(listed[0]["user_agent"], PHONE);
+}
@@ -0,0 +1,437 @@
+// Walks two-factor sign-in against a running server: enrolling with an authenticator,
+// logging in through the pending token with a code or a recovery code, a role whose
+// policy forces enrollment before the first full login, and wrong codes counting towards
+// the lockouts whichever endpoint they are sent to.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test two_factor -- --ignored
+
+mod common;
+
+use base32::Alphabet;
+use chrono::Utc;
+use common::{create_user, token, Server};
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha1::Sha1;
+use sqlx::PgPool;
+
+const PORT: u16 = 18086;
+const LOCKOUT_PORT: u16 = 18096;
+const PASSWORD: &str = "second-factor";
+
+async fn send(
+    server: &Server,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, Value) {
+    let response = server.request(method, path, token, &[], body).await;
+    (response.status, response.json())
+}
+
+// As `send`, with `ip` sent as X-Forwarded-For
+async fn send_from(
+    server: &Server,
+    ip: &str,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, Value) {
+    let headers = [("X-Forwarded-For", ip)];
+    let response = server.request(method, path, token, &headers, body).await;
+    (response.status, response.json())
+}
+
+async fn login(server: &Server, email: &str, role: &str) -> (u16, Value) {
+    let body = format!(
+        r#"{{"login_type":"{}","email":"{}","password":"{}"}}"#,
+        role, email, PASSWORD
+    );
+    send(server, "POST", "/api/login", None, &body).await
+}
+
+// The code an authenticator shows `steps_ahead` periods from now. Codes from the next
+// period are still accepted, which lets one test use several codes without waiting.
+fn totp(secret: &str, steps_ahead: i64) -> String {
+    let key = base32::decode(Alphabet::Rfc4648 { padding: false }, secret).unwrap();
+    let step = Utc::now().timestamp() / 30 + steps_ahead;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).unwrap();
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:06}", value % 1_000_000)
+}
+
+fn code(code: &str) -> String {
+    format!(r#"{{"code":"{}"}}"#, code)
+}
+
+async fn create_account(pool: &PgPool, email: &str, role: &str, profile_table: &str) -> i32 {
+    let user_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_users (email, password, role) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(email)
+    .bind(bcrypt::hash(PASSWORD, 4).unwrap())
+    .bind(role)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    sqlx::query(&format!(
+        "INSERT INTO {} (email, name, user_id) VALUES ($1, 'Two Factor Test', $2)",
+        profile_table
+    ))
+    .bind(email)
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .unwrap();
+    user_id
+}
+
+async fn failed_logins(pool: &PgPool, email: &str) -> i32 {
+    sqlx::query_scalar("SELECT failed_login_count FROM tn_users WHERE email = $1")
+        .bind(email)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn second_factor_guards_sign_in() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let doctor_email = format!("2fa-doctor-{}@hospital.test", suffix);
+    let desk_email = format!("2fa-desk-{}@hospital.test", suffix);
+    create_account(&pool, &doctor_email, "doctor", "tn_doctors").await;
+    create_account(&pool, &desk_email, "receptionist", "tn_receptionist").await;
+    let admin_email = format!("2fa-admin-{}@hospital.test", suffix);
+    let admin_id = create_user(&pool, &admin_email, "admin").await;
+
+    let server = Server::start(&database_url, PORT).await;
+
+    // Enrollment, with a full session from a password-only login
+    let (status, body) = login(&server, &doctor_email, "doctor").await;
+    assert_eq!(status, 200);
+    let access_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let (status, body) = send(&server, "POST", "/api/2fa/enroll", Some(&access_token), "").await;
+    assert_eq!(status, 200);
+    let secret = body["data"]["secret"].as_str().unwrap().to_string();
+    assert!(body["data"]["otpauth_uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/"));
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/enable",
+        Some(&access_token),
+        &code("000000"),
+    )
+    .await;
+    assert_eq!(status, 401, "a wrong code must not enable 2FA");
+    let enable_code = totp(&secret, 0);
+    let (status, body) = send(
+        &server,
+        "POST",
+        "/api/2fa/enable",
+        Some(&access_token),
+        &code(&enable_code),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let recovery_codes: Vec<String> = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|code| code.as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(recovery_codes.len(), 10);
+
+    // The password alone now only earns a pending token
+    let (status, body) = login(&server, &doctor_email, "doctor").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["second_factor_required"], true);
+    assert!(
+        body["data"].is_null(),
+        "no real tokens before the second factor"
+    );
+    let pending = body["second_factor_token"].as_str().unwrap().to_string();
+    let (status, _) = send(&server, "GET", "/api/doctor/self", Some(&pending), "").await;
+    assert_eq!(status, 401, "a pending token must not reach other routes");
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &code("000000"),
+    )
+    .await;
+    assert_eq!(status, 401);
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &code(&format!("0{}", totp(&secret, 1))),
+    )
+    .await;
+    assert_eq!(status, 401, "only six digits make a code");
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &code(&enable_code),
+    )
+    .await;
+    assert_eq!(
+        status, 401,
+        "the code used for enabling must not work again"
+    );
+    // The password on its own does not wipe out wrong second factors
+    let (status, _) = login(&server, &doctor_email, "doctor").await;
+    assert_eq!(status, 200);
+    assert_eq!(failed_logins(&pool, &doctor_email).await, 4);
+    let (status, body) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &code(&totp(&secret, 1)),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let access_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let (status, _) = send(&server, "GET", "/api/doctor/self", Some(&access_token), "").await;
+    assert_eq!(status, 200, "the verified session must work");
+    assert_eq!(failed_logins(&pool, &doctor_email).await, 0);
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &code(&totp(&secret, 1)),
+    )
+    .await;
+    assert_eq!(status, 401, "a pending token must only be traded once");
+
+    // Recovery codes work once each
+    let recovery = format!(
+        r#"{{"recovery_code":"{}"}}"#,
+        recovery_codes[0].to_uppercase()
+    );
+    let (_, body) = login(&server, &doctor_email, "doctor").await;
+    let pending = body["second_factor_token"].as_str().unwrap().to_string();
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &recovery,
+    )
+    .await;
+    assert_eq!(status, 200, "a recovery code must sign in");
+    let (_, body) = login(&server, &doctor_email, "doctor").await;
+    let pending = body["second_factor_token"].as_str().unwrap().to_string();
+    let (status, _) = send(
+        &server,
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &recovery,
+    )
+    .await;
+    assert_eq!(status, 401, "a recovery code must only work once");
+
+    // A role that must use 2FA enrolls before the first full login
+    let admin = token(admin_id, "admin", 0);
+    let policy = "/api/admin/two-factor-policy/receptionist";
+    let (status, _) = send(&server, "PUT", policy, Some(&admin), r#"{"required":true}"#).await;
+    assert_eq!(status, 200);
+    let (status, body) = login(&server, &desk_email, "receptionist").await;
+    let (_, _) = send(
+        &server,
+        "PUT",
+        policy,
+        Some(&admin),
+        r#"{"required":false}"#,
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(body["enrollment_required"], true);
+    let pending = body["second_factor_token"].as_str().unwrap().to_string();
+    let (status, _) = send(&server, "GET", "/api/patient/all", Some(&pending), "").await;
+    assert_eq!(status, 401);
+    let (status, body) = send(&server, "POST", "/api/2fa/enroll", Some(&pending), "").await;
+    assert_eq!(status, 200);
+    let secret = body["data"]["secret"].as_str().unwrap().to_string();
+    let (status, body) = send(
+        &server,
+        "POST",
+        "/api/2fa/enable",
+        Some(&pending),
+        &code(&totp(&secret, 0)),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let access_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let (status, _) = send(&server, "GET", "/api/patient/all", Some(&access_token), "").await;
+    assert_eq!(status, 200, "enabling must finish the pending login");
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn wrong_codes_lock_every_endpoint() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let email = format!("2fa-lockout-{}@hospital.test", suffix);
+    let user_id = create_account(&pool, &email, "patient", "tn_patients").await;
+    // Addresses unique to this run, so earlier runs cannot have locked them
+    let address = |n: i64| format!("10.{}.{}.{}", 100 + n, (suffix / 256) % 256, suffix % 256);
+
+    let server = Server::start_with(&database_url, LOCKOUT_PORT, |command| {
+        command
+            .env("LOGIN_MAX_FAILURES", "3")
+            .env("TRUST_FORWARDED_FOR", "true");
+    })
+    .await;
+
+    let body = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, PASSWORD
+    );
+    let (status, body) = send_from(&server, &address(1), "POST", "/api/login", None, &body).await;
+    assert_eq!(status, 200);
+    let access_token = body["data"]["access_token"].as_str().unwrap().to_string();
+    let token = Some(access_token.as_str());
+    let (status, body) =
+        send_from(&server, &address(1), "POST", "/api/2fa/enroll", token, "").await;
+    assert_eq!(status, 200);
+    let secret = body["data"]["secret"].as_str().unwrap().to_string();
+
+    // Wrong codes count wherever they are sent, until the account locks
+    let wrong = code("000000");
+    let (status, _) = send_from(
+        &server,
+        &address(1),
+        "POST",
+        "/api/2fa/enable",
+        token,
+        &wrong,
+    )
+    .await;
+    assert_eq!(status, 401);
+    assert_eq!(failed_logins(&pool, &email).await, 1);
+    let (status, body) = send_from(
+        &server,
+        &address(1),
+        "POST",
+        "/api/2fa/enable",
+        token,
+        &code(&totp(&secret, 0)),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let recovery = format!(
+        r#"{{"recovery_code":"{}"}}"#,
+        body["recovery_codes"][0].as_str().unwrap()
+    );
+    let recovery_codes = "/api/auth/2fa/recovery-codes";
+    let (status, _) = send_from(&server, &address(1), "POST", recovery_codes, token, &wrong).await;
+    assert_eq!(status, 401);
+    assert_eq!(failed_logins(&pool, &email).await, 2);
+    let (status, _) = send_from(
+        &server,
+        &address(1),
+        "POST",
+        "/api/auth/2fa/disable",
+        token,
+        r#"{"recovery_code":"aaaaa-aaaaa"}"#,
+    )
+    .await;
+    assert_eq!(status, 401);
+
+    // Once locked, not even the right code is looked at
+    let right = code(&totp(&secret, 1));
+    let (status, _) = send_from(
+        &server,
+        &address(2),
+        "POST",
+        "/api/auth/2fa/disable",
+        token,
+        &right,
+    )
+    .await;
+    assert_eq!(status, 429, "a locked account must not disable 2FA");
+    let (status, _) = send_from(&server, &address(2), "POST", recovery_codes, token, &right).await;
+    assert_eq!(
+        status, 429,
+        "a locked account must not get new recovery codes"
+    );
+    let enabled: i32 = sqlx::query_scalar("SELECT totp_enabled FROM tn_users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(enabled, 1);
+
+    // A locked address is turned away the same way, also when finishing a login
+    sqlx::query("UPDATE tn_users SET failed_login_count = 0, locked_until = NULL WHERE id = $1")
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query(
+        "INSERT INTO tn_login_ip_attempts (ip, failed_count, locked_until) VALUES ($1, 0, now() + interval '15 minutes')",
+    )
+    .bind(address(3))
+    .execute(&pool)
+    .await
+    .unwrap();
+    let body = format!(
+        r#"{{"login_type":"patient","email":"{}","password":"{}"}}"#,
+        email, PASSWORD
+    );
+    let (status, body) = send_from(&server, &address(2), "POST", "/api/login", None, &body).await;
+    assert_eq!(status, 200);
+    let pending = body["second_factor_token"].as_str().unwrap().to_string();
+    let (status, _) = send_from(
+        &server,
+        &address(3),
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &right,
+    )
+    .await;
+    assert_eq!(status, 429, "a locked address must not finish a login");
+    let (status, _) = send_from(
+        &server,
+        &address(3),
+        "POST",
+        "/api/auth/2fa/disable",
+        token,
+        &recovery,
+    )
+    .await;
+    assert_eq!(status, 429, "a locked address must not disable 2FA");
+    let (status, _) = send_from(
+        &server,
+        &address(2),
+        "POST",
+        "/api/2fa/verify",
+        Some(&pending),
+        &right,
+    )
+    .await;
+    assert_eq!(status, 200);
+}
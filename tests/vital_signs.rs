@@ -0,0 +1,260 @@
+// Checks vital signs against a running server: readings in imperial units are stored in
+// metric ones with their BMI, impossible readings are refused, and readings outside the
+// reference ranges for the patient's age come back flagged.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test vital_signs -- --ignored
+
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{create_user, token, Server};
+use serde_json::Value;
+use sqlx::PgPool;
+
+// A patient born on `birthday` with a record written by the doctor
+async fn create_record(
+    pool: &PgPool,
+    suffix: i64,
+    name: &str,
+    birthday: &str,
+    doctor_id: i32,
+    speciality_id: i32,
+) -> i32 {
+    let patient_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_patients (email, name, birthday) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(format!("vitals-{}-{}@hospital.test", name, suffix))
+    .bind(name)
+    .bind(birthday)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient_id)
+    .bind(doctor_id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+fn flagged(reading: &Value) -> Vec<(String, String)> {
+    reading["flags"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| {
+            (
+                f["vital"].as_str().unwrap().to_string(),
+                f["flag"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn readings_are_converted_validated_and_flagged() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("vitals-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let doctor_email = format!("vitals-doctor-{}@hospital.test", suffix);
+    let doctor_user = create_user(&pool, &doctor_email, "doctor").await;
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, 'Vitals Doctor', $2, 1, $3) RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(speciality_id)
+    .bind(doctor_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let admin_user = create_user(
+        &pool,
+        &format!("vitals-admin-{}@hospital.test", suffix),
+        "admin",
+    )
+    .await;
+    let eight_years_ago = (Utc::now() - Duration::days(8 * 365))
+        .format("%Y-%m-%d")
+        .to_string();
+    let adult_record = create_record(
+        &pool,
+        suffix,
+        "adult",
+        "1980-02-29",
+        doctor_id,
+        speciality_id,
+    )
+    .await;
+    let child_record = create_record(
+        &pool,
+        suffix,
+        "child",
+        &eight_years_ago,
+        doctor_id,
+        speciality_id,
+    )
+    .await;
+
+    let server = Server::start(&database_url, 18090).await;
+    let doctor = token(doctor_user, "doctor", doctor_id);
+    let admin = token(admin_user, "admin", 0);
+
+    // Imperial units are converted and the BMI worked out
+    let body = format!(
+        r#"{{"medical_record_id":{},"temperature":98.6,"temperature_unit":"fahrenheit",
+            "weight":154,"weight_unit":"lb","height":70,"height_unit":"in",
+            "blood_pressure_systolic":150,"blood_pressure_diastolic":95,"heart_rate":72,"spo2":98,
+            "recorded_at":"2026-01-10T08:30:00"}}"#,
+        adult_record
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    let reading = &body["data"];
+    assert_eq!(reading["temperature"], 37.0);
+    assert_eq!(reading["weight"], 69.85);
+    assert_eq!(reading["height"], 177.8);
+    assert_eq!(reading["bmi"], 22.1);
+    assert_eq!(reading["units"]["temperature"], "°C");
+    assert_eq!(reading["recorded_at"], "2026-01-10T08:30:00");
+    assert_eq!(reading["recorded_by"], doctor_user);
+    assert_eq!(reading["age_group"], "adult");
+    assert_eq!(
+        flagged(reading),
+        [
+            ("blood_pressure_systolic".to_string(), "high".to_string()),
+            ("blood_pressure_diastolic".to_string(), "high".to_string())
+        ]
+    );
+
+    // Readings no patient could have are refused, each problem named
+    let body = format!(
+        r#"{{"medical_record_id":{},"temperature":50,"spo2":120,
+            "blood_pressure_systolic":80,"blood_pressure_diastolic":120}}"#,
+        adult_record
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 400);
+    assert_eq!(body["errors"].as_array().unwrap().len(), 3, "{}", body);
+    let body = format!(
+        r#"{{"medical_record_id":{},"height":70000000000000000000000000000,"height_unit":"in"}}"#,
+        adult_record
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 400, "converting must not overflow");
+    assert_eq!(body["errors"][0], "height is too large", "{}", body);
+    let body = format!(r#"{{"medical_record_id":{}}}"#, adult_record);
+    let (status, _) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 400, "a reading needs a measurement");
+    let future = (Utc::now() + Duration::hours(2)).format("%Y-%m-%dT%H:%M:%S");
+    let body = format!(
+        r#"{{"medical_record_id":{},"heart_rate":70,"recorded_at":"{}"}}"#,
+        adult_record, future
+    );
+    let (status, _) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 400, "a reading cannot be from the future");
+
+    // The same heart rate is normal for a child and high for an adult
+    let body = format!(
+        r#"{{"medical_record_id":{},"heart_rate":110}}"#,
+        child_record
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 200, "{}", body);
+    assert_eq!(body["data"]["age_group"], "pediatric");
+    assert!(flagged(&body["data"]).is_empty());
+    let body = format!(
+        r#"{{"medical_record_id":{},"heart_rate":110}}"#,
+        adult_record
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", &doctor, &body)
+        .await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        flagged(&body["data"]),
+        [("heart_rate".to_string(), "high".to_string())]
+    );
+    assert_eq!(body["data"]["flags"][0]["high"], 100.0);
+    assert!(body["data"]["bmi"].is_null());
+
+    // Admins move the ranges, and readings are flagged by the current ones
+    let range = "/api/admin/vital-reference-ranges/adult/heart_rate";
+    let (status, _) = server
+        .send("PUT", range, &admin, r#"{"low":120,"high":50}"#)
+        .await;
+    assert_eq!(status, 400);
+    let (status, _) = server
+        .send(
+            "PUT",
+            "/api/admin/vital-reference-ranges/adult/pulse",
+            &admin,
+            r#"{"low":50,"high":120}"#,
+        )
+        .await;
+    assert_eq!(status, 404);
+    let (status, _) = server
+        .send("PUT", range, &admin, r#"{"low":50,"high":120}"#)
+        .await;
+    assert_eq!(status, 200);
+    let (status, body) = server
+        .send("GET", "/api/admin/vital-reference-ranges", &admin, "")
+        .await;
+    assert_eq!(status, 200);
+    assert!(body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|r| r["age_group"] == "adult"
+            && r["vital"] == "heart_rate"
+            && r["low"] == 50.0
+            && r["high"] == 120.0));
+    let list = format!("/api/medical-record/vital-signs/{}", adult_record);
+    let (status, body) = server.send("GET", &list, &doctor, "").await;
+    let (_, restored) = server
+        .send("PUT", range, &admin, r#"{"low":60,"high":100}"#)
+        .await;
+    assert_eq!(restored["success"], true);
+    assert_eq!(status, 200);
+    let readings = body["data"].as_array().unwrap();
+    assert_eq!(readings.len(), 2, "refused readings are not stored");
+    assert_eq!(
+        readings[0]["recorded_at"], "2026-01-10T08:30:00",
+        "oldest first"
+    );
+    assert!(flagged(&readings[1]).is_empty());
+}
@@ -0,0 +1,224 @@
+// Checks the vital signs trend against a running server: readings from all of a patient's
+// visits, and only theirs, come back as one series per metric with min, max, average and
+// the change from the previous visit.
+//
+// Needs a database with the migrations applied:
+//     DATABASE_URL=postgres://... cargo test --test vital_trends -- --ignored
+
+mod common;
+
+use chrono::Utc;
+use common::{create_user, token, Server};
+use serde_json::Value;
+use sqlx::PgPool;
+
+async fn create_patient(pool: &PgPool, suffix: i64, name: &str) -> i32 {
+    sqlx::query_scalar("INSERT INTO tn_patients (email, name) VALUES ($1, $2) RETURNING id")
+        .bind(format!("trends-{}-{}@hospital.test", name, suffix))
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+// A visit of the patient to the doctor and the record written during it
+async fn create_record(pool: &PgPool, patient_id: i32, doctor_id: i32, speciality_id: i32) -> i32 {
+    let appointment_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_appointments (patient_id, doctor_id, speciality_id, date, appointment_time, status)
+         VALUES ($1, $2, $3, CURRENT_DATE, '09:00', 'Unpaid') RETURNING id",
+    )
+    .bind(patient_id)
+    .bind(doctor_id)
+    .bind(speciality_id)
+    .fetch_one(pool)
+    .await
+    .unwrap();
+    sqlx::query_scalar(
+        "INSERT INTO tn_medical_records (appointment_id, patient_id, doctor_id) VALUES ($1, $2, $3) RETURNING id",
+    )
+    .bind(appointment_id)
+    .bind(patient_id)
+    .bind(doctor_id)
+    .fetch_one(pool)
+    .await
+    .unwrap()
+}
+
+async fn record_reading(
+    server: &Server,
+    token: &str,
+    record_id: i32,
+    recorded_at: &str,
+    measurements: &str,
+) {
+    let body = format!(
+        r#"{{"medical_record_id":{},"recorded_at":"{}",{}}}"#,
+        record_id, recorded_at, measurements
+    );
+    let (status, body) = server
+        .send("POST", "/api/medical-record/vital-signs", token, &body)
+        .await;
+    assert_eq!(status, 200, "{}", body);
+}
+
+async fn trend(server: &Server, token: &str, patient_id: i32, query: &str) -> (u16, Value) {
+    let path = format!("/api/patient/{}/vitals/trend?{}", patient_id, query);
+    server.send("GET", &path, token, "").await
+}
+
+fn values(series: &Value, field: &str) -> Vec<Value> {
+    series["points"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|point| point[field].clone())
+        .collect()
+}
+
+#[tokio::test]
+#[ignore = "requires DATABASE_URL pointing at a migrated Postgres database"]
+async fn trends_span_every_visit_of_the_patient() {
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url).await.unwrap();
+
+    let suffix = Utc::now().timestamp_nanos_opt().unwrap();
+    let speciality_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_specialities (name, slot_duration) VALUES ($1, 30) RETURNING id",
+    )
+    .bind(format!("trends-{}", suffix % 1_000_000_000))
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let doctor_email = format!("trends-doctor-{}@hospital.test", suffix);
+    let doctor_user = create_user(&pool, &doctor_email, "doctor").await;
+    let doctor_id: i32 = sqlx::query_scalar(
+        "INSERT INTO tn_doctors (email, name, speciality_id, active, user_id) VALUES ($1, 'Trends Doctor', $2, 1, $3) RETURNING id",
+    )
+    .bind(&doctor_email)
+    .bind(speciality_id)
+    .bind(doctor_user)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let patient_user = create_user(
+        &pool,
+        &format!("trends-user-{}@hospital.test", suffix),
+        "patient",
+    )
+    .await;
+    let patient_id = create_patient(&pool, suffix, "patient").await;
+    let other_patient_id = create_patient(&pool, suffix, "other").await;
+    let march = create_record(&pool, patient_id, doctor_id, speciality_id).await;
+    let june = create_record(&pool, patient_id, doctor_id, speciality_id).await;
+    let other_record = create_record(&pool, other_patient_id, doctor_id, speciality_id).await;
+
+    let server = Server::start(&database_url, 18091).await;
+    let doctor = token(doctor_user, "doctor", doctor_id);
+    let patient = token(patient_user, "patient", patient_id);
+
+    // Two readings in March, one in June, and someone else's in between
+    record_reading(
+        &server,
+        &doctor,
+        march,
+        "2026-03-02T08:00:00",
+        r#""blood_pressure_systolic":140,"weight":80,"height":180"#,
+    )
+    .await;
+    record_reading(
+        &server,
+        &doctor,
+        march,
+        "2026-03-02T08:30:00",
+        r#""blood_pressure_systolic":136,"heart_rate":70"#,
+    )
+    .await;
+    record_reading(
+        &server,
+        &doctor,
+        other_record,
+        "2026-04-01T08:00:00",
+        r#""blood_pressure_systolic":190"#,
+    )
+    .await;
+    record_reading(
+        &server,
+        &doctor,
+        june,
+        "2026-06-01T09:00:00",
+        r#""blood_pressure_systolic":128,"weight":78.5"#,
+    )
+    .await;
+
+    let (status, body) = trend(
+        &server,
+        &patient,
+        patient_id,
+        "metric=blood_pressure_systolic,bmi",
+    )
+    .await;
+    assert_eq!(status, 200, "{}", body);
+    let series = body["data"]["series"].as_array().unwrap();
+    assert_eq!(series.len(), 2);
+    let systolic = &series[0];
+    assert_eq!(systolic["metric"], "blood_pressure_systolic");
+    assert_eq!(systolic["unit"], "mmHg");
+    assert_eq!(systolic["count"], 3, "only the patient's own readings");
+    assert_eq!(values(systolic, "value"), [140.0, 136.0, 128.0]);
+    assert_eq!(
+        values(systolic, "delta"),
+        [Value::Null, Value::Null, serde_json::json!(-8.0)],
+        "deltas are from the last reading of the previous visit"
+    );
+    assert_eq!(values(systolic, "medical_record_id"), [march, march, june]);
+    assert_eq!(systolic["min"], 128.0);
+    assert_eq!(systolic["max"], 140.0);
+    assert_eq!(systolic["avg"], 134.7);
+    let bmi = &series[1];
+    assert_eq!(bmi["metric"], "bmi");
+    assert_eq!(values(bmi, "value"), [24.7], "BMI needs weight and height");
+
+    // Every metric unless asked, empty series included
+    let (_, body) = trend(&server, &doctor, patient_id, "").await;
+    let series = body["data"]["series"].as_array().unwrap();
+    assert_eq!(series.len(), 8);
+    let weight = series.iter().find(|s| s["metric"] == "weight").unwrap();
+    assert_eq!(
+        values(weight, "delta"),
+        [Value::Null, serde_json::json!(-1.5)]
+    );
+    let spo2 = series.iter().find(|s| s["metric"] == "spo2").unwrap();
+    assert_eq!(spo2["count"], 0);
+    assert!(spo2["avg"].is_null());
+
+    // Dates bound the readings, both ends included
+    let (_, body) = trend(
+        &server,
+        &doctor,
+        patient_id,
+        "metric=blood_pressure_systolic&to=2026-03-02",
+    )
+    .await;
+    assert_eq!(body["data"]["series"][0]["count"], 2);
+    let (_, body) = trend(
+        &server,
+        &doctor,
+        patient_id,
+        "metric=blood_pressure_systolic&from=2026-06-01",
+    )
+    .await;
+    assert_eq!(values(&body["data"]["series"][0], "value"), [128.0]);
+
+    let (status, _) = trend(&server, &doctor, patient_id, "metric=pulse").await;
+    assert_eq!(status, 400);
+    let (status, _) = trend(
+        &server,
+        &doctor,
+        patient_id,
+        "from=2026-06-01&to=2026-03-01",
+    )
+    .await;
+    assert_eq!(status, 400);
+    let (status, _) = trend(&server, &patient, other_patient_id, "").await;
+    assert_eq!(status, 403);
+}